@@ -0,0 +1,38 @@
+#![feature(test)]
+
+extern crate test;
+
+use merk::owner::Owner;
+use merk::test_utils::*;
+use merk::tree::{kv_hash, node_hash, Hasher, NULL_HASH};
+use test::Bencher;
+
+// Run with and without `--features blake3-hash` to compare hash algorithms.
+
+#[bench]
+fn kv_hash_32b_key_64b_value(b: &mut Bencher) {
+    let key = [1; 32];
+    let value = [2; 64];
+    b.iter(|| kv_hash::<Hasher>(&key, &value).unwrap());
+}
+
+#[bench]
+fn node_hash_3x32b(b: &mut Bencher) {
+    let kv = [1; 32];
+    b.iter(|| node_hash::<Hasher>(&kv, &NULL_HASH, &NULL_HASH));
+}
+
+#[bench]
+fn insert_1m_100k_rand_memonly(b: &mut Bencher) {
+    let initial_size = 1_000_000;
+    let batch_size = 100_000;
+
+    let mut tree = Owner::new(make_tree_rand(initial_size, batch_size, 0));
+
+    let mut i = initial_size / batch_size;
+    b.iter(|| {
+        let batch = make_batch_rand(batch_size, i);
+        tree.own(|tree| apply_memonly_unchecked(tree, &batch));
+        i += 1;
+    });
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merk::tree::Tree;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tree) = Tree::decode(vec![], data) {
+        // anything which decodes must survive a round trip
+        let encoded = tree.encode();
+        let decoded = Tree::decode(vec![], &encoded).unwrap();
+        assert_eq!(decoded.encode(), encoded);
+    }
+});
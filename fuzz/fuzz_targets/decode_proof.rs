@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merk::proofs::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+    for op in Decoder::new(data) {
+        if op.is_err() {
+            break;
+        }
+    }
+    let _ = merk::verify(data, [0; merk::HASH_LENGTH]);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use merk::proofs::chunk::{verify_leaf, verify_trunk};
+use merk::proofs::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = verify_trunk(Decoder::new(data), None);
+    let _ = verify_leaf(Decoder::new(data), [0; merk::HASH_LENGTH]);
+});
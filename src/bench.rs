@@ -0,0 +1,325 @@
+//! Workload generators and a runner for benchmarking a store, so performance
+//! changes can be measured reproducibly with workloads resembling real usage.
+//!
+//! The `merk-bench` binary runs these workloads from the command line.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use rand::prelude::*;
+
+use crate::test_utils::TempMerk;
+use crate::tree::{BatchEntry, Op};
+use crate::{Error, Result};
+
+/// How the keys read and written by a workload are chosen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyDistribution {
+    /// Every existing key is equally likely.
+    Uniform,
+    /// Keys are chosen with probability proportional to `1 / rank^exponent`,
+    /// so a few keys are much hotter than the rest.
+    Zipfian { exponent: f64 },
+    /// Writes append new keys after all existing keys, and reads cycle through
+    /// the keys in order.
+    Sequential,
+}
+
+/// A benchmark workload, run against a temporary store with `run`.
+#[derive(Clone, Debug)]
+pub struct Workload {
+    /// The number of keys written before the workload is timed.
+    pub initial_keys: u64,
+    /// The number of timed reads and writes.
+    pub operations: u64,
+    /// The fraction of operations which are reads, between 0 and 1.
+    pub read_ratio: f64,
+    /// The number of writes applied in each batch.
+    pub batch_size: usize,
+    /// The size of written values, in bytes.
+    pub value_size: usize,
+    pub distribution: KeyDistribution,
+    /// The seed for all random choices, so runs are reproducible.
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            initial_keys: 100_000,
+            operations: 100_000,
+            read_ratio: 0.5,
+            batch_size: 1_000,
+            value_size: 64,
+            distribution: KeyDistribution::Uniform,
+            seed: 0,
+        }
+    }
+}
+
+/// Latency percentiles for one kind of operation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Latencies {
+    /// The number of timed operations.
+    pub count: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latencies {
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+        let percentile = |p: usize| {
+            if samples.is_empty() {
+                return Duration::ZERO;
+            }
+            samples[(samples.len() - 1) * p / 100]
+        };
+
+        Latencies {
+            count: samples.len(),
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: percentile(100),
+        }
+    }
+}
+
+/// The results of running a `Workload`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Report {
+    /// The latencies of single-key reads.
+    pub reads: Latencies,
+    /// The latencies of applying (and committing) each batch of writes.
+    pub batches: Latencies,
+    /// The number of reads and writes performed.
+    pub operations: u64,
+    /// The total time taken by the timed operations.
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Returns the number of reads and writes performed per second.
+    pub fn throughput(&self) -> f64 {
+        self.operations as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ops in {:.3?} ({:.0} ops/s)",
+            self.operations,
+            self.elapsed,
+            self.throughput()
+        )?;
+        for (name, latencies) in [("reads", &self.reads), ("batches", &self.batches)] {
+            writeln!(
+                f,
+                "{:<8} n={:<8} p50={:<10.3?} p90={:<10.3?} p99={:<10.3?} max={:.3?}",
+                name, latencies.count, latencies.p50, latencies.p90, latencies.p99, latencies.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Chooses keys from `0..len` following a `KeyDistribution`.
+pub struct KeyGenerator {
+    distribution: KeyDistribution,
+    len: u64,
+    /// The cumulative probability of each rank, for zipfian distributions.
+    cdf: Vec<f64>,
+    /// The next key read, for sequential distributions.
+    cursor: u64,
+}
+
+impl KeyGenerator {
+    /// Creates a generator over `len` keys.
+    pub fn new(distribution: KeyDistribution, len: u64) -> Self {
+        let cdf = match distribution {
+            KeyDistribution::Zipfian { exponent } => {
+                let mut sum = 0.0;
+                let mut cdf: Vec<f64> = (1..=len)
+                    .map(|rank| {
+                        sum += 1.0 / (rank as f64).powf(exponent);
+                        sum
+                    })
+                    .collect();
+                cdf.iter_mut().for_each(|p| *p /= sum);
+                cdf
+            }
+            _ => vec![],
+        };
+
+        KeyGenerator {
+            distribution,
+            len,
+            cdf,
+            cursor: 0,
+        }
+    }
+
+    /// Returns the index of an existing key to read or update.
+    pub fn existing<R: Rng>(&mut self, rng: &mut R) -> u64 {
+        match self.distribution {
+            KeyDistribution::Uniform => rng.gen_range(0..self.len),
+            KeyDistribution::Zipfian { .. } => {
+                // the hottest keys are the lowest ones
+                let p: f64 = rng.gen();
+                let rank = self.cdf.partition_point(|&q| q < p) as u64;
+                rank.min(self.len - 1)
+            }
+            KeyDistribution::Sequential => {
+                let n = self.cursor % self.len;
+                self.cursor += 1;
+                n
+            }
+        }
+    }
+
+    /// Returns the index of a key to write: a new key after all existing keys
+    /// for sequential distributions, or an existing key otherwise.
+    pub fn write<R: Rng>(&mut self, rng: &mut R) -> u64 {
+        match self.distribution {
+            KeyDistribution::Sequential => {
+                self.len += 1;
+                self.len - 1
+            }
+            _ => self.existing(rng),
+        }
+    }
+}
+
+/// Returns the key with the given index.
+pub fn key(n: u64) -> Vec<u8> {
+    n.to_be_bytes().to_vec()
+}
+
+/// Runs `workload` against a new temporary store, which is deleted afterwards.
+pub fn run(workload: &Workload) -> Result<Report> {
+    if !(0.0..=1.0).contains(&workload.read_ratio) {
+        return Err(Error::Config(format!(
+            "Invalid read ratio {}",
+            workload.read_ratio
+        )));
+    }
+    if workload.initial_keys == 0 || workload.batch_size == 0 {
+        return Err(Error::Config(
+            "Workloads need initial keys and a non-zero batch size".into(),
+        ));
+    }
+
+    let mut rng = SmallRng::seed_from_u64(workload.seed);
+    let value = vec![0x42; workload.value_size];
+
+    let mut merk = TempMerk::new()?;
+    for start in (0..workload.initial_keys).step_by(workload.batch_size) {
+        let end = (start + workload.batch_size as u64).min(workload.initial_keys);
+        let batch: Vec<_> = (start..end)
+            .map(|n| (key(n), Op::Put(value.clone())))
+            .collect();
+        merk.apply(&batch, &[])?;
+    }
+
+    let mut keys = KeyGenerator::new(workload.distribution, workload.initial_keys);
+    let mut reads = Vec::new();
+    let mut batches = Vec::new();
+    let mut batch = Vec::with_capacity(workload.batch_size);
+    let start = Instant::now();
+    for _ in 0..workload.operations {
+        if rng.gen_bool(workload.read_ratio) {
+            let key = key(keys.existing(&mut rng));
+            let read_start = Instant::now();
+            merk.get(&key)?;
+            reads.push(read_start.elapsed());
+        } else {
+            batch.push((key(keys.write(&mut rng)), Op::Put(value.clone())));
+            if batch.len() == workload.batch_size {
+                batches.push(apply_writes(&mut merk, &mut batch)?);
+            }
+        }
+    }
+    if !batch.is_empty() {
+        batches.push(apply_writes(&mut merk, &mut batch)?);
+    }
+
+    Ok(Report {
+        reads: Latencies::from_samples(reads),
+        batches: Latencies::from_samples(batches),
+        operations: workload.operations,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Applies and clears the pending writes, returning the time taken. Later
+/// writes to the same key replace earlier ones.
+fn apply_writes(merk: &mut TempMerk, batch: &mut Vec<BatchEntry>) -> Result<Duration> {
+    // the sort is stable, so the last write to each key is kept
+    batch.sort_by(|a, b| a.0.cmp(&b.0));
+    batch.reverse();
+    batch.dedup_by(|a, b| a.0 == b.0);
+    batch.reverse();
+
+    let start = Instant::now();
+    merk.apply(batch, &[])?;
+    let elapsed = start.elapsed();
+    batch.clear();
+    Ok(elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_workloads() {
+        for distribution in [
+            KeyDistribution::Uniform,
+            KeyDistribution::Zipfian { exponent: 1.0 },
+            KeyDistribution::Sequential,
+        ] {
+            let workload = Workload {
+                initial_keys: 1_000,
+                operations: 2_000,
+                read_ratio: 0.75,
+                batch_size: 100,
+                distribution,
+                ..Default::default()
+            };
+            let report = run(&workload).unwrap();
+            assert_eq!(report.operations, 2_000);
+            assert!(report.reads.count > 1_000);
+            assert!(report.batches.count >= 5);
+            assert!(report.reads.p50 <= report.reads.p99);
+        }
+    }
+
+    #[test]
+    fn zipfian_keys_are_skewed() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut keys = KeyGenerator::new(KeyDistribution::Zipfian { exponent: 1.2 }, 10_000);
+        let mut counts = vec![0; 10_000];
+        for _ in 0..10_000 {
+            counts[keys.existing(&mut rng) as usize] += 1;
+        }
+        counts.sort_unstable();
+        // the hottest key is chosen far more often than under a uniform
+        // distribution, and most keys are never chosen
+        assert!(counts[9_999] > 1_000);
+        assert!(counts[5_000] == 0);
+    }
+
+    #[test]
+    fn invalid_workload() {
+        let workload = Workload {
+            read_ratio: 1.5,
+            ..Default::default()
+        };
+        assert!(run(&workload).is_err());
+    }
+}
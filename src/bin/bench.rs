@@ -0,0 +1,84 @@
+//! Runs a benchmark workload against a temporary store and prints its
+//! throughput and latency percentiles.
+
+use std::process;
+use std::str::FromStr;
+
+use merk::bench::{run, KeyDistribution, Workload};
+use merk::{Error, Result};
+
+const USAGE: &str = "Usage: merk-bench [option=value]...
+
+Options:
+  keys=<n>              Number of keys written before the run (default 100000)
+  ops=<n>               Number of timed operations (default 100000)
+  read-ratio=<r>        Fraction of operations which are reads (default 0.5)
+  batch-size=<n>        Number of writes in each applied batch (default 1000)
+  value-size=<n>        Size of written values in bytes (default 64)
+  keys-from=<dist>      uniform, zipfian or sequential (default uniform)
+  zipf-exponent=<s>     Skew of the zipfian distribution (default 0.99)
+  seed=<n>              Seed for random choices (default 0)";
+
+/// The skew of zipfian workloads if none is given, as used by YCSB.
+const DEFAULT_ZIPF_EXPONENT: f64 = 0.99;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let workload = match parse_workload(&args) {
+        Ok(workload) => workload,
+        Err(err) => {
+            eprintln!("Error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&workload) {
+        Ok(report) => print!("{}", report),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_workload(args: &[String]) -> Result<Workload> {
+    let mut workload = Workload::default();
+    let mut distribution = "uniform";
+    let mut exponent = DEFAULT_ZIPF_EXPONENT;
+
+    for arg in args {
+        let (name, value) = arg
+            .split_once('=')
+            .ok_or_else(|| Error::Config(format!("Invalid option {}", arg)))?;
+        match name {
+            "keys" => workload.initial_keys = parse(name, value)?,
+            "ops" => workload.operations = parse(name, value)?,
+            "read-ratio" => workload.read_ratio = parse(name, value)?,
+            "batch-size" => workload.batch_size = parse(name, value)?,
+            "value-size" => workload.value_size = parse(name, value)?,
+            "keys-from" => distribution = value,
+            "zipf-exponent" => exponent = parse(name, value)?,
+            "seed" => workload.seed = parse(name, value)?,
+            _ => return Err(Error::Config(format!("Unknown option {}", name))),
+        }
+    }
+
+    workload.distribution = match distribution {
+        "uniform" => KeyDistribution::Uniform,
+        "zipfian" => KeyDistribution::Zipfian { exponent },
+        "sequential" => KeyDistribution::Sequential,
+        _ => {
+            return Err(Error::Config(format!(
+                "Unknown distribution {}",
+                distribution
+            )))
+        }
+    };
+    Ok(workload)
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid {} {}", name, value)))
+}
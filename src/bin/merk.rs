@@ -0,0 +1,430 @@
+//! A command line tool for inspecting and maintaining merk stores.
+
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use merk::proofs::{Decoder, Node, Op as ProofOp};
+use merk::tree::{DotOptions, Hasher, Link, RefWalker, Tree};
+use merk::{vectors, verify, Error, Hash, Merk, MerkSource, Result};
+use sha2::Digest;
+
+const USAGE: &str = "Usage: merk <command> [args]
+
+Commands:
+  root-hash <db>                           Prints the root hash of the store
+  checksum <db>                            Prints a digest of all stored data, for comparing stores
+  dump <db> [start] [end] [limit]          Prints the entries with keys in start..end
+  node <db> <key>                          Prints a node and its links
+  dot <db> [max-depth]                     Prints the top of the tree in the Graphviz DOT format
+  check <db>                               Checks the stored nodes and their links for corruption
+  export-chunks <db> <dir>                 Adds the state sync chunks of the store and a manifest of
+                                           its root hash to the snapshot directory dir
+  import-chunks <dir> <db> <root-hash>     Checks the manifest of root-hash in dir, then restores a new
+                                           store at db from its chunks
+  verify-proof <proof-file> <root-hash>    Verifies a proof and prints the entries it contains
+  test-vectors                             Prints canonical test vectors of proofs as JSON
+
+Keys and hashes are given and printed as hex.";
+
+/// The number of entries printed by `dump` if no limit is given.
+const DEFAULT_DUMP_LIMIT: usize = 100;
+
+/// The number of levels printed by `dot` if no maximum depth is given.
+const DEFAULT_DOT_DEPTH: usize = 4;
+
+/// The subdirectory of a snapshot directory chunks are stored in, each in a
+/// file named by its hash, so chunks which are unchanged between the exported
+/// versions of a store are only stored once.
+const CHUNKS_DIR: &str = "chunks";
+
+/// The subdirectory of a snapshot directory manifests are stored in, each in a
+/// file named by the root hash of its version of the store.
+const MANIFESTS_DIR: &str = "manifests";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let res = match args.as_slice() {
+        ["root-hash", db] => root_hash(db),
+        ["checksum", db] => checksum(db),
+        ["dump", db, rest @ ..] if rest.len() <= 3 => dump(db, rest),
+        ["node", db, key] => node(db, key),
+        ["dot", db, rest @ ..] if rest.len() <= 1 => dot(db, rest.first()),
+        ["check", db] => check(db),
+        ["export-chunks", db, dir] => export_chunks(db, dir),
+        ["import-chunks", dir, db, root_hash] => import_chunks(dir, db, root_hash),
+        ["verify-proof", path, root_hash] => verify_proof(path, root_hash),
+        ["test-vectors"] => test_vectors(),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = res {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Opens an existing store, rather than creating one if the path is wrong.
+fn open(path: &str) -> Result<Merk> {
+    if !Path::new(path).exists() {
+        return Err(Error::Path(format!("No store exists at {}", path)));
+    }
+    Merk::open(path)
+}
+
+fn root_hash(db: &str) -> Result<()> {
+    let merk = open(db)?;
+    println!("{}", hex::encode(merk.root_hash()));
+    Ok(())
+}
+
+fn checksum(db: &str) -> Result<()> {
+    let merk = open(db)?;
+    println!("{}", hex::encode(merk.checksum()?));
+    Ok(())
+}
+
+fn dump(db: &str, args: &[&str]) -> Result<()> {
+    let start = args.first().map(|start| parse_hex(start)).transpose()?;
+    let end = args.get(1).map(|end| parse_hex(end)).transpose()?;
+    let limit = match args.get(2) {
+        Some(limit) => limit
+            .parse()
+            .map_err(|_| Error::Key(format!("Invalid limit {}", limit)))?,
+        None => DEFAULT_DUMP_LIMIT,
+    };
+
+    let merk = open(db)?;
+    let start = start.unwrap_or_default();
+    for (key, value) in merk.get_range(&start, end.as_deref(), limit)? {
+        println!("{} {}", hex::encode(key), hex::encode(value));
+    }
+    Ok(())
+}
+
+fn node(db: &str, key: &str) -> Result<()> {
+    fn find(mut walker: RefWalker<MerkSource>, key: &[u8]) -> Result<bool> {
+        let left = match key.cmp(walker.tree().key()) {
+            Ordering::Equal => {
+                print_node(walker.tree());
+                return Ok(true);
+            }
+            Ordering::Less => true,
+            Ordering::Greater => false,
+        };
+        match walker.walk(left)? {
+            Some(child) => find(child, key),
+            None => Ok(false),
+        }
+    }
+
+    let key = parse_hex(key)?;
+    let merk = open(db)?;
+    let found = merk.walk(|maybe_walker| match maybe_walker {
+        Some(walker) => find(walker, &key),
+        None => Ok(false),
+    })?;
+
+    if !found {
+        return Err(Error::KeyNotFound(hex::encode(key)));
+    }
+    Ok(())
+}
+
+fn print_node(tree: &Tree) {
+    println!("{:<16}{}", "key:", hex::encode(tree.key()));
+    println!("{:<16}{}", "value:", hex::encode(tree.value()));
+    println!("{:<16}{}", "hash:", hex::encode(tree.hash()));
+    println!("{:<16}{}", "kv hash:", hex::encode(tree.kv_hash()));
+    println!("{:<16}{}", "height:", tree.height());
+    println!("{:<16}{}", "balance factor:", tree.balance_factor());
+    print_link("left", tree.link(true));
+    print_link("right", tree.link(false));
+}
+
+fn print_link(side: &str, maybe_link: Option<&Link>) {
+    let label = format!("{} link:", side);
+    match maybe_link {
+        Some(link) => println!(
+            "{:<16}key {}, hash {}, height {}",
+            label,
+            hex::encode(link.key()),
+            hex::encode(link.hash()),
+            link.height()
+        ),
+        None => println!("{:<16}none", label),
+    }
+}
+
+fn dot(db: &str, max_depth: Option<&&str>) -> Result<()> {
+    let max_depth = match max_depth {
+        Some(depth) => depth
+            .parse()
+            .map_err(|_| Error::Key(format!("Invalid depth {}", depth)))?,
+        None => DEFAULT_DOT_DEPTH,
+    };
+    let options = DotOptions {
+        max_depth: Some(max_depth),
+        ..Default::default()
+    };
+
+    let merk = open(db)?;
+    merk.walk(|maybe_walker| match maybe_walker {
+        Some(mut walker) => walker.write_dot(&mut std::io::stdout(), &options),
+        None => Ok(()),
+    })
+}
+
+fn check(db: &str) -> Result<()> {
+    let merk = open(db)?;
+
+    let corrupted = merk.scrub()?;
+    for key in corrupted.iter() {
+        println!("corrupted node: {}", hex::encode(key));
+    }
+
+    let mismatches = merk.check_links()?;
+    for mismatch in mismatches.iter() {
+        println!(
+            "inconsistent {} link from {} to {}: {:?}",
+            if mismatch.left { "left" } else { "right" },
+            hex::encode(&mismatch.parent),
+            hex::encode(&mismatch.child),
+            mismatch.kind
+        );
+    }
+
+    let root_hash = merk.root_hash();
+    if let Some(record) = merk.last_commit()? {
+        if record.root_hash != root_hash {
+            return Err(Error::HashMismatch(record.root_hash, root_hash));
+        }
+    }
+
+    if !corrupted.is_empty() {
+        return Err(Error::Corruption {
+            key: corrupted[0].clone(),
+        });
+    }
+    if let Some(mismatch) = mismatches.first() {
+        return Err(Error::Corruption {
+            key: mismatch.parent.clone(),
+        });
+    }
+    println!("ok, root hash {}", hex::encode(root_hash));
+    Ok(())
+}
+
+fn export_chunks(db: &str, dir: &str) -> Result<()> {
+    let merk = open(db)?;
+    fs::create_dir_all(format!("{}/{}", dir, CHUNKS_DIR))?;
+    fs::create_dir_all(format!("{}/{}", dir, MANIFESTS_DIR))?;
+
+    let mut chunk_hashes = vec![];
+    let mut written = 0;
+    for chunk in merk.chunks()?.into_iter() {
+        let chunk = chunk?;
+        let chunk_hash = hash(&chunk);
+        let path = chunk_path(dir, &chunk_hash);
+        if !Path::new(&path).exists() {
+            write_atomic(&path, &chunk)?;
+            written += 1;
+        }
+        chunk_hashes.push(chunk_hash);
+    }
+
+    let manifest = Manifest {
+        root_hash: merk.root_hash(),
+        height: merk.last_height(),
+        chunk_hashes,
+    };
+    write_atomic(
+        &manifest_path(dir, &manifest.root_hash),
+        manifest.encode().as_bytes(),
+    )?;
+
+    println!(
+        "exported {} chunks ({} new), root hash {}",
+        manifest.chunk_hashes.len(),
+        written,
+        hex::encode(manifest.root_hash)
+    );
+    Ok(())
+}
+
+fn import_chunks(dir: &str, db: &str, root_hash: &str) -> Result<()> {
+    let root_hash = parse_hash(root_hash)?;
+    let path = manifest_path(dir, &root_hash);
+    if !Path::new(&path).exists() {
+        return Err(Error::Path(format!(
+            "No manifest of root hash {} exists in {}",
+            hex::encode(root_hash),
+            dir
+        )));
+    }
+    let manifest = Manifest::decode(&fs::read_to_string(path)?)?;
+    if manifest.root_hash != root_hash {
+        return Err(Error::HashMismatch(root_hash, manifest.root_hash));
+    }
+
+    let count = manifest.chunk_hashes.len();
+    for (index, expected) in manifest.chunk_hashes.iter().enumerate() {
+        let actual = hash(&fs::read(chunk_path(dir, expected))?);
+        if actual != *expected {
+            return Err(Error::ChunkProcessing(format!(
+                "Chunk {} does not match its hash in the manifest",
+                index
+            )));
+        }
+    }
+
+    let mut restorer = Merk::restore(db, root_hash, count)?;
+    for chunk_hash in manifest.chunk_hashes.iter() {
+        let chunk = fs::read(chunk_path(dir, chunk_hash))?;
+        restorer.process_chunk(&chunk)?;
+    }
+    let merk = restorer.finalize()?;
+
+    println!(
+        "restored {} chunks, root hash {}",
+        count,
+        hex::encode(merk.root_hash())
+    );
+    Ok(())
+}
+
+/// The manifest of a chunk export: the root hash and height of the exported
+/// store, and the hash of each chunk. It is written as text lines, ending with
+/// a checksum of the lines before it:
+///
+/// ```text
+/// root-hash <hash>
+/// height <height, or "none">
+/// chunk-count <count>
+/// chunk <hash>        (once per chunk, in order)
+/// checksum <hash>
+/// ```
+struct Manifest {
+    root_hash: Hash,
+    height: Option<u64>,
+    chunk_hashes: Vec<Hash>,
+}
+
+impl Manifest {
+    fn encode(&self) -> String {
+        let height = self
+            .height
+            .map_or_else(|| "none".to_string(), |height| height.to_string());
+        let mut text = format!(
+            "root-hash {}\nheight {}\nchunk-count {}\n",
+            hex::encode(self.root_hash),
+            height,
+            self.chunk_hashes.len()
+        );
+        for chunk_hash in self.chunk_hashes.iter() {
+            text += &format!("chunk {}\n", hex::encode(chunk_hash));
+        }
+        let checksum = hash(text.as_bytes());
+        text + &format!("checksum {}\n", hex::encode(checksum))
+    }
+
+    fn decode(text: &str) -> Result<Self> {
+        let invalid =
+            |reason: &str| Error::ChunkProcessing(format!("Invalid manifest: {}", reason));
+
+        let body_len = text
+            .trim_end()
+            .rfind('\n')
+            .map(|index| index + 1)
+            .ok_or_else(|| invalid("missing checksum"))?;
+        let (body, checksum_line) = text.split_at(body_len);
+        match checksum_line.trim_end().strip_prefix("checksum ") {
+            Some(checksum) if parse_hash(checksum)? == hash(body.as_bytes()) => {}
+            Some(_) => return Err(invalid("checksum does not match")),
+            None => return Err(invalid("missing checksum")),
+        }
+
+        let mut lines = body.lines();
+        let mut field = |name: &str| match lines.next().and_then(|line| line.split_once(' ')) {
+            Some((key, value)) if key == name => Ok(value),
+            _ => Err(invalid(&format!("expected {}", name))),
+        };
+        let root_hash = parse_hash(field("root-hash")?)?;
+        let height = match field("height")? {
+            "none" => None,
+            height => Some(height.parse().map_err(|_| invalid("invalid height"))?),
+        };
+        let count: usize = field("chunk-count")?
+            .parse()
+            .map_err(|_| invalid("invalid chunk count"))?;
+        let chunk_hashes = (0..count)
+            .map(|_| parse_hash(field("chunk")?))
+            .collect::<Result<_>>()?;
+        if lines.next().is_some() {
+            return Err(invalid("unexpected lines after the chunk hashes"));
+        }
+
+        Ok(Manifest {
+            root_hash,
+            height,
+            chunk_hashes,
+        })
+    }
+}
+
+fn hash(bytes: &[u8]) -> Hash {
+    let mut hash: Hash = Default::default();
+    hash.copy_from_slice(&Hasher::digest(bytes)[..]);
+    hash
+}
+
+fn verify_proof(path: &str, root_hash: &str) -> Result<()> {
+    let root_hash = parse_hash(root_hash)?;
+    let bytes = fs::read(path)?;
+    verify(&bytes, root_hash)?;
+
+    for op in Decoder::new(&bytes) {
+        if let ProofOp::Push(Node::KV(key, value)) = op? {
+            println!("{} {}", hex::encode(key), hex::encode(value));
+        }
+    }
+    Ok(())
+}
+
+fn chunk_path(dir: &str, chunk_hash: &Hash) -> String {
+    format!("{}/{}/{}", dir, CHUNKS_DIR, hex::encode(chunk_hash))
+}
+
+fn manifest_path(dir: &str, root_hash: &Hash) -> String {
+    format!("{}/{}/{}", dir, MANIFESTS_DIR, hex::encode(root_hash))
+}
+
+/// Writes a file through a temporary file, so an interrupted export never
+/// leaves a partial chunk under its hash.
+fn write_atomic(path: &str, bytes: &[u8]) -> Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+fn parse_hex(input: &str) -> Result<Vec<u8>> {
+    hex::decode(input).map_err(|err| Error::Key(format!("Invalid hex {}: {}", input, err)))
+}
+
+fn parse_hash(input: &str) -> Result<Hash> {
+    parse_hex(input)?
+        .try_into()
+        .map_err(|_| Error::Key(format!("Invalid hash {}", input)))
+}
+
+fn test_vectors() -> Result<()> {
+    vectors::write_json(&vectors::default_cases(), &mut std::io::stdout())
+}
@@ -1,21 +1,46 @@
+// `Error::BatchKey` is kept for compatibility, and is matched in the impls
+// derived here
+#![allow(deprecated)]
+
 pub use thiserror::Error;
 
+/// An alias of `Error`, for callers which import it alongside other crates'
+/// `Error` types.
+pub type MerkError = Error;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Attach Error: {0}")]
     Attach(String),
+    #[deprecated(
+        note = "batch validation returns `DuplicateBatchKey` or `UnsortedBatch` instead, which \
+                carry the offending key; match on those, or on `ErrorKind::Batch` from \
+                `Error::kind`"
+    )]
     #[error("Batch Key Error: {0}")]
     BatchKey(String),
     #[error("Bound Error: {0}")]
     Bound(String),
     #[error("Chunk Processing Error: {0}")]
     ChunkProcessing(String),
+    #[error("Compression Error: {0}")]
+    Compression(String),
+    #[error("Config Error: {0}")]
+    Config(String),
+    #[error("Corrupted node at key {key:?}")]
+    Corruption { key: Vec<u8> },
+    #[error("Keys in batch must be unique, but {key:?} appears more than once")]
+    DuplicateBatchKey { key: Vec<u8> },
     #[error(transparent)]
     Ed(#[from] ed::Error),
+    #[error("Encryption Error: {0}")]
+    Encryption(String),
     #[error("Fetch Error: {0}")]
     Fetch(String),
     #[error("Proof did not match expected hash\n\tExpected: {0:?}\n\tActual: {1:?}")]
     HashMismatch([u8; 32], [u8; 32]),
+    #[error("Height {height} is not after the current height {current}")]
+    HeightRegression { height: u64, current: u64 },
     #[error("Index OoB Error: {0}")]
     IndexOutOfBounds(String),
     #[error("Integer conversion error: {0}")]
@@ -28,23 +53,99 @@ pub enum Error {
     Key(String),
     #[error("Key not found: {0}")]
     KeyNotFound(String),
+    #[error("Key {key:?} exceeds the {limit} limit of {max}")]
+    LimitExceeded {
+        key: Vec<u8>,
+        limit: &'static str,
+        max: usize,
+    },
     #[error("Proof is missing data for query")]
     MissingData,
     #[error("Path Error: {0}")]
     Path(String),
+    #[error("Lock was poisoned by a panicked thread")]
+    Poisoned,
     #[error("Proof Error: {0}")]
     Proof(String),
+    #[error("Rate Limited: {0}")]
+    RateLimited(String),
+    #[error("Replication Error: {0}")]
+    Replication(String),
+    #[error("Root hash {actual:?} does not match the expected {expected:?}")]
+    RootConflict {
+        expected: [u8; 32],
+        actual: [u8; 32],
+    },
     #[cfg(feature = "full")]
     #[error(transparent)]
     RocksDB(#[from] rocksdb::Error),
     #[error("Stack Underflow")]
     StackUnderflow,
+    #[error("Torn Commit Error: {0}")]
+    TornCommit(String),
     #[error("Tree Error: {0}")]
     Tree(String),
     #[error("Unexpected Node Error: {0}")]
     UnexpectedNode(String),
+    #[error("Keys in batch must be sorted, but {key:?} is out of order")]
+    UnsortedBatch { key: Vec<u8> },
+    #[error("Unsupported Operation: {0}")]
+    Unsupported(String),
     #[error("Unknown Error")]
     Unknown,
+    #[error("Version Error: {0}")]
+    Version(String),
+}
+
+/// The broad category of an `Error`, for callers which handle errors by kind
+/// rather than matching every variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A batch was invalid, e.g. unsorted or deleting a missing key.
+    Batch,
+    /// The store was changed by another writer since the caller last read it.
+    Conflict,
+    /// Stored data failed an integrity check or could not be decoded.
+    Corruption,
+    /// An argument or configuration was invalid.
+    InvalidInput,
+    /// A key or value exceeded a size limit.
+    LimitExceeded,
+    /// A requested key, chunk or record does not exist.
+    NotFound,
+    /// A proof or chunk was invalid or did not match the expected hash.
+    Proof,
+    /// The database or filesystem failed.
+    Storage,
+    /// The operation is not possible in the store's current state.
+    Unsupported,
+    Other,
+}
+
+impl Error {
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        use Error::*;
+        match self {
+            BatchKey(_) | DuplicateBatchKey { .. } | KeyDelete(_) | UnsortedBatch { .. } => {
+                ErrorKind::Batch
+            }
+            RootConflict { .. } => ErrorKind::Conflict,
+            Corruption { .. } | TornCommit(_) | Version(_) => ErrorKind::Corruption,
+            Bound(_) | Config(_) | IntegerConversionError(_) | Key(_) | Path(_) => {
+                ErrorKind::InvalidInput
+            }
+            LimitExceeded { .. } => ErrorKind::LimitExceeded,
+            IndexOutOfBounds(_) | KeyNotFound(_) => ErrorKind::NotFound,
+            Attach(_) | ChunkProcessing(_) | Ed(_) | HashMismatch(..) | MissingData | Proof(_)
+            | StackUnderflow | Tree(_) | UnexpectedNode(_) => ErrorKind::Proof,
+            #[cfg(feature = "full")]
+            RocksDB(_) => ErrorKind::Storage,
+            Fetch(_) | IO(_) | Replication(_) => ErrorKind::Storage,
+            Poisoned | RateLimited(_) | Unsupported(_) => ErrorKind::Unsupported,
+            Compression(_) | Encryption(_) | HeightRegression { .. } | Unknown => ErrorKind::Other,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -2,11 +2,19 @@
 #[cfg(feature = "jemallocator")]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+#[cfg(not(any(feature = "full", feature = "verify")))]
+compile_error!("Either the `full` or the `verify` feature must be enabled");
+
 #[cfg(feature = "full")]
 pub use rocksdb;
 
+/// Workload generators and a runner for benchmarking stores.
+#[cfg(feature = "full")]
+pub mod bench;
 /// Error and Result types.
 mod error;
+/// Limits on keys, values, batches and proofs.
+mod limits;
 /// The top-level store API.
 #[cfg(feature = "full")]
 mod merk;
@@ -21,14 +29,36 @@ pub mod proofs;
 pub mod test_utils;
 /// The core tree data structure.
 pub mod tree;
+/// Canonical test vectors for implementations of proof verification in other
+/// languages.
+#[cfg(feature = "full")]
+pub mod vectors;
 
 #[cfg(feature = "full")]
-pub use crate::merk::{chunks, restore, Merk, MerkSource, Snapshot};
+pub use crate::merk::{
+    chunks, restore, ApplyStats, AuditEntry, AuditMode, AuditOps, ChangeRecord, ChunkServer,
+    ChunkServerConfig, CommitRecord, CostModel, Cursor, DbMetrics, Entry, Fork, HashAlgorithm,
+    KeyExtractor, KvFormat, LinkInfo, LinkMismatch, LinkMismatchKind, MemMerk, Merk, MerkOptions,
+    MerkReader, MerkSource, NodeAccess, NodeCodec, NodeInfo, Overlay, OverlayIter, PendingBatch,
+    PerfMetrics, ProofCacheStats, ProofService, ProofStats, PruningPolicy, RangeChunkProducer,
+    ReadTransaction, RecoveryReport, RootAttestation, RootSigner, SharedMerk, SimulatedApply,
+    Snapshot, Store, StoreMetadata, StoreMode, StoreMut, TypedMerk, VersionedMerk, WatchEvent,
+    ATTESTATION_PREFIX, AUDIT_LOG_PREFIX, ENCODING_VERSION, EXPIRY_INDEX_PREFIX, EXPIRY_PREFIX,
+    INDEX_PREFIX, TOMBSTONE_PREFIX,
+};
+
+#[cfg(feature = "metrics")]
+pub use crate::merk::describe_metrics;
+#[cfg(feature = "grpc")]
+pub use crate::merk::grpc;
+#[cfg(feature = "async")]
+pub use crate::merk::AsyncMerk;
 
-pub use error::{Error, Result};
-pub use tree::{Batch, BatchEntry, Hash, Op, PanicSource, HASH_LENGTH};
+pub use error::{Error, ErrorKind, MerkError, Result};
+pub use limits::{Limits, MAX_KEY_LENGTH, MAX_VALUE_LENGTH};
+pub use tree::{Batch, BatchEntry, Hash, Op, PanicSource, HASH_LENGTH, NULL_HASH};
 
 #[allow(deprecated)]
 pub use proofs::query::verify_query;
 
-pub use proofs::query::verify;
+pub use proofs::query::{verify, verify_with_limits};
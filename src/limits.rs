@@ -0,0 +1,165 @@
+//! Provides `Limits`, the bounds on keys, values, batches and proofs enforced
+//! when applying batches, decoding and verifying proofs, and restoring from
+//! chunks.
+
+use std::cmp::Ordering;
+
+use crate::error::{Error, Result};
+use crate::proofs::MAX_TREE_HEIGHT;
+use crate::tree::{Batch, Op};
+
+/// The longest key which can be stored, since links and proofs encode the
+/// length of their key in a single byte.
+pub const MAX_KEY_LENGTH: usize = u8::MAX as usize;
+
+/// The longest value which can be proven, since proofs encode the length of
+/// their values in two bytes. Longer values can be stored, but proving them
+/// fails.
+pub const MAX_VALUE_LENGTH: usize = u16::MAX as usize;
+
+/// Resource limits for a store or a verifier. The defaults are the largest
+/// keys and trees the encodings support, and no limit on value lengths, batch
+/// sizes or the number of ops in a proof, so they only need to be lowered, e.g.
+/// by nodes verifying proofs or chunks from untrusted peers.
+///
+/// Limits are set on a store with `Merk::set_limits`, on a proof decoder with
+/// `Decoder::with_limits`, and on a restore with `Restorer::with_limits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The longest key which can be written or decoded from a proof, at most
+    /// `MAX_KEY_LENGTH`.
+    pub max_key_len: usize,
+    /// The longest value which can be written or decoded from a proof.
+    /// Proofs can never hold values longer than `MAX_VALUE_LENGTH`.
+    pub max_value_len: usize,
+    /// The greatest height of a tree built while verifying a proof, or of a
+    /// tree being restored, at most `MAX_TREE_HEIGHT`.
+    pub max_tree_height: usize,
+    /// The most ops decoded from a single proof or chunk.
+    pub max_proof_ops: usize,
+    /// The most operations in a single batch.
+    pub max_batch_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_key_len: MAX_KEY_LENGTH,
+            max_value_len: usize::MAX,
+            max_tree_height: MAX_TREE_HEIGHT,
+            max_proof_ops: usize::MAX,
+            max_batch_size: usize::MAX,
+        }
+    }
+}
+
+impl Limits {
+    /// Returns an error if any limit is above what the encodings support.
+    pub fn validate(&self) -> Result<()> {
+        let check = |name, value, max| {
+            if value > max {
+                return Err(Error::Config(format!(
+                    "The {} limit must be at most {}",
+                    name, max
+                )));
+            }
+            Ok(())
+        };
+        check("key length", self.max_key_len, MAX_KEY_LENGTH)?;
+        check("tree height", self.max_tree_height, MAX_TREE_HEIGHT)
+    }
+
+    /// Returns an error if `key` is longer than `max_key_len`.
+    pub fn check_key(&self, key: &[u8]) -> Result<()> {
+        if key.len() > self.max_key_len {
+            return Err(Error::LimitExceeded {
+                key: key.to_vec(),
+                limit: "key length",
+                max: self.max_key_len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the value written to `key` is longer than
+    /// `max_value_len`.
+    pub fn check_value(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        if value.len() > self.max_value_len {
+            return Err(Error::LimitExceeded {
+                key: key.to_vec(),
+                limit: "value length",
+                max: self.max_value_len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns an error if the value of `key` is too long to be encoded in a
+    /// proof, whatever the limits.
+    pub(crate) fn check_provable(key: &[u8], value: &[u8]) -> Result<()> {
+        if value.len() > MAX_VALUE_LENGTH {
+            return Err(Error::LimitExceeded {
+                key: key.to_vec(),
+                limit: "provable value length",
+                max: MAX_VALUE_LENGTH,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns an error if `batch` has more operations than `max_batch_size`,
+    /// a key or value over the limits, or keys which are not sorted and
+    /// unique.
+    pub fn check_batch(&self, batch: &Batch) -> Result<()> {
+        if batch.len() > self.max_batch_size {
+            return Err(Error::LimitExceeded {
+                key: batch[self.max_batch_size].0.clone(),
+                limit: "batch size",
+                max: self.max_batch_size,
+            });
+        }
+
+        let mut maybe_prev_key: Option<&[u8]> = None;
+        for (key, op) in batch.iter() {
+            self.check_key(key)?;
+            if let Op::Put(value) = op {
+                self.check_value(key, value)?;
+            }
+            if let Some(prev_key) = maybe_prev_key {
+                match prev_key.cmp(key) {
+                    Ordering::Greater => {
+                        return Err(Error::UnsortedBatch { key: key.clone() });
+                    }
+                    Ordering::Equal => {
+                        return Err(Error::DuplicateBatchKey { key: key.clone() });
+                    }
+                    _ => (),
+                }
+            }
+            maybe_prev_key = Some(key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_limits() {
+        let limits = Limits::default();
+        limits.validate().unwrap();
+        limits.check_key(&[0; 255]).unwrap();
+        assert!(limits.check_key(&[0; 256]).is_err());
+        limits.check_value(&[], &[0; 65_536]).unwrap();
+        Limits::check_provable(&[], &[0; 65_535]).unwrap();
+        assert!(Limits::check_provable(&[], &[0; 65_536]).is_err());
+
+        let limits = Limits {
+            max_key_len: 256,
+            ..Default::default()
+        };
+        assert!(matches!(limits.validate(), Err(Error::Config(_))));
+    }
+}
@@ -0,0 +1,220 @@
+//! Maintains an optional Merkle mountain range over the root hashes of every
+//! height of a `VersionedMerk` (see `proofs::mmr`), stored in the internal
+//! column family of its latest version.
+
+use super::{Merk, VersionedMerk, INTERNAL_CF_NAME};
+use crate::proofs::mmr::{self, RootProof};
+use crate::tree::{Hash, HASH_LENGTH};
+use crate::{Error, Result};
+use rocksdb::WriteBatch;
+use std::convert::TryInto;
+
+const LEAF_COUNT_KEY: &[u8] = b"mmr_leaves";
+const NODE_PREFIX: &[u8] = b"mmr/";
+
+fn node_key(level: u8, index: u64) -> Vec<u8> {
+    let mut key = NODE_PREFIX.to_vec();
+    key.push(level);
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Returns the number of root hashes accumulated by the store, or `None` if
+/// it does not accumulate its root hashes.
+pub(crate) fn load_leaf_count(merk: &Merk) -> Result<Option<u64>> {
+    let internal_cf = merk.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+    merk.db
+        .get_cf(internal_cf, merk.prefixed(LEAF_COUNT_KEY))?
+        .map(|bytes| {
+            let bytes = bytes.as_slice().try_into().map_err(|_| Error::Corruption {
+                key: LEAF_COUNT_KEY.to_vec(),
+            })?;
+            Ok(u64::from_be_bytes(bytes))
+        })
+        .transpose()
+}
+
+impl VersionedMerk {
+    /// Starts accumulating the root hash of every height into a Merkle
+    /// mountain range, so light clients holding only the latest
+    /// `root_commitment` can verify historical root hashes with
+    /// `prove_root_at`, even for heights which are no longer retained.
+    ///
+    /// The accumulator must cover every height, so it can only be enabled
+    /// before the first batch is applied.
+    pub fn enable_root_accumulator(&mut self) -> Result<()> {
+        if self.accumulates_roots {
+            return Ok(());
+        }
+        if self.height != 0 {
+            return Err(Error::Unsupported(
+                "Root accumulator must be enabled before the first batch is applied".into(),
+            ));
+        }
+
+        let internal_cf = self.merk.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        self.merk.db.put_cf(
+            internal_cf,
+            self.merk.prefixed(LEAF_COUNT_KEY),
+            0u64.to_be_bytes(),
+        )?;
+        self.accumulates_roots = true;
+        Ok(())
+    }
+
+    /// Returns true if the store accumulates its root hashes.
+    pub fn accumulates_roots(&self) -> bool {
+        self.accumulates_roots
+    }
+
+    /// Returns the commitment to the root hashes of every height up to the
+    /// latest one.
+    pub fn root_commitment(&self) -> Result<Hash> {
+        let leaf_count = self.leaf_count()?;
+        let peaks = mmr::peaks(leaf_count)
+            .into_iter()
+            .map(|(level, index)| self.node(level, index))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(mmr::commitment(leaf_count, &peaks))
+    }
+
+    /// Creates a proof that `root_hash_at(height)` was the state at `height`,
+    /// which can be verified against `root_commitment()`.
+    pub fn prove_root_at(&self, height: u64) -> Result<RootProof> {
+        let leaf_count = self.leaf_count()?;
+        if height == 0 || height > leaf_count {
+            return Err(Error::Version(format!(
+                "Height {} has not been accumulated",
+                height
+            )));
+        }
+
+        let siblings = mmr::sibling_path(height - 1, leaf_count)?
+            .into_iter()
+            .map(|(level, index)| self.node(level, index))
+            .collect::<Result<_>>()?;
+        let peaks = mmr::peaks(leaf_count)
+            .into_iter()
+            .map(|(level, index)| self.node(level, index))
+            .collect::<Result<_>>()?;
+
+        Ok(RootProof {
+            height,
+            leaf_count,
+            siblings,
+            peaks,
+        })
+    }
+
+    /// Appends the root hashes of any retained heights which have not been
+    /// accumulated yet, e.g. because the process crashed between creating a
+    /// version and accumulating its root hash.
+    pub(crate) fn sync_roots(&mut self) -> Result<()> {
+        if !self.accumulates_roots {
+            return Ok(());
+        }
+
+        for height in self.leaf_count()? + 1..=self.height {
+            let root_hash = self.root_hash_at(height)?;
+            self.append_root(height - 1, root_hash)?;
+        }
+        Ok(())
+    }
+
+    fn append_root(&mut self, leaf_index: u64, root_hash: Hash) -> Result<()> {
+        let internal_cf = self.merk.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+
+        let mut hash = root_hash;
+        batch.put_cf(
+            internal_cf,
+            self.merk.prefixed(&node_key(0, leaf_index)),
+            hash,
+        );
+        for ((level, index), (left_level, left_index), _) in mmr::parents(leaf_index) {
+            hash = mmr::parent_hash(&self.node(left_level, left_index)?, &hash);
+            batch.put_cf(
+                internal_cf,
+                self.merk.prefixed(&node_key(level, index)),
+                hash,
+            );
+        }
+        batch.put_cf(
+            internal_cf,
+            self.merk.prefixed(LEAF_COUNT_KEY),
+            (leaf_index + 1).to_be_bytes(),
+        );
+
+        self.merk.write(batch)
+    }
+
+    fn leaf_count(&self) -> Result<u64> {
+        load_leaf_count(&self.merk)?
+            .ok_or_else(|| Error::Unsupported("Root accumulator is not enabled".into()))
+    }
+
+    fn node(&self, level: u8, index: u64) -> Result<Hash> {
+        let internal_cf = self.merk.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let key = self.merk.prefixed(&node_key(level, index));
+        let bytes = self
+            .merk
+            .db
+            .get_cf(internal_cf, &key)?
+            .ok_or_else(|| Error::Corruption { key: key.clone() })?;
+        if bytes.len() != HASH_LENGTH {
+            return Err(Error::Corruption { key });
+        }
+        Ok(bytes.as_slice().try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{NodeCodec, PruningPolicy, VersionedMerk};
+
+    #[test]
+    fn prove_historical_roots() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepLast(2), NodeCodec::new()).unwrap();
+        merk.enable_root_accumulator().unwrap();
+
+        let mut root_hashes = vec![];
+        for i in 0..10 {
+            merk.apply(&make_batch_seq(i * 10..(i + 1) * 10), &[])
+                .unwrap();
+            root_hashes.push(merk.root_hash());
+        }
+
+        // heights which are no longer retained can still be proven
+        let commitment = merk.root_commitment().unwrap();
+        for (i, root_hash) in root_hashes.iter().enumerate() {
+            let proof = merk.prove_root_at(i as u64 + 1).unwrap();
+            proof.verify(root_hash, &commitment).unwrap();
+        }
+        let proof = merk.prove_root_at(3).unwrap();
+        assert!(proof.verify(&root_hashes[3], &commitment).is_err());
+        assert!(merk.prove_root_at(11).is_err());
+
+        merk.rollback_to(9).unwrap();
+        assert!(merk.prove_root_at(10).is_err());
+        merk.apply(&make_batch_seq(1_000..1_001), &[]).unwrap();
+        let commitment = merk.root_commitment().unwrap();
+        merk.prove_root_at(10)
+            .unwrap()
+            .verify(&merk.root_hash(), &commitment)
+            .unwrap();
+        merk.prove_root_at(4)
+            .unwrap()
+            .verify(&root_hashes[3], &commitment)
+            .unwrap();
+        drop(merk);
+
+        let merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepLast(2), NodeCodec::new()).unwrap();
+        assert!(merk.accumulates_roots());
+        assert_eq!(merk.root_commitment().unwrap(), commitment);
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+//! Provides `AsyncMerk`, an adapter for using a store from tokio-based
+//! applications without blocking the executor.
+
+use std::sync::Arc;
+
+use super::{Merk, SharedMerk};
+use crate::proofs::Query;
+use crate::tree::{BatchEntry, Hash};
+use crate::{Error, Result};
+
+/// A cloneable async handle to a `SharedMerk`. Every operation which reads
+/// from or writes to the database runs on tokio's blocking thread pool, so the
+/// calling task yields instead of blocking its executor thread.
+///
+/// Reads and writes follow the locking strategy of `SharedMerk`.
+#[derive(Clone)]
+pub struct AsyncMerk {
+    merk: Arc<SharedMerk>,
+}
+
+impl AsyncMerk {
+    /// Wraps `merk`.
+    pub fn new(merk: Merk) -> Self {
+        Self::from_shared(Arc::new(SharedMerk::new(merk)))
+    }
+
+    /// Wraps a store which is also used synchronously elsewhere.
+    pub fn from_shared(merk: Arc<SharedMerk>) -> Self {
+        AsyncMerk { merk }
+    }
+
+    /// Returns the underlying shared store.
+    pub fn shared(&self) -> &Arc<SharedMerk> {
+        &self.merk
+    }
+
+    /// Gets the value for `key` at the last committed version.
+    pub async fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.spawn(move |merk| merk.get(&key)).await
+    }
+
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key if `end` is `None`, at the last
+    /// committed version.
+    pub async fn get_range(
+        &self,
+        start: Vec<u8>,
+        end: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.spawn(move |merk| merk.get_range(&start, end.as_deref(), limit))
+            .await
+    }
+
+    /// Returns the root hash of the last committed version. This does not read
+    /// from the database, so it is not offloaded.
+    pub fn root_hash(&self) -> Hash {
+        self.merk.root_hash()
+    }
+
+    /// Creates a Merkle proof for the list of queried keys against the last
+    /// committed version.
+    pub async fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        self.spawn(move |merk| merk.prove(query)).await
+    }
+
+    /// Applies a batch of operations as in `Merk::apply`.
+    pub async fn apply(&self, batch: Vec<BatchEntry>, aux: Vec<BatchEntry>) -> Result<()> {
+        self.spawn(move |merk| merk.apply(&batch, &aux)).await
+    }
+
+    /// Returns the number of state sync chunks for the current tree.
+    pub async fn chunk_count(&self) -> Result<usize> {
+        self.write(|merk| Ok(merk.chunks()?.len())).await
+    }
+
+    /// Returns the state sync chunk at `index`.
+    pub async fn chunk(&self, index: usize) -> Result<Vec<u8>> {
+        self.write(move |merk| merk.chunks()?.chunk(index)).await
+    }
+
+    /// Calls `f` with exclusive access to the store on the blocking thread
+    /// pool.
+    pub async fn write<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Merk) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn(move |merk| merk.write(f)).await
+    }
+
+    async fn spawn<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&SharedMerk) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let merk = self.merk.clone();
+        match tokio::task::spawn_blocking(move || f(&merk)).await {
+            Ok(res) => res,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(_) => Err(Error::Unsupported(
+                "Blocking task was cancelled by runtime shutdown".into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[tokio::test]
+    async fn async_operations() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let merk = AsyncMerk::new(Merk::open(path).unwrap());
+
+        merk.apply(make_batch_seq(0..100), vec![]).await.unwrap();
+        assert_eq!(
+            merk.get(seq_key(10)).await.unwrap(),
+            Some(put_entry_value())
+        );
+        let range = merk.get_range(seq_key(10), None, 5).await.unwrap();
+        assert_eq!(range.len(), 5);
+
+        let mut query = Query::new();
+        query.insert_key(seq_key(10));
+        let proof = merk.prove(query).await.unwrap();
+        crate::verify(&proof, merk.root_hash()).unwrap();
+        assert!(merk.chunk_count().await.unwrap() > 0);
+        merk.chunk(0).await.unwrap();
+
+        let shared = Arc::try_unwrap(merk.merk).ok().unwrap();
+        shared.into_inner().unwrap().destroy().unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! Signs each committed root hash with a caller-provided signer and stores the
+//! signature in aux storage, so replicas and auditors can later check the
+//! operator's attestations of state.
+
+use std::convert::TryInto;
+
+use super::Merk;
+use crate::tree::Hash;
+use crate::{Error, Result};
+
+/// The prefix of the aux keys attestations are stored under, followed by the
+/// attested root hash.
+pub const ATTESTATION_PREFIX: &[u8] = b"\x00merk/attestation/";
+
+/// Signs root hashes as they are committed, set with `Merk::set_root_signer`.
+///
+/// Implemented for closures taking the root hash and height.
+pub trait RootSigner: Send {
+    /// Returns a signature of the root hash of a commit, and the height it was
+    /// applied at with `apply_at_height`, if any. An error fails the commit.
+    fn sign(&self, root_hash: &Hash, height: Option<u64>) -> Result<Vec<u8>>;
+}
+
+impl<F> RootSigner for F
+where
+    F: Fn(&Hash, Option<u64>) -> Result<Vec<u8>> + Send,
+{
+    fn sign(&self, root_hash: &Hash, height: Option<u64>) -> Result<Vec<u8>> {
+        self(root_hash, height)
+    }
+}
+
+/// A signature of a committed root hash, read with `Merk::attestation`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootAttestation {
+    pub root_hash: Hash,
+    /// The height the commit was applied at, if any.
+    pub height: Option<u64>,
+    pub signature: Vec<u8>,
+}
+
+impl RootAttestation {
+    /// Returns the aux key the attestation of `root_hash` is stored under.
+    pub fn aux_key(root_hash: &Hash) -> Vec<u8> {
+        let mut key = ATTESTATION_PREFIX.to_vec();
+        key.extend_from_slice(root_hash);
+        key
+    }
+
+    /// Encodes the height and signature, the root hash being part of the key.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(9 + self.signature.len());
+        match self.height {
+            Some(height) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&height.to_be_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    fn decode(root_hash: Hash, bytes: &[u8]) -> Result<Self> {
+        let corrupted = || Error::Corruption {
+            key: RootAttestation::aux_key(&root_hash),
+        };
+        let (height, signature) = match bytes.split_first() {
+            Some((0, signature)) => (None, signature),
+            Some((1, rest)) if rest.len() >= 8 => {
+                let (height, signature) = rest.split_at(8);
+                (
+                    Some(u64::from_be_bytes(height.try_into().unwrap())),
+                    signature,
+                )
+            }
+            _ => return Err(corrupted()),
+        };
+
+        Ok(RootAttestation {
+            root_hash,
+            height,
+            signature: signature.to_vec(),
+        })
+    }
+}
+
+impl Merk {
+    /// Sets the signer called with the root hash (and height) of every
+    /// subsequent commit. Each signature is written to aux storage in the same
+    /// batch as the commit, and can be read with `attestation`.
+    pub fn set_root_signer<S: RootSigner + 'static>(&mut self, signer: S) {
+        self.signer = Some(Box::new(signer));
+    }
+
+    /// Stops signing commits.
+    pub fn clear_root_signer(&mut self) {
+        self.signer = None;
+    }
+
+    /// Returns the attestation stored for `root_hash`, if a signer was set
+    /// when it was committed.
+    pub fn attestation(&self, root_hash: &Hash) -> Result<Option<RootAttestation>> {
+        self.get_aux(&RootAttestation::aux_key(root_hash))?
+            .map(|bytes| RootAttestation::decode(*root_hash, &bytes))
+            .transpose()
+    }
+
+    /// Signs a commit with the signer, if any, returning the aux entry to
+    /// write along with it.
+    pub(crate) fn sign_root(
+        &self,
+        root_hash: Hash,
+        height: Option<u64>,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let signer = match self.signer.as_ref() {
+            Some(signer) => signer,
+            None => return Ok(None),
+        };
+
+        let attestation = RootAttestation {
+            root_hash,
+            height,
+            signature: signer.sign(&root_hash, height)?,
+        };
+        Ok(Some((
+            RootAttestation::aux_key(&root_hash),
+            attestation.encode(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::tree::NULL_HASH;
+
+    #[test]
+    fn sign_commits() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        let unsigned = merk.root_hash();
+
+        merk.set_root_signer(|root_hash: &Hash, height: Option<u64>| -> Result<Vec<u8>> {
+            let mut signature = root_hash.to_vec();
+            signature.extend(height.unwrap_or_default().to_be_bytes());
+            Ok(signature)
+        });
+        merk.apply(&make_batch_seq(10..20), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        let attestation = merk.attestation(&root_hash).unwrap().unwrap();
+        assert_eq!(attestation.height, None);
+        assert_eq!(&attestation.signature[..32], &root_hash[..]);
+
+        merk.apply_at_height(&make_batch_seq(20..30), &[], 7)
+            .unwrap();
+        let attestation = merk.attestation(&merk.root_hash()).unwrap().unwrap();
+        assert_eq!(attestation.height, Some(7));
+        assert_eq!(attestation.signature[32..], 7u64.to_be_bytes());
+
+        assert_eq!(merk.attestation(&unsigned).unwrap(), None);
+        assert_eq!(merk.attestation(&NULL_HASH).unwrap(), None);
+
+        // a failing signer fails the commit
+        merk.set_root_signer(|_: &Hash, _: Option<u64>| -> Result<Vec<u8>> {
+            Err(Error::Config("signer unavailable".into()))
+        });
+        let root_hash = merk.root_hash();
+        assert!(merk.apply(&make_batch_seq(30..40), &[]).is_err());
+        merk.clear_root_signer();
+        assert_eq!(merk.root_hash(), root_hash);
+    }
+}
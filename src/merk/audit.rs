@@ -0,0 +1,444 @@
+//! Provides an optional audit log of the batches committed to a store, kept in
+//! aux storage as a hash chain, so an auditor can check that the store's
+//! current state is the result of replaying the logged batches from a
+//! checkpoint.
+
+use std::convert::TryInto;
+
+use rocksdb::DB;
+use sha2::Digest;
+
+use super::changelog::{decode_entries, encode_entries, take};
+use super::{batch_entries, prefix_read_opts, prefixed, Merk, AUX_CF_NAME};
+use crate::tree::{Batch, Hash, Hasher, Op, HASH_LENGTH, NULL_HASH};
+use crate::{Error, Result};
+
+/// The prefix of the aux keys audit log entries are stored under, followed by
+/// the big-endian index of the entry.
+pub const AUDIT_LOG_PREFIX: &[u8] = b"\x00merk/audit/";
+
+/// The aux key of the log's mode, latest index and head hash.
+const AUDIT_HEAD_KEY: &[u8] = b"\x00merk/audit-head";
+
+/// What the audit log records of each commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditMode {
+    /// The full batches, which can be replayed with `Merk::replay_audit_log`.
+    Batches,
+    /// Only the hashes of the batches, which is enough to check the log's
+    /// chain but not to replay it.
+    Hashes,
+}
+
+/// A single commit recorded in the audit log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// The position of the commit in the log, starting at 1 for the first
+    /// commit after the log was enabled.
+    pub index: u64,
+    /// The hash of the previous entry, or `NULL_HASH` for the first entry.
+    pub prev_hash: Hash,
+    /// The root hash of the tree after the commit.
+    pub root_hash: Hash,
+    /// The hash of the batch and aux operations of the commit.
+    pub batch_hash: Hash,
+    /// The batch and aux operations, if the log records full batches.
+    pub batches: Option<(AuditOps, AuditOps)>,
+}
+
+/// The puts (`Some`) and deletes (`None`) of a logged batch, sorted by key.
+pub type AuditOps = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+impl AuditEntry {
+    /// Returns the hash of the entry, which the next entry links to. The
+    /// batches are covered through `batch_hash`, so entries hash the same
+    /// whichever mode they were logged in.
+    pub fn hash(&self) -> Hash {
+        let mut hasher = <Hasher as Digest>::new();
+        Digest::update(&mut hasher, self.index.to_be_bytes());
+        Digest::update(&mut hasher, self.prev_hash);
+        Digest::update(&mut hasher, self.root_hash);
+        Digest::update(&mut hasher, self.batch_hash);
+        finalize(hasher)
+    }
+
+    /// Returns the aux key the entry at `index` is stored under.
+    pub fn aux_key(index: u64) -> Vec<u8> {
+        let mut key = AUDIT_LOG_PREFIX.to_vec();
+        key.extend_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    /// Returns true if the logged batches, if any, hash to `batch_hash`.
+    fn batches_match(&self) -> bool {
+        match self.batches.as_ref() {
+            Some((batch, aux)) => batch_hash(batch, aux) == self.batch_hash,
+            None => true,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.prev_hash);
+        bytes.extend_from_slice(&self.root_hash);
+        bytes.extend_from_slice(&self.batch_hash);
+        match self.batches.as_ref() {
+            Some((batch, aux)) => {
+                bytes.push(1);
+                encode_entries(&mut bytes, batch);
+                encode_entries(&mut bytes, aux);
+            }
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    fn decode(mut bytes: &[u8]) -> Option<AuditEntry> {
+        let index = u64::from_be_bytes(take(&mut bytes, 8)?.try_into().ok()?);
+        let prev_hash = take(&mut bytes, HASH_LENGTH)?.try_into().ok()?;
+        let root_hash = take(&mut bytes, HASH_LENGTH)?.try_into().ok()?;
+        let batch_hash = take(&mut bytes, HASH_LENGTH)?.try_into().ok()?;
+        let batches = match take(&mut bytes, 1)?[0] {
+            0 => None,
+            1 => Some((decode_entries(&mut bytes)?, decode_entries(&mut bytes)?)),
+            _ => return None,
+        };
+        if !bytes.is_empty() {
+            return None;
+        }
+
+        Some(AuditEntry {
+            index,
+            prev_hash,
+            root_hash,
+            batch_hash,
+            batches,
+        })
+    }
+}
+
+/// The state of a store's audit log, if it is enabled.
+pub(crate) struct AuditLog {
+    mode: AuditMode,
+    index: u64,
+    head: Hash,
+}
+
+impl AuditLog {
+    /// Returns the aux entries which log the next commit, along with the state
+    /// to advance to once they are staged or written.
+    pub(crate) fn entries(
+        &self,
+        root_hash: Hash,
+        batch: &Batch,
+        aux: &Batch,
+    ) -> (AuditLog, Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        let batch = batch_entries(batch);
+        let aux = batch_entries(aux);
+        let entry = AuditEntry {
+            index: self.index + 1,
+            prev_hash: self.head,
+            root_hash,
+            batch_hash: batch_hash(&batch, &aux),
+            batches: match self.mode {
+                AuditMode::Batches => Some((batch, aux)),
+                AuditMode::Hashes => None,
+            },
+        };
+        let next = AuditLog {
+            mode: self.mode,
+            index: entry.index,
+            head: entry.hash(),
+        };
+        let entries = vec![
+            (AuditEntry::aux_key(entry.index), Some(entry.encode())),
+            (AUDIT_HEAD_KEY.to_vec(), Some(next.encode())),
+        ];
+        (next, entries)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + 8 + HASH_LENGTH);
+        bytes.push(match self.mode {
+            AuditMode::Batches => 0,
+            AuditMode::Hashes => 1,
+        });
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.head);
+        bytes
+    }
+
+    fn decode(mut bytes: &[u8]) -> Option<AuditLog> {
+        let mode = match take(&mut bytes, 1)?[0] {
+            0 => AuditMode::Batches,
+            1 => AuditMode::Hashes,
+            _ => return None,
+        };
+        let index = u64::from_be_bytes(take(&mut bytes, 8)?.try_into().ok()?);
+        let head = take(&mut bytes, HASH_LENGTH)?.try_into().ok()?;
+        if !bytes.is_empty() {
+            return None;
+        }
+        Some(AuditLog { mode, index, head })
+    }
+}
+
+/// Loads the state of the audit log for the store under `prefix`, or `None`
+/// if its audit log is not enabled.
+pub(crate) fn load_audit_log(db: &DB, prefix: &[u8]) -> Result<Option<AuditLog>> {
+    let aux_cf = db.cf_handle(AUX_CF_NAME).unwrap();
+    db.get_cf(aux_cf, prefixed(prefix, AUDIT_HEAD_KEY))?
+        .map(|bytes| {
+            AuditLog::decode(&bytes).ok_or_else(|| Error::Corruption {
+                key: AUDIT_HEAD_KEY.to_vec(),
+            })
+        })
+        .transpose()
+}
+
+fn to_batch(ops: AuditOps) -> Vec<(Vec<u8>, Op)> {
+    ops.into_iter()
+        .map(|(key, maybe_value)| match maybe_value {
+            Some(value) => (key, Op::Put(value)),
+            None => (key, Op::Delete),
+        })
+        .collect()
+}
+
+fn batch_hash(batch: &[(Vec<u8>, Option<Vec<u8>>)], aux: &[(Vec<u8>, Option<Vec<u8>>)]) -> Hash {
+    let mut bytes = vec![];
+    encode_entries(&mut bytes, batch);
+    encode_entries(&mut bytes, aux);
+    let mut hasher = <Hasher as Digest>::new();
+    Digest::update(&mut hasher, &bytes);
+    finalize(hasher)
+}
+
+fn finalize<D: Digest>(hasher: D) -> Hash {
+    let mut hash: Hash = Default::default();
+    hash.copy_from_slice(&hasher.finalize()[..]);
+    hash
+}
+
+impl Merk {
+    /// Starts appending an `AuditEntry` to the audit log for every subsequent
+    /// commit, written to aux storage in the same batch as the commit. Returns
+    /// an error if the log is already enabled in a different mode.
+    pub fn enable_audit_log(&mut self, mode: AuditMode) -> Result<()> {
+        if let Some(audit) = self.audit.as_ref() {
+            if audit.mode != mode {
+                return Err(Error::Config(format!(
+                    "Audit log is already enabled in {:?} mode",
+                    audit.mode
+                )));
+            }
+            return Ok(());
+        }
+
+        let audit = AuditLog {
+            mode,
+            index: 0,
+            head: NULL_HASH,
+        };
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        self.db
+            .put_cf(aux_cf, self.prefixed(AUDIT_HEAD_KEY), audit.encode())?;
+        self.audit = Some(audit);
+        Ok(())
+    }
+
+    /// Returns the index and hash of the latest audit log entry, or `None` if
+    /// the audit log is not enabled.
+    pub fn audit_log_head(&self) -> Option<(u64, Hash)> {
+        self.audit.as_ref().map(|audit| (audit.index, audit.head))
+    }
+
+    /// Returns up to `limit` audit log entries, starting at index `from`.
+    pub fn audit_entries(&self, from: u64, limit: usize) -> Result<Vec<AuditEntry>> {
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let entry_prefix = prefixed(&self.prefix, AUDIT_LOG_PREFIX);
+        let mut iter = self
+            .db
+            .raw_iterator_cf_opt(aux_cf, prefix_read_opts(&entry_prefix));
+        iter.seek(self.prefixed(&AuditEntry::aux_key(from)));
+
+        let mut entries = vec![];
+        while iter.valid() && entries.len() < limit {
+            let key = iter.key().unwrap();
+            let entry =
+                AuditEntry::decode(iter.value().unwrap()).ok_or_else(|| Error::Corruption {
+                    key: key[self.prefix.len()..].to_vec(),
+                })?;
+            entries.push(entry);
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(entries)
+    }
+
+    /// Checks the whole audit log: that each entry links to the hash of the
+    /// one before it, that logged batches match their hashes, that the last
+    /// entry is the recorded head, and that its root hash is the store's
+    /// current root hash. Returns the number of entries checked.
+    pub fn verify_audit_log(&self) -> Result<u64> {
+        let audit = self
+            .audit
+            .as_ref()
+            .ok_or_else(|| Error::Unsupported("Audit log is not enabled".into()))?;
+
+        let mut prev = (0, NULL_HASH);
+        let mut root_hash = None;
+        for entry in self.audit_entries(1, usize::MAX)? {
+            if entry.index != prev.0 + 1 {
+                return Err(Error::Proof(format!(
+                    "Audit log is missing entry {}",
+                    prev.0 + 1
+                )));
+            }
+            if entry.prev_hash != prev.1 {
+                return Err(Error::HashMismatch(prev.1, entry.prev_hash));
+            }
+            if !entry.batches_match() {
+                return Err(Error::Proof(format!(
+                    "Batches of audit log entry {} don't match their hash",
+                    entry.index
+                )));
+            }
+            prev = (entry.index, entry.hash());
+            root_hash = Some(entry.root_hash);
+        }
+
+        if prev != (audit.index, audit.head) {
+            return Err(Error::HashMismatch(audit.head, prev.1));
+        }
+        if let Some(root_hash) = root_hash {
+            if root_hash != self.root_hash() {
+                return Err(Error::HashMismatch(root_hash, self.root_hash()));
+            }
+        }
+
+        Ok(prev.0)
+    }
+
+    /// Replays the batches logged since `checkpoint` was taken (e.g. with
+    /// `Merk::checkpoint`) onto it, checking the root hash after each one
+    /// against the log, and checks that the replayed store ends up with this
+    /// store's root hash and audit log head. Returns the number of batches
+    /// replayed.
+    ///
+    /// The checkpoint must have been taken after the audit log was enabled in
+    /// `AuditMode::Batches`.
+    pub fn replay_audit_log(&self, checkpoint: &mut Merk) -> Result<u64> {
+        let audit = self
+            .audit
+            .as_ref()
+            .ok_or_else(|| Error::Unsupported("Audit log is not enabled".into()))?;
+        if audit.mode != AuditMode::Batches {
+            return Err(Error::Unsupported(
+                "Audit log only records batch hashes".into(),
+            ));
+        }
+        let (from, _) = checkpoint.audit_log_head().ok_or_else(|| {
+            Error::Unsupported("Audit log is not enabled in the checkpoint".into())
+        })?;
+
+        let mut replayed = 0;
+        for entry in self.audit_entries(from + 1, usize::MAX)? {
+            if !entry.batches_match() {
+                return Err(Error::Proof(format!(
+                    "Batches of audit log entry {} don't match their hash",
+                    entry.index
+                )));
+            }
+            let (batch, aux) = entry.batches.unwrap();
+            checkpoint.apply(&to_batch(batch), &to_batch(aux))?;
+            if checkpoint.root_hash() != entry.root_hash {
+                return Err(Error::HashMismatch(entry.root_hash, checkpoint.root_hash()));
+            }
+            replayed += 1;
+        }
+
+        if checkpoint.root_hash() != self.root_hash() {
+            return Err(Error::HashMismatch(
+                self.root_hash(),
+                checkpoint.root_hash(),
+            ));
+        }
+        if checkpoint.audit_log_head() != Some((audit.index, audit.head)) {
+            return Err(Error::Proof(
+                "Replayed audit log does not reach the log's head".into(),
+            ));
+        }
+
+        Ok(replayed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use std::thread;
+
+    #[test]
+    fn replay_from_checkpoint() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        merk.enable_audit_log(AuditMode::Batches).unwrap();
+        assert_eq!(merk.audit_log_head(), Some((0, NULL_HASH)));
+        assert!(merk.enable_audit_log(AuditMode::Hashes).is_err());
+
+        merk.apply(&make_batch_seq(10..20), &[(vec![1], Op::Put(vec![2]))])
+            .unwrap();
+        let mut checkpoint = merk.checkpoint(path.clone() + ".checkpoint").unwrap();
+
+        merk.apply(&make_batch_seq(20..30), &[]).unwrap();
+        merk.apply(&make_del_batch_seq(0..5), &[(vec![1], Op::Delete)])
+            .unwrap();
+        assert_eq!(merk.verify_audit_log().unwrap(), 3);
+
+        let entries = merk.audit_entries(1, 10).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].root_hash, merk.root_hash());
+        assert_eq!(merk.audit_log_head(), Some((3, entries[2].hash())));
+
+        assert_eq!(merk.replay_audit_log(&mut checkpoint).unwrap(), 2);
+        assert_eq!(checkpoint.root_hash(), merk.root_hash());
+        assert_eq!(checkpoint.get_aux(&[1]).unwrap(), None);
+        drop(checkpoint);
+        std::fs::remove_dir_all(path + ".checkpoint").unwrap();
+
+        // tampering with a logged root hash breaks the chain
+        let mut entry = entries[1].clone();
+        entry.root_hash = [1; 32];
+        let aux_cf = merk.db.cf_handle(AUX_CF_NAME).unwrap();
+        merk.db
+            .put_cf(aux_cf, AuditEntry::aux_key(2), entry.encode())
+            .unwrap();
+        assert!(merk.verify_audit_log().is_err());
+    }
+
+    #[test]
+    fn hashes_only() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.enable_audit_log(AuditMode::Hashes).unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        merk.apply(&make_batch_seq(10..20), &[]).unwrap();
+        assert_eq!(merk.verify_audit_log().unwrap(), 2);
+
+        let entries = merk.audit_entries(0, 10).unwrap();
+        assert!(entries.iter().all(|entry| entry.batches.is_none()));
+        let batch = batch_entries(&make_batch_seq(10..20));
+        assert_eq!(entries[1].batch_hash, batch_hash(&batch, &[]));
+
+        let mut other = TempMerk::new().unwrap();
+        other.enable_audit_log(AuditMode::Hashes).unwrap();
+        assert!(matches!(
+            merk.replay_audit_log(&mut other),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
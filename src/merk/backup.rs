@@ -0,0 +1,97 @@
+//! Provides online backups of a Merk store, built on RocksDB's `BackupEngine`.
+
+use super::{Merk, NodeCodec};
+use crate::{Error, Result};
+use rocksdb::backup::{BackupEngine, BackupEngineOptions, RestoreOptions};
+use std::path::Path;
+
+impl Merk {
+    /// Creates a backup of the store in the backup directory at `path`,
+    /// creating the directory if it does not exist. The store is flushed
+    /// before the backup is taken and can keep serving reads and writes while
+    /// it runs.
+    ///
+    /// Backups are incremental: SST files already contained in an earlier
+    /// backup in the same directory are shared rather than copied again.
+    pub fn create_backup<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.ensure_not_shared("create_backup")?;
+        let mut engine = BackupEngine::open(&BackupEngineOptions::default(), path)?;
+        engine.create_new_backup_flush(&*self.db, true)?;
+        Ok(())
+    }
+
+    /// Deletes all but the `keep` most recent backups in the backup directory
+    /// at `path`.
+    pub fn purge_backups<P: AsRef<Path>>(path: P, keep: usize) -> Result<()> {
+        let mut engine = BackupEngine::open(&BackupEngineOptions::default(), path)?;
+        engine.purge_old_backups(keep)?;
+        Ok(())
+    }
+
+    /// Restores the latest backup from the backup directory at `path` into a
+    /// new store at `to`, then opens it with `codec`, which must be the codec
+    /// the backed up store was opened with. Errors if `to` already exists.
+    pub fn restore_backup<P, Q>(path: P, to: Q, codec: NodeCodec) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let to = to.as_ref();
+        if to.exists() {
+            return Err(Error::Path("The given path already exists".into()));
+        }
+
+        let mut engine = BackupEngine::open(&BackupEngineOptions::default(), path)?;
+        engine.restore_from_latest_backup(to, to, &RestoreOptions::default())?;
+
+        Merk::open_with_codec(to, Merk::default_db_opts(), codec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Merk, NodeCodec, Op};
+    use std::path::PathBuf;
+
+    #[test]
+    fn backup_and_restore() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let backup_path: PathBuf = (path.clone() + ".backup").into();
+        let restore_path: PathBuf = (path.clone() + ".restored").into();
+        for p in [&backup_path, &restore_path] {
+            if p.exists() {
+                std::fs::remove_dir_all(p).unwrap();
+            }
+        }
+
+        let codec = NodeCodec::new().with_checksums();
+        let mut merk =
+            Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone()).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[(vec![1], Op::Put(vec![2]))])
+            .expect("apply failed");
+        merk.create_backup(&backup_path).unwrap();
+
+        // incremental backup after more writes
+        merk.apply(&make_batch_seq(100..200), &[])
+            .expect("apply failed");
+        merk.create_backup(&backup_path).unwrap();
+        Merk::purge_backups(&backup_path, 1).unwrap();
+
+        let restored = Merk::restore_backup(&backup_path, &restore_path, codec.clone()).unwrap();
+        assert_eq!(restored.root_hash(), merk.root_hash());
+        assert_eq!(
+            restored.get(&seq_key(150)).unwrap(),
+            Some(put_entry_value())
+        );
+        assert_eq!(restored.get_aux(&[1]).unwrap(), Some(vec![2]));
+
+        assert!(restored.scrub().unwrap().is_empty());
+
+        assert!(Merk::restore_backup(&backup_path, &restore_path, codec).is_err());
+
+        restored.destroy().unwrap();
+        merk.destroy().unwrap();
+        std::fs::remove_dir_all(&backup_path).unwrap();
+    }
+}
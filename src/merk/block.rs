@@ -0,0 +1,185 @@
+//! Provides block-scoped group commits, which accumulate the writes of several
+//! calls to `apply` and write them to RocksDB in a single batch.
+
+use super::import::StagedWrites;
+use super::{ChangeRecord, Merk, WatchEvent};
+use crate::{Error, Result};
+
+/// Writes staged by the commits made during a block.
+#[derive(Default)]
+pub(crate) struct BlockBuffer {
+    pub(crate) writes: StagedWrites,
+    /// Change records of the block's commits, sent to changelog subscribers
+    /// once the block is written.
+    pub(crate) change_records: Vec<ChangeRecord>,
+    /// Changes to watched keys made by the block's commits, sent to watchers
+    /// once the block is written.
+    pub(crate) watch_events: Vec<WatchEvent>,
+}
+
+impl Merk {
+    /// Starts a block. Until `commit_block` is called, the writes made by
+    /// `apply` are kept in memory rather than written to the database, so
+    /// the root hash after each `apply` is only computed in memory, and a node
+    /// written several times during the block is only written to disk once.
+    ///
+    /// Writes made during a block are visible through `get` and `prove`, but
+    /// not through snapshots, chunks or raw iterators, and are lost if the
+    /// process crashes before `commit_block` returns.
+    ///
+    /// If a commit fails after its batch was applied to the tree (e.g. with a
+    /// `HashMismatch` from `apply_replication_record`, or because the root
+    /// signer failed), the whole block is aborted: its writes are discarded,
+    /// the store is reloaded as of before `begin_block`, and the error is
+    /// returned. `in_block` is false afterwards.
+    pub fn begin_block(&mut self) -> Result<()> {
+        if self.block.is_some() {
+            return Err(Error::Unsupported("A block is already in progress".into()));
+        }
+        if self.is_importing() {
+            return Err(Error::Unsupported(
+                "Cannot begin a block while importing".into(),
+            ));
+        }
+
+        self.block = Some(BlockBuffer::default());
+        Ok(())
+    }
+
+    /// Atomically writes all of the changes made since `begin_block` in a
+    /// single RocksDB write batch.
+    pub fn commit_block(&mut self) -> Result<()> {
+        let block = self
+            .block
+            .take()
+            .ok_or_else(|| Error::Unsupported("No block is in progress".into()))?;
+
+        let StagedWrites {
+            nodes,
+            aux,
+            internal,
+            ..
+        } = block.writes;
+        self.write_entries(nodes, aux, internal)?;
+
+        if let Some(changelog) = self.changelog.as_mut() {
+            for record in block.change_records {
+                changelog.notify(record);
+            }
+        }
+        self.watchers.notify(block.watch_events);
+        Ok(())
+    }
+
+    /// Returns true if a block is in progress.
+    pub fn in_block(&self) -> bool {
+        self.block.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Error, Merk, Op, NULL_HASH};
+
+    #[test]
+    fn group_commit() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut plain = TempMerk::new().unwrap();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.enable_changelog().unwrap();
+        let receiver = merk.subscribe().unwrap();
+
+        merk.begin_block().unwrap();
+        assert!(merk.begin_block().is_err());
+        for i in 0..5 {
+            let batch = make_batch_seq(i * 100..(i + 1) * 100);
+            merk.apply(&batch, &[]).unwrap();
+            plain.apply(&batch, &[]).unwrap();
+            assert_eq!(merk.root_hash(), plain.root_hash());
+        }
+        assert_eq!(merk.get(&seq_key(250)).unwrap(), Some(put_entry_value()));
+        assert!(merk.db.get(seq_key(250)).unwrap().is_none());
+        assert!(receiver.try_recv().is_err());
+
+        merk.commit_block().unwrap();
+        assert!(!merk.in_block());
+        assert!(merk.commit_block().is_err());
+        assert_eq!(receiver.try_iter().count(), 5);
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.root_hash(), plain.root_hash());
+        assert_eq!(merk.changelog_height(), Some(5));
+        assert!(merk.scrub().unwrap().is_empty());
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn block_writes_each_node_once() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.begin_block().unwrap();
+        for i in 0..10u8 {
+            let mut batch = make_batch_seq(0..100);
+            for (_, op) in batch.iter_mut() {
+                *op = Op::Put(vec![i]);
+            }
+            merk.apply(&batch, &[(vec![1], Op::Put(vec![i]))]).unwrap();
+        }
+
+        let writes = &merk.block.as_ref().unwrap().writes;
+        assert_eq!(writes.nodes.len(), 100);
+        assert_eq!(writes.aux.len(), 1);
+        let size: usize = writes
+            .nodes
+            .iter()
+            .chain(writes.aux.iter())
+            .chain(writes.internal.iter())
+            .map(|(key, value)| key.len() + value.as_ref().map_or(0, Vec::len))
+            .sum();
+        assert_eq!(writes.size, size);
+
+        merk.commit_block().unwrap();
+        assert_eq!(merk.get(&seq_key(50)).unwrap(), Some(vec![9]));
+        assert_eq!(merk.get_aux(&[1]).unwrap(), Some(vec![9]));
+    }
+
+    #[test]
+    fn failed_commit_aborts_block() {
+        let mut leader = TempMerk::new().unwrap();
+        leader.enable_changelog().unwrap();
+        leader.apply(&make_batch_seq(0..100), &[]).unwrap();
+        leader.apply(&make_del_batch_seq(10..20), &[]).unwrap();
+        let records = leader.change_records(1, 10).unwrap();
+
+        let mut follower = TempMerk::new().unwrap();
+        follower.enable_changelog().unwrap();
+        let receiver = follower.subscribe().unwrap();
+        follower.begin_block().unwrap();
+        follower.apply_replication_record(&records[0]).unwrap();
+        let mut tampered = records[1].clone();
+        tampered.root_hash = NULL_HASH;
+        match follower.apply_replication_record(&tampered) {
+            Err(Error::HashMismatch(..)) => {}
+            res => panic!("expected hash mismatch, got {:?}", res),
+        }
+
+        // the block's earlier commit is discarded along with the failed one
+        assert!(!follower.in_block());
+        assert!(follower.commit_block().is_err());
+        assert_eq!(follower.root_hash(), NULL_HASH);
+        assert_eq!(follower.len(), 0);
+        assert_eq!(follower.changelog_height(), Some(0));
+        assert_eq!(follower.get(&seq_key(50)).unwrap(), None);
+        assert!(receiver.try_recv().is_err());
+
+        follower.begin_block().unwrap();
+        for record in records.iter() {
+            follower.apply_replication_record(record).unwrap();
+        }
+        follower.commit_block().unwrap();
+        assert_eq!(follower.root_hash(), leader.root_hash());
+        assert_eq!(follower.len(), 90);
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+}
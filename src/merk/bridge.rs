@@ -0,0 +1,89 @@
+//! Creates bridge proofs (see `proofs::query::verify_bridge`) of entries in a
+//! child store whose root hash is stored in this store.
+
+use super::Merk;
+use crate::proofs::query::encode_bridge_proof;
+use crate::proofs::Query;
+use crate::{Error, Result};
+
+impl Merk {
+    /// Creates a proof of `query` in `child`, bridged through this store,
+    /// which holds the child's root hash as the value of `child_key`. The
+    /// proof can be checked with `verify_bridge` against this store's root
+    /// hash.
+    ///
+    /// Returns an error if `child_key` is not in the store, or if its value
+    /// is not the child's current root hash, e.g. because the child was
+    /// changed after its root hash was stored.
+    pub fn prove_bridge(&self, child_key: &[u8], child: &Merk, query: Query) -> Result<Vec<u8>> {
+        let stored = self
+            .get(child_key)?
+            .ok_or_else(|| Error::KeyNotFound(hex::encode(child_key)))?;
+        let child_root = child.root_hash();
+        if stored != child_root {
+            return Err(Error::Proof(format!(
+                "Stored child root {} does not match the child's root {}",
+                hex::encode(&stored),
+                hex::encode(child_root)
+            )));
+        }
+
+        let parent = self.prove(Query::from(vec![child_key.to_vec()]))?;
+        let child = child.prove(query)?;
+        Ok(encode_bridge_proof(&parent, &child))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rocksdb::DB;
+
+    use super::*;
+    use crate::proofs::query::verify_bridge;
+    use crate::test_utils::*;
+    use crate::{NodeCodec, Op};
+
+    #[test]
+    fn bridge_proofs() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let db = DB::open_cf_descriptors(&Merk::default_db_opts(), &path, Merk::column_families())
+            .map(Arc::new)
+            .unwrap();
+        let mut parent =
+            Merk::open_shared(db.clone(), b"parent/".to_vec(), NodeCodec::new()).unwrap();
+        let mut child =
+            Merk::open_shared(db.clone(), b"child/".to_vec(), NodeCodec::new()).unwrap();
+
+        child.apply(&make_batch_seq(0..50), &[]).unwrap();
+        let mut batch = make_batch_seq(0..10);
+        batch.push((b"z/child".to_vec(), Op::Put(child.root_hash().to_vec())));
+        parent.apply(&batch, &[]).unwrap();
+
+        let mut query = Query::new();
+        query.insert_key(seq_key(5));
+        query.insert_key(seq_key(60));
+        let proof = parent.prove_bridge(b"z/child", &child, query).unwrap();
+        let (child_root, map) = verify_bridge(&proof, parent.root_hash(), b"z/child").unwrap();
+        assert_eq!(child_root, child.root_hash());
+        assert_eq!(
+            map.get(&seq_key(5)).unwrap(),
+            Some(put_entry_value().as_slice())
+        );
+        assert_eq!(map.get(&seq_key(60)).unwrap(), None);
+
+        assert!(verify_bridge(&proof, child.root_hash(), b"z/child").is_err());
+        assert!(verify_bridge(&proof, parent.root_hash(), &seq_key(5)).is_err());
+
+        // the stored root must be the child's current root
+        child.apply(&make_del_batch_seq(0..1), &[]).unwrap();
+        let mut query = Query::new();
+        query.insert_key(seq_key(5));
+        assert!(parent.prove_bridge(b"z/child", &child, query).is_err());
+
+        drop((parent, child));
+        drop(db);
+        DB::destroy(&Merk::default_db_opts(), &path).unwrap();
+    }
+}
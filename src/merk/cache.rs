@@ -0,0 +1,146 @@
+//! An LRU cache of recently read values, so repeated reads of hot keys skip
+//! tree traversal and node decoding.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use super::Merk;
+use crate::tree::Batch;
+
+/// A least-recently-used cache mapping keys to their values, or to `None` for
+/// keys known not to be in the store.
+pub(crate) struct ValueCache {
+    capacity: usize,
+    entries: HashMap<Vec<u8>, (Option<Vec<u8>>, u64)>,
+    /// Cached keys by the tick of their last use, oldest first.
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+impl ValueCache {
+    fn new(capacity: usize) -> Self {
+        ValueCache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        let tick = self.next_tick();
+        let (value, last_used) = self.entries.get_mut(key)?;
+        let key = self.order.remove(&*last_used).unwrap();
+        self.order.insert(tick, key);
+        *last_used = tick;
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            let oldest = *self.order.keys().next().unwrap();
+            let key = self.order.remove(&oldest).unwrap();
+            self.entries.remove(&key);
+        }
+        let tick = self.next_tick();
+        self.order.insert(tick, key.clone());
+        self.entries.insert(key, (value, tick));
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some((_, last_used)) = self.entries.remove(key) {
+            self.order.remove(&last_used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl Merk {
+    /// Caches the values of up to `capacity` recently read keys, including
+    /// keys which were not found. Subsequent calls to `get` for cached keys do
+    /// not traverse the tree. Keys are evicted when a batch writes to them.
+    pub fn enable_value_cache(&mut self, capacity: usize) {
+        self.cache = Some(RefCell::new(ValueCache::new(capacity)));
+    }
+
+    /// Returns the cached value for `key`, if it is in the cache.
+    pub(crate) fn cached_value(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        self.cache.as_ref()?.borrow_mut().get(key)
+    }
+
+    pub(crate) fn cache_value(&self, key: &[u8], value: &Option<Vec<u8>>) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.borrow_mut().insert(key.to_vec(), value.clone());
+        }
+    }
+
+    /// Evicts the keys written by `batch` from the cache.
+    pub(crate) fn invalidate_cached(&self, batch: &Batch) {
+        if let Some(cache) = self.cache.as_ref() {
+            let mut cache = cache.borrow_mut();
+            for (key, _) in batch {
+                cache.remove(key);
+            }
+        }
+    }
+
+    /// Empties the cache, e.g. after the tree is reloaded from the database.
+    pub(crate) fn clear_cache(&self) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.borrow_mut().clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn lru_eviction() {
+        let mut cache = ValueCache::new(2);
+        cache.insert(vec![1], Some(vec![1]));
+        cache.insert(vec![2], None);
+        assert_eq!(cache.get(&[1]), Some(Some(vec![1])));
+        cache.insert(vec![3], Some(vec![3]));
+        assert_eq!(cache.get(&[2]), None);
+        assert_eq!(cache.get(&[1]), Some(Some(vec![1])));
+        assert_eq!(cache.get(&[3]), Some(Some(vec![3])));
+    }
+
+    #[test]
+    fn invalidated_on_apply() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.enable_value_cache(10);
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.get(&seq_key(20)).unwrap(), None);
+        assert!(merk.cached_value(&seq_key(5)).is_some());
+        assert_eq!(merk.cached_value(&seq_key(20)), Some(None));
+
+        merk.apply(
+            &[(seq_key(5), Op::Delete), (seq_key(20), Op::Put(vec![1]))],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(merk.cached_value(&seq_key(5)), None);
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), None);
+        assert_eq!(merk.get(&seq_key(20)).unwrap(), Some(vec![1]));
+    }
+}
@@ -0,0 +1,329 @@
+//! Provides an optional changelog of the batches committed to a store, so
+//! indexers and replicas can follow state changes without diffing trees.
+
+use super::{prefix_read_opts, prefixed, Merk, INTERNAL_CF_NAME};
+use crate::tree::{Hash, HASH_LENGTH};
+use crate::{Error, Result};
+use rocksdb::DB;
+use std::convert::TryInto;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+const CHANGELOG_HEIGHT_KEY: &[u8] = b"changelog";
+const CHANGE_RECORD_PREFIX: &[u8] = b"changelog/";
+
+/// The changes made to a store by a single commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChangeRecord {
+    /// The position of the commit in the changelog, starting at 1 for the first
+    /// commit after the changelog was enabled.
+    pub height: u64,
+    /// The root hash of the tree after the commit.
+    pub root_hash: Hash,
+    /// The puts (`Some`) and deletes (`None`) applied to the tree, sorted by
+    /// key.
+    pub batch: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    /// The puts and deletes applied to the auxiliary data.
+    pub aux: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl ChangeRecord {
+    /// Returns the keys written or deleted in the tree by the commit.
+    pub fn changed_keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.batch.iter().map(|(key, _)| key.as_slice())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.extend_from_slice(&self.root_hash);
+        encode_entries(&mut bytes, &self.batch);
+        encode_entries(&mut bytes, &self.aux);
+        bytes
+    }
+
+    fn decode(mut bytes: &[u8]) -> Option<ChangeRecord> {
+        let height = u64::from_be_bytes(take(&mut bytes, 8)?.try_into().ok()?);
+        let root_hash = take(&mut bytes, HASH_LENGTH)?.try_into().ok()?;
+
+        let batch = decode_entries(&mut bytes)?;
+        let aux = decode_entries(&mut bytes)?;
+        if !bytes.is_empty() {
+            return None;
+        }
+
+        Some(ChangeRecord {
+            height,
+            root_hash,
+            batch,
+            aux,
+        })
+    }
+}
+
+/// Encodes a list of puts (`Some`) and deletes (`None`) as a count followed by
+/// the length-prefixed keys and values.
+pub(super) fn encode_entries(bytes: &mut Vec<u8>, entries: &[(Vec<u8>, Option<Vec<u8>>)]) {
+    bytes.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for (key, maybe_value) in entries.iter() {
+        encode_bytes(bytes, key);
+        match maybe_value {
+            Some(value) => {
+                bytes.push(1);
+                encode_bytes(bytes, value);
+            }
+            None => bytes.push(0),
+        }
+    }
+}
+
+/// Decodes a list encoded with `encode_entries` from the front of `bytes`.
+pub(super) fn decode_entries(bytes: &mut &[u8]) -> Option<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+    let count = u32::from_be_bytes(take(bytes, 4)?.try_into().ok()?);
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let key = decode_bytes(bytes)?;
+        let maybe_value = match take(bytes, 1)?[0] {
+            0 => None,
+            1 => Some(decode_bytes(bytes)?),
+            _ => return None,
+        };
+        entries.push((key, maybe_value));
+    }
+    Some(entries)
+}
+
+fn encode_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(value);
+}
+
+fn decode_bytes(bytes: &mut &[u8]) -> Option<Vec<u8>> {
+    let len = u32::from_be_bytes(take(bytes, 4)?.try_into().ok()?);
+    Some(take(bytes, len as usize)?.to_vec())
+}
+
+pub(super) fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if bytes.len() < len {
+        return None;
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Some(taken)
+}
+
+fn record_key(prefix: &[u8], height: u64) -> Vec<u8> {
+    let mut key = prefixed(prefix, CHANGE_RECORD_PREFIX);
+    key.extend_from_slice(&height.to_be_bytes());
+    key
+}
+
+/// The state of a store's changelog, if it is enabled.
+pub(crate) struct Changelog {
+    height: u64,
+    subscribers: Vec<Sender<ChangeRecord>>,
+}
+
+impl Changelog {
+    /// Returns the internal column family entries which record the next
+    /// commit, along with the record itself.
+    pub(crate) fn entries(
+        &self,
+        prefix: &[u8],
+        root_hash: Hash,
+        batch: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+        aux: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    ) -> (ChangeRecord, Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+        let record = ChangeRecord {
+            height: self.height + 1,
+            root_hash,
+            batch,
+            aux,
+        };
+        let entries = vec![
+            (record_key(prefix, record.height), Some(record.encode())),
+            (
+                prefixed(prefix, CHANGELOG_HEIGHT_KEY),
+                Some(record.height.to_be_bytes().to_vec()),
+            ),
+        ];
+        (record, entries)
+    }
+
+    /// Advances the changelog once `record` has been written, and sends it to
+    /// the subscribers.
+    pub(crate) fn committed(&mut self, record: ChangeRecord) {
+        self.advance(record.height);
+        self.notify(record);
+    }
+
+    /// Advances the changelog past a record which has been staged but not yet
+    /// written.
+    pub(crate) fn advance(&mut self, height: u64) {
+        self.height = height;
+    }
+
+    /// Moves the subscribers of `previous` to this changelog, e.g. when the
+    /// store's state is reloaded from the database.
+    pub(crate) fn keep_subscribers(&mut self, previous: Changelog) {
+        self.subscribers = previous.subscribers;
+    }
+
+    /// Sends a written record to the subscribers, dropping those which have
+    /// hung up.
+    pub(crate) fn notify(&mut self, record: ChangeRecord) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(record.clone()).is_ok());
+    }
+}
+
+/// Loads the state of the changelog for the store under `prefix`, or `None`
+/// if its changelog is not enabled.
+pub(crate) fn load_changelog(db: &DB, prefix: &[u8]) -> Result<Option<Changelog>> {
+    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+    let maybe_height = db.get_cf(internal_cf, prefixed(prefix, CHANGELOG_HEIGHT_KEY))?;
+    maybe_height
+        .map(|bytes| {
+            let height = bytes.as_slice().try_into().map_err(|_| Error::Corruption {
+                key: CHANGELOG_HEIGHT_KEY.to_vec(),
+            })?;
+            Ok(Changelog {
+                height: u64::from_be_bytes(height),
+                subscribers: vec![],
+            })
+        })
+        .transpose()
+}
+
+impl Merk {
+    /// Starts recording a `ChangeRecord` for every subsequent commit. Records
+    /// are written in the same batch as the commit they describe.
+    pub fn enable_changelog(&mut self) -> Result<()> {
+        if self.changelog.is_some() {
+            return Ok(());
+        }
+
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        self.db.put_cf(
+            internal_cf,
+            self.prefixed(CHANGELOG_HEIGHT_KEY),
+            0u64.to_be_bytes(),
+        )?;
+        self.changelog = Some(Changelog {
+            height: 0,
+            subscribers: vec![],
+        });
+        Ok(())
+    }
+
+    /// Returns the height of the latest change record, or `None` if the
+    /// changelog is not enabled.
+    pub fn changelog_height(&self) -> Option<u64> {
+        self.changelog.as_ref().map(|changelog| changelog.height)
+    }
+
+    /// Returns a receiver which is sent the change record of every subsequent
+    /// commit, in order. Subscribers which fall behind can catch up with
+    /// `change_records`.
+    pub fn subscribe(&mut self) -> Result<Receiver<ChangeRecord>> {
+        let changelog = self
+            .changelog
+            .as_mut()
+            .ok_or_else(|| Error::Unsupported("Changelog is not enabled".into()))?;
+        let (sender, receiver) = channel();
+        changelog.subscribers.push(sender);
+        Ok(receiver)
+    }
+
+    /// Returns up to `limit` stored change records, starting at height `from`.
+    pub fn change_records(&self, from: u64, limit: usize) -> Result<Vec<ChangeRecord>> {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let record_prefix = prefixed(&self.prefix, CHANGE_RECORD_PREFIX);
+        let mut iter = self
+            .db
+            .raw_iterator_cf_opt(internal_cf, prefix_read_opts(&record_prefix));
+        iter.seek(record_key(&self.prefix, from));
+
+        let mut records = vec![];
+        while iter.valid() && records.len() < limit {
+            let key = iter.key().unwrap();
+            let record =
+                ChangeRecord::decode(iter.value().unwrap()).ok_or_else(|| Error::Corruption {
+                    key: key[self.prefix.len()..].to_vec(),
+                })?;
+            records.push(record);
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(records)
+    }
+
+    /// Deletes the stored change records below height `before`, e.g. once all
+    /// followers have consumed them.
+    pub fn truncate_changelog(&mut self, before: u64) -> Result<()> {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        self.db.delete_range_cf(
+            internal_cf,
+            record_key(&self.prefix, 0),
+            record_key(&self.prefix, before),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn encode_decode() {
+        let record = ChangeRecord {
+            height: 12,
+            root_hash: [3; 32],
+            batch: vec![(vec![1], Some(vec![2, 3])), (vec![4], None)],
+            aux: vec![(vec![5, 6], Some(vec![]))],
+        };
+        let bytes = record.encode();
+        assert_eq!(ChangeRecord::decode(&bytes), Some(record));
+        assert_eq!(ChangeRecord::decode(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn subscribe_to_changes() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        assert!(merk.subscribe().is_err());
+        assert_eq!(merk.changelog_height(), None);
+
+        merk.enable_changelog().unwrap();
+        let receiver = merk.subscribe().unwrap();
+        merk.apply(&make_batch_seq(10..20), &[(vec![1], Op::Put(vec![2]))])
+            .unwrap();
+        merk.apply(&make_del_batch_seq(0..5), &[]).unwrap();
+
+        let first = receiver.recv().unwrap();
+        assert_eq!(first.height, 1);
+        assert_eq!(first.changed_keys().count(), 10);
+        assert_eq!(first.aux, vec![(vec![1], Some(vec![2]))]);
+        let second = receiver.recv().unwrap();
+        assert_eq!(second.height, 2);
+        assert_eq!(second.root_hash, merk.root_hash());
+        assert_eq!(second.batch[0], (seq_key(0), None));
+        drop(merk);
+
+        let mut merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.changelog_height(), Some(2));
+        assert_eq!(
+            merk.change_records(0, 10).unwrap(),
+            vec![first, second.clone()]
+        );
+
+        merk.truncate_changelog(2).unwrap();
+        assert_eq!(merk.change_records(0, 10).unwrap(), vec![second]);
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+//! Provides `Merk::checksum`, a digest of everything stored in the store, for
+//! comparing two stores without exchanging dumps.
+
+use std::borrow::Cow;
+
+use rocksdb::DBRawIterator;
+use sha2::Digest;
+
+use super::{prefix_read_opts, Merk, NodeCodec, AUX_CF_NAME};
+use crate::tree::{Hash, Hasher};
+use crate::Result;
+
+/// Distinguishes checksums from node and KV hashes.
+const CHECKSUM_DOMAIN: &[u8] = b"merk-checksum-v1";
+
+impl Merk {
+    /// Returns a digest of every stored node and aux entry, read from a
+    /// consistent snapshot.
+    ///
+    /// Unlike the root hash, the checksum covers the nodes' storage fields
+    /// (their cached child heights and link keys) and the aux entries, so two
+    /// stores with the same checksum contain the same data, not just the same
+    /// key/value pairs. Nodes are digested after decoding with the store's
+    /// codec, so stores with different compression or encryption settings can
+    /// still be compared.
+    pub fn checksum(&self) -> Result<Hash> {
+        let snapshot = self.db.snapshot();
+        let mut hasher = <Hasher as Digest>::new();
+        Digest::update(&mut hasher, CHECKSUM_DOMAIN);
+
+        let mut iter = snapshot.raw_iterator_opt(prefix_read_opts(&self.prefix));
+        fold_entries(&mut hasher, &mut iter, &self.prefix, 0, Some(&self.codec))?;
+
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let mut iter = snapshot.raw_iterator_cf_opt(aux_cf, prefix_read_opts(&self.prefix));
+        fold_entries(&mut hasher, &mut iter, &self.prefix, 1, None)?;
+
+        let mut checksum: Hash = Default::default();
+        checksum.copy_from_slice(&Digest::finalize(hasher)[..]);
+        Ok(checksum)
+    }
+}
+
+/// Feeds each entry of `iter` into `hasher` in key order, tagged with `tag`.
+/// Values are first decoded with `codec`, if given.
+fn fold_entries<D: Digest>(
+    hasher: &mut D,
+    iter: &mut DBRawIterator,
+    prefix: &[u8],
+    tag: u8,
+    codec: Option<&NodeCodec>,
+) -> Result<()> {
+    let mut count = 0u64;
+    iter.seek_to_first();
+    while iter.valid() {
+        let key = &iter.key().unwrap()[prefix.len()..];
+        let value = match codec {
+            Some(codec) => codec.decode(key, iter.value().unwrap())?,
+            None => Cow::Borrowed(iter.value().unwrap()),
+        };
+
+        // lengths are included so entries can't be split differently while
+        // hashing the same bytes
+        hasher.update([tag]);
+        hasher.update((key.len() as u32).to_le_bytes());
+        hasher.update(key);
+        hasher.update((value.len() as u32).to_le_bytes());
+        hasher.update(&value);
+
+        count += 1;
+        iter.next();
+    }
+    iter.status()?;
+
+    hasher.update([tag]);
+    hasher.update(count.to_le_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::tree::Op;
+
+    #[test]
+    fn checksum_compares_stores() {
+        let mut a = TempMerk::new().unwrap();
+        let mut b = TempMerk::new().unwrap();
+        assert_eq!(a.checksum().unwrap(), b.checksum().unwrap());
+
+        let batch = make_batch_seq(0..1_000);
+        a.apply(&batch, &[]).unwrap();
+        b.apply(&batch, &[]).unwrap();
+        assert_eq!(a.checksum().unwrap(), b.checksum().unwrap());
+        assert_ne!(a.checksum().unwrap(), a.root_hash());
+
+        // aux entries aren't covered by the root hash, but are by the checksum
+        b.apply(&[], &[(vec![1], Op::Put(vec![2]))]).unwrap();
+        assert_eq!(a.root_hash(), b.root_hash());
+        assert_ne!(a.checksum().unwrap(), b.checksum().unwrap());
+
+        a.apply(&[], &[(vec![1], Op::Put(vec![2]))]).unwrap();
+        assert_eq!(a.checksum().unwrap(), b.checksum().unwrap());
+
+        // cached child heights aren't covered by the root hash either
+        let root_key = b.use_tree(|maybe_tree| maybe_tree.unwrap().key().to_vec());
+        let mut root = b.fetch_node(&root_key).unwrap().unwrap();
+        *root.link_mut(true).unwrap().child_heights_mut() = (0, 0);
+        let bytes = b.codec.encode(&root_key, root.encode()).unwrap();
+        b.db.put(b.prefixed(&root_key), bytes).unwrap();
+        assert_eq!(a.root_hash(), b.root_hash());
+        assert_ne!(a.checksum().unwrap(), b.checksum().unwrap());
+    }
+}
@@ -0,0 +1,356 @@
+//! Provides `ChunkServer`, which serves state sync chunks to many peers from
+//! the committed versions of a store, without holding up the store while it
+//! processes blocks.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::reader::CommittedView;
+use super::{prefix_read_opts, prefixed, MerkReader};
+use crate::proofs::chunk::{get_next_chunk, ChunkEnd};
+use crate::proofs::{encode_into, Node, Op};
+use crate::tree::{Fetch, Hash, RefWalker};
+use crate::{Error, Result};
+use ed::Encode;
+
+/// The limits a `ChunkServer` serves chunks within.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkServerConfig {
+    /// The most chunks generated at once, across all peers.
+    pub max_concurrent: usize,
+    /// The number of chunks each peer may request per second, on average.
+    pub peer_chunks_per_sec: f64,
+    /// The number of chunks each peer may request at once after being idle.
+    pub peer_burst: u32,
+    /// The number of recent root hashes whose chunks are served, so peers
+    /// which started syncing a root can finish after new versions are
+    /// committed.
+    pub retained_roots: usize,
+}
+
+impl Default for ChunkServerConfig {
+    fn default() -> Self {
+        ChunkServerConfig {
+            max_concurrent: 4,
+            peer_chunks_per_sec: 10.0,
+            peer_burst: 20,
+            retained_roots: 2,
+        }
+    }
+}
+
+/// Serves state sync chunks to syncing peers, created with
+/// `ChunkServer::new` from a `MerkReader`. It can be shared between the
+/// threads serving each peer.
+///
+/// Chunks are generated from database snapshots of committed versions of the
+/// store, each of which is shared by every peer syncing its root hash along
+/// with its trunk, so serving chunks never locks the store. To keep serving
+/// from competing with block processing for I/O, the number of chunks
+/// generated at once and the rate of each peer's requests are bounded, and
+/// requests over either limit fail with `Error::RateLimited` (to be retried
+/// later) rather than waiting.
+pub struct ChunkServer {
+    reader: MerkReader,
+    config: ChunkServerConfig,
+    /// The chunk state of recently served roots, newest last.
+    roots: Mutex<VecDeque<Arc<ServedRoot>>>,
+    active: Mutex<usize>,
+    peers: Mutex<HashMap<String, TokenBucket>>,
+}
+
+/// A committed version of the store being served, with its trunk.
+struct ServedRoot {
+    view: Arc<CommittedView>,
+    root_hash: Hash,
+    trunk: Vec<u8>,
+    chunk_boundaries: Vec<Vec<u8>>,
+}
+
+impl ServedRoot {
+    fn len(&self) -> usize {
+        match self.chunk_boundaries.len() {
+            0 => 1,
+            len => len + 2,
+        }
+    }
+}
+
+/// Allows a request each time a token is taken, refilling at a fixed rate up
+/// to a maximum.
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl ChunkServer {
+    /// Creates a server for the chunks of the committed versions of the store
+    /// `reader` reads.
+    pub fn new(reader: MerkReader, config: ChunkServerConfig) -> Self {
+        ChunkServer {
+            reader,
+            config,
+            roots: Mutex::new(VecDeque::new()),
+            active: Mutex::new(0),
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the root hash of the latest committed version and its number
+    /// of chunks, which peers should sync. Errors if the store is empty.
+    pub fn latest(&self) -> Result<(Hash, usize)> {
+        let root = self.latest_root()?;
+        Ok((root.root_hash, root.len()))
+    }
+
+    /// Gets the chunk with the given index of the version with root hash
+    /// `root_hash`, for the peer identified by `peer`. Errors if the root hash
+    /// is no longer served, the index is out of bounds, or the request is over
+    /// the peer's rate limit or the limit of concurrent generations.
+    pub fn chunk(&self, peer: &str, root_hash: Hash, index: usize) -> Result<Vec<u8>> {
+        self.take_token(peer)?;
+        let root = self
+            .roots
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|root| root.root_hash == root_hash)
+            .cloned();
+        let root = match root {
+            Some(root) => root,
+            None if self.reader.root_hash() == root_hash => self.latest_root()?,
+            None => {
+                return Err(Error::Fetch(format!(
+                    "Chunks of root hash {} are no longer served",
+                    hex::encode(root_hash)
+                )))
+            }
+        };
+        if index >= root.len() {
+            return Err(Error::IndexOutOfBounds("Chunk index out-of-bounds".into()));
+        }
+        if index == 0 {
+            return Ok(root.trunk.clone());
+        }
+
+        let _slot = self.acquire()?;
+        self.leaf_chunk(&root, index)
+    }
+
+    /// Returns the served state of the latest committed version, creating its
+    /// trunk if it is not served yet.
+    fn latest_root(&self) -> Result<Arc<ServedRoot>> {
+        let view = self.reader.view();
+        let (root_key, root_hash) = match view.root.as_ref() {
+            Some(root) => root.clone(),
+            None => {
+                return Err(Error::Fetch(
+                    "Attempted to fetch chunk on empty tree".into(),
+                ))
+            }
+        };
+        if let Some(root) = self.roots.lock().unwrap().back() {
+            if root.root_hash == root_hash {
+                return Ok(root.clone());
+            }
+        }
+
+        let (trunk, has_more) = {
+            let _slot = self.acquire()?;
+            let source = self.reader.source(&view);
+            let mut tree = source.fetch_by_key_expect(&root_key)?;
+            RefWalker::new(&mut tree, source).create_trunk_proof(None)?
+        };
+        let chunk_boundaries = if has_more {
+            trunk
+                .iter()
+                .filter_map(|op| match op {
+                    Op::Push(Node::KV(key, _)) => Some(key.clone()),
+                    _ => None,
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        let mut encoded_trunk = vec![];
+        encode_into(trunk.iter(), &mut encoded_trunk);
+
+        let root = Arc::new(ServedRoot {
+            view,
+            root_hash,
+            trunk: encoded_trunk,
+            chunk_boundaries,
+        });
+        let mut roots = self.roots.lock().unwrap();
+        // another request may have created it while the trunk was generated
+        if roots
+            .back()
+            .map_or(true, |last| last.root_hash != root_hash)
+        {
+            roots.push_back(root.clone());
+            while roots.len() > self.config.retained_roots.max(1) {
+                roots.pop_front();
+            }
+        }
+        Ok(root)
+    }
+
+    /// Generates the leaf chunk at `index` from the root's snapshot, as in
+    /// `ChunkProducer::chunk`.
+    fn leaf_chunk(&self, root: &ServedRoot, index: usize) -> Result<Vec<u8>> {
+        let prefix = self.reader.prefix.as_slice();
+        let mut iter = root
+            .view
+            .snapshot
+            .raw_iterator_opt(prefix_read_opts(prefix));
+        match index {
+            1 => iter.seek_to_first(),
+            _ => {
+                iter.seek(prefixed(prefix, &root.chunk_boundaries[index - 2]));
+                iter.next();
+            }
+        }
+
+        let end = match root.chunk_boundaries.get(index - 1) {
+            Some(end_key) => ChunkEnd::Boundary(end_key),
+            None => ChunkEnd::Unbounded,
+        };
+        let ops = get_next_chunk(&mut iter, end, prefix, &self.reader.codec)?;
+        Ok(ops.encode()?)
+    }
+
+    /// Takes one of the slots for concurrent generations, which is returned
+    /// when the guard is dropped.
+    fn acquire(&self) -> Result<GenerationSlot> {
+        let mut active = self.active.lock().unwrap();
+        if *active >= self.config.max_concurrent {
+            return Err(Error::RateLimited(
+                "Too many chunks are being generated".into(),
+            ));
+        }
+        *active += 1;
+        Ok(GenerationSlot(&self.active))
+    }
+
+    /// Takes a token from the peer's bucket.
+    fn take_token(&self, peer: &str) -> Result<()> {
+        let now = Instant::now();
+        let burst = self.config.peer_burst as f64;
+        let rate = self.config.peer_chunks_per_sec;
+        let refilled = |bucket: &TokenBucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated);
+            (bucket.tokens + elapsed.as_secs_f64() * rate).min(burst)
+        };
+
+        let mut peers = self.peers.lock().unwrap();
+        if !peers.contains_key(peer) {
+            // forget the peers which have been idle long enough to be at
+            // their burst again, so the map doesn't grow with every peer seen
+            peers.retain(|_, bucket| refilled(bucket) < burst);
+        }
+        let bucket = peers.entry(peer.to_string()).or_insert(TokenBucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = refilled(&*bucket);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(Error::RateLimited(format!(
+                "Peer {} exceeded its chunk rate limit",
+                peer
+            )));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Holds a slot for a concurrent chunk generation, released when dropped.
+struct GenerationSlot<'a>(&'a Mutex<usize>);
+
+impl<'a> Drop for GenerationSlot<'a> {
+    fn drop(&mut self) {
+        *self.0.lock().unwrap() -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::chunk::{verify_leaf, verify_trunk};
+    use crate::proofs::Decoder;
+    use crate::test_utils::*;
+
+    #[test]
+    fn serve_chunks() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        let config = ChunkServerConfig {
+            peer_burst: 1_000,
+            ..Default::default()
+        };
+        let server = ChunkServer::new(merk.reader(), config);
+
+        let (root_hash, count) = server.latest().unwrap();
+        assert_eq!(root_hash, merk.root_hash());
+        let expected: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(count, expected.len());
+        for (index, expected) in expected.iter().enumerate() {
+            assert_eq!(&server.chunk("a", root_hash, index).unwrap(), expected);
+        }
+        assert!(server.chunk("a", root_hash, count).is_err());
+
+        // the old root is still served after a new version is committed
+        merk.apply(&make_batch_seq(10_000..10_100), &[]).unwrap();
+        let (new_root_hash, _) = server.latest().unwrap();
+        assert_eq!(new_root_hash, merk.root_hash());
+        let trunk = server.chunk("a", root_hash, 0).unwrap();
+        let (trunk, height) = verify_trunk(Decoder::new(&trunk), None).unwrap();
+        assert_eq!(trunk.hash().unwrap(), root_hash);
+        let leaf = server.chunk("a", root_hash, 1).unwrap();
+        let node = trunk.layer(height / 2).next().unwrap();
+        verify_leaf(Decoder::new(&leaf), node.hash().unwrap()).unwrap();
+
+        merk.apply(&make_batch_seq(10_100..10_200), &[]).unwrap();
+        server.latest().unwrap();
+        assert!(matches!(
+            server.chunk("a", root_hash, 0),
+            Err(Error::Fetch(_))
+        ));
+    }
+
+    #[test]
+    fn rate_limits() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        let config = ChunkServerConfig {
+            max_concurrent: 1,
+            peer_chunks_per_sec: 0.001,
+            peer_burst: 2,
+            ..Default::default()
+        };
+        let server = ChunkServer::new(merk.reader(), config);
+        let (root_hash, _) = server.latest().unwrap();
+
+        server.chunk("a", root_hash, 1).unwrap();
+        server.chunk("a", root_hash, 2).unwrap();
+        assert!(matches!(
+            server.chunk("a", root_hash, 3),
+            Err(Error::RateLimited(_))
+        ));
+        server.chunk("b", root_hash, 3).unwrap();
+
+        let _slot = server.acquire().unwrap();
+        assert!(matches!(
+            server.chunk("c", root_hash, 1),
+            Err(Error::RateLimited(_))
+        ));
+        assert!(server.chunk("c", root_hash, 0).is_ok());
+    }
+}
@@ -1,32 +1,111 @@
 //! Provides `ChunkProducer`, which creates chunk proofs for full replication of
 //! a Merk.
 
-use super::Merk;
-use crate::proofs::{chunk::get_next_chunk, Node, Op};
+use std::time::Duration;
+
+use super::{Merk, NodeCodec, AUX_CF_NAME};
+use crate::proofs::chunk::{get_next_chunk, ChunkEnd, ChunkHeader};
+use crate::proofs::{encode_into, Decoder, Node, Op};
+use crate::tree::{Hash, HASH_LENGTH};
 
 use crate::{Error, Result};
 use ed::Encode;
 use rocksdb::DBRawIterator;
 
+/// The smallest part size `ChunkProducer::chunk_parts` shrinks to after
+/// failed transfers.
+pub const MIN_PART_SIZE: usize = 4 * 1024;
+
+/// The time the transfer of a single part should take, used to derive the
+/// part size from the reported throughput.
+const TARGET_PART_DURATION: Duration = Duration::from_secs(1);
+
+/// The aux key the trunk cache is stored under, if enabled with
+/// `Merk::enable_trunk_cache`: the root hash, whether there are leaf chunks,
+/// and the encoded trunk.
+pub const TRUNK_CACHE_KEY: &[u8] = b"\x00merk/trunk-cache";
+
+/// Recent transfer conditions reported to a `ChunkProducer` by the feedback
+/// callback passed to `with_feedback`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TransferFeedback {
+    /// The throughput of recent transfers to the peer in bytes per second, if
+    /// known.
+    pub bytes_per_sec: Option<u64>,
+    /// The number of transfers which failed since the last report.
+    pub failures: usize,
+    /// The largest message the peer accepts, if known.
+    pub max_message_size: Option<usize>,
+}
+
+impl TransferFeedback {
+    /// Returns the part size to use after `part_size` given this feedback:
+    /// halved after failures (starting from the largest part sent, if it was
+    /// smaller), doubled otherwise, and capped by the size the peer can receive
+    /// within `TARGET_PART_DURATION` and by its message limit.
+    fn next_part_size(&self, part_size: usize, largest_part: usize) -> usize {
+        let mut size = if self.failures > 0 {
+            part_size.min(largest_part) / 2
+        } else {
+            part_size.saturating_mul(2)
+        };
+        if let Some(bytes_per_sec) = self.bytes_per_sec {
+            let target = bytes_per_sec as u128 * TARGET_PART_DURATION.as_millis() / 1000;
+            size = size.min(target.min(usize::MAX as u128) as usize);
+        }
+        if let Some(max_message_size) = self.max_message_size {
+            size = size.min(max_message_size);
+        }
+        size.max(MIN_PART_SIZE)
+    }
+}
+
 /// A `ChunkProducer` allows the creation of chunk proofs, used for trustlessly
 /// replicating entire Merk trees. Chunks can be generated on the fly in a
 /// random order, or iterated in order for slightly better performance.
 pub struct ChunkProducer<'a> {
+    root_hash: Hash,
     trunk: Vec<Op>,
     chunk_boundaries: Vec<Vec<u8>>,
     raw_iter: DBRawIterator<'a>,
     index: usize,
+    codec: &'a NodeCodec,
+    prefix: &'a [u8],
+    part_size: usize,
+    largest_part: usize,
+    feedback: Option<Box<dyn FnMut() -> TransferFeedback + 'a>>,
 }
 
 impl<'a> ChunkProducer<'a> {
     /// Creates a new `ChunkProducer` for the given `Merk` instance. In the
     /// constructor, the first chunk (the "trunk") will be created.
     pub fn new(merk: &'a Merk) -> Result<Self> {
-        let (trunk, has_more) = merk.walk(|maybe_walker| match maybe_walker {
-            Some(mut walker) => walker.create_trunk_proof(),
-            None => Ok((vec![], false)),
-        })?;
+        let (trunk, has_more) = match merk.cached_trunk()? {
+            Some(cached) => cached,
+            None => {
+                let (trunk, has_more) = create_trunk(merk, None)?;
+                merk.cache_trunk(&trunk, has_more)?;
+                (trunk, has_more)
+            }
+        };
+        Self::from_trunk(merk, trunk, has_more)
+    }
 
+    /// Creates a new `ChunkProducer` as in `new`, with a trunk of
+    /// `trunk_height` layers rather than half of the height of the tree (see
+    /// `create_trunk_proof`). The trunk splits the rest of the tree into
+    /// `2^trunk_height` leaf chunks, so a taller trunk makes for more, smaller
+    /// leaf chunks. Restorers must be created `with_trunk_height` set to the
+    /// same height.
+    ///
+    /// Returns an error if the tree has a node with less than two children
+    /// above the bottom layer of the trunk. These trunks are not cached.
+    pub fn with_trunk_height(merk: &'a Merk, trunk_height: usize) -> Result<Self> {
+        let (trunk, has_more) = create_trunk(merk, Some(trunk_height))?;
+        Self::from_trunk(merk, trunk, has_more)
+    }
+
+    fn from_trunk(merk: &'a Merk, trunk: Vec<Op>, has_more: bool) -> Result<Self> {
         let chunk_boundaries = if has_more {
             trunk
                 .iter()
@@ -43,13 +122,37 @@ impl<'a> ChunkProducer<'a> {
         raw_iter.seek_to_first();
 
         Ok(ChunkProducer {
+            root_hash: merk.root_hash(),
             trunk,
             chunk_boundaries,
             raw_iter,
             index: 0,
+            codec: &merk.codec,
+            prefix: &merk.prefix,
+            part_size: usize::MAX,
+            largest_part: usize::MAX,
+            feedback: None,
         })
     }
 
+    /// Sets a callback reporting recent transfer conditions, which is called
+    /// each time `chunk_parts` is called to adjust the size of the parts leaf
+    /// chunks are split into. Until the callback reports any limits, chunks are
+    /// not split.
+    pub fn with_feedback<F>(mut self, feedback: F) -> Self
+    where
+        F: FnMut() -> TransferFeedback + 'a,
+    {
+        self.feedback = Some(Box::new(feedback));
+        self
+    }
+
+    /// Returns the size leaf chunks are currently split into by
+    /// `chunk_parts`, or `usize::MAX` if they are not split.
+    pub fn part_size(&self) -> usize {
+        self.part_size
+    }
+
     /// Gets the chunk with the given index. Errors if the index is out of
     /// bounds or the tree is empty - the number of chunks can be checked by calling
     /// `producer.len()`.
@@ -58,17 +161,85 @@ impl<'a> ChunkProducer<'a> {
             return Err(Error::IndexOutOfBounds("Chunk index out-of-bounds".into()));
         }
 
+        self.seek(index);
+        self.next_chunk()
+    }
+
+    /// Gets the chunk with the given index as in `chunk`, prefixed with its
+    /// `ChunkHeader`. Chunks with headers are processed with
+    /// `Restorer::process_chunk_with_header`.
+    pub fn chunk_with_header(&mut self, index: usize) -> Result<Vec<u8>> {
+        if index >= self.len() {
+            return Err(Error::IndexOutOfBounds("Chunk index out-of-bounds".into()));
+        }
+        if self.trunk.is_empty() {
+            return Err(Error::Fetch(
+                "Attempted to fetch chunk on empty tree".into(),
+            ));
+        }
+
+        self.seek(index);
+        self.index += 1;
+        let mut bytes = vec![];
+        if index == 0 {
+            ChunkHeader::new(index, self.root_hash, &self.trunk)?.encode_into(&mut bytes);
+            encode_into(self.trunk.iter(), &mut bytes);
+        } else {
+            let ops = self.next_leaf_ops()?;
+            ChunkHeader::new(index, self.root_hash, &ops)?.encode_into(&mut bytes);
+            encode_into(ops.iter(), &mut bytes);
+        }
+        Ok(bytes)
+    }
+
+    /// Gets the chunk with the given index as in `chunk`, split into parts of
+    /// at most `part_size()` bytes (or a single op, if an op is larger), after
+    /// adjusting the part size from the feedback callback.
+    ///
+    /// The trunk chunk is never split. The parts of a leaf chunk must be passed
+    /// to `Restorer::process_chunk_part` in order, and the chunk is only
+    /// verified once its last part is received. Concatenating the parts yields
+    /// the chunk returned by `chunk`.
+    pub fn chunk_parts(&mut self, index: usize) -> Result<Vec<Vec<u8>>> {
+        if let Some(feedback) = self.feedback.as_mut() {
+            self.part_size = feedback().next_part_size(self.part_size, self.largest_part);
+        }
+
+        if index == 0 || index >= self.len() {
+            let chunk = self.chunk(index)?;
+            self.largest_part = chunk.len();
+            return Ok(vec![chunk]);
+        }
+
+        self.seek(index);
+        self.index += 1;
+        let ops = self.next_leaf_ops()?;
+
+        let mut parts = vec![];
+        let mut part = vec![];
+        for op in ops.iter() {
+            if !part.is_empty() && part.len() + op.encoded_len() > self.part_size {
+                parts.push(std::mem::take(&mut part));
+            }
+            op.encode_into(&mut part);
+        }
+        parts.push(part);
+        self.largest_part = parts.iter().map(Vec::len).max().unwrap();
+        Ok(parts)
+    }
+
+    /// Positions the producer so the next chunk read is the one at `index`.
+    fn seek(&mut self, index: usize) {
         self.index = index;
 
         if index == 0 || index == 1 {
             self.raw_iter.seek_to_first();
         } else {
             let preceding_key = self.chunk_boundaries.get(index - 2).unwrap();
-            self.raw_iter.seek(preceding_key);
+            self.raw_iter
+                .seek(super::prefixed(self.prefix, preceding_key));
             self.raw_iter.next();
         }
-
-        self.next_chunk()
     }
 
     /// Returns the total number of chunks for the underlying Merk tree.
@@ -98,13 +269,19 @@ impl<'a> ChunkProducer<'a> {
 
         assert!(self.index < self.len(), "Called next_chunk after end");
 
-        let end_key = self.chunk_boundaries.get(self.index - 1);
-        let end_key_slice = end_key.as_ref().map(|k| k.as_slice());
-
         self.index += 1;
+        Ok(self.next_leaf_ops()?.encode()?)
+    }
+
+    /// Reads the ops of the leaf chunk before `self.index`, which must already
+    /// have been advanced past it.
+    fn next_leaf_ops(&mut self) -> Result<Vec<Op>> {
+        let end = match self.chunk_boundaries.get(self.index - 2) {
+            Some(end_key) => ChunkEnd::Boundary(end_key),
+            None => ChunkEnd::Unbounded,
+        };
 
-        let chunk = get_next_chunk(&mut self.raw_iter, end_key_slice)?;
-        Ok(chunk.encode()?)
+        get_next_chunk(&mut self.raw_iter, end, self.prefix, self.codec)
     }
 }
 
@@ -138,12 +315,63 @@ impl<'a> Iterator for ChunkIter<'a> {
     }
 }
 
+/// Creates the trunk of `merk`'s tree with the given height (see
+/// `create_trunk_proof`), returning whether there are leaf chunks.
+fn create_trunk(merk: &Merk, trunk_height: Option<usize>) -> Result<(Vec<Op>, bool)> {
+    merk.walk(|maybe_walker| match maybe_walker {
+        Some(mut walker) => walker.create_trunk_proof(trunk_height),
+        None => Ok((vec![], false)),
+    })
+}
+
 impl Merk {
     /// Creates a `ChunkProducer` which can return chunk proofs for replicating
     /// the entire Merk tree.
     pub fn chunks(&self) -> Result<ChunkProducer> {
         ChunkProducer::new(self)
     }
+
+    /// Stores the trunk of the latest root hash chunks were produced for in
+    /// aux storage, so `chunks` only creates the trunk (walking the top half
+    /// of the tree) once per root hash, even across restarts. The store must
+    /// be writable.
+    pub fn enable_trunk_cache(&mut self) {
+        self.trunk_cache = true;
+    }
+
+    /// Returns the cached trunk and whether there are leaf chunks, if the
+    /// trunk cache is enabled and holds the trunk of the current root hash.
+    fn cached_trunk(&self) -> Result<Option<(Vec<Op>, bool)>> {
+        if !self.trunk_cache {
+            return Ok(None);
+        }
+        let bytes = match self.get_aux(TRUNK_CACHE_KEY)? {
+            Some(bytes) if bytes.len() > HASH_LENGTH => bytes,
+            _ => return Ok(None),
+        };
+        if bytes[..HASH_LENGTH] != self.root_hash()[..] {
+            return Ok(None);
+        }
+
+        let has_more = bytes[HASH_LENGTH] == 1;
+        let trunk = Decoder::new(&bytes[HASH_LENGTH + 1..]).collect::<Result<_>>()?;
+        Ok(Some((trunk, has_more)))
+    }
+
+    /// Replaces the cached trunk, if the trunk cache is enabled.
+    fn cache_trunk(&self, trunk: &[Op], has_more: bool) -> Result<()> {
+        if !self.trunk_cache || trunk.is_empty() {
+            return Ok(());
+        }
+
+        let mut bytes = self.root_hash().to_vec();
+        bytes.push(has_more as u8);
+        encode_into(trunk.iter(), &mut bytes);
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        self.db
+            .put_cf(aux_cf, self.prefixed(TRUNK_CACHE_KEY), bytes)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -157,6 +385,38 @@ mod tests {
         test_utils::*,
     };
 
+    #[test]
+    fn trunk_cache() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.enable_trunk_cache();
+        merk.apply(&make_batch_seq(1..10_000), &[]).unwrap();
+
+        let chunks: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap();
+        let cached = merk.get_aux(TRUNK_CACHE_KEY).unwrap().unwrap();
+        assert_eq!(cached[..HASH_LENGTH], merk.root_hash()[..]);
+        assert!(merk.cached_trunk().unwrap().unwrap().1);
+        let cached_chunks: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(cached_chunks, chunks);
+
+        merk.apply(&make_batch_seq(10_000..10_001), &[]).unwrap();
+        assert!(merk.cached_trunk().unwrap().is_none());
+        let mut chunks = merk.chunks().unwrap();
+        let (trunk, _) = verify_trunk(Decoder::new(&chunks.chunk(0).unwrap()), None).unwrap();
+        assert_eq!(trunk.hash().unwrap(), merk.root_hash());
+        let cached = merk.get_aux(TRUNK_CACHE_KEY).unwrap().unwrap();
+        assert_eq!(cached[..HASH_LENGTH], merk.root_hash()[..]);
+    }
+
     #[test]
     fn len_small() {
         let mut merk = TempMerk::new().unwrap();
@@ -189,7 +449,7 @@ mod tests {
 
         let chunk = chunks.next().unwrap();
         let ops = Decoder::new(chunk.as_slice());
-        let (trunk, height) = verify_trunk(ops).unwrap();
+        let (trunk, height) = verify_trunk(ops, None).unwrap();
         assert_eq!(height, 14);
         assert_eq!(trunk.hash()?, merk.root_hash());
 
@@ -202,6 +462,48 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn split_chunks_adapt_to_feedback() {
+        use std::{cell::Cell, rc::Rc};
+
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(1..10_000), &[]).unwrap();
+
+        let feedback = Rc::new(Cell::new(TransferFeedback::default()));
+        let reported = feedback.clone();
+        let mut chunks = merk.chunks().unwrap().with_feedback(move || reported.get());
+        let whole = (0..chunks.len())
+            .map(|i| chunks.chunk(i).unwrap())
+            .collect::<Vec<_>>();
+
+        // without limits, chunks are not split
+        assert_eq!(chunks.chunk_parts(1).unwrap(), vec![whole[1].clone()]);
+        assert_eq!(chunks.part_size(), usize::MAX);
+
+        // a failure halves the size of the last chunk, down to the minimum
+        feedback.set(TransferFeedback {
+            failures: 1,
+            ..Default::default()
+        });
+        let parts = chunks.chunk_parts(1).unwrap();
+        assert!(whole[1].len() > MIN_PART_SIZE);
+        assert_eq!(chunks.part_size(), (whole[1].len() / 2).max(MIN_PART_SIZE));
+        assert!(parts.len() > 1);
+        assert_eq!(parts.concat(), whole[1]);
+
+        // the part size never exceeds the peer's message limit
+        feedback.set(TransferFeedback {
+            max_message_size: Some(MIN_PART_SIZE),
+            ..Default::default()
+        });
+        for i in 1..chunks.len() {
+            let parts = chunks.chunk_parts(i).unwrap();
+            assert!(parts.iter().all(|part| part.len() <= MIN_PART_SIZE));
+            assert_eq!(parts.concat(), whole[i]);
+        }
+        assert_eq!(chunks.chunk_parts(0).unwrap(), vec![whole[0].clone()]);
+    }
+
     #[test]
     fn chunks_from_reopen() {
         let time = std::time::SystemTime::now()
@@ -0,0 +1,636 @@
+//! Provides `NodeCodec`, which transforms encoded tree nodes on their way to
+//! and from RocksDB.
+
+use super::Merk;
+use crate::{Error, Result};
+use std::borrow::Cow;
+use std::convert::TryInto;
+
+#[cfg(feature = "compression")]
+use {
+    super::INTERNAL_CF_NAME,
+    crate::limits::{MAX_KEY_LENGTH, MAX_VALUE_LENGTH},
+    rocksdb::DB,
+};
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+
+/// The current version of the node encoding, which is written as a one-byte
+/// header in front of each node's plain encoding. Stores created before the
+/// header was introduced are version 0, and can be upgraded with
+/// `Merk::migrate_encoding`.
+pub const ENCODING_VERSION: u8 = 1;
+
+/// The length of the CRC32C checksum appended to each node when checksums are
+/// enabled.
+const CHECKSUM_LENGTH: usize = 4;
+
+/// The length of the random nonce prepended to each encrypted node.
+#[cfg(feature = "encryption")]
+const NONCE_LENGTH: usize = 24;
+
+/// The key in the internal column family where a trained compression
+/// dictionary is persisted. It is kept out of aux storage so that the
+/// application's aux writes can never replace or delete it, which would make
+/// every node compressed with it unreadable.
+#[cfg(feature = "compression")]
+const COMPRESSION_DICTIONARY_KEY: &[u8] = b"compression_dictionary";
+
+/// The longest plain encoding of a node (with the version header) which is
+/// compressed: a value of `MAX_VALUE_LENGTH` bytes, its hash, and links to two
+/// children with the longest keys. Longer nodes (holding values which cannot
+/// be proven) are stored uncompressed, so the length read from a compressed
+/// node is checked against this before anything is allocated.
+#[cfg(feature = "compression")]
+const MAX_COMPRESSED_NODE_LENGTH: usize =
+    1 + 32 + 2 * (1 + 1 + MAX_KEY_LENGTH + 32 + 2) + MAX_VALUE_LENGTH;
+
+#[cfg(feature = "compression")]
+const COMPRESSION_LEVEL: i32 = 3;
+
+// tags prepended to each node when compression is enabled
+#[cfg(feature = "compression")]
+const UNCOMPRESSED: u8 = 0;
+#[cfg(feature = "compression")]
+const ZSTD: u8 = 1;
+#[cfg(feature = "compression")]
+const ZSTD_DICTIONARY: u8 = 2;
+
+/// A `NodeCodec` is applied to the encoded bytes of every tree node written to
+/// or read from the backing store. Node hashes are always computed over the
+/// plain encoding, so the codec has no effect on root hashes or proofs - two
+/// stores holding the same data with different codecs have the same root hash.
+///
+/// The default codec only adds the encoding version header.
+#[derive(Clone)]
+pub struct NodeCodec {
+    checksums: bool,
+    /// The encoding version of the store's nodes. While a migration to
+    /// `ENCODING_VERSION` is in progress, nodes with keys up to and including
+    /// `migration_cursor` have already been migrated.
+    pub(crate) version: u8,
+    pub(crate) migration_cursor: Option<Vec<u8>>,
+    #[cfg(feature = "encryption")]
+    cipher: Option<XChaCha20Poly1305>,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+}
+
+#[cfg(feature = "compression")]
+#[derive(Clone)]
+struct Compression {
+    threshold: usize,
+    dictionary: Option<Vec<u8>>,
+}
+
+impl Default for NodeCodec {
+    fn default() -> Self {
+        NodeCodec {
+            checksums: false,
+            version: ENCODING_VERSION,
+            migration_cursor: None,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            #[cfg(feature = "compression")]
+            compression: None,
+        }
+    }
+}
+
+impl NodeCodec {
+    /// Creates a `NodeCodec` which only adds the encoding version header.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Appends a CRC32C checksum to each stored node, which is verified
+    /// whenever the node is read back. Nodes which fail verification are
+    /// reported as `Error::Corruption` rather than being mis-decoded.
+    pub fn with_checksums(mut self) -> Self {
+        self.checksums = true;
+        self
+    }
+
+    /// Encrypts stored nodes with XChaCha20-Poly1305 using the given 256-bit
+    /// key. Each node is encrypted with a random nonce and authenticated
+    /// together with its key, so tampering with the stored bytes, or moving
+    /// them to another key, will be detected when the node is read back.
+    /// Prefixed stores sharing a database should use different encryption
+    /// keys, since the prefix is not authenticated.
+    ///
+    /// Keys are not encrypted since RocksDB relies on them for ordering.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(mut self, key: [u8; 32]) -> Self {
+        self.cipher = Some(XChaCha20Poly1305::new(Key::from_slice(&key)));
+        self
+    }
+
+    /// Compresses stored nodes whose encoding is at least `threshold` bytes
+    /// long with zstd. If the store has a dictionary (see
+    /// `Merk::train_compression_dictionary`), it is loaded when the store is
+    /// opened and used for all nodes written afterwards.
+    #[cfg(feature = "compression")]
+    pub fn with_compression(mut self, threshold: usize) -> Self {
+        self.compression = Some(Compression {
+            threshold,
+            dictionary: None,
+        });
+        self
+    }
+
+    /// Returns the encoding version of the node stored at `key`.
+    pub(crate) fn node_version(&self, key: &[u8]) -> u8 {
+        match &self.migration_cursor {
+            Some(cursor) if key <= cursor.as_slice() => ENCODING_VERSION,
+            _ => self.version,
+        }
+    }
+
+    /// Transforms the plain encoding of the node at `key` into the bytes to be
+    /// stored.
+    pub(crate) fn encode(&self, key: &[u8], bytes: Vec<u8>) -> Result<Vec<u8>> {
+        self.encode_version(key, self.node_version(key), bytes)
+    }
+
+    /// Transforms the plain encoding of the node at `key` into the bytes to be
+    /// stored, using the given encoding version.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    pub(crate) fn encode_version(
+        &self,
+        key: &[u8],
+        version: u8,
+        bytes: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        let bytes = if version == 0 {
+            bytes
+        } else {
+            let mut output = Vec::with_capacity(1 + bytes.len());
+            output.push(version);
+            output.extend_from_slice(&bytes);
+            output
+        };
+
+        #[cfg(feature = "compression")]
+        let bytes = match &self.compression {
+            Some(compression) => compression.compress(bytes)?,
+            None => bytes,
+        };
+
+        #[cfg(feature = "encryption")]
+        let bytes = match &self.cipher {
+            Some(cipher) => encrypt(cipher, key, bytes.as_slice())?,
+            None => bytes,
+        };
+
+        let mut bytes = bytes;
+        if self.checksums {
+            let checksum = crc32c::crc32c(&bytes);
+            bytes.extend_from_slice(&checksum.to_le_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    /// Reverses `encode`, returning a node's plain encoding from the bytes
+    /// stored at `key`. Avoids copying if the codec does not transform the
+    /// stored bytes.
+    pub(crate) fn decode<'a>(&self, key: &[u8], bytes: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let bytes = if self.checksums {
+            verify_checksum(key, bytes)?
+        } else {
+            bytes
+        };
+
+        #[cfg(feature = "encryption")]
+        let bytes = match &self.cipher {
+            Some(cipher) => Cow::Owned(decrypt(cipher, key, bytes)?),
+            None => Cow::Borrowed(bytes),
+        };
+        #[cfg(not(feature = "encryption"))]
+        let bytes = Cow::Borrowed(bytes);
+
+        #[cfg(feature = "compression")]
+        let bytes = match &self.compression {
+            Some(compression) => Cow::Owned(compression.decompress(&bytes)?),
+            None => bytes,
+        };
+
+        let version = self.node_version(key);
+        if version == 0 {
+            return Ok(bytes);
+        }
+        if bytes.first() != Some(&version) {
+            return Err(Error::Corruption { key: key.to_vec() });
+        }
+        Ok(match bytes {
+            Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[1..]),
+            Cow::Owned(mut bytes) => {
+                bytes.remove(0);
+                Cow::Owned(bytes)
+            }
+        })
+    }
+
+    /// Loads the compression dictionary persisted in `db` under `prefix`, if
+    /// compression is enabled and the store has one.
+    #[cfg(feature = "compression")]
+    pub(crate) fn load_dictionary(mut self, db: &DB, prefix: &[u8]) -> Result<Self> {
+        if let Some(compression) = self.compression.as_mut() {
+            let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+            let key = super::prefixed(prefix, COMPRESSION_DICTIONARY_KEY);
+            compression.dictionary = db.get_cf(internal_cf, key)?;
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Compression {
+    fn compress(&self, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        if bytes.len() < self.threshold || bytes.len() > MAX_COMPRESSED_NODE_LENGTH {
+            let mut output = Vec::with_capacity(1 + bytes.len());
+            output.push(UNCOMPRESSED);
+            output.extend_from_slice(&bytes);
+            return Ok(output);
+        }
+
+        let (tag, compressed) = match &self.dictionary {
+            Some(dictionary) => (
+                ZSTD_DICTIONARY,
+                zstd::bulk::Compressor::with_dictionary(COMPRESSION_LEVEL, dictionary)?
+                    .compress(&bytes)?,
+            ),
+            None => (ZSTD, zstd::bulk::compress(&bytes, COMPRESSION_LEVEL)?),
+        };
+
+        let length: u32 = bytes.len().try_into()?;
+        let mut output = Vec::with_capacity(5 + compressed.len());
+        output.push(tag);
+        output.extend_from_slice(&length.to_le_bytes());
+        output.extend_from_slice(&compressed);
+        Ok(output)
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let (tag, bytes) = bytes
+            .split_first()
+            .ok_or_else(|| Error::Compression("Stored node is empty".into()))?;
+        if *tag == UNCOMPRESSED {
+            return Ok(bytes.to_vec());
+        }
+
+        if bytes.len() < 4 {
+            return Err(Error::Compression("Compressed node is too short".into()));
+        }
+        let (length, compressed) = bytes.split_at(4);
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        if length > MAX_COMPRESSED_NODE_LENGTH {
+            return Err(Error::Compression(format!(
+                "Compressed node claims a length of {} bytes, above the maximum of {}",
+                length, MAX_COMPRESSED_NODE_LENGTH
+            )));
+        }
+
+        Ok(match (*tag, &self.dictionary) {
+            (ZSTD, _) => zstd::bulk::decompress(compressed, length)?,
+            (ZSTD_DICTIONARY, Some(dictionary)) => {
+                zstd::bulk::Decompressor::with_dictionary(dictionary)?
+                    .decompress(compressed, length)?
+            }
+            (ZSTD_DICTIONARY, None) => {
+                return Err(Error::Compression(
+                    "Node was compressed with a dictionary, but none is loaded".into(),
+                ))
+            }
+            (tag, _) => {
+                return Err(Error::Compression(format!(
+                    "Unknown compression tag {}",
+                    tag
+                )))
+            }
+        })
+    }
+}
+
+impl Merk {
+    /// Scans every node in the store, verifying that it can be decoded by the
+    /// store's codec, and returns the keys of any nodes which are corrupted,
+    /// including nodes which fail to be decrypted or decompressed.
+    ///
+    /// Corruption can only be detected reliably if the codec was configured
+    /// with `NodeCodec::with_checksums` when the nodes were written.
+    pub fn scrub(&self) -> Result<Vec<Vec<u8>>> {
+        let mut corrupted = vec![];
+
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+            if self.codec.decode(key, iter.value().unwrap()).is_err() {
+                corrupted.push(key.to_vec());
+            }
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(corrupted)
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Merk {
+    /// Trains a zstd dictionary of at most `max_size` bytes from the first
+    /// `sample_count` stored nodes, persists it in the internal column family,
+    /// and uses it to compress all nodes written from now on. Nodes written
+    /// earlier remain readable.
+    ///
+    /// Errors if compression is not enabled in the store's codec, or if the
+    /// store already has a dictionary.
+    pub fn train_compression_dictionary(
+        &mut self,
+        sample_count: usize,
+        max_size: usize,
+    ) -> Result<()> {
+        match &self.codec.compression {
+            None => {
+                return Err(Error::Compression(
+                    "Compression is not enabled for this store".into(),
+                ))
+            }
+            Some(Compression {
+                dictionary: Some(_),
+                ..
+            }) => {
+                return Err(Error::Compression(
+                    "Store already has a compression dictionary".into(),
+                ))
+            }
+            Some(_) => {}
+        }
+
+        let mut samples = Vec::with_capacity(sample_count);
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() && samples.len() < sample_count {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+            let node = self.codec.decode(key, iter.value().unwrap())?;
+            samples.push(node.into_owned());
+            iter.next();
+        }
+        drop(iter);
+
+        let dictionary = zstd::dict::from_samples(&samples, max_size)?;
+
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let key = self.prefixed(COMPRESSION_DICTIONARY_KEY);
+        self.db.put_cf(internal_cf, key, &dictionary)?;
+        self.codec.compression.as_mut().unwrap().dictionary = Some(dictionary);
+
+        Ok(())
+    }
+}
+
+/// Checks and strips the checksum appended to the node stored at `key`.
+fn verify_checksum<'a>(key: &[u8], bytes: &'a [u8]) -> Result<&'a [u8]> {
+    let corruption = || Error::Corruption { key: key.to_vec() };
+    if bytes.len() < CHECKSUM_LENGTH {
+        return Err(corruption());
+    }
+
+    let (bytes, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LENGTH);
+    if crc32c::crc32c(bytes) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(corruption());
+    }
+
+    Ok(bytes)
+}
+
+/// Encrypts the plain encoding of the node at `key`, with the key as
+/// associated data so the ciphertext only decrypts at the same key.
+#[cfg(feature = "encryption")]
+fn encrypt(cipher: &XChaCha20Poly1305, key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let nonce: [u8; NONCE_LENGTH] = rand::random();
+    let payload = Payload {
+        msg: plaintext,
+        aad: key,
+    };
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), payload)
+        .map_err(|_| Error::Encryption("Failed to encrypt node".into()))?;
+
+    let mut bytes = Vec::with_capacity(NONCE_LENGTH + ciphertext.len());
+    bytes.extend_from_slice(&nonce);
+    bytes.extend_from_slice(&ciphertext);
+    Ok(bytes)
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt(cipher: &XChaCha20Poly1305, key: &[u8], bytes: &[u8]) -> Result<Vec<u8>> {
+    if bytes.len() < NONCE_LENGTH {
+        return Err(Error::Encryption("Encrypted node is too short".into()));
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: key,
+    };
+    cipher
+        .decrypt(XNonce::from_slice(nonce), payload)
+        .map_err(|_| Error::Encryption("Failed to decrypt node".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::Merk;
+    #[cfg(feature = "compression")]
+    use crate::Op;
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn encrypt_roundtrip() {
+        let codec = NodeCodec::new().with_encryption([7; 32]);
+        let encrypted = codec.encode(&[], vec![1, 2, 3]).unwrap();
+        assert_ne!(&encrypted[NONCE_LENGTH..], &[1, 2, 3]);
+        assert_eq!(codec.decode(&[], &encrypted).unwrap().as_ref(), &[1, 2, 3]);
+
+        let other = NodeCodec::new().with_encryption([8; 32]);
+        assert!(other.decode(&[], &encrypted).is_err());
+
+        // ciphertexts only decrypt at the key they were written for
+        let encrypted = codec.encode(&[1], vec![1, 2, 3]).unwrap();
+        assert!(codec.decode(&[2], &encrypted).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn swapped_encrypted_nodes() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let codec = NodeCodec::new().with_encryption([7; 32]);
+        let mut merk =
+            Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone()).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+
+        let other = merk.db.get(seq_key(101)).unwrap().unwrap();
+        merk.db.put(seq_key(100), other).unwrap();
+        drop(merk);
+
+        let merk = Merk::open_with_codec(&path, Merk::default_db_opts(), codec).unwrap();
+        assert!(merk.get(&seq_key(100)).is_err());
+        assert_eq!(merk.get(&seq_key(102)).unwrap(), Some(put_entry_value()));
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn encrypted_merk() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let codec = NodeCodec::new().with_encryption([7; 32]);
+
+        let mut plain = TempMerk::new().unwrap();
+        plain.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+
+        let root_hash = {
+            let mut merk =
+                Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone()).unwrap();
+            merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+            merk.root_hash()
+        };
+        assert_eq!(root_hash, plain.root_hash());
+
+        let merk = Merk::open_with_codec(&path, Merk::default_db_opts(), codec).unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(500)).unwrap(), Some(put_entry_value()));
+
+        let mut iter = merk.raw_iter();
+        iter.seek(seq_key(500));
+        assert!(!iter
+            .value()
+            .unwrap()
+            .windows(put_entry_value().len())
+            .any(|w| w == put_entry_value().as_slice()));
+        drop(iter);
+
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compress_roundtrip() {
+        let codec = NodeCodec::new().with_compression(16);
+
+        let small = codec.encode(&[], vec![1, 2, 3]).unwrap();
+        assert_eq!(small, vec![UNCOMPRESSED, ENCODING_VERSION, 1, 2, 3]);
+        assert_eq!(codec.decode(&[], &small).unwrap().as_ref(), &[1, 2, 3]);
+
+        let large = codec.encode(&[], vec![123; 1_000]).unwrap();
+        assert_eq!(large[0], ZSTD);
+        assert!(large.len() < 100);
+        assert_eq!(
+            codec.decode(&[], &large).unwrap().as_ref(),
+            &[123; 1_000][..]
+        );
+
+        // the stated length is checked before it is allocated
+        let mut huge = large.clone();
+        huge[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(codec.decode(&[], &huge).is_err());
+
+        // nodes too long to be compressed are stored as they are
+        let long = vec![0; MAX_COMPRESSED_NODE_LENGTH];
+        let stored = codec.encode(&[], long.clone()).unwrap();
+        assert_eq!(stored[0], UNCOMPRESSED);
+        assert_eq!(
+            codec.decode(&[], &stored).unwrap().as_ref(),
+            long.as_slice()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn scrub_reports_undecodable_nodes() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let codec = NodeCodec::new().with_compression(16);
+        let mut merk = Merk::open_with_codec(&path, Merk::default_db_opts(), codec).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        assert!(merk.scrub().unwrap().is_empty());
+
+        for (key, tag) in [(seq_key(10), 9), (seq_key(50), ZSTD)] {
+            let mut bytes = merk.db.get(&key).unwrap().unwrap();
+            bytes[0] = tag;
+            bytes.truncate(8);
+            merk.db.put(&key, bytes).unwrap();
+        }
+
+        assert_eq!(merk.scrub().unwrap(), vec![seq_key(10), seq_key(50)]);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compressed_merk_with_dictionary() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let codec = NodeCodec::new().with_compression(32);
+        let value = |i: u64| format!("{{\"balance\":{},\"nonce\":{}}}", i, i * 7).into_bytes();
+        let batch = |range: std::ops::Range<u64>| -> Vec<_> {
+            range.map(|i| (seq_key(i), Op::Put(value(i)))).collect()
+        };
+
+        let mut plain = TempMerk::new().unwrap();
+        plain.apply(&batch(0..1_000), &[]).unwrap();
+        plain.apply(&batch(1_000..2_000), &[]).unwrap();
+
+        {
+            let mut merk =
+                Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone()).unwrap();
+            merk.apply(&batch(0..1_000), &[]).unwrap();
+            merk.train_compression_dictionary(1_000, 4_096).unwrap();
+            assert!(merk.train_compression_dictionary(1_000, 4_096).is_err());
+            merk.apply(&batch(1_000..2_000), &[]).unwrap();
+            assert_eq!(merk.root_hash(), plain.root_hash());
+        }
+
+        let merk = Merk::open_with_codec(&path, Merk::default_db_opts(), codec).unwrap();
+        assert_eq!(merk.root_hash(), plain.root_hash());
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), Some(value(10)));
+        assert_eq!(merk.get(&seq_key(1_500)).unwrap(), Some(value(1_500)));
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn checksum_roundtrip() {
+        let codec = NodeCodec::new().with_checksums();
+        let mut stored = codec.encode(&[9], vec![1, 2, 3]).unwrap();
+        assert_eq!(stored.len(), 1 + 3 + CHECKSUM_LENGTH);
+        assert_eq!(codec.decode(&[9], &stored).unwrap().as_ref(), &[1, 2, 3]);
+
+        stored[1] ^= 1;
+        match codec.decode(&[9], &stored) {
+            Err(Error::Corruption { key }) => assert_eq!(key, vec![9]),
+            _ => panic!("expected corruption error"),
+        }
+        assert!(codec.decode(&[9], &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn scrub_finds_corrupted_nodes() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let codec = NodeCodec::new().with_checksums();
+        let mut merk = Merk::open_with_codec(&path, Merk::default_db_opts(), codec).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        assert!(merk.scrub().unwrap().is_empty());
+
+        let mut bytes = merk.db.get(seq_key(50)).unwrap().unwrap();
+        bytes[0] ^= 1;
+        merk.db.put(seq_key(50), bytes).unwrap();
+
+        assert_eq!(merk.scrub().unwrap(), vec![seq_key(50)]);
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+//! Provides `CommitRecord`, which is written in the same batch as every commit
+//! so that a torn root pointer can be detected and repaired when the store is
+//! opened.
+//!
+//! A commit's nodes, root pointer and record are written in a single RocksDB
+//! write batch, which RocksDB applies atomically (including on recovery from
+//! its write-ahead log), so a crash can't leave only part of a commit behind.
+//! The record guards against the root pointer being written on its own or
+//! lost, e.g. by a partial restore or by tools writing to the database
+//! directly, by checking the root it points to against the recorded hash.
+
+use super::{prefixed, Merk, MerkSource, NodeCodec, INTERNAL_CF_NAME, ROOT_KEY_KEY};
+use crate::tree::{Fetch, Hash, Tree};
+use crate::{Error, Result};
+use ed::{Decode, Encode};
+use rocksdb::{WriteBatch, DB};
+
+const COMMIT_RECORD_KEY: &[u8] = b"commit";
+
+/// Describes the most recent commit to a store.
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommitRecord {
+    /// The root hash of the tree as of the commit.
+    pub root_hash: Hash,
+    /// The height of the tree as of the commit.
+    pub height: u8,
+    /// The key of the root node as of the commit.
+    pub root_key: Vec<u8>,
+}
+
+impl CommitRecord {
+    pub(crate) fn new(tree: &Tree) -> Self {
+        CommitRecord {
+            root_hash: tree.hash(),
+            height: tree.height(),
+            root_key: tree.key().to_vec(),
+        }
+    }
+
+    /// Returns the internal column family entry which stores the record, or
+    /// deletes the stored record if the tree is empty.
+    pub(crate) fn entry(
+        maybe_record: Option<&CommitRecord>,
+        prefix: &[u8],
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let key = prefixed(prefix, COMMIT_RECORD_KEY);
+        let maybe_value = maybe_record.map(Encode::encode).transpose()?;
+        Ok((key, maybe_value))
+    }
+
+    /// Adds the record to `batch`, or deletes the stored record if the tree is
+    /// empty.
+    pub(crate) fn write(
+        maybe_record: Option<&CommitRecord>,
+        db: &DB,
+        prefix: &[u8],
+        batch: &mut WriteBatch,
+    ) -> Result<()> {
+        let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        match CommitRecord::entry(maybe_record, prefix)? {
+            (key, Some(value)) => batch.put_cf(internal_cf, key, value),
+            (key, None) => batch.delete_cf(internal_cf, key),
+        }
+        Ok(())
+    }
+
+    fn load(db: &DB, prefix: &[u8]) -> Result<Option<CommitRecord>> {
+        let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        db.get_pinned_cf(internal_cf, prefixed(prefix, COMMIT_RECORD_KEY))?
+            .map(|bytes| CommitRecord::decode(bytes.as_ref()).map_err(Into::into))
+            .transpose()
+    }
+}
+
+/// Loads the root node, checking it against the commit record.
+///
+/// If the root pointer does not match the record (e.g. because the pointer
+/// was lost or rewritten without the rest of its commit), the pointer is reset
+/// to the root described by the record, as long as that node is intact. Stores
+/// which have no record yet (created before records existed, or by a
+/// `Restorer`) are given one.
+pub(crate) fn load_root_with_recovery(
+    db: &DB,
+    prefix: &[u8],
+    codec: &NodeCodec,
+) -> Result<Option<Tree>> {
+    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+    let source = MerkSource {
+        db,
+        codec,
+        prefix,
+        meter: None,
+        counters: None,
+    };
+
+    let maybe_record = CommitRecord::load(db, prefix)?;
+    let maybe_root_key = db.get_cf(internal_cf, prefixed(prefix, ROOT_KEY_KEY))?;
+    let maybe_root = match &maybe_root_key {
+        Some(key) => source.fetch_by_key(key)?,
+        None => None,
+    };
+
+    let record = match (maybe_record, maybe_root) {
+        (None, None) if maybe_root_key.is_none() => return Ok(None),
+        (None, None) => {
+            return Err(Error::TornCommit(
+                "Root node is missing and there is no commit record".into(),
+            ))
+        }
+        (None, Some(root)) => {
+            let mut batch = WriteBatch::default();
+            CommitRecord::write(Some(&CommitRecord::new(&root)), db, prefix, &mut batch)?;
+            db.write(batch)?;
+            return Ok(Some(root));
+        }
+        (Some(record), Some(root))
+            if root.hash() == record.root_hash && root.height() == record.height =>
+        {
+            return Ok(Some(root));
+        }
+        (Some(record), _) => record,
+    };
+
+    match source.fetch_by_key(&record.root_key)? {
+        Some(root) if root.hash() == record.root_hash => {
+            db.put_cf(
+                internal_cf,
+                prefixed(prefix, ROOT_KEY_KEY),
+                &record.root_key,
+            )?;
+            Ok(Some(root))
+        }
+        _ => Err(Error::TornCommit(format!(
+            "Root node does not match commit record (expected root hash {:?})",
+            record.root_hash
+        ))),
+    }
+}
+
+impl Merk {
+    /// Returns the record of the most recent commit, or `None` if the tree is
+    /// empty.
+    pub fn last_commit(&self) -> Result<Option<CommitRecord>> {
+        CommitRecord::load(&self.db, &self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn record_roundtrip() {
+        let record = CommitRecord {
+            root_hash: [3; 32],
+            height: 7,
+            root_key: vec![1, 2, 3],
+        };
+        let bytes = record.encode().unwrap();
+        assert_eq!(CommitRecord::decode(bytes.as_slice()).unwrap(), record);
+    }
+
+    #[test]
+    fn repair_torn_root_pointer() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let record = merk.last_commit().unwrap().unwrap();
+        assert_eq!(record.root_hash, merk.root_hash());
+        let root_hash = merk.root_hash();
+
+        // point the root pointer at some other node
+        let internal_cf = merk.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        merk.db
+            .put_cf(internal_cf, ROOT_KEY_KEY, seq_key(10))
+            .unwrap();
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(50)).unwrap(), Some(put_entry_value()));
+
+        // lose the root node itself
+        merk.db.delete(&record.root_key).unwrap();
+        drop(merk);
+        assert!(Merk::open(&path).is_err());
+
+        rocksdb::DB::destroy(&Merk::default_db_opts(), &path).unwrap();
+    }
+
+    #[test]
+    fn record_added_to_existing_store() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let mut batch = WriteBatch::default();
+        CommitRecord::write(None, &merk.db, &[], &mut batch).unwrap();
+        merk.db.write(batch).unwrap();
+        assert_eq!(merk.last_commit().unwrap(), None);
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        let record = merk.last_commit().unwrap().unwrap();
+        assert_eq!(record.root_hash, merk.root_hash());
+        merk.destroy().unwrap();
+    }
+}
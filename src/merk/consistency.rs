@@ -0,0 +1,160 @@
+//! Provides `Merk::check_links`, which detects links whose cached hash or
+//! child heights don't match the child stored on disk (e.g. because a bug
+//! rewrote a child without rewriting its parent).
+
+use super::Merk;
+use crate::tree::{Hash, Link, Tree};
+use crate::Result;
+use ed::Decode;
+
+/// A link from a stored node which doesn't match its child.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkMismatch {
+    /// The key of the node holding the link.
+    pub parent: Vec<u8>,
+    /// The key of the linked child.
+    pub child: Vec<u8>,
+    /// Whether the link is the parent's left link.
+    pub left: bool,
+    pub kind: LinkMismatchKind,
+}
+
+/// How a link differs from its child.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkMismatchKind {
+    /// The child is not in the store.
+    Missing,
+    /// The hash cached in the link is not the hash of the stored child.
+    Hash { cached: Hash, actual: Hash },
+    /// The child heights cached in the link are not those of the stored
+    /// child.
+    Heights { cached: (u8, u8), actual: (u8, u8) },
+}
+
+impl Merk {
+    /// Scans every node in the store and checks that the hash and child
+    /// heights cached in each of its links match the stored child, returning
+    /// all mismatches found. Nodes which can't be decoded are skipped (see
+    /// `Merk::scrub`).
+    ///
+    /// This reads every node and every child, so is meant for offline checks
+    /// rather than running alongside writes.
+    pub fn check_links(&self) -> Result<Vec<LinkMismatch>> {
+        let mut mismatches = vec![];
+
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+
+            let maybe_node = self
+                .codec
+                .decode(key, iter.value().unwrap())
+                .ok()
+                .and_then(|bytes| <Tree as Decode>::decode(bytes.as_ref()).ok());
+
+            if let Some(node) = maybe_node {
+                for left in [true, false] {
+                    if let Some(link) = node.link(left) {
+                        let kind = match self.fetch_node(link.key()) {
+                            Ok(Some(child)) => check_link(link, &child),
+                            Ok(None) => Some(LinkMismatchKind::Missing),
+                            // children which can't be decoded are reported by
+                            // `scrub`
+                            Err(_) => None,
+                        };
+                        if let Some(kind) = kind {
+                            mismatches.push(LinkMismatch {
+                                parent: key.to_vec(),
+                                child: link.key().to_vec(),
+                                left,
+                                kind,
+                            });
+                        }
+                    }
+                }
+            }
+
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(mismatches)
+    }
+}
+
+/// Compares a link's cached hash and heights to its stored child.
+fn check_link(link: &Link, child: &Tree) -> Option<LinkMismatchKind> {
+    let cached = *link.hash();
+    let actual = child.hash();
+    if cached != actual {
+        return Some(LinkMismatchKind::Hash { cached, actual });
+    }
+
+    let cached = link.child_heights();
+    let actual = child.child_heights();
+    if cached != actual {
+        return Some(LinkMismatchKind::Heights { cached, actual });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    /// Rewrites the stored node at `key` after applying `f` to it.
+    fn rewrite_node(merk: &Merk, key: &[u8], f: impl FnOnce(&mut Tree)) {
+        let mut node = merk.fetch_node(key).unwrap().unwrap();
+        f(&mut node);
+        let bytes = merk.codec.encode(key, node.encode()).unwrap();
+        merk.db.put(merk.prefixed(key), bytes).unwrap();
+    }
+
+    #[test]
+    fn detect_stale_links() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        assert!(merk.check_links().unwrap().is_empty());
+
+        // a leaf whose parent still links to its old hash
+        let root_key = merk.use_tree(|maybe_tree| maybe_tree.unwrap().key().to_vec());
+        let mut parent = root_key.clone();
+        let mut child = merk.fetch_node(&parent).unwrap().unwrap();
+        while let Some(link) = child.link(true) {
+            let key = link.key().to_vec();
+            parent = child.key().to_vec();
+            child = merk.fetch_node(&key).unwrap().unwrap();
+        }
+        let child = child.key().to_vec();
+        rewrite_node(&merk, &child, |node| {
+            *node = Tree::from_fields(node.key().to_vec(), vec![], [1; 32], None, None);
+        });
+
+        // a link caching the wrong heights
+        rewrite_node(&merk, &root_key, |node| {
+            *node.link_mut(false).unwrap().child_heights_mut() = (9, 9);
+        });
+
+        let mismatches = merk.check_links().unwrap();
+        assert_eq!(mismatches.len(), 2);
+        assert!(mismatches.iter().any(|mismatch| mismatch.parent == parent
+            && mismatch.child == child
+            && mismatch.left
+            && matches!(mismatch.kind, LinkMismatchKind::Hash { .. })));
+        assert!(mismatches.iter().any(|mismatch| mismatch.parent == root_key
+            && !mismatch.left
+            && matches!(
+                mismatch.kind,
+                LinkMismatchKind::Heights { cached: (9, 9), .. }
+            )));
+
+        merk.db.delete(merk.prefixed(&child)).unwrap();
+        let mismatches = merk.check_links().unwrap();
+        assert!(mismatches
+            .iter()
+            .any(|mismatch| mismatch.child == child && mismatch.kind == LinkMismatchKind::Missing));
+    }
+}
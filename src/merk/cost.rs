@@ -0,0 +1,244 @@
+//! Metering of the node accesses made by `apply`, so runtimes can charge gas
+//! for state access.
+
+use std::sync::{Arc, Mutex};
+
+use super::{CommitOptions, Merk, NodeCodec};
+use crate::tree::{Batch, Fetch, Op, RefWalker, HASH_LENGTH};
+use crate::Result;
+
+/// The length of the input hashed to compute a node hash: a tag byte followed
+/// by the KV hash and the hashes of both children.
+const NODE_HASH_INPUT_LENGTH: usize = 1 + 3 * HASH_LENGTH;
+
+/// A kind of node access made while applying a batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeAccess {
+    /// A node on the path to one of the batch's keys was read, whether it was
+    /// fetched from the database or already held in memory. The length is the
+    /// size of its encoding (see `Tree::encode`), which does not depend on the
+    /// store's codec.
+    Fetch,
+    /// A node was written to the database. The length is the size of its
+    /// encoding as for `Fetch`, or 0 if the node was deleted.
+    Write,
+    /// A hash was computed for a node. The length is the size of the hashed
+    /// input: the key and value for a KV hash, or the child hashes for a node
+    /// hash.
+    Hash,
+}
+
+/// Prices the node accesses made by `Merk::apply_with_cost`.
+///
+/// Closures taking `(NodeAccess, key, length)` implement this trait.
+pub trait CostModel {
+    /// Returns the cost of a single access to the node with the given key.
+    fn cost(&mut self, access: NodeAccess, key: &[u8], len: usize) -> u64;
+}
+
+impl<F> CostModel for F
+where
+    F: FnMut(NodeAccess, &[u8], usize) -> u64,
+{
+    fn cost(&mut self, access: NodeAccess, key: &[u8], len: usize) -> u64 {
+        self(access, key, len)
+    }
+}
+
+/// Records the node accesses made during a single apply. Fetches may be
+/// recorded from several threads when a batch is applied in parallel.
+#[derive(Default)]
+pub(crate) struct Meter {
+    accesses: Mutex<Vec<(NodeAccess, Vec<u8>, usize)>>,
+}
+
+impl Meter {
+    pub(crate) fn record(&self, access: NodeAccess, key: &[u8], len: usize) {
+        self.accesses
+            .lock()
+            .unwrap()
+            .push((access, key.to_vec(), len));
+    }
+
+    /// Returns the number of nodes fetched and the total size of their stored
+    /// encodings.
+    pub(crate) fn fetched(&self) -> (u64, u64) {
+        self.accesses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(access, ..)| *access == NodeAccess::Fetch)
+            .fold((0, 0), |(count, bytes), (_, _, len)| {
+                (count + 1, bytes + *len as u64)
+            })
+    }
+
+    /// Records a read of each node on the paths to the keys in `batch` (which
+    /// must be sorted), loading pruned nodes as needed, so the nodes recorded
+    /// only depend on the tree and not on which nodes were held in memory.
+    pub(crate) fn record_paths<S>(&self, mut walker: RefWalker<S>, batch: &Batch) -> Result<()>
+    where
+        S: Fetch + Sized + Clone + Send,
+    {
+        let tree = walker.tree();
+        self.record(NodeAccess::Fetch, tree.key(), tree.encoding_length());
+        let (left_end, right_start) =
+            match batch.binary_search_by(|(key, _)| key.as_slice().cmp(tree.key())) {
+                Ok(index) => (index, index + 1),
+                Err(index) => (index, index),
+            };
+
+        let (left, right) = (&batch[..left_end], &batch[right_start..]);
+        if !left.is_empty() {
+            if let Some(child) = walker.walk(true)? {
+                self.record_paths(child, left)?;
+            }
+        }
+        if !right.is_empty() {
+            if let Some(child) = walker.walk(false)? {
+                self.record_paths(child, right)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the KV hashes computed for the puts in `batch`, and the writes
+    /// and node hashes for the nodes written by the commit, which are given
+    /// as encoded by `codec`.
+    pub(crate) fn record_commit(
+        &self,
+        batch: &Batch,
+        nodes: &[(Vec<u8>, Option<Vec<u8>>)],
+        codec: &NodeCodec,
+    ) -> Result<()> {
+        let mut accesses = self.accesses.lock().unwrap();
+        for (key, op) in batch {
+            if let Op::Put(value) = op {
+                // tag byte and two length prefixes
+                let len = 9 + key.len() + value.len();
+                accesses.push((NodeAccess::Hash, key.clone(), len));
+            }
+        }
+        for (key, maybe_value) in nodes {
+            let len = match maybe_value {
+                Some(value) => codec.decode(key, value)?.len(),
+                None => 0,
+            };
+            accesses.push((NodeAccess::Write, key.clone(), len));
+            if maybe_value.is_some() {
+                accesses.push((NodeAccess::Hash, key.clone(), NODE_HASH_INPUT_LENGTH));
+            }
+        }
+        Ok(())
+    }
+
+    /// Prices the recorded accesses with `model`, in a fixed order so the
+    /// calls made to the model do not depend on thread scheduling.
+    fn total(&self, model: &mut impl CostModel) -> u64 {
+        let mut accesses = self.accesses.lock().unwrap();
+        accesses.sort();
+        accesses
+            .iter()
+            .map(|(access, key, len)| model.cost(*access, key, *len))
+            .fold(0, u64::saturating_add)
+    }
+}
+
+impl Merk {
+    /// Applies a batch as in `apply`, calling `model` for every node read,
+    /// written and hashed, and returning the total cost.
+    ///
+    /// The accesses only depend on the tree and the batch: every node on the
+    /// paths to the batch's keys is charged as read, even if it was already
+    /// held in memory, and lengths are those of the nodes' plain encodings
+    /// rather than of their compressed or encrypted storage. Stores with the
+    /// same root hash therefore charge the same cost for the same batch.
+    pub fn apply_with_cost(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+        model: &mut impl CostModel,
+    ) -> Result<u64> {
+        self.limits.check_batch(batch)?;
+        let meter = Arc::new(Meter::default());
+        let options = CommitOptions {
+            meter: Some(meter.clone()),
+            ..Default::default()
+        };
+        unsafe { self.apply_sorted(batch, aux, options)? };
+        Ok(meter.total(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn apply_with_cost() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        let mut counts = [0u64; 3];
+        let mut model = |access: NodeAccess, _: &[u8], _: usize| {
+            counts[access as usize] += 1;
+            1
+        };
+        let cost = merk
+            .apply_with_cost(&make_batch_seq(0..100), &[], &mut model)
+            .unwrap();
+        assert_eq!(counts, [0, 100, 200]);
+        assert_eq!(cost, 300);
+
+        // the nodes on the path are read once the store is reopened
+        drop(merk);
+        let mut merk = Merk::open(&path).unwrap();
+        let mut fetches = 0;
+        let mut model = |access: NodeAccess, _: &[u8], len: usize| {
+            if access == NodeAccess::Fetch {
+                fetches += 1;
+            }
+            len as u64
+        };
+        let cost = merk
+            .apply_with_cost(&make_del_batch_seq(0..1), &[], &mut model)
+            .unwrap();
+        assert!(fetches > 0);
+        assert!(cost > 0);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn deterministic_cost() {
+        fn model(access: NodeAccess, key: &[u8], len: usize) -> u64 {
+            (access as u64 + 1) * (key.len() + len) as u64
+        }
+
+        let path = std::thread::current().name().unwrap().to_owned();
+        #[allow(unused_mut)]
+        let mut codecs = vec![NodeCodec::new(), NodeCodec::new().with_checksums()];
+        #[cfg(feature = "compression")]
+        codecs.push(NodeCodec::new().with_compression(0));
+
+        let mut batch = make_del_batch_seq(0..100);
+        batch.extend(make_batch_seq(900..1_100));
+        let mut costs = vec![];
+        for (i, codec) in codecs.into_iter().enumerate() {
+            // a warm store holds every node in memory after applying, a cold
+            // one only the root after being reopened
+            for warm in [true, false] {
+                let path = format!("{}-{}-{}", path, i, warm);
+                let open = || Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone());
+                let mut merk = open().unwrap();
+                merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+                if !warm {
+                    drop(merk);
+                    merk = open().unwrap();
+                }
+                costs.push(merk.apply_with_cost(&batch, &[], &mut model).unwrap());
+                merk.destroy().unwrap();
+            }
+        }
+        assert!(costs.iter().all(|cost| *cost == costs[0]));
+    }
+}
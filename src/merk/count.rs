@@ -0,0 +1,180 @@
+//! Tracks the number of keys in the store, recorded atomically along with each
+//! commit so it can be read without traversing the tree.
+
+use super::{prefix_read_opts, prefixed, Merk, INTERNAL_CF_NAME};
+use crate::{Error, Result};
+use rocksdb::{WriteBatch, DB};
+use std::convert::TryInto;
+
+const KEY_COUNT_KEY: &[u8] = b"keycount";
+
+/// Returns the internal column family entry which records `count`.
+pub(crate) fn key_count_entry(count: u64, prefix: &[u8]) -> (Vec<u8>, Option<Vec<u8>>) {
+    (
+        prefixed(prefix, KEY_COUNT_KEY),
+        Some(count.to_be_bytes().to_vec()),
+    )
+}
+
+/// Reads the key count recorded for the store under `prefix`, if any. Stores
+/// written before key counts were recorded have none until
+/// `migration::load_key_count` backfills it.
+pub(crate) fn read_key_count(db: &DB, prefix: &[u8]) -> Result<Option<u64>> {
+    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+    db.get_cf(internal_cf, prefixed(prefix, KEY_COUNT_KEY))?
+        .map(|bytes| {
+            let bytes = bytes.as_slice().try_into().map_err(|_| Error::Corruption {
+                key: KEY_COUNT_KEY.to_vec(),
+            })?;
+            Ok(u64::from_be_bytes(bytes))
+        })
+        .transpose()
+}
+
+/// Counts the nodes stored under `prefix`, reading every key.
+pub(crate) fn count_nodes(db: &DB, prefix: &[u8]) -> Result<u64> {
+    let mut count = 0;
+    let mut iter = db.raw_iterator_opt(prefix_read_opts(prefix));
+    iter.seek_to_first();
+    while iter.valid() {
+        count += 1;
+        iter.next();
+    }
+    iter.status()?;
+    Ok(count)
+}
+
+impl Merk {
+    /// Returns the height of the tree (the number of levels), or 0 if the
+    /// store is empty. This is read from the root node, which is always held
+    /// in memory. Not to be confused with the height recorded by
+    /// `apply_at_height` (see `last_height`).
+    pub fn height(&self) -> u8 {
+        self.use_tree(|maybe_tree| maybe_tree.map_or(0, |tree| tree.height()))
+    }
+
+    /// Returns the number of keys in the store, as recorded by the last
+    /// commit.
+    pub fn len(&self) -> u64 {
+        self.key_count
+    }
+
+    /// Returns true if the store has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.use_tree(|maybe_tree| maybe_tree.is_none())
+    }
+
+    /// Records `count` as the number of keys in `batch`, for writes which
+    /// replace the tree's nodes without applying a batch (e.g. restores),
+    /// whose writers know how many nodes they wrote.
+    pub(crate) fn set_key_count(&mut self, count: u64, batch: &mut WriteBatch) {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let (key, value) = key_count_entry(count, &self.prefix);
+        batch.put_cf(internal_cf, key, value.unwrap());
+        self.key_count = count;
+    }
+
+    /// Returns the number of keys after writing the prefixed `nodes`, where
+    /// `None` deletes a node, for commits which do not know which of their
+    /// writes are inserts. Only the written keys are looked up, in the writes
+    /// staged by a block or import and then in the database.
+    pub(crate) fn count_after(&self, nodes: &[(Vec<u8>, Option<Vec<u8>>)]) -> Result<u64> {
+        let staged = self
+            .block
+            .as_ref()
+            .map(|block| &block.writes)
+            .or_else(|| self.import.as_ref().map(|import| &import.writes));
+        let mut count = self.key_count;
+        for (key, maybe_value) in nodes {
+            let stored = match staged.and_then(|writes| writes.nodes.get(key)) {
+                Some(staged) => staged.is_some(),
+                None => self.db.get_pinned(key)?.is_some(),
+            };
+            match (stored, maybe_value.is_some()) {
+                (false, true) => count += 1,
+                (true, false) => count = count.saturating_sub(1),
+                _ => {}
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::tree::Walker;
+
+    #[test]
+    fn key_count() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        assert_eq!((merk.len(), merk.height()), (0, 0));
+        assert!(merk.is_empty());
+
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        assert_eq!((merk.len(), merk.height()), (1_000, 10));
+        assert!(!merk.is_empty());
+
+        // updates and deletes of missing keys don't change the count
+        let mut batch = make_batch_seq(500..1_500);
+        batch.extend(make_del_batch_seq(2_000..2_010));
+        merk.apply(&batch, &[]).unwrap();
+        assert_eq!(merk.len(), 1_500);
+        merk.apply(&make_del_batch_seq(0..100), &[]).unwrap();
+        assert_eq!(merk.len(), 1_400);
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.len(), 1_400);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn key_count_of_commits() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        merk.begin_block().unwrap();
+        merk.apply(&make_del_batch_seq(0..5), &[]).unwrap();
+        assert_eq!(merk.len(), 5);
+
+        // changes made to the tree in memory are not known to be inserts, so
+        // their keys are looked up in the block's writes and the database
+        let source = merk.source();
+        let walker = merk
+            .tree
+            .take()
+            .map(|tree| Walker::new(tree, source.clone()));
+        let (tree, deleted_keys) =
+            Walker::apply_to(walker, &make_batch_seq(3..15), source).unwrap();
+        merk.tree.set(tree);
+        merk.commit(deleted_keys, &[]).unwrap();
+        assert_eq!(merk.len(), 12);
+        merk.commit_block().unwrap();
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.len(), 12);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn backfill_key_count() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        // stores written before key counts were recorded have no count
+        let internal_cf = merk.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        merk.db
+            .delete_cf(internal_cf, prefixed(&[], KEY_COUNT_KEY))
+            .unwrap();
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.len(), 100);
+        assert_eq!(read_key_count(&merk.db, &[]).unwrap(), Some(100));
+        merk.destroy().unwrap();
+    }
+}
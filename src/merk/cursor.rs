@@ -0,0 +1,172 @@
+//! Provides `Cursor`, a stateful position in a store's keys which can be moved
+//! in either direction and used to prove iteration page by page.
+
+use super::Merk;
+use crate::proofs::query::{QueryItem, KEY_UPPER_BOUND};
+use crate::tree::Tree;
+use crate::Result;
+
+/// A position in the keys of a `Merk`, created with `Merk::cursor`.
+///
+/// The cursor reads the stored nodes, so writes made during a block or an
+/// import which have not been written to the database yet are not visible.
+pub struct Cursor<'a> {
+    merk: &'a Merk,
+    iter: rocksdb::DBRawIterator<'a>,
+    /// The lower bound of the range proven by the next call to `prove_page`.
+    page_start: Vec<u8>,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(merk: &'a Merk) -> Self {
+        let mut iter = merk.raw_iter();
+        iter.seek(merk.prefixed(&[]));
+        Cursor {
+            merk,
+            iter,
+            page_start: vec![],
+        }
+    }
+
+    /// Moves to the first key which is greater than or equal to `key`.
+    pub fn seek(&mut self, key: &[u8]) {
+        self.iter.seek(self.merk.prefixed(key));
+        self.page_start = key.to_vec();
+    }
+
+    /// Moves to the last key which is less than or equal to `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) {
+        self.iter.seek_for_prev(self.merk.prefixed(key));
+        self.page_start = self.current_key().unwrap_or_default();
+    }
+
+    /// Moves to the first key in the store.
+    pub fn seek_to_first(&mut self) {
+        self.seek(&[]);
+    }
+
+    /// Returns the entry at the cursor, or `None` if the cursor has moved past
+    /// either end of the store.
+    pub fn current(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if !self.iter.valid() {
+            self.iter.status()?;
+            return Ok(None);
+        }
+
+        let key = &self.iter.key().unwrap()[self.merk.prefix.len()..];
+        let bytes = self.merk.codec.decode(key, self.iter.value().unwrap())?;
+        let node = Tree::decode(key.to_vec(), &bytes)?;
+        Ok(Some((key.to_vec(), node.value().to_vec())))
+    }
+
+    /// Moves to the next key and returns its entry, or `None` if there is no
+    /// next key.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if self.iter.valid() {
+            self.iter.next();
+        }
+        self.current()
+    }
+
+    /// Moves to the previous key and returns its entry, or `None` if there is
+    /// no previous key.
+    pub fn prev(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if self.iter.valid() {
+            self.iter.prev();
+        }
+        self.current()
+    }
+
+    /// Reads up to `limit` entries starting at the cursor, moving the cursor
+    /// to the entry after them, and returns them along with a proof of the
+    /// range they were read from.
+    ///
+    /// The proven range starts where the previous page ended (or at the key
+    /// passed to the last seek) and ends before the cursor's new position, or
+    /// at the end of the store, so a client can check that consecutive pages
+    /// skip no keys by verifying each proof and reading its range from the
+    /// resulting map.
+    pub fn prove_page(&mut self, limit: usize) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Vec<u8>)> {
+        let mut entries = Vec::with_capacity(limit);
+        while entries.len() < limit {
+            match self.current()? {
+                Some(entry) => entries.push(entry),
+                None => break,
+            }
+            self.iter.next();
+        }
+
+        let page_end = self
+            .current_key()
+            .unwrap_or_else(|| KEY_UPPER_BOUND.to_vec());
+        let start = std::mem::replace(&mut self.page_start, page_end.clone());
+        let proof = self
+            .merk
+            .prove_unchecked(vec![QueryItem::Range(start..page_end)])?;
+
+        Ok((entries, proof))
+    }
+
+    fn current_key(&self) -> Option<Vec<u8>> {
+        self.iter
+            .key()
+            .map(|key| key[self.merk.prefix.len()..].to_vec())
+    }
+}
+
+impl Merk {
+    /// Creates a cursor positioned at the first key in the store.
+    pub fn cursor(&self) -> Cursor {
+        Cursor::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn cursor_steps_and_pages() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..25), &[]).unwrap();
+
+        let mut cursor = merk.cursor();
+        assert_eq!(cursor.current().unwrap().unwrap().0, seq_key(0));
+        assert_eq!(cursor.prev().unwrap(), None);
+
+        cursor.seek(&seq_key(10));
+        assert_eq!(cursor.next().unwrap().unwrap().0, seq_key(11));
+        assert_eq!(cursor.prev().unwrap().unwrap().0, seq_key(10));
+        cursor.seek_for_prev(&[255]);
+        assert_eq!(cursor.current().unwrap().unwrap().0, seq_key(24));
+        assert_eq!(cursor.next().unwrap(), None);
+
+        cursor.seek(&seq_key(3));
+        let mut start = seq_key(3);
+        let mut seen = 0;
+        loop {
+            let (entries, proof) = cursor.prove_page(10).unwrap();
+            let map = crate::verify(&proof, merk.root_hash()).unwrap();
+            let end = if entries.len() == 10 {
+                seq_key(3 + seen as u64 + 10)
+            } else {
+                KEY_UPPER_BOUND.to_vec()
+            };
+            let proven: Vec<_> = map
+                .range(start.as_slice()..end.as_slice())
+                .map(|entry| entry.unwrap().0.to_vec())
+                .collect();
+            let keys: Vec<_> = entries.into_iter().map(|(key, _)| key).collect();
+            assert_eq!(proven, keys);
+
+            seen += keys.len();
+            if end == KEY_UPPER_BOUND {
+                break;
+            }
+            start = end;
+        }
+        assert_eq!(seen, 22);
+    }
+}
@@ -0,0 +1,179 @@
+//! Provides plain dumps of a store's key/value pairs, for moving data in and
+//! out of merk without proofs (e.g. for analytics pipelines or migrations).
+
+use super::Merk;
+use crate::tree::{Op, Tree};
+use crate::{Error, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// The number of entries applied in each batch by `Merk::import_kv`.
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+/// The header line of CSV dumps.
+const CSV_HEADER: &str = "key,value";
+
+/// The format of a key/value dump. In both formats each entry is written on
+/// its own line, with its key and value encoded as hex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KvFormat {
+    /// One JSON object per line, e.g. `{"key":"0102","value":"0a0b"}`.
+    JsonLines,
+    /// A `key,value` header line followed by one `key,value` line per entry.
+    Csv,
+}
+
+impl KvFormat {
+    fn write_entry<W: Write>(self, writer: &mut W, key: &[u8], value: &[u8]) -> Result<()> {
+        match self {
+            KvFormat::JsonLines => writeln!(
+                writer,
+                "{{\"key\":\"{}\",\"value\":\"{}\"}}",
+                hex::encode(key),
+                hex::encode(value)
+            )?,
+            KvFormat::Csv => writeln!(writer, "{},{}", hex::encode(key), hex::encode(value))?,
+        }
+        Ok(())
+    }
+
+    fn parse_entry(self, line: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+        let invalid = || Error::Key(format!("Invalid {:?} entry: {}", self, line));
+
+        let (key, value) = match self {
+            KvFormat::JsonLines => {
+                let fields = line
+                    .trim()
+                    .strip_prefix('{')
+                    .and_then(|line| line.strip_suffix('}'))
+                    .ok_or_else(invalid)?;
+                let (key, value) = fields.split_once(',').ok_or_else(invalid)?;
+                let key = json_field(key, "key").ok_or_else(invalid)?;
+                let value = json_field(value, "value").ok_or_else(invalid)?;
+                (key, value)
+            }
+            KvFormat::Csv => line.trim().split_once(',').ok_or_else(invalid)?,
+        };
+
+        let key = hex::decode(key.trim()).map_err(|_| invalid())?;
+        let value = hex::decode(value.trim()).map_err(|_| invalid())?;
+        Ok((key, value))
+    }
+}
+
+/// Returns the string value of a `"name":"value"` JSON field.
+fn json_field<'a>(field: &'a str, name: &str) -> Option<&'a str> {
+    let (field_name, value) = field.split_once(':')?;
+    if field_name.trim() != format!("\"{}\"", name) {
+        return None;
+    }
+    value.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+impl Merk {
+    /// Writes every key/value pair in the store to `writer` in key order,
+    /// returning the number of entries written. The dump does not include any
+    /// proof data, so it can not be verified against the root hash.
+    pub fn export_kv<W: Write>(&self, mut writer: W, format: KvFormat) -> Result<usize> {
+        if format == KvFormat::Csv {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+
+        let mut count = 0;
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+        let mut node = Tree::new(vec![], vec![])?;
+        while iter.valid() {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+            let bytes = self.codec.decode(key, iter.value().unwrap())?;
+            node.decode_into(vec![], &bytes)?;
+            format.write_entry(&mut writer, key, node.value())?;
+
+            count += 1;
+            iter.next();
+        }
+        iter.status()?;
+        writer.flush()?;
+
+        Ok(count)
+    }
+
+    /// Reads key/value pairs written in `format` from `reader` and puts them
+    /// into the store, returning the number of entries read. Entries do not
+    /// need to be sorted; if a key appears more than once, the last value
+    /// wins. Entries are applied in batches, so if an error is returned, the
+    /// entries before it may have been applied.
+    pub fn import_kv<R: Read>(&mut self, reader: R, format: KvFormat) -> Result<usize> {
+        let mut count = 0;
+        let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+        for (i, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || (i == 0 && format == KvFormat::Csv && line == CSV_HEADER) {
+                continue;
+            }
+
+            let (key, value) = format.parse_entry(&line)?;
+            batch.push((key, Op::Put(value)));
+            count += 1;
+
+            if batch.len() == IMPORT_BATCH_SIZE {
+                self.apply_unsorted(&mut batch)?;
+            }
+        }
+        self.apply_unsorted(&mut batch)?;
+
+        Ok(count)
+    }
+
+    /// Sorts and deduplicates `batch` (keeping the last entry for each key),
+    /// applies it, and clears it.
+    fn apply_unsorted(&mut self, batch: &mut Vec<(Vec<u8>, Op)>) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        // the sort is stable, so the last entry for each key is kept by
+        // deduplicating from the back
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+        batch.reverse();
+        batch.dedup_by(|a, b| a.0 == b.0);
+        batch.reverse();
+
+        unsafe { self.apply_unchecked(batch, &[])? };
+        batch.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn export_import_roundtrip() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        for format in [KvFormat::JsonLines, KvFormat::Csv] {
+            let mut dump = vec![];
+            assert_eq!(merk.export_kv(&mut dump, format).unwrap(), 100);
+
+            let mut imported = TempMerk::new().unwrap();
+            assert_eq!(imported.import_kv(&dump[..], format).unwrap(), 100);
+            assert_eq!(imported.root_hash(), merk.root_hash());
+        }
+    }
+
+    #[test]
+    fn import_unsorted() {
+        let dump = "key,value\n02,0b\n01,0a\n02,0c\n";
+        let mut merk = TempMerk::new().unwrap();
+        assert_eq!(merk.import_kv(dump.as_bytes(), KvFormat::Csv).unwrap(), 3);
+        assert_eq!(merk.get(&[1]).unwrap(), Some(vec![10]));
+        assert_eq!(merk.get(&[2]).unwrap(), Some(vec![12]));
+
+        let dump = "{ \"key\": \"01\", \"value\": \"0a\" }\n{\"key\":\"zz\"}\n";
+        let res = merk.import_kv(dump.as_bytes(), KvFormat::JsonLines);
+        assert!(matches!(res, Err(Error::Key(_))));
+    }
+}
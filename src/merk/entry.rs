@@ -0,0 +1,192 @@
+//! Provides read-modify-write helpers, which read the current value of a key
+//! and stage a change to it in a pending batch.
+
+use std::collections::BTreeMap;
+
+use super::Merk;
+use crate::tree::{Batch, BatchEntry, Op};
+use crate::Result;
+
+/// Operations staged against a `Merk`, which are applied as a single batch by
+/// `commit`. Reads made through the pending batch see the staged operations.
+///
+/// Dropping the pending batch without committing discards its operations.
+pub struct PendingBatch<'a> {
+    merk: &'a mut Merk,
+    ops: BTreeMap<Vec<u8>, Op>,
+}
+
+impl<'a> PendingBatch<'a> {
+    /// Gets the value for `key`, including any staged operation for it.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.ops.get(key) {
+            Some(Op::Put(value)) => Ok(Some(value.clone())),
+            Some(Op::Delete) => Ok(None),
+            None => self.merk.get(key),
+        }
+    }
+
+    /// Fetches the current value for `key` so it can be modified.
+    pub fn entry(&mut self, key: Vec<u8>) -> Result<Entry> {
+        let value = self.get(&key)?;
+        Ok(Entry {
+            key,
+            value,
+            ops: &mut self.ops,
+        })
+    }
+
+    /// Stages putting `value` at `key`.
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.insert(key, Op::Put(value));
+    }
+
+    /// Stages deleting `key`.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.insert(key, Op::Delete);
+    }
+
+    /// Returns the number of staged operations.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns true if no operations are staged.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Applies the staged operations, along with the `aux` batch.
+    pub fn commit(self, aux: &Batch) -> Result<()> {
+        let batch: Vec<BatchEntry> = self.ops.into_iter().collect();
+        // keys from the map are already sorted and unique
+        unsafe { self.merk.apply_unchecked(&batch, aux) }
+    }
+}
+
+/// The value of a key read through a `PendingBatch`, which can be replaced or
+/// deleted.
+pub struct Entry<'b> {
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+    ops: &'b mut BTreeMap<Vec<u8>, Op>,
+}
+
+impl<'b> Entry<'b> {
+    /// Returns the entry's key.
+    pub fn key(&self) -> &[u8] {
+        self.key.as_slice()
+    }
+
+    /// Returns the current value, or `None` if the key is not in the store.
+    pub fn value(&self) -> Option<&[u8]> {
+        self.value.as_deref()
+    }
+
+    /// Stages putting `value` at the entry's key.
+    pub fn put(self, value: Vec<u8>) {
+        self.ops.insert(self.key, Op::Put(value));
+    }
+
+    /// Stages deleting the entry's key, if it is in the store.
+    pub fn delete(self) {
+        if self.value.is_some() {
+            self.ops.insert(self.key, Op::Delete);
+        }
+    }
+
+    /// Stages putting `default` if the key is not in the store, returning the
+    /// resulting value.
+    pub fn or_put(self, default: Vec<u8>) -> Vec<u8> {
+        match self.value {
+            Some(value) => value,
+            None => {
+                self.ops.insert(self.key, Op::Put(default.clone()));
+                default
+            }
+        }
+    }
+
+    /// Stages the value returned by `f` for the current value, where `None`
+    /// means the key is not in the store (or should be deleted).
+    pub fn update<F>(self, f: F)
+    where
+        F: FnOnce(Option<Vec<u8>>) -> Option<Vec<u8>>,
+    {
+        let existed = self.value.is_some();
+        match f(self.value) {
+            Some(value) => {
+                self.ops.insert(self.key, Op::Put(value));
+            }
+            None if existed => {
+                self.ops.insert(self.key, Op::Delete);
+            }
+            None => {}
+        }
+    }
+}
+
+impl Merk {
+    /// Starts a batch of staged operations, whose reads see the operations
+    /// staged before them. Nothing is written until the batch is committed.
+    pub fn pending_batch(&mut self) -> PendingBatch {
+        PendingBatch {
+            merk: self,
+            ops: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces the value of `key` with the result of `f` called on its current
+    /// value, where `None` means the key is not in the store (or should be
+    /// deleted), and applies the change.
+    pub fn update<F>(&mut self, key: &[u8], f: F) -> Result<()>
+    where
+        F: FnOnce(Option<Vec<u8>>) -> Option<Vec<u8>>,
+    {
+        let mut batch = self.pending_batch();
+        batch.entry(key.to_vec())?.update(f);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        batch.commit(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+
+    #[test]
+    fn read_modify_write() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+
+        let increment = |value: Option<Vec<u8>>| Some(vec![value.map_or(0, |v| v[0]) + 1]);
+        merk.update(b"counter", increment).unwrap();
+        merk.update(b"counter", increment).unwrap();
+        assert_eq!(merk.get(b"counter").unwrap(), Some(vec![2]));
+        merk.update(&seq_key(3), |_| None).unwrap();
+        assert_eq!(merk.get(&seq_key(3)).unwrap(), None);
+
+        let root_hash = merk.root_hash();
+        let mut batch = merk.pending_batch();
+        batch.entry(b"counter".to_vec()).unwrap().update(increment);
+        batch.entry(b"counter".to_vec()).unwrap().update(increment);
+        assert_eq!(
+            batch.entry(b"new".to_vec()).unwrap().or_put(vec![7]),
+            vec![7]
+        );
+        batch.entry(seq_key(4)).unwrap().delete();
+        assert_eq!(batch.get(b"counter").unwrap(), Some(vec![4]));
+        assert_eq!(batch.len(), 3);
+        drop(batch);
+        assert_eq!(merk.root_hash(), root_hash);
+
+        let mut batch = merk.pending_batch();
+        batch.entry(seq_key(4)).unwrap().delete();
+        batch.put(b"new".to_vec(), vec![7]);
+        batch.commit(&[]).unwrap();
+        assert_eq!(merk.get(&seq_key(4)).unwrap(), None);
+        assert_eq!(merk.get(b"new").unwrap(), Some(vec![7]));
+    }
+}
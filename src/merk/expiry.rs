@@ -0,0 +1,295 @@
+//! Provides expiring keys. Expiry times are kept in aux storage, indexed by
+//! time, so `Merk::expire_until` can find the expired keys without scanning
+//! the tree.
+//!
+//! Times are opaque `u64`s chosen by the application (e.g. block times or
+//! heights), so sweeps are deterministic across nodes.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use super::import::Entries;
+use super::{prefix_read_opts, prefixed, Merk, AUX_CF_NAME};
+use crate::tree::{Batch, BatchEntry, Op};
+use crate::{Error, Result};
+
+/// The prefix of the aux keys holding each key's expiry time, followed by the
+/// key.
+pub const EXPIRY_PREFIX: &[u8] = b"\x00merk/expiry/";
+
+/// The prefix of the aux keys of the expiry index, followed by the
+/// big-endian expiry time and the key.
+pub const EXPIRY_INDEX_PREFIX: &[u8] = b"\x00merk/expiry-index/";
+
+fn expiry_key(key: &[u8]) -> Vec<u8> {
+    let mut expiry_key = EXPIRY_PREFIX.to_vec();
+    expiry_key.extend_from_slice(key);
+    expiry_key
+}
+
+fn index_key(expires_at: u64, key: &[u8]) -> Vec<u8> {
+    let mut index_key = EXPIRY_INDEX_PREFIX.to_vec();
+    index_key.extend_from_slice(&expires_at.to_be_bytes());
+    index_key.extend_from_slice(key);
+    index_key
+}
+
+impl Merk {
+    /// Returns the time `key` expires at, or `None` if it does not expire.
+    ///
+    /// Expiry times set during a block are only read once the block is
+    /// committed.
+    pub fn expiry(&self, key: &[u8]) -> Result<Option<u64>> {
+        decode_expiry(key, self.get_aux(&expiry_key(key))?)
+    }
+
+    /// Returns the time `key` expires at, including changes staged in a block
+    /// or an import.
+    fn staged_expiry(&self, key: &[u8]) -> Result<Option<u64>> {
+        match self
+            .staged_aux()
+            .and_then(|aux| aux.get(&self.prefixed(&expiry_key(key))))
+        {
+            Some(maybe_bytes) => decode_expiry(key, maybe_bytes.clone()),
+            None => self.expiry(key),
+        }
+    }
+
+    /// Returns the aux writes staged in a block or an import, keyed by their
+    /// prefixed keys.
+    fn staged_aux(&self) -> Option<&Entries> {
+        self.block
+            .as_ref()
+            .map(|block| &block.writes)
+            .or_else(|| self.import.as_ref().map(|import| &import.writes))
+            .map(|writes| &writes.aux)
+    }
+
+    /// Applies a batch as in `apply`, along with changes to the expiry times
+    /// of keys: `Some(time)` sets the time the key expires at (replacing any
+    /// earlier time), and `None` removes it. The expiry times of keys deleted
+    /// by `batch` are removed.
+    ///
+    /// Expiry times are only maintained by this method and `expire_until`, so
+    /// keys deleted with `apply` keep their expiry time. Changes made earlier
+    /// in a block, or earlier in `expiries`, are replaced like committed ones.
+    pub fn apply_with_expiry(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+        expiries: &[(Vec<u8>, Option<u64>)],
+    ) -> Result<()> {
+        let deleted = batch
+            .iter()
+            .filter(|(_, op)| matches!(op, Op::Delete))
+            .map(|(key, _)| (key.clone(), None));
+
+        let mut aux: Vec<_> = aux
+            .iter()
+            .map(|(key, op)| match op {
+                Op::Put(value) => (key.clone(), Op::Put(value.clone())),
+                Op::Delete => (key.clone(), Op::Delete),
+            })
+            .collect();
+        let mut changes = ExpiryChanges::default();
+        for (key, maybe_expires_at) in deleted.chain(expiries.iter().cloned()) {
+            self.change_expiry(&mut changes, &key, maybe_expires_at)?;
+        }
+        aux.extend(changes.ops);
+        self.apply(batch, &aux)
+    }
+
+    /// Deletes the keys which expire at or before `time`, in one commit, and
+    /// returns the batch applied. Keys are removed in order of expiry time
+    /// (then key), at most the `max_batch_size` limit of them per call, so
+    /// calling this with the same time on every node removes the same keys.
+    pub fn expire_until(&mut self, time: u64) -> Result<Vec<BatchEntry>> {
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let index_prefix = prefixed(&self.prefix, EXPIRY_INDEX_PREFIX);
+        let mut iter = self
+            .db
+            .raw_iterator_cf_opt(aux_cf, prefix_read_opts(&index_prefix));
+        iter.seek_to_first();
+
+        // index entries staged in a block or an import replace the stored ones
+        let mut staged = self
+            .staged_aux()
+            .into_iter()
+            .flat_map(|aux| aux.range(index_prefix.clone()..))
+            .take_while(|(key, _)| key.starts_with(&index_prefix))
+            .peekable();
+
+        let mut expired = vec![];
+        while expired.len() < self.limits.max_batch_size {
+            let stored_key = if iter.valid() { iter.key() } else { None };
+            let take_staged = match (stored_key, staged.peek()) {
+                (None, None) => break,
+                (Some(stored), Some((staged_key, _))) => staged_key.as_slice() <= stored,
+                (None, Some(_)) => true,
+                (Some(_), None) => false,
+            };
+            let (index_key, present) = if take_staged {
+                let (staged_key, maybe_value) = staged.next().unwrap();
+                if stored_key == Some(staged_key.as_slice()) {
+                    iter.next();
+                }
+                (staged_key.clone(), maybe_value.is_some())
+            } else {
+                let stored_key = stored_key.unwrap().to_vec();
+                iter.next();
+                (stored_key, true)
+            };
+
+            let unprefixed = &index_key[index_prefix.len()..];
+            if unprefixed.len() < 8 {
+                return Err(Error::Corruption {
+                    key: index_key[self.prefix.len()..].to_vec(),
+                });
+            }
+            let (expires_at, key) = unprefixed.split_at(8);
+            if u64::from_be_bytes(expires_at.try_into().unwrap()) > time {
+                break;
+            }
+            if present {
+                expired.push(key.to_vec());
+            }
+        }
+        iter.status()?;
+        drop(iter);
+        drop(staged);
+
+        let mut changes = ExpiryChanges::default();
+        let mut batch = vec![];
+        for key in expired {
+            self.change_expiry(&mut changes, &key, None)?;
+            if self.get(&key)?.is_some() {
+                batch.push((key, Op::Delete));
+            }
+        }
+        if changes.ops.is_empty() {
+            return Ok(batch);
+        }
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let aux: Vec<_> = changes.ops.into_iter().collect();
+        self.apply(&batch, &aux)?;
+        Ok(batch)
+    }
+
+    /// Adds the aux operations which change the expiry time of `key` to
+    /// `changes`, replacing the time set by earlier changes if there are any.
+    fn change_expiry(
+        &self,
+        changes: &mut ExpiryChanges,
+        key: &[u8],
+        maybe_expires_at: Option<u64>,
+    ) -> Result<()> {
+        let previous = match changes.expiries.get(key) {
+            Some(previous) => *previous,
+            None => self.staged_expiry(key)?,
+        };
+        if let Some(expires_at) = previous {
+            changes.ops.insert(index_key(expires_at, key), Op::Delete);
+        }
+        match maybe_expires_at {
+            Some(expires_at) => {
+                let time = expires_at.to_be_bytes().to_vec();
+                changes.ops.insert(expiry_key(key), Op::Put(time));
+                changes
+                    .ops
+                    .insert(index_key(expires_at, key), Op::Put(vec![]));
+            }
+            None => {
+                changes.ops.insert(expiry_key(key), Op::Delete);
+            }
+        }
+        changes.expiries.insert(key.to_vec(), maybe_expires_at);
+        Ok(())
+    }
+}
+
+/// The aux operations of a set of expiry changes, keyed by aux key so later
+/// changes to a key replace earlier ones, and the resulting expiry times.
+#[derive(Default)]
+struct ExpiryChanges {
+    ops: BTreeMap<Vec<u8>, Op>,
+    expiries: BTreeMap<Vec<u8>, Option<u64>>,
+}
+
+fn decode_expiry(key: &[u8], maybe_bytes: Option<Vec<u8>>) -> Result<Option<u64>> {
+    maybe_bytes
+        .map(|bytes| {
+            let bytes = bytes.as_slice().try_into().map_err(|_| Error::Corruption {
+                key: expiry_key(key),
+            })?;
+            Ok(u64::from_be_bytes(bytes))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn expire_keys() {
+        let mut merk = TempMerk::new().unwrap();
+        let expiries: Vec<_> = (0..10).map(|i| (seq_key(i), Some(100 - i))).collect();
+        merk.apply_with_expiry(&make_batch_seq(0..20), &[], &expiries)
+            .unwrap();
+        assert_eq!(merk.expiry(&seq_key(3)).unwrap(), Some(97));
+        assert_eq!(merk.expiry(&seq_key(15)).unwrap(), None);
+
+        // extending a key's expiry replaces its earlier time, and deleting a
+        // key removes it
+        merk.apply_with_expiry(&[(seq_key(1), Op::Delete)], &[], &[(seq_key(9), Some(200))])
+            .unwrap();
+        assert_eq!(merk.expiry(&seq_key(1)).unwrap(), None);
+
+        assert!(merk.expire_until(90).unwrap().is_empty());
+        let batch = merk.expire_until(97).unwrap();
+        let keys: Vec<_> = batch.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(keys, (3..9).map(seq_key).collect::<Vec<_>>());
+        assert_eq!(merk.get(&seq_key(4)).unwrap(), None);
+        assert_eq!(merk.get(&seq_key(9)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.expiry(&seq_key(4)).unwrap(), None);
+
+        let batch = merk.expire_until(1_000).unwrap();
+        assert_eq!(batch.len(), 3);
+        assert!(merk.expire_until(1_000).unwrap().is_empty());
+        assert_eq!(merk.len(), 10);
+    }
+
+    #[test]
+    fn expire_keys_in_block() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.begin_block().unwrap();
+        merk.apply_with_expiry(&make_batch_seq(0..3), &[], &[(seq_key(0), Some(10))])
+            .unwrap();
+
+        // re-expiring a key in the block, or twice in one call, replaces the
+        // earlier time
+        merk.apply_with_expiry(&[], &[], &[(seq_key(0), Some(20))])
+            .unwrap();
+        let expiries = [(seq_key(1), Some(5)), (seq_key(1), Some(30))];
+        merk.apply_with_expiry(&[], &[], &expiries).unwrap();
+        assert!(merk.expire_until(10).unwrap().is_empty());
+
+        // sweeps see the expiry times set in the block
+        merk.apply_with_expiry(&[], &[], &[(seq_key(2), Some(3))])
+            .unwrap();
+        let batch = merk.expire_until(3).unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].0, seq_key(2));
+        assert_eq!(merk.get(&seq_key(2)).unwrap(), None);
+        merk.commit_block().unwrap();
+
+        assert_eq!(merk.expiry(&seq_key(0)).unwrap(), Some(20));
+        assert_eq!(merk.expiry(&seq_key(1)).unwrap(), Some(30));
+        assert!(merk.expire_until(19).unwrap().is_empty());
+        assert_eq!(merk.expire_until(20).unwrap().len(), 1);
+        assert_eq!(merk.expire_until(30).unwrap().len(), 1);
+        assert!(merk.is_empty());
+    }
+}
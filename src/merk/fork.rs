@@ -0,0 +1,112 @@
+//! Provides forks of a store, for speculatively applying batches (e.g. when
+//! evaluating candidate blocks) without affecting the original.
+
+use super::Merk;
+use crate::Result;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+
+/// A copy-on-write branch of a store, created by `Merk::fork`.
+///
+/// Batches applied to a fork do not affect the store it was created from.
+/// A fork is deleted when it is dropped, unless it is promoted with
+/// `Merk::promote`.
+pub struct Fork {
+    inner: Option<Merk>,
+}
+
+impl Fork {
+    /// Deletes the fork, discarding any changes made to it.
+    pub fn discard(mut self) -> Result<()> {
+        self.inner.take().unwrap().destroy()
+    }
+}
+
+impl Drop for Fork {
+    fn drop(&mut self) {
+        if let Some(merk) = self.inner.take() {
+            let _ = merk.destroy();
+        }
+    }
+}
+
+impl Deref for Fork {
+    type Target = Merk;
+
+    fn deref(&self) -> &Merk {
+        self.inner.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Fork {
+    fn deref_mut(&mut self) -> &mut Merk {
+        self.inner.as_mut().unwrap()
+    }
+}
+
+impl Merk {
+    /// Creates a fork of the store at `path`, which must be on the same
+    /// filesystem as the store.
+    ///
+    /// The fork is a RocksDB checkpoint, which hard-links the store's
+    /// immutable files rather than copying them, so unchanged nodes are shared
+    /// with the store until they are rewritten by either side.
+    pub fn fork<P: AsRef<Path>>(&self, path: P) -> Result<Fork> {
+        Ok(Fork {
+            inner: Some(self.checkpoint(path)?),
+        })
+    }
+
+    /// Replaces the contents of the store with those of `fork`, then deletes
+    /// the fork. The fork does not need to have been created from this store.
+    ///
+    /// The entries which differ are written in a single batch, so an
+    /// interrupted promotion leaves the store unchanged. Finding them requires
+    /// iterating over both stores.
+    pub fn promote(&mut self, mut fork: Fork) -> Result<()> {
+        let merk = fork.inner.take().unwrap();
+        self.copy_from(&merk)?;
+        merk.destroy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn fork_and_promote() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(&path).unwrap();
+        merk.enable_changelog().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        let receiver = merk.subscribe().unwrap();
+
+        let mut discarded = merk.fork(path.clone() + ".fork1").unwrap();
+        let mut promoted = merk.fork(path.clone() + ".fork2").unwrap();
+        discarded.apply(&make_del_batch_seq(0..50), &[]).unwrap();
+        promoted
+            .apply(&make_batch_seq(100..200), &[(vec![1], Op::Put(vec![1]))])
+            .unwrap();
+
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.get(&seq_key(150)).unwrap(), None);
+        assert_eq!(discarded.get(&seq_key(10)).unwrap(), None);
+
+        discarded.discard().unwrap();
+        let promoted_hash = promoted.root_hash();
+        merk.promote(promoted).unwrap();
+
+        assert_eq!(merk.root_hash(), promoted_hash);
+        assert_eq!(merk.get(&seq_key(150)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.get_aux(&[1]).unwrap(), Some(vec![1]));
+        assert!(!std::path::Path::new(&(path + ".fork2")).exists());
+
+        // subscribers stay subscribed to the promoted store's changes
+        merk.apply(&make_batch_seq(200..210), &[]).unwrap();
+        assert_eq!(receiver.recv().unwrap().root_hash, merk.root_hash());
+    }
+}
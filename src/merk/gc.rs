@@ -0,0 +1,130 @@
+//! Provides mark-and-sweep garbage collection of nodes which are no longer
+//! reachable from the root, e.g. after a crash or an interrupted restore.
+
+use super::Merk;
+use crate::{Error, Result};
+use std::collections::HashSet;
+
+impl Merk {
+    /// Deletes all stored nodes which are not reachable from the root,
+    /// returning the number of nodes deleted. Nodes are deleted in batches of
+    /// `batch_size`, and the key range of each batch is compacted afterwards so
+    /// the space is reclaimed.
+    ///
+    /// Marking holds the keys of all reachable nodes in memory.
+    ///
+    /// Returns an error during a block or an import, since the nodes of the
+    /// current root may only be staged in memory and marking reads the nodes
+    /// written to the database.
+    pub fn collect_garbage(&mut self, batch_size: usize) -> Result<usize> {
+        if self.in_block() || self.is_importing() {
+            return Err(Error::Unsupported(
+                "Cannot collect garbage during a block or an import".into(),
+            ));
+        }
+
+        let reachable = self.mark_reachable()?;
+
+        let mut orphans = vec![];
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+            if !reachable.contains(key) {
+                orphans.push(key.to_vec());
+            }
+            iter.next();
+        }
+        iter.status()?;
+        drop(iter);
+
+        for chunk in orphans.chunks(batch_size.max(1)) {
+            let deletes: Vec<_> = chunk.iter().map(|key| (self.prefixed(key), None)).collect();
+            self.write_entries(deletes, vec![], vec![])?;
+
+            let first = chunk.first().unwrap();
+            let last = chunk.last().unwrap();
+            self.compact_range(Some(first), Some(last));
+        }
+
+        Ok(orphans.len())
+    }
+
+    /// Returns the keys of all nodes reachable from the roots which must be
+    /// retained.
+    fn mark_reachable(&self) -> Result<HashSet<Vec<u8>>> {
+        let mut reachable = HashSet::new();
+        let mut stack = self.retained_roots();
+
+        while let Some(key) = stack.pop() {
+            if reachable.contains(&key) {
+                continue;
+            }
+            if let Some(node) = self.fetch_node(&key)? {
+                for link in [node.link(true), node.link(false)].iter().flatten() {
+                    stack.push(link.key().to_vec());
+                }
+            }
+            reachable.insert(key);
+        }
+
+        Ok(reachable)
+    }
+
+    /// Returns the keys of the root nodes whose trees must be kept by garbage
+    /// collection.
+    pub(crate) fn retained_roots(&self) -> Vec<Vec<u8>> {
+        self.use_tree(|maybe_tree| maybe_tree.map(|tree| tree.key().to_vec()))
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Merk;
+
+    #[test]
+    fn collect_orphans() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        let root_hash = merk.root_hash();
+
+        // copy a node to keys which are not part of the tree
+        let node = merk.db.get(seq_key(500)).unwrap().unwrap();
+        for i in 0..5u8 {
+            merk.db.put([0xff, i], &node).unwrap();
+        }
+
+        assert_eq!(merk.collect_garbage(2).unwrap(), 5);
+        assert_eq!(merk.db.get([0xff, 0]).unwrap(), None);
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(999)).unwrap(), Some(put_entry_value()));
+
+        assert_eq!(merk.collect_garbage(2).unwrap(), 0);
+    }
+
+    #[test]
+    fn no_collection_during_block() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+
+        // the new root and the nodes on its paths are only staged
+        merk.begin_block().unwrap();
+        merk.apply(&make_batch_seq(1_000..1_100), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        assert!(merk.collect_garbage(100).is_err());
+        merk.commit_block().unwrap();
+        drop(merk);
+
+        let mut merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.collect_garbage(100).unwrap(), 0);
+        assert!(merk.scrub().unwrap().is_empty());
+        assert_eq!(merk.get(&seq_key(500)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.get(&seq_key(1_050)).unwrap(), Some(put_entry_value()));
+        merk.destroy().unwrap();
+    }
+}
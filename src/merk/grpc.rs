@@ -0,0 +1,253 @@
+//! Provides `MerkService`, a gRPC service (defined in `proto/merk.proto`)
+//! which serves reads, proofs and state sync chunks from a `Merk`.
+//!
+//! ```no_run
+//! # async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+//! use merk::{grpc::MerkService, Merk};
+//! use std::sync::{Arc, Mutex};
+//!
+//! let merk = Arc::new(Mutex::new(Merk::open("./merk.db")?));
+//! tonic::transport::Server::builder()
+//!     .add_service(MerkService::new(merk.clone()).into_server())
+//!     .serve("127.0.0.1:26660".parse()?)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tonic::{Request, Response, Status};
+
+use super::Merk;
+use crate::proofs::Query;
+use crate::{Error, ErrorKind};
+
+/// The types generated from `proto/merk.proto`.
+pub mod proto {
+    tonic::include_proto!("merk");
+}
+
+use proto::store_server::{Store, StoreServer};
+use proto::{
+    ChunkCountRequest, ChunkCountResponse, ChunkRequest, ChunkResponse, Entry, GetRequest,
+    GetResponse, GetWithProofResponse, RangeRequest, RangeResponse,
+};
+
+/// The maximum number of entries returned by a single `Range` call.
+pub const MAX_RANGE_LIMIT: u32 = 10_000;
+
+/// Serves queries against a `Merk` shared with the application, which should
+/// apply its changes through the same mutex. Each call holds the lock while it
+/// reads, so every response is consistent with the root hash it returns.
+#[derive(Clone)]
+pub struct MerkService {
+    merk: Arc<Mutex<Merk>>,
+}
+
+impl MerkService {
+    /// Creates a service which serves queries against `merk`.
+    pub fn new(merk: Arc<Mutex<Merk>>) -> Self {
+        MerkService { merk }
+    }
+
+    /// Wraps the service in the server type which can be added to a
+    /// `tonic::transport::Server`.
+    pub fn into_server(self) -> StoreServer<Self> {
+        StoreServer::new(self)
+    }
+
+    fn lock(&self) -> std::result::Result<MutexGuard<Merk>, Status> {
+        self.merk
+            .lock()
+            .map_err(|_| Status::internal("Merk lock was poisoned"))
+    }
+}
+
+#[tonic::async_trait]
+impl Store for MerkService {
+    async fn get(
+        &self,
+        request: Request<GetRequest>,
+    ) -> std::result::Result<Response<GetResponse>, Status> {
+        let merk = self.lock()?;
+        let value = merk.get(&request.get_ref().key).map_err(status)?;
+
+        Ok(Response::new(GetResponse {
+            value,
+            root_hash: merk.root_hash().to_vec(),
+        }))
+    }
+
+    async fn get_with_proof(
+        &self,
+        request: Request<GetRequest>,
+    ) -> std::result::Result<Response<GetWithProofResponse>, Status> {
+        let key = request.into_inner().key;
+        let merk = self.lock()?;
+        let value = merk.get(&key).map_err(status)?;
+        let proof = merk.prove(Query::from(vec![key])).map_err(status)?;
+
+        Ok(Response::new(GetWithProofResponse {
+            value,
+            proof,
+            root_hash: merk.root_hash().to_vec(),
+        }))
+    }
+
+    async fn range(
+        &self,
+        request: Request<RangeRequest>,
+    ) -> std::result::Result<Response<RangeResponse>, Status> {
+        let RangeRequest {
+            start,
+            end,
+            limit,
+            prove,
+        } = request.into_inner();
+        if start >= end {
+            return Err(Status::invalid_argument("Range start must be before end"));
+        }
+        let limit = match limit {
+            0 => MAX_RANGE_LIMIT,
+            limit => limit.min(MAX_RANGE_LIMIT),
+        };
+
+        let merk = self.lock()?;
+        // read one extra entry to find out whether the range was truncated
+        let mut entries = merk
+            .get_range(&start, Some(&end), limit as usize + 1)
+            .map_err(status)?;
+        let truncated = entries.len() > limit as usize;
+        entries.truncate(limit as usize);
+        let entries: Vec<_> = entries
+            .into_iter()
+            .map(|(key, value)| Entry { key, value })
+            .collect();
+
+        let proof = if prove {
+            let mut query = Query::new();
+            match entries.last() {
+                Some(last) if truncated => query.insert_range_inclusive(start..=last.key.clone()),
+                _ => query.insert_range(start..end),
+            }
+            merk.prove(query).map_err(status)?
+        } else {
+            vec![]
+        };
+
+        Ok(Response::new(RangeResponse {
+            entries,
+            truncated,
+            proof,
+            root_hash: merk.root_hash().to_vec(),
+        }))
+    }
+
+    async fn chunk_count(
+        &self,
+        _request: Request<ChunkCountRequest>,
+    ) -> std::result::Result<Response<ChunkCountResponse>, Status> {
+        let merk = self.lock()?;
+        let count = merk.chunks().map_err(status)?.len();
+
+        Ok(Response::new(ChunkCountResponse {
+            count: count as u64,
+            root_hash: merk.root_hash().to_vec(),
+        }))
+    }
+
+    async fn chunk(
+        &self,
+        request: Request<ChunkRequest>,
+    ) -> std::result::Result<Response<ChunkResponse>, Status> {
+        let index = usize::try_from(request.get_ref().index)
+            .map_err(|_| Status::out_of_range("Chunk index out-of-bounds"))?;
+
+        let merk = self.lock()?;
+        // TODO: keep producers for recently served roots rather than
+        // rebuilding the trunk for every request
+        let chunk = merk
+            .chunks()
+            .map_err(status)?
+            .chunk(index)
+            .map_err(status)?;
+
+        Ok(Response::new(ChunkResponse {
+            chunk,
+            root_hash: merk.root_hash().to_vec(),
+        }))
+    }
+}
+
+/// Maps an error to the gRPC status returned for it.
+fn status(err: Error) -> Status {
+    if err.kind() == ErrorKind::Batch || err.kind() == ErrorKind::LimitExceeded {
+        return Status::invalid_argument(err.to_string());
+    }
+    match err {
+        Error::IndexOutOfBounds(_) => Status::out_of_range(err.to_string()),
+        Error::RootConflict { .. } => Status::aborted(err.to_string()),
+        // chunks can not be created for an empty tree
+        Error::Proof(_) | Error::Fetch(_) => Status::failed_precondition(err.to_string()),
+        _ => Status::internal(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::{chunk::verify_trunk, Decoder};
+    use crate::test_utils::*;
+    use crate::verify;
+
+    #[tokio::test]
+    async fn serve_queries() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        let merk = Arc::new(Mutex::new(merk));
+        let service = MerkService::new(merk.clone());
+
+        let request = GetRequest { key: seq_key(5) };
+        let res = service.get_with_proof(Request::new(request)).await;
+        let res = res.unwrap().into_inner();
+        assert_eq!(res.value, Some(put_entry_value()));
+        assert_eq!(res.root_hash, root_hash.to_vec());
+        let map = verify(&res.proof, root_hash).unwrap();
+        assert_eq!(map.get(&seq_key(5)).unwrap(), Some(&put_entry_value()[..]));
+
+        let request = RangeRequest {
+            start: seq_key(10),
+            end: seq_key(100),
+            limit: 50,
+            prove: true,
+        };
+        let res = service.range(Request::new(request)).await;
+        let res = res.unwrap().into_inner();
+        assert_eq!(res.entries.len(), 50);
+        assert!(res.truncated);
+        assert_eq!(res.entries[49].key, seq_key(59));
+        let map = verify(&res.proof, root_hash).unwrap();
+        let (start, end) = (seq_key(10), seq_key(59));
+        assert_eq!(map.range(&start[..]..=&end[..]).count(), 50);
+
+        let res = service
+            .chunk_count(Request::new(ChunkCountRequest {}))
+            .await;
+        let count = res.unwrap().into_inner().count;
+        let request = ChunkRequest { index: count };
+        let err = service.chunk(Request::new(request)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::OutOfRange);
+        let res = service.chunk(Request::new(ChunkRequest { index: 0 })).await;
+        let chunk = res.unwrap().into_inner().chunk;
+        let (trunk, _) = verify_trunk(Decoder::new(&chunk), None).unwrap();
+        assert_eq!(trunk.hash().unwrap(), root_hash);
+
+        drop(service);
+        let merk = Arc::try_unwrap(merk).ok().unwrap();
+        merk.into_inner().unwrap().destroy().unwrap();
+    }
+}
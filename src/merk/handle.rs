@@ -0,0 +1,142 @@
+//! Provides `SharedMerk`, a thread-safe handle to a store for multi-threaded
+//! servers.
+
+use std::sync::{Mutex, MutexGuard};
+
+use super::{Merk, MerkReader};
+use crate::proofs::Query;
+use crate::tree::{Batch, Hash};
+use crate::{Error, Result};
+
+/// A `Merk` which can be shared between threads (e.g. in an `Arc`).
+///
+/// # Locking
+///
+/// Writes take an exclusive lock on the store, so they are applied one at a
+/// time. Reads do not take the lock: they go through a `MerkReader`, so they
+/// run concurrently with each other and with writes, and see the last version
+/// written to the database. Changes made during a block or an import are not
+/// visible to reads until they are written.
+pub struct SharedMerk {
+    merk: Mutex<Merk>,
+    reader: MerkReader,
+}
+
+impl SharedMerk {
+    /// Wraps `merk`.
+    pub fn new(mut merk: Merk) -> Self {
+        let reader = merk.reader();
+        SharedMerk {
+            merk: Mutex::new(merk),
+            reader,
+        }
+    }
+
+    /// Gets the value for `key` at the last committed version.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.reader.get(key)
+    }
+
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key if `end` is `None`, at the last
+    /// committed version.
+    pub fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.reader.get_range(start, end, limit)
+    }
+
+    /// Returns the root hash of the last committed version.
+    pub fn root_hash(&self) -> Hash {
+        self.reader.root_hash()
+    }
+
+    /// Creates a Merkle proof for the list of queried keys against the last
+    /// committed version.
+    pub fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        self.reader.prove(query)
+    }
+
+    /// Applies a batch of operations as in `Merk::apply`, holding the write
+    /// lock.
+    pub fn apply(&self, batch: &Batch, aux: &Batch) -> Result<()> {
+        self.lock()?.apply(batch, aux)
+    }
+
+    /// Applies a batch of operations as in `Merk::apply_if_root`, holding the
+    /// write lock so the root hash can't change between the check and the
+    /// commit.
+    pub fn apply_if_root(&self, batch: &Batch, aux: &Batch, expected_root: Hash) -> Result<()> {
+        self.lock()?.apply_if_root(batch, aux, expected_root)
+    }
+
+    /// Calls `f` with exclusive access to the store, e.g. to apply several
+    /// batches in a block or to read uncommitted changes.
+    pub fn write<T>(&self, f: impl FnOnce(&mut Merk) -> Result<T>) -> Result<T> {
+        f(&mut *self.lock()?)
+    }
+
+    /// Returns a reader which shares this handle's view of the store.
+    pub fn reader(&self) -> MerkReader {
+        self.reader.clone()
+    }
+
+    /// Unwraps the store.
+    pub fn into_inner(self) -> Result<Merk> {
+        drop(self.reader);
+        self.merk.into_inner().map_err(|_| Error::Poisoned)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<Merk>> {
+        self.merk.lock().map_err(|_| Error::Poisoned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn concurrent_reads_and_writes() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let merk = Arc::new(SharedMerk::new(Merk::open(path).unwrap()));
+
+        let writer = {
+            let merk = merk.clone();
+            std::thread::spawn(move || {
+                for i in 0..10 {
+                    merk.apply(&make_batch_seq(i * 100..(i + 1) * 100), &[])
+                        .unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let merk = merk.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        if let Some(value) = merk.get(&seq_key(50)).unwrap() {
+                            assert_eq!(value, put_entry_value());
+                        }
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(merk.get_range(&[], None, 2_000).unwrap().len(), 1_000);
+        let root_hash = merk.write(|merk| Ok(merk.root_hash())).unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+
+        let merk = Arc::try_unwrap(merk).ok().unwrap();
+        merk.into_inner().unwrap().destroy().unwrap();
+    }
+}
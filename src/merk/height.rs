@@ -0,0 +1,95 @@
+//! Records an application-defined height (e.g. a block height) atomically
+//! along with each commit, rejecting heights which do not increase.
+
+use super::{prefixed, CommitOptions, Merk, INTERNAL_CF_NAME};
+use crate::tree::Batch;
+use crate::{Error, Result};
+use rocksdb::DB;
+use std::convert::TryInto;
+
+const HEIGHT_KEY: &[u8] = b"height";
+
+/// Returns the internal column family entry which records `height`.
+pub(crate) fn height_entry(height: u64, prefix: &[u8]) -> (Vec<u8>, Option<Vec<u8>>) {
+    (
+        prefixed(prefix, HEIGHT_KEY),
+        Some(height.to_be_bytes().to_vec()),
+    )
+}
+
+/// Loads the height recorded for the store under `prefix`, if any.
+pub(crate) fn load_height(db: &DB, prefix: &[u8]) -> Result<Option<u64>> {
+    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+    db.get_cf(internal_cf, prefixed(prefix, HEIGHT_KEY))?
+        .map(|bytes| {
+            let bytes = bytes.as_slice().try_into().map_err(|_| Error::Corruption {
+                key: HEIGHT_KEY.to_vec(),
+            })?;
+            Ok(u64::from_be_bytes(bytes))
+        })
+        .transpose()
+}
+
+impl Merk {
+    /// Applies a batch as in `apply`, recording `height` in the same atomic
+    /// write as the commit. Returns a `HeightRegression` error without
+    /// applying anything if `height` is not greater than the last recorded
+    /// height.
+    pub fn apply_at_height(&mut self, batch: &Batch, aux: &Batch, height: u64) -> Result<()> {
+        if let Some(current) = self.height {
+            if height <= current {
+                return Err(Error::HeightRegression { height, current });
+            }
+        }
+
+        self.limits.check_batch(batch)?;
+        let options = CommitOptions {
+            height: Some(height),
+            ..Default::default()
+        };
+        unsafe { self.apply_sorted(batch, aux, options) }
+    }
+
+    /// Returns the height recorded by the last call to `apply_at_height`, or
+    /// `None` if it has never been called.
+    pub fn last_height(&self) -> Option<u64> {
+        self.height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Error, Merk};
+
+    #[test]
+    fn monotonic_heights() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.last_height(), None);
+
+        merk.apply_at_height(&make_batch_seq(0..10), &[], 5)
+            .unwrap();
+        assert_eq!(merk.last_height(), Some(5));
+        // plain applies leave the height unchanged
+        merk.apply(&make_batch_seq(10..20), &[]).unwrap();
+        assert_eq!(merk.last_height(), Some(5));
+
+        for height in [4, 5] {
+            match merk.apply_at_height(&make_batch_seq(20..30), &[], height) {
+                Err(Error::HeightRegression { current: 5, .. }) => {}
+                res => panic!("expected height regression, got {:?}", res),
+            }
+        }
+        assert_eq!(merk.get(&seq_key(25)).unwrap(), None);
+
+        merk.apply_at_height(&make_batch_seq(20..30), &[], 7)
+            .unwrap();
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.last_height(), Some(7));
+        assert_eq!(merk.get(&seq_key(25)).unwrap(), Some(put_entry_value()));
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Provides a bulk import mode for loading large amounts of data quickly (e.g.
+//! during initial sync), which bypasses the write-ahead log by buffering writes
+//! in memory and ingesting them into RocksDB as SST files.
+
+use super::{Merk, AUX_CF_NAME, INTERNAL_CF_NAME};
+use crate::{Error, Result};
+use rocksdb::{IngestExternalFileOptions, Options, SstFileWriter};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub(crate) type Entries = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// Writes staged in memory by commits, to be written to the database later.
+#[derive(Default)]
+pub(crate) struct StagedWrites {
+    pub(crate) size: usize,
+    pub(crate) nodes: Entries,
+    pub(crate) aux: Entries,
+    pub(crate) internal: Entries,
+}
+
+impl StagedWrites {
+    /// Adds the writes of a commit, replacing any earlier writes to the same
+    /// keys, so a key written by several commits is only written once.
+    /// `size` only counts the latest write of each key.
+    pub(crate) fn stage(
+        &mut self,
+        nodes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+        aux: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+        internal: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    ) {
+        let size = |maybe_value: &Option<Vec<u8>>| maybe_value.as_ref().map_or(0, Vec::len);
+        for (entries, writes) in [
+            (&mut self.nodes, nodes),
+            (&mut self.aux, aux),
+            (&mut self.internal, internal),
+        ] {
+            for (key, maybe_value) in writes {
+                self.size += key.len() + size(&maybe_value);
+                if let Some(replaced) = entries.get(&key) {
+                    self.size -= key.len() + size(replaced);
+                }
+                entries.insert(key, maybe_value);
+            }
+        }
+    }
+}
+
+/// Writes buffered while a store is in import mode.
+pub(crate) struct ImportBuffer {
+    dir: PathBuf,
+    max_size: usize,
+    pub(crate) writes: StagedWrites,
+}
+
+impl ImportBuffer {
+    pub(crate) fn is_full(&self) -> bool {
+        self.writes.size >= self.max_size
+    }
+}
+
+impl Merk {
+    /// Puts the store into bulk import mode. Until `finish_import` is called,
+    /// the writes made by `apply` are buffered in memory, and each time the
+    /// buffer reaches `buffer_size` bytes it is written out as SST files and
+    /// ingested into the database, bypassing the write-ahead log.
+    ///
+    /// Buffered writes are lost if the process crashes, and are only visible
+    /// through `get` and `prove`, not through snapshots, chunks or raw
+    /// iterators. Each ingestion ingests the nodes first, then the aux
+    /// entries, then the internal entries (the root pointer, commit record
+    /// and height). A crash during an ingestion therefore leaves the store at
+    /// the root of the previous ingestion. The store may also hold
+    /// unreachable nodes, which `collect_garbage` removes, and the aux entries
+    /// of the interrupted ingestion. In either case the import should be
+    /// resumed from the last recorded height (see `apply_at_height`), which
+    /// rewrites those aux entries.
+    pub fn start_import(&mut self, buffer_size: usize) -> Result<()> {
+        if self.import.is_some() {
+            return Err(Error::Unsupported("Store is already importing".into()));
+        }
+        if self.in_block() {
+            return Err(Error::Unsupported("Cannot import during a block".into()));
+        }
+
+        let mut dir = self.db.path().to_path_buf().into_os_string();
+        dir.push("-import");
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir)?;
+
+        self.import = Some(ImportBuffer {
+            dir,
+            max_size: buffer_size,
+            writes: StagedWrites::default(),
+        });
+        Ok(())
+    }
+
+    /// Ingests any writes still buffered by an import, syncs the write-ahead
+    /// log and leaves import mode.
+    ///
+    /// RocksDB syncs each SST file as it is ingested, so imported writes are
+    /// durable once this returns. The single sync of the write-ahead log makes
+    /// writes made through it during the import (e.g. by `enable_tombstones`)
+    /// durable as well.
+    pub fn finish_import(&mut self) -> Result<()> {
+        if self.import.is_none() {
+            return Err(Error::Unsupported("Store is not importing".into()));
+        }
+
+        self.flush_import()?;
+        let import = self.import.take().unwrap();
+        self.db.flush_wal(true)?;
+        std::fs::remove_dir_all(import.dir)?;
+        Ok(())
+    }
+
+    /// Returns true if the store is in bulk import mode.
+    pub fn is_importing(&self) -> bool {
+        self.import.is_some()
+    }
+
+    /// Writes the import buffer to SST files and ingests them. Nodes are
+    /// ingested before the root pointer, so a crash part way through leaves
+    /// the store at its previous root (see `start_import`).
+    pub(crate) fn flush_import(&mut self) -> Result<()> {
+        let import = self.import.as_mut().unwrap();
+        let StagedWrites {
+            nodes,
+            aux,
+            internal,
+            ..
+        } = std::mem::take(&mut import.writes);
+        let dir = import.dir.clone();
+
+        let mut ingest_opts = IngestExternalFileOptions::default();
+        ingest_opts.set_move_files(true);
+
+        if let Some(path) = write_sst(&dir, "nodes.sst", nodes)? {
+            self.db
+                .ingest_external_file_opts(&ingest_opts, vec![path])?;
+        }
+        for (name, entries) in [(AUX_CF_NAME, aux), (INTERNAL_CF_NAME, internal)] {
+            if let Some(path) = write_sst(&dir, &format!("{}.sst", name), entries)? {
+                let cf = self.db.cf_handle(name).unwrap();
+                self.db
+                    .ingest_external_file_cf_opts(cf, &ingest_opts, vec![path])?;
+            }
+        }
+        self.publish_view();
+
+        Ok(())
+    }
+}
+
+/// Writes `entries` to an SST file named `name` in `dir`, returning its path,
+/// or `None` if there are no entries.
+pub(crate) fn write_sst(dir: &Path, name: &str, entries: Entries) -> Result<Option<PathBuf>> {
+    if entries.is_empty() {
+        return Ok(None);
+    }
+
+    let path = dir.join(name);
+    let opts = Options::default();
+    let mut writer = SstFileWriter::create(&opts);
+    writer.open(&path)?;
+    for (key, maybe_value) in entries {
+        match maybe_value {
+            Some(value) => writer.put(key, value)?,
+            None => writer.delete(key)?,
+        }
+    }
+    writer.finish()?;
+
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Merk, Op};
+
+    #[test]
+    fn bulk_import() {
+        let path = std::thread::current().name().unwrap().to_owned();
+
+        let mut plain = TempMerk::new().unwrap();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.start_import(64 * 1024).unwrap();
+        assert!(merk.is_importing());
+
+        for i in 0..20 {
+            let batch = make_batch_seq(i * 500..(i + 1) * 500);
+            let aux = [(vec![1], Op::Put(vec![i as u8]))];
+            merk.apply(&batch, &aux).unwrap();
+            plain.apply(&batch, &aux).unwrap();
+        }
+        let deletes: Vec<_> = (0..100).map(|i| (seq_key(i), Op::Delete)).collect();
+        merk.apply(&deletes, &[]).unwrap();
+        plain.apply(&deletes, &[]).unwrap();
+        assert_eq!(merk.get(&seq_key(5_000)).unwrap(), Some(put_entry_value()));
+
+        merk.finish_import().unwrap();
+        assert!(!merk.is_importing());
+        assert!(merk.finish_import().is_err());
+        assert_eq!(merk.root_hash(), plain.root_hash());
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.root_hash(), plain.root_hash());
+        assert_eq!(merk.get(&seq_key(50)).unwrap(), None);
+        assert_eq!(merk.get(&seq_key(9_999)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.get_aux(&[1]).unwrap(), Some(vec![19]));
+        assert!(merk.scrub().unwrap().is_empty());
+        merk.destroy().unwrap();
+    }
+}
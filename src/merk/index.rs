@@ -0,0 +1,359 @@
+//! Provides secondary indexes, kept in aux storage and updated in the same
+//! batch as each commit, so applications can look entries up by something
+//! other than their key without maintaining the index themselves.
+//!
+//! An index is defined by a `KeyExtractor`, which returns the index keys of an
+//! entry. Each index entry is stored under the index's name and the index key,
+//! encoded so that entries are ordered by index key and then by the key of the
+//! entry they point to.
+
+use std::collections::BTreeSet;
+
+use rocksdb::WriteBatch;
+
+use super::{prefix_read_opts, prefixed, Merk, AUX_CF_NAME};
+use crate::tree::{Batch, Op};
+use crate::{Error, Result};
+
+/// The prefix of the aux keys of index entries, followed by the encoded index
+/// name, the encoded index key and the key of the indexed entry.
+pub const INDEX_PREFIX: &[u8] = b"\x00merk/index/";
+
+/// Returns the index keys of an entry, for an index registered with
+/// `Merk::register_index`.
+///
+/// Implemented for closures taking the key and value of the entry.
+pub trait KeyExtractor: Send {
+    /// Returns the keys the entry with the given key and value is indexed
+    /// under, which may be none.
+    fn index_keys(&self, key: &[u8], value: &[u8]) -> Vec<Vec<u8>>;
+}
+
+impl<F> KeyExtractor for F
+where
+    F: Fn(&[u8], &[u8]) -> Vec<Vec<u8>> + Send,
+{
+    fn index_keys(&self, key: &[u8], value: &[u8]) -> Vec<Vec<u8>> {
+        self(key, value)
+    }
+}
+
+/// Appends `bytes` to `out`, escaping zero bytes and adding a terminator, so
+/// that encoded values sort in the same order as the values and none is a
+/// prefix of another.
+fn encode_escaped(out: &mut Vec<u8>, bytes: &[u8]) {
+    for byte in bytes {
+        out.push(*byte);
+        if *byte == 0 {
+            out.push(0xff);
+        }
+    }
+    out.extend_from_slice(&[0, 0]);
+}
+
+/// Splits a value encoded with `encode_escaped` from the bytes following it.
+fn decode_escaped(bytes: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let mut value = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != 0 {
+            value.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1)? {
+            0xff => value.push(0),
+            0 => return Some((value, &bytes[i + 2..])),
+            _ => return None,
+        }
+        i += 2;
+    }
+    None
+}
+
+fn index_prefix(name: &str) -> Vec<u8> {
+    let mut index_prefix = INDEX_PREFIX.to_vec();
+    encode_escaped(&mut index_prefix, name.as_bytes());
+    index_prefix
+}
+
+fn entry_key(name: &str, index_key: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut entry_key = index_prefix(name);
+    encode_escaped(&mut entry_key, index_key);
+    entry_key.extend_from_slice(key);
+    entry_key
+}
+
+impl Merk {
+    /// Registers an index, which is updated by every subsequent commit.
+    /// Returns an error if an index with the same name is registered.
+    ///
+    /// Indexes are not persisted, so they must be registered each time the
+    /// store is opened, before any batch is applied. Entries already in the
+    /// store are only indexed once `rebuild_index` is called.
+    pub fn register_index<E: KeyExtractor + 'static>(
+        &mut self,
+        name: &str,
+        extractor: E,
+    ) -> Result<()> {
+        if self.indexes.contains_key(name) {
+            return Err(Error::Config(format!(
+                "An index named {:?} is already registered",
+                name
+            )));
+        }
+        self.indexes.insert(name.to_string(), Box::new(extractor));
+        Ok(())
+    }
+
+    /// Unregisters an index, returning false if it was not registered. Its
+    /// entries are kept, but no longer updated.
+    pub fn unregister_index(&mut self, name: &str) -> bool {
+        self.indexes.remove(name).is_some()
+    }
+
+    /// Deletes the entries of an index and indexes every entry in the store
+    /// again, in a single write. Returns an error if the index is not
+    /// registered, or if a block or import is in progress.
+    pub fn rebuild_index(&mut self, name: &str) -> Result<()> {
+        self.ensure_registered(name)?;
+        if self.block.is_some() || self.import.is_some() {
+            return Err(Error::Unsupported(
+                "Indexes cannot be rebuilt during a block or import".into(),
+            ));
+        }
+
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+        let index_prefix = prefixed(&self.prefix, &index_prefix(name));
+        let mut iter = self
+            .db
+            .raw_iterator_cf_opt(aux_cf, prefix_read_opts(&index_prefix));
+        iter.seek_to_first();
+        while iter.valid() {
+            batch.delete_cf(aux_cf, iter.key().unwrap());
+            iter.next();
+        }
+        iter.status()?;
+        drop(iter);
+
+        let extractor = &self.indexes[name];
+        let mut start = vec![];
+        loop {
+            let entries = self.get_range(&start, None, 1_000)?;
+            for (key, value) in entries.iter() {
+                for index_key in extractor.index_keys(key, value) {
+                    batch.put_cf(
+                        aux_cf,
+                        self.prefixed(&entry_key(name, &index_key, key)),
+                        b"",
+                    );
+                }
+            }
+            match entries.last() {
+                Some((key, _)) => {
+                    start = key.clone();
+                    start.push(0);
+                }
+                None => break,
+            }
+        }
+
+        self.write(batch)
+    }
+
+    /// Returns the keys of the entries indexed under `index_key`, in key
+    /// order. Returns an error if the index is not registered.
+    ///
+    /// Index entries written during a block are only read once the block is
+    /// committed.
+    pub fn index_get(&self, name: &str, index_key: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut end = index_key.to_vec();
+        end.push(0);
+        Ok(self
+            .index_range(name, index_key, Some(&end), usize::MAX)?
+            .into_iter()
+            .map(|(_, key)| key)
+            .collect())
+    }
+
+    /// Returns up to `limit` entries of an index with index keys in
+    /// `start..end`, or from `start` to the last index key if `end` is
+    /// `None`, as pairs of the index key and the key of the indexed entry,
+    /// ordered by index key and then by key. Returns an error if the index is
+    /// not registered.
+    ///
+    /// Index entries written during a block are only read once the block is
+    /// committed.
+    pub fn index_range(
+        &self,
+        name: &str,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.ensure_registered(name)?;
+
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let index_prefix = prefixed(&self.prefix, &index_prefix(name));
+        let mut iter = self
+            .db
+            .raw_iterator_cf_opt(aux_cf, prefix_read_opts(&index_prefix));
+        let mut seek_key = index_prefix.clone();
+        encode_escaped(&mut seek_key, start);
+        iter.seek(seek_key);
+
+        let mut entries = vec![];
+        while iter.valid() && entries.len() < limit {
+            let aux_key = iter.key().unwrap();
+            let (index_key, key) =
+                decode_escaped(&aux_key[index_prefix.len()..]).ok_or_else(|| {
+                    Error::Corruption {
+                        key: aux_key[self.prefix.len()..].to_vec(),
+                    }
+                })?;
+            if matches!(end, Some(end) if index_key.as_slice() >= end) {
+                break;
+            }
+            entries.push((index_key, key.to_vec()));
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(entries)
+    }
+
+    /// Returns the changes to the registered indexes made by `batch`, as aux
+    /// entries. Must be called before `batch` is applied.
+    pub(crate) fn index_entries(&self, batch: &Batch) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+        if self.indexes.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = vec![];
+        for (key, op) in batch {
+            let old_value = self.get(key)?;
+            let new_value = match op {
+                Op::Put(value) => Some(value),
+                Op::Delete => None,
+            };
+
+            for (name, extractor) in self.indexes.iter() {
+                let index_keys = |maybe_value: Option<&Vec<u8>>| -> BTreeSet<Vec<u8>> {
+                    maybe_value.map_or_else(BTreeSet::new, |value| {
+                        extractor.index_keys(key, value).into_iter().collect()
+                    })
+                };
+                let old_keys = index_keys(old_value.as_ref());
+                let new_keys = index_keys(new_value);
+
+                for index_key in old_keys.difference(&new_keys) {
+                    entries.push((entry_key(name, index_key, key), None));
+                }
+                for index_key in new_keys.difference(&old_keys) {
+                    entries.push((entry_key(name, index_key, key), Some(vec![])));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn ensure_registered(&self, name: &str) -> Result<()> {
+        if self.indexes.contains_key(name) {
+            Ok(())
+        } else {
+            Err(Error::Config(format!(
+                "No index named {:?} is registered",
+                name
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn by_first_byte(_: &[u8], value: &[u8]) -> Vec<Vec<u8>> {
+        value.first().map(|byte| vec![*byte]).into_iter().collect()
+    }
+
+    #[test]
+    fn escaped_encoding() {
+        let values: [&[u8]; 7] = [
+            b"",
+            b"\x00",
+            b"\x00\x00",
+            b"\x00\x01",
+            b"a",
+            b"a\x00",
+            b"ab",
+        ];
+        let mut encoded = vec![];
+        for value in values.iter() {
+            let mut bytes = vec![];
+            encode_escaped(&mut bytes, value);
+            bytes.extend_from_slice(b"rest");
+            assert_eq!(
+                decode_escaped(&bytes),
+                Some((value.to_vec(), b"rest".as_ref()))
+            );
+            encoded.push(bytes);
+        }
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(sorted, encoded);
+        assert_eq!(decode_escaped(b"a\x00\x01"), None);
+        assert_eq!(decode_escaped(b"a\x00"), None);
+    }
+
+    #[test]
+    fn maintain_index() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&[(vec![0], Op::Put(vec![9]))], &[]).unwrap();
+        merk.register_index("first", by_first_byte).unwrap();
+        assert!(merk.register_index("first", by_first_byte).is_err());
+        assert!(merk.index_get("other", &[1]).is_err());
+
+        merk.apply(
+            &[
+                (vec![1], Op::Put(vec![1, 2])),
+                (vec![2], Op::Put(vec![2])),
+                (vec![3], Op::Put(vec![1])),
+                (vec![4], Op::Put(vec![])),
+            ],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            merk.index_get("first", &[1]).unwrap(),
+            vec![vec![1], vec![3]]
+        );
+        assert_eq!(
+            merk.index_get("first", &[9]).unwrap(),
+            Vec::<Vec<u8>>::new()
+        );
+
+        // updates move entries between index keys, and deletes remove them
+        merk.apply(&[(vec![1], Op::Put(vec![2])), (vec![3], Op::Delete)], &[])
+            .unwrap();
+        assert_eq!(
+            merk.index_get("first", &[1]).unwrap(),
+            Vec::<Vec<u8>>::new()
+        );
+        assert_eq!(
+            merk.index_range("first", &[], None, 10).unwrap(),
+            vec![(vec![2], vec![1]), (vec![2], vec![2])]
+        );
+
+        // existing entries are indexed once the index is rebuilt
+        merk.rebuild_index("first").unwrap();
+        assert_eq!(
+            merk.index_range("first", &[2], Some(&[10][..]), 10)
+                .unwrap(),
+            vec![(vec![2], vec![1]), (vec![2], vec![2]), (vec![9], vec![0])]
+        );
+        assert_eq!(merk.index_range("first", &[0], None, 1).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,159 @@
+//! Provides `Merk::inspect`, a read-only view of the shape of the tree around
+//! a node, for debugging and for tools which check or visualize the tree.
+
+use super::Merk;
+use crate::tree::{Fetch, Hash, Link, Tree};
+use crate::Result;
+
+/// A node of the tree as returned by `Merk::inspect`, with its hashes, its
+/// balance and the links to its children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub key: Vec<u8>,
+    pub hash: Hash,
+    pub kv_hash: Hash,
+    /// The height of the node's subtree, counting the node itself.
+    pub height: u8,
+    /// The height of the right subtree minus the height of the left subtree,
+    /// which is always between -1 and 1 in a balanced tree.
+    pub balance_factor: i8,
+    pub left: Option<LinkInfo>,
+    pub right: Option<LinkInfo>,
+}
+
+impl NodeInfo {
+    /// Returns the heights of the left and right subtrees, 0 if there is no
+    /// child on that side.
+    pub fn child_heights(&self) -> (u8, u8) {
+        let height = |link: &Option<LinkInfo>| link.as_ref().map_or(0, LinkInfo::height);
+        (height(&self.left), height(&self.right))
+    }
+
+    /// Returns the link to the child on the given side, if any.
+    pub fn link(&self, left: bool) -> Option<&LinkInfo> {
+        if left {
+            self.left.as_ref()
+        } else {
+            self.right.as_ref()
+        }
+    }
+}
+
+/// A link from a node to one of its children, as stored in the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkInfo {
+    /// The key of the child.
+    pub key: Vec<u8>,
+    /// The hash of the child's subtree.
+    pub hash: Hash,
+    /// The heights of the child's left and right subtrees.
+    pub child_heights: (u8, u8),
+}
+
+impl LinkInfo {
+    /// Returns the height of the child's subtree.
+    pub fn height(&self) -> u8 {
+        1 + self.child_heights.0.max(self.child_heights.1)
+    }
+}
+
+impl Merk {
+    /// Returns the node with the given key, or `None` if the key is not in
+    /// the store. Nodes are read from memory if they are loaded (including
+    /// writes of a block in progress), otherwise from the database.
+    pub fn inspect(&self, key: &[u8]) -> Result<Option<NodeInfo>> {
+        self.use_tree(|maybe_tree| -> Result<Option<NodeInfo>> {
+            let mut cursor = match maybe_tree {
+                Some(tree) => tree,
+                None => return Ok(None),
+            };
+            let mut fetched;
+            loop {
+                if key == cursor.key() {
+                    return Ok(Some(node_info(cursor)));
+                }
+
+                let link = match cursor.link(key < cursor.key()) {
+                    Some(link) => link,
+                    None => return Ok(None),
+                };
+                match link.tree() {
+                    Some(child) => cursor = child,
+                    None => {
+                        fetched = self.source().fetch_by_key_expect(link.key())?;
+                        cursor = &fetched;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Returns the root node, or `None` if the store is empty.
+    pub fn inspect_root(&self) -> Option<NodeInfo> {
+        self.use_tree(|maybe_tree| maybe_tree.map(node_info))
+    }
+}
+
+fn node_info(tree: &Tree) -> NodeInfo {
+    let link_info = |link: &Link| LinkInfo {
+        key: link.key().to_vec(),
+        hash: *link.hash(),
+        child_heights: link.child_heights(),
+    };
+
+    NodeInfo {
+        key: tree.key().to_vec(),
+        hash: tree.hash(),
+        kv_hash: *tree.kv_hash(),
+        height: tree.height(),
+        balance_factor: tree.balance_factor(),
+        left: tree.link(true).map(link_info),
+        right: tree.link(false).map(link_info),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Merk;
+
+    #[test]
+    fn inspect_nodes() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        assert!(merk.inspect_root().is_none());
+        assert!(merk.inspect(&seq_key(0)).unwrap().is_none());
+
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        drop(merk);
+
+        // only the root is loaded after reopening, so the rest is fetched
+        let merk = Merk::open(&path).unwrap();
+        let root = merk.inspect_root().unwrap();
+        assert_eq!(root.hash, merk.root_hash());
+        assert_eq!(root.height, merk.height());
+
+        let mut stack = vec![root];
+        let mut count = 0;
+        while let Some(node) = stack.pop() {
+            assert!((-1..=1).contains(&node.balance_factor));
+            let (left_height, right_height) = node.child_heights();
+            assert_eq!(node.height, 1 + left_height.max(right_height));
+            assert_eq!(node.balance_factor, right_height as i8 - left_height as i8);
+
+            for left in [true, false] {
+                if let Some(link) = node.link(left) {
+                    let child = merk.inspect(&link.key).unwrap().unwrap();
+                    assert_eq!(child.hash, link.hash);
+                    assert_eq!(child.height, link.height());
+                    stack.push(child);
+                }
+            }
+            count += 1;
+        }
+        assert_eq!(count, 1_000);
+
+        assert!(merk.inspect(&seq_key(1_000)).unwrap().is_none());
+        merk.destroy().unwrap();
+    }
+}
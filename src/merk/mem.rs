@@ -0,0 +1,124 @@
+//! Provides `MemMerk`, a store which keeps its whole tree in memory without a
+//! database, e.g. for unit tests of code written against `Store`.
+
+use std::cell::Cell;
+
+use super::{prove_unchecked, root_hash};
+use crate::limits::Limits;
+use crate::proofs::Query;
+use crate::tree::{Batch, NoopCommit, PanicSource, Tree, Walker};
+use crate::{Hash, Result};
+
+/// A Merkle key/value store held entirely in memory. Its root hash and proofs
+/// match those of a `Merk` with the same entries, but nothing is persisted.
+#[derive(Default)]
+pub struct MemMerk {
+    tree: Cell<Option<Tree>>,
+}
+
+impl MemMerk {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the value for `key`, or `None` if it is not in the store.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.use_tree(|maybe_tree| {
+            maybe_tree
+                .and_then(|tree| super::get(tree, PanicSource {}, key).transpose())
+                .transpose()
+        })
+    }
+
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key in the store if `end` is `None`.
+    pub fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.use_tree(|maybe_tree| {
+            maybe_tree.map_or_else(Vec::new, |tree| {
+                tree.iter()
+                    .skip_while(|(key, _)| key.as_slice() < start)
+                    .take_while(|(key, _)| !matches!(end, Some(end) if key.as_slice() >= end))
+                    .take(limit)
+                    .collect()
+            })
+        }))
+    }
+
+    /// Returns the root hash of the tree, or the null hash if it is empty.
+    pub fn root_hash(&self) -> Hash {
+        self.use_tree(root_hash)
+    }
+
+    /// Applies a batch of operations, whose keys must be sorted and unique.
+    pub fn apply(&mut self, batch: &Batch) -> Result<()> {
+        Limits::default().check_batch(batch)?;
+
+        let maybe_walker = self
+            .tree
+            .take()
+            .map(|tree| Walker::new(tree, PanicSource {}));
+        let (mut maybe_tree, _) = Walker::apply_to(maybe_walker, batch, PanicSource {})?;
+        if let Some(tree) = maybe_tree.as_mut() {
+            tree.commit(&mut NoopCommit {})?;
+        }
+        self.tree.set(maybe_tree);
+        Ok(())
+    }
+
+    /// Creates a Merkle proof for the list of queried keys.
+    pub fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        let mut tree = self.tree.take();
+        let res = prove_unchecked(tree.as_mut(), PanicSource {}, query);
+        self.tree.set(tree);
+        res
+    }
+
+    fn use_tree<T>(&self, f: impl FnOnce(Option<&Tree>) -> T) -> T {
+        let tree = self.tree.take();
+        let res = f(tree.as_ref());
+        self.tree.set(tree);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn matches_merk() {
+        let mut merk = TempMerk::new().unwrap();
+        let mut mem = MemMerk::new();
+        assert_eq!(mem.root_hash(), merk.root_hash());
+
+        let batch = make_batch_seq(0..100);
+        merk.apply(&batch, &[]).unwrap();
+        mem.apply(&batch).unwrap();
+        let batch = vec![(seq_key(10), Op::Delete), (seq_key(200), Op::Put(vec![1]))];
+        merk.apply(&batch, &[]).unwrap();
+        mem.apply(&batch).unwrap();
+
+        assert_eq!(mem.root_hash(), merk.root_hash());
+        assert_eq!(mem.get(&seq_key(10)).unwrap(), None);
+        assert_eq!(mem.get(&seq_key(200)).unwrap(), Some(vec![1]));
+        assert_eq!(
+            mem.get_range(&seq_key(5), Some(&seq_key(20)), 8).unwrap(),
+            merk.get_range(&seq_key(5), Some(&seq_key(20)), 8).unwrap()
+        );
+
+        let query = || {
+            let mut query = Query::new();
+            query.insert_key(seq_key(20));
+            query
+        };
+        assert_eq!(mem.prove(query()).unwrap(), merk.prove(query()).unwrap());
+    }
+}
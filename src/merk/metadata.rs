@@ -0,0 +1,238 @@
+//! Persists a store's operating mode and hash algorithm, so that opening a
+//! store with a mismatched configuration fails instead of silently corrupting
+//! its history.
+
+use super::{prefixed, Merk, PruningPolicy, INTERNAL_CF_NAME};
+use crate::{Error, Result};
+use rocksdb::DB;
+use std::convert::TryInto;
+
+const METADATA_KEY: &[u8] = b"metadata";
+
+/// The hash algorithm a store's tree is built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha512_256,
+    /// Used by builds with the `blake3-hash` feature.
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Returns the hash algorithm this build hashes trees with.
+    pub fn current() -> Self {
+        if cfg!(feature = "blake3-hash") {
+            HashAlgorithm::Blake3
+        } else {
+            HashAlgorithm::Sha512_256
+        }
+    }
+}
+
+/// Whether a store keeps historical versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreMode {
+    /// Only the latest version is kept (a plain `Merk`).
+    Latest,
+    /// Historical versions are kept by a `VersionedMerk` according to its
+    /// pruning policy.
+    Versioned(PruningPolicy),
+}
+
+/// The configuration a store was created with, recorded in its database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreMetadata {
+    pub mode: StoreMode,
+    pub hash_algorithm: HashAlgorithm,
+}
+
+impl Default for StoreMetadata {
+    fn default() -> Self {
+        StoreMetadata {
+            mode: StoreMode::Latest,
+            hash_algorithm: HashAlgorithm::current(),
+        }
+    }
+}
+
+impl StoreMetadata {
+    /// Returns true if the store keeps every historical version.
+    pub fn is_archive(&self) -> bool {
+        self.mode == StoreMode::Versioned(PruningPolicy::KeepAll)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![match self.hash_algorithm {
+            HashAlgorithm::Sha512_256 => 0,
+            HashAlgorithm::Blake3 => 1,
+        }];
+        let (policy_tag, param) = match self.mode {
+            StoreMode::Latest => (0, 0),
+            StoreMode::Versioned(PruningPolicy::KeepAll) => (1, 0),
+            StoreMode::Versioned(PruningPolicy::KeepLast(n)) => (2, n),
+            StoreMode::Versioned(PruningPolicy::KeepEvery(interval)) => (3, interval),
+        };
+        bytes.push(policy_tag);
+        bytes.extend_from_slice(&param.to_be_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<StoreMetadata> {
+        if bytes.len() != 10 {
+            return Err(Error::Corruption {
+                key: METADATA_KEY.to_vec(),
+            });
+        }
+
+        let hash_algorithm = match bytes[0] {
+            0 => HashAlgorithm::Sha512_256,
+            1 => HashAlgorithm::Blake3,
+            tag => return Err(Error::Config(format!("Unsupported hash algorithm {}", tag))),
+        };
+        let param = u64::from_be_bytes(bytes[2..].try_into().unwrap());
+        let mode = match bytes[1] {
+            0 => StoreMode::Latest,
+            1 => StoreMode::Versioned(PruningPolicy::KeepAll),
+            2 => StoreMode::Versioned(PruningPolicy::KeepLast(param)),
+            3 => StoreMode::Versioned(PruningPolicy::KeepEvery(param)),
+            tag => return Err(Error::Config(format!("Unsupported store mode {}", tag))),
+        };
+
+        Ok(StoreMetadata {
+            mode,
+            hash_algorithm,
+        })
+    }
+}
+
+/// Loads the metadata of the store under `prefix`. Stores without metadata
+/// (new, or created before metadata was recorded) are plain stores, and their
+/// metadata is recorded if `writable`. Returns an error if the store is hashed
+/// with a different algorithm than this build.
+pub(crate) fn load_metadata(db: &DB, prefix: &[u8], writable: bool) -> Result<StoreMetadata> {
+    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+    match db.get_cf(internal_cf, prefixed(prefix, METADATA_KEY))? {
+        Some(bytes) => {
+            let metadata = StoreMetadata::decode(&bytes)?;
+            if metadata.hash_algorithm != HashAlgorithm::current() {
+                return Err(Error::Config(format!(
+                    "Store is hashed with {:?}, but this build uses {:?}",
+                    metadata.hash_algorithm,
+                    HashAlgorithm::current()
+                )));
+            }
+            Ok(metadata)
+        }
+        None => {
+            let metadata = StoreMetadata::default();
+            if writable {
+                db.put_cf(
+                    internal_cf,
+                    prefixed(prefix, METADATA_KEY),
+                    metadata.encode(),
+                )?;
+            }
+            Ok(metadata)
+        }
+    }
+}
+
+/// Returns an error if a store with `metadata` may not be opened as a plain
+/// `Merk`, which would let writes bypass its version history.
+pub(crate) fn ensure_latest_mode(metadata: &StoreMetadata) -> Result<()> {
+    match metadata.mode {
+        StoreMode::Latest => Ok(()),
+        StoreMode::Versioned(_) => Err(Error::Config(
+            "Store keeps historical versions and must be opened as a VersionedMerk".into(),
+        )),
+    }
+}
+
+impl Merk {
+    /// Returns the configuration recorded in the store's database.
+    pub fn metadata(&self) -> Result<StoreMetadata> {
+        load_metadata(&self.db, &self.prefix, false)
+    }
+
+    pub(crate) fn set_metadata(&self, metadata: &StoreMetadata) -> Result<()> {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        self.db
+            .put_cf(internal_cf, self.prefixed(METADATA_KEY), metadata.encode())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::{NodeCodec, VersionedMerk};
+
+    #[test]
+    fn encode_decode() {
+        for mode in [
+            StoreMode::Latest,
+            StoreMode::Versioned(PruningPolicy::KeepAll),
+            StoreMode::Versioned(PruningPolicy::KeepLast(3)),
+            StoreMode::Versioned(PruningPolicy::KeepEvery(100)),
+        ] {
+            for hash_algorithm in [HashAlgorithm::Sha512_256, HashAlgorithm::Blake3] {
+                let metadata = StoreMetadata {
+                    mode,
+                    hash_algorithm,
+                };
+                assert_eq!(StoreMetadata::decode(&metadata.encode()).unwrap(), metadata);
+            }
+        }
+        assert!(StoreMetadata::decode(&[9; 10]).is_err());
+    }
+
+    #[test]
+    fn mismatched_hash_algorithm() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let merk = Merk::open(&path).unwrap();
+        let other = match HashAlgorithm::current() {
+            HashAlgorithm::Sha512_256 => HashAlgorithm::Blake3,
+            HashAlgorithm::Blake3 => HashAlgorithm::Sha512_256,
+        };
+        merk.set_metadata(&StoreMetadata {
+            mode: StoreMode::Latest,
+            hash_algorithm: other,
+        })
+        .unwrap();
+        drop(merk);
+
+        assert!(Merk::open(&path).is_err());
+        let opts = Merk::default_db_opts();
+        rocksdb::DB::destroy(&opts, &path).unwrap();
+    }
+
+    #[test]
+    fn mismatched_mode() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.metadata().unwrap(), StoreMetadata::default());
+        drop(merk);
+
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepAll, NodeCodec::new()).unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        assert!(merk.metadata().unwrap().is_archive());
+        drop(merk);
+
+        assert!(Merk::open(&path).is_err());
+        assert!(VersionedMerk::open(&path, PruningPolicy::KeepLast(5), NodeCodec::new()).is_err());
+
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepAll, NodeCodec::new()).unwrap();
+        merk.set_pruning_policy(PruningPolicy::KeepLast(5)).unwrap();
+        drop(merk);
+
+        let merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepLast(5), NodeCodec::new()).unwrap();
+        assert_eq!(
+            merk.metadata().unwrap().mode,
+            StoreMode::Versioned(PruningPolicy::KeepLast(5))
+        );
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! Exposes RocksDB statistics and perf context counters, so merk-level latency
+//! can be correlated with storage behavior.
+
+use super::Merk;
+use crate::Result;
+use rocksdb::perf::{set_perf_stats, PerfContext, PerfMetric, PerfStatsLevel};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+thread_local! {
+    /// The current thread's perf stats level, as last set through
+    /// `Merk::set_perf_level`, since RocksDB has no way to read it back.
+    /// Starts at RocksDB's default level.
+    static PERF_LEVEL: Cell<PerfStatsLevel> = Cell::new(PerfStatsLevel::EnableCount);
+}
+
+/// Restores the perf stats level a thread had before `with_perf_context`,
+/// even if the operation panics.
+struct PerfLevelGuard(PerfStatsLevel);
+
+impl Drop for PerfLevelGuard {
+    fn drop(&mut self) {
+        Merk::set_perf_level(self.0);
+    }
+}
+
+/// Cumulative RocksDB statistics for a store, returned by `Merk::db_metrics`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DbMetrics {
+    /// RocksDB's ticker counters (e.g. `rocksdb.block.cache.hit`), keyed by
+    /// name. Empty unless statistics were enabled in the options the store was
+    /// opened with (see `rocksdb::Options::enable_statistics`).
+    pub tickers: BTreeMap<String, u64>,
+    /// The memory used by the block cache, in bytes.
+    pub block_cache_usage: u64,
+    /// The memory used by memtables, in bytes.
+    pub memtable_usage: u64,
+}
+
+/// Counters collected from RocksDB's perf context while running an operation
+/// with `Merk::with_perf_context`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PerfMetrics {
+    pub block_cache_hits: u64,
+    pub block_reads: u64,
+    pub bytes_read: u64,
+    pub memtable_gets: u64,
+    pub memtable_seeks: u64,
+    pub seeks: u64,
+    pub internal_keys_skipped: u64,
+}
+
+impl Merk {
+    /// Returns the store's cumulative RocksDB statistics.
+    pub fn db_metrics(&self) -> Result<DbMetrics> {
+        let tickers = self
+            .db
+            .property_value("rocksdb.options-statistics")?
+            .map(|stats| parse_tickers(&stats))
+            .unwrap_or_default();
+
+        Ok(DbMetrics {
+            tickers,
+            block_cache_usage: self.sum_int_property("rocksdb.block-cache-usage")?,
+            memtable_usage: self.sum_int_property("rocksdb.cur-size-all-mem-tables")?,
+        })
+    }
+
+    /// Sets RocksDB's perf stats level for the current thread, as
+    /// `rocksdb::perf::set_perf_stats` does, but also records it so that
+    /// `with_perf_context` can restore it. Applications which set their own
+    /// level should set it with this function.
+    pub fn set_perf_level(level: PerfStatsLevel) {
+        set_perf_stats(level);
+        PERF_LEVEL.with(|current| current.set(level));
+    }
+
+    /// Calls `f`, collecting RocksDB's perf context counters for the storage
+    /// operations it performs on the current thread.
+    ///
+    /// The thread's perf stats level is raised to count operations if it is
+    /// lower, then restored to the level set with `Merk::set_perf_level`
+    /// (or RocksDB's default) afterwards.
+    pub fn with_perf_context<T>(&self, f: impl FnOnce(&Merk) -> T) -> (T, PerfMetrics) {
+        let previous = PERF_LEVEL.with(Cell::get);
+        let _guard = PerfLevelGuard(previous);
+        if (previous as i32) < PerfStatsLevel::EnableCount as i32 {
+            Merk::set_perf_level(PerfStatsLevel::EnableCount);
+        }
+        let mut context = PerfContext::default();
+        context.reset();
+
+        let res = f(self);
+
+        let metrics = PerfMetrics {
+            block_cache_hits: context.metric(PerfMetric::BlockCacheHitCount),
+            block_reads: context.metric(PerfMetric::BlockReadCount),
+            bytes_read: context.metric(PerfMetric::BlockReadByte),
+            memtable_gets: context.metric(PerfMetric::GetFromMemtableCount),
+            memtable_seeks: context.metric(PerfMetric::SeekOnMemtableCount),
+            seeks: context.metric(PerfMetric::SeekChildSeekCount),
+            internal_keys_skipped: context.metric(PerfMetric::InternalKeySkippedCount),
+        };
+
+        (res, metrics)
+    }
+}
+
+/// Parses the ticker lines (`<name> COUNT : <count>`) of a RocksDB statistics
+/// dump, skipping histograms.
+fn parse_tickers(stats: &str) -> BTreeMap<String, u64> {
+    stats
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some("COUNT"), Some(":"), Some(count)) if parts.next().is_none() => {
+                    Some((name.to_string(), count.parse().ok()?))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn parse_statistics() {
+        let stats = "rocksdb.block.cache.miss COUNT : 12\n\
+                     rocksdb.block.cache.hit COUNT : 34\n\
+                     rocksdb.db.get.micros P50 : 1.0 P95 : 2.0 P99 : 3.0 P100 : 4.0 COUNT : 5 SUM : 6\n";
+        let tickers = parse_tickers(stats);
+        assert_eq!(tickers.len(), 2);
+        assert_eq!(tickers["rocksdb.block.cache.miss"], 12);
+        assert_eq!(tickers["rocksdb.block.cache.hit"], 34);
+    }
+
+    #[test]
+    fn collect_metrics() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut opts = Merk::default_db_opts();
+        opts.enable_statistics();
+
+        let mut merk = Merk::open_opt(&path, opts).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        drop(merk);
+
+        let mut opts = Merk::default_db_opts();
+        opts.enable_statistics();
+        let merk = Merk::open_opt(&path, opts).unwrap();
+
+        let (value, perf) = merk.with_perf_context(|merk| merk.get(&seq_key(0)).unwrap());
+        assert_eq!(value, Some(put_entry_value()));
+        assert!(perf.memtable_gets + perf.block_reads + perf.block_cache_hits > 0);
+
+        // the caller's level is kept, and a lower one is only raised for the
+        // duration of the call
+        for level in [PerfStatsLevel::EnableTime, PerfStatsLevel::Disable] {
+            Merk::set_perf_level(level);
+            let (_, perf) = merk.with_perf_context(|merk| merk.get(&seq_key(0)).unwrap());
+            assert!(perf.memtable_gets + perf.block_reads + perf.block_cache_hits > 0);
+            assert_eq!(PERF_LEVEL.with(Cell::get), level);
+        }
+
+        let metrics = merk.db_metrics().unwrap();
+        assert!(!metrics.tickers.is_empty());
+        assert!(metrics.tickers["rocksdb.number.keys.read"] > 0);
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Tracks the node encoding version of a store, and migrates stores written
+//! with an older version to the current one.
+
+use super::codec::ENCODING_VERSION;
+use super::count::{count_nodes, key_count_entry, read_key_count};
+use super::{prefix_read_opts, prefixed, Merk, NodeCodec, INTERNAL_CF_NAME};
+use crate::{Error, Result};
+use rocksdb::{WriteBatch, DB};
+
+const ENCODING_VERSION_KEY: &[u8] = b"encoding_version";
+const MIGRATION_CURSOR_KEY: &[u8] = b"encoding_migration_cursor";
+
+/// Reads the encoding version (and migration progress) of the store under
+/// `prefix` into `codec`.
+///
+/// Stores without a recorded version are either new, in which case they get
+/// the current version (recorded in the database if `writable`), or were
+/// created before versions were recorded, in which case they are version 0.
+pub(crate) fn load_encoding_version(
+    mut codec: NodeCodec,
+    db: &DB,
+    prefix: &[u8],
+    writable: bool,
+) -> Result<NodeCodec> {
+    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+
+    codec.version = match db.get_cf(internal_cf, prefixed(prefix, ENCODING_VERSION_KEY))? {
+        Some(version) => *version.first().ok_or_else(|| Error::Corruption {
+            key: ENCODING_VERSION_KEY.to_vec(),
+        })?,
+        None => {
+            let mut iter = db.raw_iterator_opt(prefix_read_opts(prefix));
+            iter.seek_to_first();
+            if iter.valid() {
+                0
+            } else {
+                if writable {
+                    db.put_cf(
+                        internal_cf,
+                        prefixed(prefix, ENCODING_VERSION_KEY),
+                        [ENCODING_VERSION],
+                    )?;
+                }
+                ENCODING_VERSION
+            }
+        }
+    };
+    codec.migration_cursor = db.get_cf(internal_cf, prefixed(prefix, MIGRATION_CURSOR_KEY))?;
+
+    Ok(codec)
+}
+
+/// Loads the number of keys in the store under `prefix`.
+///
+/// Stores written before key counts were recorded have their nodes counted
+/// once, and the count is recorded in the database if `writable`, after which
+/// each commit keeps it up to date.
+pub(crate) fn load_key_count(db: &DB, prefix: &[u8], writable: bool) -> Result<u64> {
+    if let Some(count) = read_key_count(db, prefix)? {
+        return Ok(count);
+    }
+
+    let count = count_nodes(db, prefix)?;
+    if writable {
+        let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let (key, value) = key_count_entry(count, prefix);
+        db.put_cf(internal_cf, key, value.unwrap())?;
+    }
+    Ok(count)
+}
+
+impl Merk {
+    /// Returns the encoding version of the store's nodes. This is older than
+    /// `ENCODING_VERSION` until `migrate_encoding` has completed.
+    pub fn encoding_version(&self) -> u8 {
+        self.codec.version
+    }
+
+    /// Rewrites up to `limit` nodes which are still stored with an older
+    /// encoding version in the current version, returning true once every node
+    /// has been migrated.
+    ///
+    /// Migration can be done all at once after opening the store (by passing
+    /// `usize::MAX`), or in small steps interleaved with other writes, e.g. from
+    /// a background task. Progress is persisted, so an interrupted migration
+    /// resumes where it left off when the store is reopened.
+    ///
+    /// Returns an error during a block or an import, since the nodes staged in
+    /// memory were encoded with the old version and would be written after the
+    /// migration has passed their keys.
+    pub fn migrate_encoding(&mut self, limit: usize) -> Result<bool> {
+        if self.codec.version == ENCODING_VERSION {
+            return Ok(true);
+        }
+        if self.in_block() || self.is_importing() {
+            return Err(Error::Unsupported(
+                "Cannot migrate the encoding during a block or an import".into(),
+            ));
+        }
+
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+        let mut last_key = None;
+        let done = {
+            let mut iter = self.raw_iter();
+            match &self.codec.migration_cursor {
+                Some(cursor) => {
+                    iter.seek(self.prefixed(cursor));
+                    if iter.valid() && iter.key() == Some(self.prefixed(cursor).as_slice()) {
+                        iter.next();
+                    }
+                }
+                None => iter.seek_to_first(),
+            }
+
+            let mut migrated = 0;
+            while iter.valid() && migrated < limit {
+                let key = &iter.key().unwrap()[self.prefix.len()..];
+                let node = self.codec.decode(key, iter.value().unwrap())?;
+                let bytes = self
+                    .codec
+                    .encode_version(key, ENCODING_VERSION, node.into_owned())?;
+                batch.put(iter.key().unwrap(), bytes);
+                last_key = Some(key.to_vec());
+                migrated += 1;
+                iter.next();
+            }
+            iter.status()?;
+            !iter.valid()
+        };
+
+        if done {
+            batch.put_cf(
+                internal_cf,
+                self.prefixed(ENCODING_VERSION_KEY),
+                [ENCODING_VERSION],
+            );
+            batch.delete_cf(internal_cf, self.prefixed(MIGRATION_CURSOR_KEY));
+        } else if let Some(last_key) = &last_key {
+            batch.put_cf(internal_cf, self.prefixed(MIGRATION_CURSOR_KEY), last_key);
+        }
+        self.write(batch)?;
+
+        if done {
+            self.codec.version = ENCODING_VERSION;
+            self.codec.migration_cursor = None;
+        } else if last_key.is_some() {
+            self.codec.migration_cursor = last_key;
+        }
+
+        Ok(done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    /// Rewrites a store as if it had been created before nodes had a version
+    /// header.
+    fn downgrade(merk: Merk) -> Merk {
+        let path = merk.path.clone();
+        let internal_cf = merk.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+        let mut iter = merk.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            batch.put(iter.key().unwrap(), &iter.value().unwrap()[1..]);
+            iter.next();
+        }
+        drop(iter);
+        batch.delete_cf(internal_cf, ENCODING_VERSION_KEY);
+        merk.db.write(batch).unwrap();
+        drop(merk);
+        Merk::open(path).unwrap()
+    }
+
+    #[test]
+    fn new_store_has_current_version() {
+        let merk = TempMerk::new().unwrap();
+        assert_eq!(merk.encoding_version(), ENCODING_VERSION);
+    }
+
+    #[test]
+    fn migrate_in_steps() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        let root_hash = merk.root_hash();
+
+        let mut merk = downgrade(merk);
+        assert_eq!(merk.encoding_version(), 0);
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), Some(put_entry_value()));
+
+        // nodes staged in a block can't be migrated
+        merk.begin_block().unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        assert!(merk.migrate_encoding(300).is_err());
+        merk.commit_block().unwrap();
+
+        assert!(!merk.migrate_encoding(300).unwrap());
+        // writes during the migration use the version expected for each key
+        merk.apply(&make_batch_seq(1_000..1_100), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        drop(merk);
+
+        // progress is persisted across reopening
+        let mut merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+        assert!(!merk.migrate_encoding(300).unwrap());
+        assert!(merk.migrate_encoding(usize::MAX).unwrap());
+        assert_eq!(merk.encoding_version(), ENCODING_VERSION);
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.encoding_version(), ENCODING_VERSION);
+        assert_eq!(merk.root_hash(), root_hash);
+        assert!(merk.scrub().unwrap().is_empty());
+        for i in 0..1_100 {
+            assert_eq!(merk.get(&seq_key(i)).unwrap(), Some(put_entry_value()));
+        }
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn empty_version_is_corruption() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let merk = Merk::open(&path).unwrap();
+        let internal_cf = merk.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        merk.db
+            .put_cf(internal_cf, ENCODING_VERSION_KEY, [])
+            .unwrap();
+        drop(merk);
+
+        let err = Merk::open(&path).err().unwrap();
+        assert!(matches!(err, Error::Corruption { .. }));
+        rocksdb::DB::destroy(&Merk::default_db_opts(), &path).unwrap();
+    }
+}
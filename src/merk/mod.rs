@@ -1,20 +1,135 @@
+mod accumulator;
+#[cfg(feature = "async")]
+mod async_merk;
+mod attestation;
+mod audit;
+mod backup;
+mod block;
+mod bridge;
+mod cache;
+mod changelog;
+mod checksum;
+mod chunk_server;
 pub mod chunks;
+pub mod codec;
+mod commit_record;
+mod consistency;
+mod cost;
+mod count;
+mod cursor;
+mod dump;
+mod entry;
+mod expiry;
+mod fork;
+mod gc;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod handle;
+mod height;
+mod import;
+mod index;
+mod inspect;
+mod mem;
+mod metadata;
+mod metrics;
+mod migration;
+mod move_range;
+mod options;
+mod overlay;
+mod prefetch;
+mod proof_cache;
+mod proof_service;
+mod range_sync;
+mod rank;
+mod raw_export;
+mod reader;
+mod recovery;
+mod replication;
 pub mod restore;
+mod secondary;
+mod select;
+mod shared;
+mod simulate;
 pub mod snapshot;
-
-use std::cell::Cell;
-use std::cmp::Ordering;
-use std::collections::LinkedList;
+mod stats;
+mod storage;
+mod store;
+mod telemetry;
+mod tombstone;
+mod typed;
+mod update;
+mod versioned;
+mod watch;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, LinkedList};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
 use rocksdb::DB;
-use rocksdb::{checkpoint::Checkpoint, ColumnFamilyDescriptor, WriteBatch};
-
+use rocksdb::{checkpoint::Checkpoint, ColumnFamilyDescriptor, ReadOptions, WriteBatch};
+
+use self::audit::{load_audit_log, AuditLog};
+use self::block::BlockBuffer;
+use self::cache::ValueCache;
+use self::changelog::{load_changelog, Changelog};
+use self::commit_record::load_root_with_recovery;
+use self::cost::Meter;
+use self::count::key_count_entry;
+use self::height::{height_entry, load_height};
+use self::import::ImportBuffer;
+use self::metadata::{ensure_latest_mode, load_metadata};
+use self::migration::{load_encoding_version, load_key_count};
+use self::prefetch::prefetch;
+use self::proof_cache::ProofCache;
+use self::reader::SharedView;
+use self::stats::ApplyCounters;
+use self::tombstone::{load_tombstones, Tombstones};
+use self::watch::Watchers;
 use crate::error::{Error, Result};
-use crate::proofs::{encode_into, query::QueryItem, Query};
+use crate::limits::Limits;
+use crate::proofs::{encode_into, encoded_len, query::QueryItem, Query};
 use crate::tree::{Batch, Commit, Fetch, GetResult, Hash, Op, RefWalker, Tree, Walker, NULL_HASH};
 
+#[cfg(feature = "async")]
+pub use self::async_merk::AsyncMerk;
+pub use self::attestation::{RootAttestation, RootSigner, ATTESTATION_PREFIX};
+pub use self::audit::{AuditEntry, AuditMode, AuditOps, AUDIT_LOG_PREFIX};
+pub use self::changelog::ChangeRecord;
+pub use self::chunk_server::{ChunkServer, ChunkServerConfig};
+pub use self::codec::{NodeCodec, ENCODING_VERSION};
+pub use self::commit_record::CommitRecord;
+pub use self::consistency::{LinkMismatch, LinkMismatchKind};
+pub use self::cost::{CostModel, NodeAccess};
+pub use self::cursor::Cursor;
+pub use self::dump::KvFormat;
+pub use self::entry::{Entry, PendingBatch};
+pub use self::expiry::{EXPIRY_INDEX_PREFIX, EXPIRY_PREFIX};
+pub use self::fork::Fork;
+pub use self::handle::SharedMerk;
+pub use self::index::{KeyExtractor, INDEX_PREFIX};
+pub use self::inspect::{LinkInfo, NodeInfo};
+pub use self::mem::MemMerk;
+pub use self::metadata::{HashAlgorithm, StoreMetadata, StoreMode};
+pub use self::metrics::{DbMetrics, PerfMetrics};
+pub use self::options::MerkOptions;
+pub use self::overlay::{Overlay, OverlayIter};
+pub use self::proof_cache::ProofCacheStats;
+pub use self::proof_service::ProofService;
+pub use self::range_sync::RangeChunkProducer;
+pub use self::reader::{MerkReader, ReadTransaction};
+pub use self::recovery::RecoveryReport;
+pub use self::simulate::SimulatedApply;
 pub use self::snapshot::Snapshot;
+pub use self::stats::{ApplyStats, ProofStats};
+pub use self::store::{Store, StoreMut};
+#[cfg(feature = "metrics")]
+pub use self::telemetry::describe_metrics;
+pub use self::tombstone::TOMBSTONE_PREFIX;
+pub use self::typed::TypedMerk;
+pub use self::versioned::{PruningPolicy, VersionedMerk};
+pub use self::watch::WatchEvent;
 
 const ROOT_KEY_KEY: &[u8] = b"root";
 const AUX_CF_NAME: &str = "aux";
@@ -28,11 +143,72 @@ fn column_families() -> Vec<ColumnFamilyDescriptor> {
     ]
 }
 
+fn column_family_names() -> [&'static str; 2] {
+    [AUX_CF_NAME, INTERNAL_CF_NAME]
+}
+
 /// A handle to a Merkle key/value store backed by RocksDB.
 pub struct Merk {
     pub(crate) tree: Cell<Option<Tree>>,
-    pub(crate) db: rocksdb::DB,
+    pub(crate) db: Arc<rocksdb::DB>,
     pub(crate) path: PathBuf,
+    pub(crate) codec: NodeCodec,
+    /// Prepended to every key this store writes, in all column families.
+    /// Empty unless the store was opened with `open_shared`.
+    pub(crate) prefix: Vec<u8>,
+    pub(crate) import: Option<ImportBuffer>,
+    pub(crate) block: Option<BlockBuffer>,
+    pub(crate) changelog: Option<Changelog>,
+    /// The height recorded by the last call to `apply_at_height`, if any.
+    pub(crate) height: Option<u64>,
+    /// The number of keys in the tree as of the last commit.
+    pub(crate) key_count: u64,
+    pub(crate) watchers: Watchers,
+    /// The last committed version, shared with readers once `reader` has been
+    /// called.
+    pub(crate) view: Option<SharedView>,
+    /// Recently read values, if enabled with `enable_value_cache`.
+    pub(crate) cache: Option<RefCell<ValueCache>>,
+    /// The counters for the last applied batch.
+    pub(crate) apply_stats: ApplyStats,
+    /// Signs the root hash of each commit, if set with `set_root_signer`.
+    pub(crate) signer: Option<Box<dyn RootSigner>>,
+    /// The limits on applied batches, set with `set_limits`.
+    pub(crate) limits: Limits,
+    /// The state of the audit log, if enabled with `enable_audit_log`.
+    pub(crate) audit: Option<AuditLog>,
+    /// Recently generated proofs, if enabled with `enable_proof_cache`.
+    pub(crate) proof_cache: Option<RefCell<ProofCache>>,
+    /// Whether trunks are cached in aux storage, set with
+    /// `enable_trunk_cache`.
+    pub(crate) trunk_cache: bool,
+    /// The state of the tombstones of deleted keys, if enabled with
+    /// `enable_tombstones`.
+    pub(crate) tombstones: Option<Tombstones>,
+    /// The indexes updated by each commit, registered with
+    /// `register_index`.
+    pub(crate) indexes: BTreeMap<String, Box<dyn KeyExtractor>>,
+}
+
+/// Options for a single commit.
+#[derive(Default)]
+pub(crate) struct CommitOptions {
+    /// If set, nothing is written if the resulting root hash differs, and the
+    /// tree is reloaded as in `discard_failed_commit`.
+    pub(crate) expected_root_hash: Option<Hash>,
+    /// If set, the height recorded along with the commit.
+    pub(crate) height: Option<u64>,
+    /// The number of keys in the tree after the commit, recorded along with
+    /// it. If not set, it is found by looking up each written key.
+    pub(crate) key_count: Option<u64>,
+    /// If set, records the nodes fetched, written and hashed.
+    pub(crate) meter: Option<Arc<Meter>>,
+    /// The tombstones of the keys deleted by the commit, written to aux
+    /// storage along with it if tombstones are enabled.
+    pub(crate) tombstones: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The changes to the registered indexes made by the commit, written to
+    /// aux storage along with it.
+    pub(crate) index_entries: Vec<(Vec<u8>, Option<Vec<u8>>)>,
 }
 
 pub type UseTreeMutResult = Result<Vec<(Vec<u8>, Option<Vec<u8>>)>>;
@@ -48,6 +224,31 @@ impl Merk {
     /// Opens a store with the specified file path and the given options. If no
     /// store exists at that path, one will be created.
     pub fn open_opt<P>(path: P, db_opts: rocksdb::Options) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+    {
+        Merk::open_with_codec(path, db_opts, NodeCodec::default())
+    }
+
+    /// Opens a store with the specified file path and the given options, using
+    /// `codec` to transform nodes as they are written to and read from disk
+    /// (e.g. to encrypt them). A store must always be opened with the same
+    /// codec it was created with.
+    pub fn open_with_codec<P>(path: P, db_opts: rocksdb::Options, codec: NodeCodec) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+    {
+        Merk::open_checked(path, db_opts, codec, false)
+    }
+
+    /// Opens a store, failing if its metadata says it keeps historical
+    /// versions unless `allow_versioned` is set.
+    pub(crate) fn open_checked<P>(
+        path: P,
+        db_opts: rocksdb::Options,
+        codec: NodeCodec,
+        allow_versioned: bool,
+    ) -> Result<Merk>
     where
         P: AsRef<Path>,
     {
@@ -55,11 +256,91 @@ impl Merk {
         path_buf.push(path);
         let db = rocksdb::DB::open_cf_descriptors(&db_opts, &path_buf, column_families())?;
 
-        Ok(Merk {
-            tree: Cell::new(load_root(&db)?),
+        Merk::load(Arc::new(db), path_buf, vec![], codec, true, allow_versioned)
+    }
+
+    /// Creates a handle to the store under `prefix` in `db`, checking its
+    /// metadata and loading all of its persisted state with `load_state`.
+    /// Every constructor goes through here, so state added to the store only
+    /// has to be loaded in one place.
+    ///
+    /// Stores which are not `writable` (secondary instances) are only read,
+    /// so missing metadata is not recorded and a torn root pointer is not
+    /// repaired.
+    pub(crate) fn load(
+        db: Arc<DB>,
+        path: PathBuf,
+        prefix: Vec<u8>,
+        codec: NodeCodec,
+        writable: bool,
+        allow_versioned: bool,
+    ) -> Result<Merk> {
+        let metadata = load_metadata(&db, &prefix, writable)?;
+        if !allow_versioned {
+            ensure_latest_mode(&metadata)?;
+        }
+
+        let mut merk = Merk::unloaded(db, path, prefix, codec);
+        merk.load_state(writable)?;
+        Ok(merk)
+    }
+
+    /// Creates a handle to the store under `prefix` in `db` without loading
+    /// any of its persisted state, so it has no root. Only used to inspect the
+    /// stored nodes of stores whose state can't be loaded.
+    fn unloaded(db: Arc<DB>, path: PathBuf, prefix: Vec<u8>, codec: NodeCodec) -> Merk {
+        Merk {
+            tree: Cell::new(None),
             db,
-            path: path_buf,
-        })
+            path,
+            codec,
+            prefix,
+            import: None,
+            block: None,
+            changelog: None,
+            height: None,
+            key_count: 0,
+            watchers: Watchers::default(),
+            view: None,
+            cache: None,
+            apply_stats: ApplyStats::default(),
+            signer: None,
+            limits: Limits::default(),
+            audit: None,
+            proof_cache: None,
+            trunk_cache: false,
+            tombstones: None,
+            indexes: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the state persisted in the database: the encoding version and
+    /// compression dictionary of the codec, the root node, and the state of
+    /// the changelog, audit log, tombstones, height and key count.
+    pub(crate) fn load_state(&mut self, writable: bool) -> Result<()> {
+        let codec = load_encoding_version(self.codec.clone(), &self.db, &self.prefix, writable)?;
+        #[cfg(feature = "compression")]
+        let codec = codec.load_dictionary(&self.db, &self.prefix)?;
+
+        let root = if writable {
+            load_root_with_recovery(&self.db, &self.prefix, &codec)?
+        } else {
+            load_root(&self.db, &self.prefix, &codec)?
+        };
+        let mut changelog = load_changelog(&self.db, &self.prefix)?;
+        if let (Some(changelog), Some(previous)) = (changelog.as_mut(), self.changelog.take()) {
+            changelog.keep_subscribers(previous);
+        }
+        self.changelog = changelog;
+        self.audit = load_audit_log(&self.db, &self.prefix)?;
+        self.tombstones = load_tombstones(&self.db, &self.prefix)?;
+        self.height = load_height(&self.db, &self.prefix)?;
+        self.key_count = load_key_count(&self.db, &self.prefix, writable)?;
+        self.codec = codec;
+        self.tree = Cell::new(root);
+        self.clear_cache();
+        self.publish_view();
+        Ok(())
     }
 
     pub fn default_db_opts() -> rocksdb::Options {
@@ -85,35 +366,79 @@ impl Merk {
     /// Gets an auxiliary value.
     pub fn get_aux(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let aux_cf = self.db.cf_handle(AUX_CF_NAME);
-        Ok(self.db.get_cf(aux_cf.unwrap(), key)?)
+        Ok(self.db.get_cf(aux_cf.unwrap(), self.prefixed(key))?)
     }
 
     /// Gets a value for the given key. If the key is not found, `None` is
     /// returned.
     ///
     /// Note that this is essentially the same as a normal RocksDB `get`, so
-    /// should be a fast operation and has almost no tree overhead.
+    /// should be a fast operation and has almost no tree overhead. Nodes which
+    /// are not in memory are read pinned, and only their values are decoded
+    /// and copied.
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.use_tree(|maybe_tree| {
+        if let Some(value) = self.cached_value(key) {
+            return Ok(value);
+        }
+
+        let value = self.use_tree(|maybe_tree| {
             maybe_tree
                 .and_then(|tree| get(tree, self.source(), key).transpose())
                 .transpose()
-        })
+        })?;
+        self.cache_value(key, &value);
+        Ok(value)
+    }
+
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key in the store if `end` is `None`.
+    ///
+    /// Entries are read by iterating over the stored nodes, so writes made
+    /// during a block or an import which have not been written to the database
+    /// yet are not included.
+    pub fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        read_range(
+            self.raw_iter(),
+            &self.prefix,
+            &self.codec,
+            start,
+            end,
+            limit,
+        )
     }
 
     /// Returns the root hash of the tree (a digest for the entire store which
     /// proofs can be checked against). If the tree is empty, returns the null
-    /// hash (zero-filled).
+    /// hash (zero-filled), which the empty proofs generated for an empty tree
+    /// verify against.
     pub fn root_hash(&self) -> Hash {
         self.use_tree(|maybe_tree| root_hash(maybe_tree))
     }
 
+    /// Sets the limits checked for every subsequently applied batch. Returns
+    /// an error if any limit is above what the encodings support.
+    pub fn set_limits(&mut self, limits: Limits) -> Result<()> {
+        limits.validate()?;
+        self.limits = limits;
+        Ok(())
+    }
+
+    /// Returns the limits checked for applied batches.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
     /// Applies a batch of operations (puts and deletes) to the tree.
     ///
-    /// This will fail if the keys in `batch` are not sorted and unique. This
-    /// check creates some overhead, so if you are sure your batch is sorted and
-    /// unique you can use the unsafe `apply_unchecked` for a small performance
-    /// gain.
+    /// This will fail if the keys in `batch` are not sorted and unique, or if
+    /// the batch exceeds the store's `limits`. This check creates some
+    /// overhead, so if you are sure your batch is valid you can use the unsafe
+    /// `apply_unchecked` for a small performance gain.
     ///
     /// # Example
     /// ```
@@ -129,26 +454,26 @@ impl Merk {
     /// store.apply(batch, &[]).unwrap();
     /// ```
     pub fn apply(&mut self, batch: &Batch, aux: &Batch) -> Result<()> {
-        // ensure keys in batch are sorted and unique
-        let mut maybe_prev_key: Option<Vec<u8>> = None;
-        for (key, _) in batch.iter() {
-            if let Some(prev_key) = maybe_prev_key {
-                match prev_key.cmp(key) {
-                    Ordering::Greater => {
-                        return Err(Error::BatchKey("Keys in batch must be sorted".into()));
-                    }
-                    Ordering::Equal => {
-                        return Err(Error::BatchKey("Keys in batch must be unique".into()));
-                    }
-                    _ => (),
-                }
-            }
-            maybe_prev_key = Some(key.to_vec());
-        }
-
+        self.limits.check_batch(batch)?;
         unsafe { self.apply_unchecked(batch, aux) }
     }
 
+    /// Applies a batch as in `apply` only if the current root hash (including
+    /// the changes of a block in progress) is `expected_root`, so writers
+    /// coordinating externally can detect that another writer changed the
+    /// store since they read it. Returns a `RootConflict` error without
+    /// applying anything otherwise.
+    pub fn apply_if_root(&mut self, batch: &Batch, aux: &Batch, expected_root: Hash) -> Result<()> {
+        let actual = self.root_hash();
+        if actual != expected_root {
+            return Err(Error::RootConflict {
+                expected: expected_root,
+                actual,
+            });
+        }
+        self.apply(batch, aux)
+    }
+
     /// Applies a batch of operations (puts and deletes) to the tree.
     ///
     /// # Safety
@@ -171,21 +496,73 @@ impl Merk {
     /// unsafe { store.apply_unchecked(batch, &[]).unwrap() };
     /// ```
     pub unsafe fn apply_unchecked(&mut self, batch: &Batch, aux: &Batch) -> Result<()> {
+        self.apply_sorted(batch, aux, CommitOptions::default())
+    }
+
+    /// Applies a batch of operations to the tree as in `apply_unchecked`, with
+    /// the given options for the commit.
+    ///
+    /// # Safety
+    /// The keys in `batch` must be sorted and unique.
+    pub(crate) unsafe fn apply_sorted(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+        mut options: CommitOptions,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let mut watch_events = self.watch_events(batch)?;
+        options.tombstones = self.tombstone_entries(batch)?;
+        options.index_entries = self.index_entries(batch)?;
+        if let Some(meter) = options.meter.as_deref() {
+            self.use_tree_mut(|maybe_tree| match maybe_tree {
+                Some(tree) => meter.record_paths(RefWalker::new(tree, self.source()), batch),
+                None => Ok(()),
+            })?;
+        }
+        let counters = ApplyCounters::default();
+        let source = MerkSource {
+            counters: Some(&counters),
+            ..self.source()
+        };
         let maybe_walker = self
             .tree
             .take()
             .take()
-            .map(|tree| Walker::new(tree, self.source()));
+            .map(|tree| Walker::new(tree, source.clone()));
 
-        let (maybe_tree, deleted_keys) = Walker::apply_to(maybe_walker, batch, self.source())?;
+        let (maybe_tree, deleted_keys) = Walker::apply_to(maybe_walker, batch, source)?;
+        let stats = counters.finish(maybe_tree.as_ref(), deleted_keys.len());
+        options.key_count =
+            Some((self.key_count + counters.inserted()).saturating_sub(deleted_keys.len() as u64));
         self.tree.set(maybe_tree);
+        self.invalidate_cached(batch);
 
         // commit changes to db
-        self.commit(deleted_keys, aux)
+        let height = options.height;
+        self.commit_batch(batch, deleted_keys, aux, options)?;
+        telemetry::record_apply(batch.len(), start.elapsed());
+        telemetry::record_apply_stats(&stats);
+        self.apply_stats = stats;
+
+        for event in watch_events.iter_mut() {
+            event.height = height;
+        }
+        match self.block.as_mut() {
+            // watchers are notified once the block is written
+            Some(block) => block.watch_events.extend(watch_events),
+            None => self.watchers.notify(watch_events),
+        }
+        Ok(())
     }
 
-    /// Closes the store and deletes all data from disk.
+    /// Closes the store and deletes all data from disk. For a shared store,
+    /// only the keys under its prefix are deleted.
     pub fn destroy(self) -> Result<()> {
+        if self.is_shared() {
+            return self.delete_prefixed();
+        }
+
         let opts = Merk::default_db_opts();
         let path = self.path.clone();
         drop(self);
@@ -198,6 +575,8 @@ impl Merk {
     pub fn repair(self) -> Result<Self> {
         use rocksdb::IteratorMode;
 
+        self.ensure_not_shared("repair")?;
+
         let path = self.path.clone();
 
         let create_path = |suffix| {
@@ -218,10 +597,10 @@ impl Merk {
             .db
             .iterator(IteratorMode::Start)
             .map(|(key, node_bytes)| {
-                node.decode_into(vec![], &node_bytes);
-                (key.to_vec(), Op::Put(node.value().to_vec()))
+                node.decode_into(vec![], &self.codec.decode(&key, &node_bytes)?)?;
+                Ok((key.to_vec(), Op::Put(node.value().to_vec())))
             })
-            .collect();
+            .collect::<Result<_>>()?;
 
         let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
         let aux: Vec<_> = self
@@ -230,9 +609,10 @@ impl Merk {
             .map(|(key, value)| (key.to_vec(), Op::Put(value.to_vec())))
             .collect();
 
+        let codec = self.codec.clone();
         drop(self);
 
-        let mut tmp = Self::open(&tmp_path)?;
+        let mut tmp = Self::open_with_codec(&tmp_path, Merk::default_db_opts(), codec.clone())?;
         tmp.apply(&batch, &aux)?;
         drop(tmp);
 
@@ -241,7 +621,7 @@ impl Merk {
         std::fs::rename(&tmp_path, &path)?;
         std::fs::remove_dir_all(&tmp_path2)?;
 
-        Self::open(path)
+        Self::open_with_codec(path, Merk::default_db_opts(), codec)
     }
 
     /// Creates a Merkle proof for the list of queried keys. For each key in the
@@ -277,61 +657,280 @@ impl Merk {
         Q: Into<QueryItem>,
         I: IntoIterator<Item = Q>,
     {
-        self.use_tree_mut(move |maybe_tree| {
-            prove_unchecked(maybe_tree, self.source(), query.into_iter())
+        let mut bytes = Vec::with_capacity(128);
+        self.prove_items_into(query, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Creates a Merkle proof for the queried keys as in `prove`, appending it
+    /// to `output` instead of returning a new buffer, so servers can encode
+    /// proofs into pooled buffers.
+    pub fn prove_into(&self, query: Query, output: &mut Vec<u8>) -> Result<()> {
+        self.prove_items_into(query, output)
+    }
+
+    /// Creates a proof that `key` is in the store, which includes the hash of
+    /// its key/value pair instead of its value, for clients which already
+    /// have the value. The proof is verified with
+    /// `proofs::query::verify_existence`, given the value.
+    ///
+    /// Returns an error if `key` is not in the store.
+    pub fn prove_existence(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let not_found = || Error::KeyNotFound(hex::encode(key));
+        self.use_tree_mut(|maybe_tree| {
+            let tree = maybe_tree.ok_or_else(not_found)?;
+            let mut walker = RefWalker::new(tree, self.source());
+            let proof = walker.create_existence_proof(key)?.ok_or_else(not_found)?;
+
+            let mut bytes = Vec::with_capacity(encoded_len(proof.iter()));
+            encode_into(proof.iter(), &mut bytes);
+            Ok(bytes)
+        })
+    }
+
+    fn prove_items_into<Q, I>(&self, query: I, output: &mut Vec<u8>) -> Result<()>
+    where
+        Q: Into<QueryItem>,
+        I: IntoIterator<Item = Q>,
+    {
+        let items: Vec<QueryItem> = query.into_iter().map(Into::into).collect();
+        self.prove_cached(items, output, move |items, output| {
+            self.use_tree_mut(move |mut maybe_tree| {
+                if let Some(tree) = maybe_tree.as_deref_mut() {
+                    prefetch(tree, &self.source(), &items)?;
+                }
+                prove_into(maybe_tree, self.source(), items, output)
+            })
         })
     }
 
+    /// Flushes the memtables of every column family to disk. Flushing them
+    /// together lets RocksDB drop the write-ahead log, which repair (see
+    /// `open_with_recovery`) would otherwise replay on top of the flushed
+    /// nodes.
     pub fn flush(&self) -> Result<()> {
-        Ok(self.db.flush()?)
+        self.db.flush()?;
+        for name in column_family_names() {
+            self.db.flush_cf(self.db.cf_handle(name).unwrap())?;
+        }
+        Ok(())
     }
 
+    /// Writes the changes made to the tree in memory, deleting the nodes with
+    /// the given keys. Since the changes are not known to be inserts or
+    /// updates, each written key is looked up to update the key count.
     pub fn commit(&mut self, deleted_keys: LinkedList<Vec<u8>>, aux: &Batch) -> Result<()> {
-        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
-        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        self.commit_batch(&[], deleted_keys, aux, CommitOptions::default())
+    }
 
-        let mut batch = rocksdb::WriteBatch::default();
+    /// Writes the changes made to the tree by applying `batch`, along with
+    /// the auxiliary data.
+    fn commit_batch(
+        &mut self,
+        batch: &Batch,
+        deleted_keys: LinkedList<Vec<u8>>,
+        aux: &Batch,
+        options: CommitOptions,
+    ) -> Result<()> {
+        let mut internal = Vec::with_capacity(2);
+        let mut record = None;
         let mut to_batch = self.use_tree_mut(|maybe_tree| -> UseTreeMutResult {
             // TODO: concurrent commit
             if let Some(tree) = maybe_tree {
                 // TODO: configurable committer
-                let mut committer = MerkCommitter::new(tree.height(), 100);
+                let mut committer = MerkCommitter::new(tree.height(), 100, &self.codec);
                 tree.commit(&mut committer)?;
 
                 // update pointer to root node
-                batch.put_cf(internal_cf, ROOT_KEY_KEY, tree.key());
+                internal.push((self.prefixed(ROOT_KEY_KEY), Some(tree.key().to_vec())));
+                record = Some(CommitRecord::new(tree));
 
                 Ok(committer.batch)
             } else {
                 // empty tree, delete pointer to root
-                batch.delete_cf(internal_cf, ROOT_KEY_KEY);
+                internal.push((self.prefixed(ROOT_KEY_KEY), None));
 
                 Ok(vec![])
             }
         })?;
 
+        if let Some(expected) = options.expected_root_hash {
+            let actual = record.as_ref().map_or(NULL_HASH, |record| record.root_hash);
+            if actual != expected {
+                self.discard_failed_commit()?;
+                return Err(Error::HashMismatch(expected, actual));
+            }
+        }
+
+        let root_hash = record.as_ref().map_or(NULL_HASH, |record| record.root_hash);
+        let attestation = match self.sign_root(root_hash, options.height) {
+            Ok(attestation) => attestation,
+            Err(err) => {
+                self.discard_failed_commit()?;
+                return Err(err);
+            }
+        };
+
         // TODO: move this to MerkCommitter impl?
         for key in deleted_keys {
             to_batch.push((key, None));
         }
         to_batch.sort_by(|a, b| a.0.cmp(&b.0));
-        for (key, maybe_value) in to_batch {
-            if let Some(value) = maybe_value {
-                batch.put(key, value);
-            } else {
-                batch.delete(key);
+        if let Some(meter) = options.meter.as_ref() {
+            meter.record_commit(batch, &to_batch, &self.codec)?;
+        }
+        let nodes: Vec<_> = to_batch
+            .into_iter()
+            .map(|(key, maybe_value)| (self.prefixed(&key), maybe_value))
+            .collect();
+        telemetry::record_commit(nodes.len());
+
+        let audit = self
+            .audit
+            .as_ref()
+            .map(|audit| audit.entries(root_hash, batch, aux));
+        let changes = self
+            .changelog
+            .as_ref()
+            .map(|_| (batch_entries(batch), batch_entries(aux)));
+        let mut aux: Vec<_> = aux
+            .iter()
+            .map(|(key, value)| match value {
+                Op::Put(value) => (self.prefixed(key), Some(value.clone())),
+                Op::Delete => (self.prefixed(key), None),
+            })
+            .collect();
+        if let Some((key, value)) = attestation {
+            aux.push((self.prefixed(&key), Some(value)));
+        }
+        let mut next_audit = None;
+        if let Some((next, entries)) = audit {
+            aux.extend(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (self.prefixed(&key), value)),
+            );
+            next_audit = Some(next);
+        }
+
+        // record the commit along with the root pointer, so a torn root
+        // pointer can be detected when the store is opened
+        internal.push(CommitRecord::entry(record.as_ref(), &self.prefix)?);
+        if let Some(height) = options.height {
+            internal.push(height_entry(height, &self.prefix));
+        }
+        let key_count = match options.key_count {
+            Some(count) => count,
+            None => self.count_after(&nodes)?,
+        };
+        internal.push(key_count_entry(key_count, &self.prefix));
+        let mut next_tombstones = None;
+        if let Some(tombstones) = self.tombstones.as_ref() {
+            let (next, entry) = tombstones.next(&self.prefix);
+            internal.push(entry);
+            aux.extend(
+                options
+                    .tombstones
+                    .into_iter()
+                    .map(|(key, value)| (self.prefixed(&key), Some(value))),
+            );
+            next_tombstones = Some(next);
+        }
+        aux.extend(
+            options
+                .index_entries
+                .into_iter()
+                .map(|(key, value)| (self.prefixed(&key), value)),
+        );
+
+        // record the changes in the same batch if the changelog is enabled
+        let mut change_record = None;
+        if let (Some(changelog), Some((batch, aux))) = (self.changelog.as_ref(), changes) {
+            let (record, entries) = changelog.entries(&self.prefix, root_hash, batch, aux);
+            internal.extend(entries);
+            change_record = Some(record);
+        }
+
+        if let Some(block) = self.block.as_mut() {
+            block.writes.stage(nodes, aux, internal);
+        } else if let Some(import) = self.import.as_mut() {
+            import.writes.stage(nodes, aux, internal);
+            if import.is_full() {
+                self.flush_import()?;
             }
+        } else {
+            self.write_entries(nodes, aux, internal)?;
         }
 
-        for (key, value) in aux {
-            match value {
-                Op::Put(value) => batch.put_cf(aux_cf, key, value),
-                Op::Delete => batch.delete_cf(aux_cf, key),
-            };
+        if let Some(change_record) = change_record {
+            let changelog = self.changelog.as_mut().unwrap();
+            match self.block.as_mut() {
+                // subscribers are notified once the block is written
+                Some(block) => {
+                    changelog.advance(change_record.height);
+                    block.change_records.push(change_record);
+                }
+                None => changelog.committed(change_record),
+            }
+        }
+        if next_audit.is_some() {
+            self.audit = next_audit;
+        }
+        if next_tombstones.is_some() {
+            self.tombstones = next_tombstones;
+        }
+        if options.height.is_some() {
+            self.height = options.height;
+        }
+        self.key_count = key_count;
+
+        Ok(())
+    }
+
+    /// Restores the state from before a commit which failed after its batch
+    /// was applied to the tree in memory. The tree as of a block's earlier
+    /// commits is not kept, so a block is aborted, discarding all of its
+    /// staged writes. An import's staged writes are written first, so the tree
+    /// is reloaded as of its last commit.
+    fn discard_failed_commit(&mut self) -> Result<()> {
+        self.block = None;
+        if self.is_importing() {
+            self.flush_import()?;
+        }
+        self.load_state(true)
+    }
+
+    /// Writes the entries staged by a commit to the database in a single
+    /// batch.
+    fn write_entries<I>(&mut self, nodes: I, aux: I, internal: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
+    {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key, maybe_value) in nodes {
+            match maybe_value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        for (key, maybe_value) in aux {
+            match maybe_value {
+                Some(value) => batch.put_cf(aux_cf, key, value),
+                None => batch.delete_cf(aux_cf, key),
+            }
+        }
+        for (key, maybe_value) in internal {
+            match maybe_value {
+                Some(value) => batch.put_cf(internal_cf, key, value),
+                None => batch.delete_cf(internal_cf, key),
+            }
         }
 
         // write to db
         self.write(batch)?;
+        self.publish_view();
 
         Ok(())
     }
@@ -346,21 +945,44 @@ impl Merk {
         res
     }
 
+    /// Returns a raw iterator over the stored nodes. For a shared store,
+    /// iteration is bounded to the store's prefix, and the keys returned
+    /// include it.
     pub fn raw_iter(&self) -> rocksdb::DBRawIterator {
-        self.db.raw_iterator()
+        self.db.raw_iterator_opt(prefix_read_opts(&self.prefix))
     }
 
     pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<Merk> {
-        Checkpoint::new(&self.db)?.create_checkpoint(&path)?;
-        Merk::open(path)
+        self.ensure_not_shared("checkpoint")?;
+        Checkpoint::new(&*self.db)?.create_checkpoint(&path)?;
+        Merk::open_checked(path, Merk::default_db_opts(), self.codec.clone(), true)
     }
 
     pub fn snapshot(&self) -> Result<Snapshot> {
-        Ok(Snapshot::new(self.db.snapshot(), load_root(&self.db)?))
+        Ok(Snapshot::new(
+            self.db.snapshot(),
+            load_root(&self.db, &self.prefix, &self.codec)?,
+            &self.codec,
+            &self.prefix,
+        ))
+    }
+
+    /// Returns a source which fetches the store's nodes from the database, for
+    /// walking pruned parts of the tree (e.g. wrapped in a
+    /// `test_utils::FlakySource` to inject failures).
+    pub fn source(&self) -> MerkSource {
+        MerkSource {
+            db: &self.db,
+            codec: &self.codec,
+            prefix: &self.prefix,
+            meter: None,
+            counters: None,
+        }
     }
 
-    fn source(&self) -> MerkSource {
-        MerkSource { db: &self.db }
+    /// Prepends the store's prefix to `key`.
+    pub(crate) fn prefixed(&self, key: &[u8]) -> Vec<u8> {
+        prefixed(&self.prefix, key)
     }
 
     fn use_tree<T>(&self, f: impl FnOnce(Option<&Tree>) -> T) -> T {
@@ -378,18 +1000,22 @@ impl Merk {
     }
 
     pub(crate) fn write(&mut self, batch: WriteBatch) -> Result<()> {
-        let mut opts = rocksdb::WriteOptions::default();
-        opts.set_sync(false);
-        // TODO: disable WAL once we can ensure consistency with transactions
-        self.db.write_opt(batch, &opts)?;
-        Ok(())
+        write_batch(&self.db, batch)
     }
 
-    pub(crate) fn set_root_key(&mut self, key: Vec<u8>) -> Result<()> {
+    /// Points the store at the root node `key` of a tree written directly to
+    /// the database, which has `key_count` nodes.
+    pub(crate) fn set_root_key(&mut self, key: Vec<u8>, key_count: u64) -> Result<()> {
         let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
         let mut batch = WriteBatch::default();
-        batch.put_cf(internal_cf, ROOT_KEY_KEY, key);
-        self.write(batch)
+        batch.put_cf(internal_cf, self.prefixed(ROOT_KEY_KEY), key);
+        // the record is rewritten on the next commit or open
+        CommitRecord::write(None, &self.db, &self.prefix, &mut batch)?;
+        self.set_key_count(key_count, &mut batch);
+        self.write(batch)?;
+        self.clear_cache();
+        self.publish_view();
+        Ok(())
     }
 
     pub(crate) fn fetch_node(&self, key: &[u8]) -> Result<Option<Tree>> {
@@ -397,8 +1023,11 @@ impl Merk {
     }
 
     pub(crate) fn load_root(&mut self) -> Result<()> {
-        let root = load_root(&self.db)?;
+        let root = load_root(&self.db, &self.prefix, &self.codec)?;
         self.tree = Cell::new(root);
+        self.key_count = load_key_count(&self.db, &self.prefix, true)?;
+        self.clear_cache();
+        self.publish_view();
         Ok(())
     }
 }
@@ -406,37 +1035,92 @@ impl Merk {
 #[derive(Clone)]
 pub struct MerkSource<'a> {
     db: &'a rocksdb::DB,
+    codec: &'a NodeCodec,
+    prefix: &'a [u8],
+    meter: Option<&'a Meter>,
+    counters: Option<&'a ApplyCounters>,
 }
 
 impl<'a> Fetch for MerkSource<'a> {
     fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
-        Ok(self
-            .db
-            .get_pinned(key)?
-            .map(|bytes| Tree::decode(key.to_vec(), &bytes)))
+        telemetry::record_fetch();
+        self.db
+            .get_pinned(prefixed(self.prefix, key))?
+            .map(|bytes| {
+                if let Some(meter) = self.meter {
+                    meter.record(NodeAccess::Fetch, key, bytes.len());
+                }
+                if let Some(counters) = self.counters {
+                    counters.record_fetch();
+                }
+                let bytes = self.codec.decode(key, &bytes)?;
+                Tree::decode(key.to_vec(), &bytes)
+            })
+            .transpose()
+    }
+
+    fn fetch_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        telemetry::record_fetch();
+        self.db
+            .get_pinned(prefixed(self.prefix, key))?
+            .map(|bytes| {
+                if let Some(meter) = self.meter {
+                    meter.record(NodeAccess::Fetch, key, bytes.len());
+                }
+                if let Some(counters) = self.counters {
+                    counters.record_fetch();
+                }
+                let bytes = self.codec.decode(key, &bytes)?;
+                Ok(Tree::decode_value(&bytes)?.to_vec())
+            })
+            .transpose()
+    }
+
+    fn record_rotation(&self) {
+        if let Some(counters) = self.counters {
+            counters.record_rotation();
+        }
     }
+
+    fn record_inserts(&self, count: usize) {
+        if let Some(counters) = self.counters {
+            counters.record_inserts(count);
+        }
+    }
+}
+
+/// Writes `batch` without syncing, as done for every write to the tree.
+pub(crate) fn write_batch(db: &DB, batch: WriteBatch) -> Result<()> {
+    let mut opts = rocksdb::WriteOptions::default();
+    opts.set_sync(false);
+    // TODO: disable WAL once we can ensure consistency with transactions
+    db.write_opt(batch, &opts)?;
+    Ok(())
 }
 
-struct MerkCommitter {
+struct MerkCommitter<'a> {
     batch: Vec<(Vec<u8>, Option<Vec<u8>>)>,
     height: u8,
     levels: u8,
+    codec: &'a NodeCodec,
 }
 
-impl MerkCommitter {
-    fn new(height: u8, levels: u8) -> Self {
+impl<'a> MerkCommitter<'a> {
+    fn new(height: u8, levels: u8, codec: &'a NodeCodec) -> Self {
         MerkCommitter {
             batch: Vec::with_capacity(10000),
             height,
             levels,
+            codec,
         }
     }
 }
 
-impl Commit for MerkCommitter {
+impl<'a> Commit for MerkCommitter<'a> {
     fn write(&mut self, tree: &Tree) -> Result<()> {
         let mut buf = Vec::with_capacity(tree.encoding_length());
         tree.encode_into(&mut buf);
+        let buf = self.codec.encode(tree.key(), buf)?;
         self.batch.push((tree.key().to_vec(), Some(buf)));
         Ok(())
     }
@@ -452,7 +1136,7 @@ pub fn get<F: Fetch>(tree: &Tree, source: F, key: &[u8]) -> Result<Option<Vec<u8
     Ok(match tree.get_value(key)? {
         GetResult::Found(value) => Some(value),
         GetResult::NotFound => None,
-        GetResult::Pruned => source.fetch_by_key(key)?.map(|node| node.value().to_vec()),
+        GetResult::Pruned => source.fetch_value(key)?,
     })
 }
 
@@ -461,6 +1145,23 @@ fn root_hash(maybe_tree: Option<&Tree>) -> Hash {
 }
 
 fn prove_unchecked<Q, I, F>(maybe_tree: Option<&mut Tree>, source: F, query: I) -> Result<Vec<u8>>
+where
+    Q: Into<QueryItem>,
+    I: IntoIterator<Item = Q>,
+    F: Fetch + Send + Clone,
+{
+    let mut bytes = Vec::with_capacity(128);
+    prove_into(maybe_tree, source, query, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Creates a proof for `query` and appends its encoding to `output`.
+fn prove_into<Q, I, F>(
+    maybe_tree: Option<&mut Tree>,
+    source: F,
+    query: I,
+    output: &mut Vec<u8>,
+) -> Result<()>
 where
     Q: Into<QueryItem>,
     I: IntoIterator<Item = Q>,
@@ -468,29 +1169,124 @@ where
 {
     let query_vec: Vec<QueryItem> = query.into_iter().map(Into::into).collect();
 
-    let tree =
-        maybe_tree.ok_or_else(|| Error::Proof("Cannot create proof for empty tree".into()))?;
+    // the proof for an empty tree is empty, and verifies against `NULL_HASH`
+    // with every queried key proven absent
+    let tree = match maybe_tree {
+        Some(tree) => tree,
+        None => {
+            telemetry::record_proof(0);
+            return Ok(());
+        }
+    };
 
     let mut ref_walker = RefWalker::new(tree, source);
     let (proof, _) = ref_walker.create_proof(query_vec.as_slice())?;
 
-    let mut bytes = Vec::with_capacity(128);
-    encode_into(proof.iter(), &mut bytes);
-    Ok(bytes)
+    let len = encoded_len(proof.iter());
+    output.reserve(len);
+    encode_into(proof.iter(), output);
+    telemetry::record_proof(len);
+    Ok(())
+}
+
+/// Reads up to `limit` entries with keys in `start..end` (or from `start` to
+/// the last key if `end` is `None`) from a raw iterator over stored nodes.
+fn read_range(
+    mut iter: rocksdb::DBRawIterator,
+    prefix: &[u8],
+    codec: &NodeCodec,
+    start: &[u8],
+    end: Option<&[u8]>,
+    limit: usize,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    iter.seek(prefixed(prefix, start));
+
+    let mut entries = vec![];
+    let mut node = Tree::new(vec![], vec![])?;
+    while iter.valid() && entries.len() < limit {
+        let key = &iter.key().unwrap()[prefix.len()..];
+        if matches!(end, Some(end) if key >= end) {
+            break;
+        }
+
+        let bytes = codec.decode(key, iter.value().unwrap())?;
+        node.decode_into(vec![], &bytes)?;
+        entries.push((key.to_vec(), node.value().to_vec()));
+
+        iter.next();
+    }
+    iter.status()?;
+
+    Ok(entries)
 }
 
-fn load_root(db: &DB) -> Result<Option<Tree>> {
+fn load_root(db: &DB, prefix: &[u8], codec: &NodeCodec) -> Result<Option<Tree>> {
     let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
-    db.get_pinned_cf(internal_cf, ROOT_KEY_KEY)?
-        .map(|key| MerkSource { db }.fetch_by_key_expect(key.to_vec().as_slice()))
+    let source = MerkSource {
+        db,
+        codec,
+        prefix,
+        meter: None,
+        counters: None,
+    };
+    db.get_pinned_cf(internal_cf, prefixed(prefix, ROOT_KEY_KEY))?
+        .map(|key| source.fetch_by_key_expect(key.to_vec().as_slice()))
         .transpose()
 }
 
+/// Converts a batch of operations to puts (`Some`) and deletes (`None`).
+fn batch_entries(batch: &Batch) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+    batch
+        .iter()
+        .map(|(key, op)| match op {
+            Op::Put(value) => (key.clone(), Some(value.clone())),
+            Op::Delete => (key.clone(), None),
+        })
+        .collect()
+}
+
+fn prefixed(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut prefixed = Vec::with_capacity(prefix.len() + key.len());
+    prefixed.extend_from_slice(prefix);
+    prefixed.extend_from_slice(key);
+    prefixed
+}
+
+/// Returns read options which bound iteration to keys starting with `prefix`.
+fn prefix_read_opts(prefix: &[u8]) -> ReadOptions {
+    let mut opts = ReadOptions::default();
+    if prefix.is_empty() {
+        return opts;
+    }
+
+    opts.set_iterate_lower_bound(prefix.to_vec());
+    if let Some(upper_bound) = prefix_upper_bound(prefix) {
+        opts.set_iterate_upper_bound(upper_bound);
+    }
+
+    opts
+}
+
+/// Returns the smallest key which is greater than every key starting with
+/// `prefix` (the prefix incremented as a big-endian integer), or `None` if
+/// there is no such key.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper_bound = prefix.to_vec();
+    while let Some(last) = upper_bound.pop() {
+        if last < u8::MAX {
+            upper_bound.push(last + 1);
+            return Some(upper_bound);
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Merk, MerkSource, RefWalker};
+    use super::{Merk, MerkSource, Query, RefWalker, NULL_HASH};
+    use crate::error::{Error, ErrorKind};
     use crate::test_utils::*;
-    use crate::Op;
+    use crate::{Limits, Op};
     use std::thread;
 
     // TODO: Close and then reopen test
@@ -568,6 +1364,51 @@ mod test {
         assert!(value.is_none());
     }
 
+    #[test]
+    fn delete_last_key() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(&path).expect("failed to open merk");
+
+        merk.apply(&[(vec![1], Op::Put(vec![2]))], &[]).unwrap();
+        merk.apply(&[(vec![1], Op::Delete)], &[]).unwrap();
+        assert_eq!(merk.root_hash(), NULL_HASH);
+        assert!(merk.get(&[1]).unwrap().is_none());
+
+        let proof = merk.prove(Query::from(vec![vec![1]])).unwrap();
+        assert!(proof.is_empty());
+        let map = crate::verify(&proof, NULL_HASH).unwrap();
+        assert_eq!(map.get(&[1]).unwrap(), None);
+        assert!(crate::verify(&proof, [1; 32]).is_err());
+
+        drop(merk);
+        let mut merk = TempMerk::open(&path).expect("failed to reopen merk");
+        assert_eq!(merk.root_hash(), NULL_HASH);
+        merk.apply(&[(vec![1], Op::Put(vec![3]))], &[]).unwrap();
+        assert_eq!(merk.get(&[1]).unwrap(), Some(vec![3]));
+    }
+
+    #[test]
+    fn existence_proofs() {
+        use crate::proofs::query::verify_existence;
+
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let root_hash = merk.root_hash();
+
+        let proof = merk.prove_existence(&seq_key(42)).unwrap();
+        let full_proof = merk.prove(Query::from(vec![seq_key(42)])).unwrap();
+        // the key's node is a 33-byte hash rather than its key and value
+        let kv_len = 4 + seq_key(42).len() + put_entry_value().len();
+        assert_eq!(proof.len(), full_proof.len() - kv_len + 33);
+        verify_existence(&proof, &seq_key(42), &put_entry_value(), root_hash).unwrap();
+        assert!(verify_existence(&proof, &seq_key(42), &[1], root_hash).is_err());
+        assert!(verify_existence(&proof, &seq_key(500), &put_entry_value(), root_hash).is_err());
+        assert!(verify_existence(&proof, &seq_key(42), &put_entry_value(), NULL_HASH).is_err());
+
+        let err = merk.prove_existence(&seq_key(100)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
     #[test]
     fn aux_data() {
         let path = thread::current().name().unwrap().to_owned();
@@ -629,6 +1470,85 @@ mod test {
         assert!(merk.get(&[3, 3, 3]).unwrap().is_none());
     }
 
+    #[test]
+    fn invalid_batches() {
+        let mut merk = TempMerk::new().unwrap();
+
+        let err = merk.apply(&[put_entry(2), put_entry(1)], &[]).unwrap_err();
+        assert!(matches!(&err, Error::UnsortedBatch { key } if *key == seq_key(1)));
+        assert_eq!(err.kind(), ErrorKind::Batch);
+
+        let err = merk.apply(&[put_entry(1), put_entry(1)], &[]).unwrap_err();
+        assert!(matches!(&err, Error::DuplicateBatchKey { key } if *key == seq_key(1)));
+
+        let err = merk
+            .apply(&[(vec![1; 256], Op::Put(vec![]))], &[])
+            .unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { max: 255, .. }));
+        assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+
+        // values too long to be proven can be written, but not proven
+        merk.apply(&[(vec![1], Op::Put(vec![0; 65_536]))], &[])
+            .unwrap();
+        let mut query = Query::new();
+        query.insert_key(vec![1]);
+        let err = merk.prove(query).unwrap_err();
+        assert!(matches!(&err, Error::LimitExceeded { key, max: 65_535, .. } if *key == vec![1]));
+        merk.apply(&[(vec![1], Op::Delete)], &[]).unwrap();
+
+        merk.set_limits(Limits {
+            max_value_len: 8,
+            max_batch_size: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let err = merk
+            .apply(&[put_entry(1), put_entry(2), put_entry(3)], &[])
+            .unwrap_err();
+        assert!(matches!(&err, Error::LimitExceeded { key, max: 2, .. } if *key == seq_key(3)));
+        assert!(merk.apply(&[put_entry(1)], &[]).is_err());
+        merk.apply(&[(vec![1], Op::Put(vec![0; 8]))], &[]).unwrap();
+    }
+
+    #[test]
+    fn apply_if_root() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply_if_root(&make_batch_seq(0..10), &[], NULL_HASH)
+            .unwrap();
+        let root_hash = merk.root_hash();
+
+        let err = merk
+            .apply_if_root(&make_batch_seq(10..20), &[], NULL_HASH)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::RootConflict { expected, actual } if expected == NULL_HASH && actual == root_hash
+        ));
+        assert_eq!(err.kind(), ErrorKind::Conflict);
+        assert_eq!(merk.root_hash(), root_hash);
+
+        merk.apply_if_root(&make_batch_seq(10..20), &[], root_hash)
+            .unwrap();
+        assert_ne!(merk.root_hash(), root_hash);
+    }
+
+    #[test]
+    fn get_range() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let entries = merk
+            .get_range(&seq_key(10), Some(&seq_key(20)), 100)
+            .unwrap();
+        let keys: Vec<_> = entries.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, (10..20).map(seq_key).collect::<Vec<_>>());
+
+        let entries = merk.get_range(&seq_key(95), None, 3).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2], (seq_key(97), put_entry_value()));
+        assert_eq!(merk.get_range(&seq_key(95), None, 100).unwrap().len(), 5);
+    }
+
     #[test]
     fn reopen() {
         fn collect(mut node: RefWalker<MerkSource>, nodes: &mut Vec<Vec<u8>>) {
@@ -805,4 +1725,22 @@ mod test {
 
         std::fs::remove_dir_all(&path).unwrap();
     }
+
+    #[test]
+    fn prove_into_buffer() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let query = || {
+            let mut query = Query::new();
+            query.insert_key(seq_key(5));
+            query.insert_range(seq_key(20)..seq_key(30));
+            query
+        };
+        let proof = merk.prove(query()).unwrap();
+        let mut buf = vec![1, 2, 3];
+        merk.prove_into(query(), &mut buf).unwrap();
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(&buf[3..], proof.as_slice());
+    }
 }
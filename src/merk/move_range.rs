@@ -0,0 +1,102 @@
+//! Provides `Merk::move_range`, which moves every key under one prefix to
+//! another prefix in a single commit.
+
+use std::collections::BTreeMap;
+
+use super::{prefix_upper_bound, Merk};
+use crate::tree::Op;
+use crate::{Error, Result};
+
+impl Merk {
+    /// Moves every key starting with `src_prefix` to the same key with
+    /// `dst_prefix` in place of `src_prefix`, keeping its value, and returns
+    /// the number of keys moved. The keys are deleted and put in a single
+    /// batch, applied as in `apply`, so the move is committed atomically (and
+    /// is subject to the batch size limit).
+    ///
+    /// Returns an error without changing anything if one prefix starts with
+    /// the other, if any destination key already exists, or if a block or
+    /// import is in progress, since the keys are read from the stored nodes.
+    pub fn move_range(&mut self, src_prefix: &[u8], dst_prefix: &[u8]) -> Result<usize> {
+        if self.block.is_some() || self.import.is_some() {
+            return Err(Error::Unsupported(
+                "Ranges cannot be moved during a block or import".into(),
+            ));
+        }
+        if src_prefix.starts_with(dst_prefix) || dst_prefix.starts_with(src_prefix) {
+            return Err(Error::Key(format!(
+                "Prefixes {:?} and {:?} overlap",
+                src_prefix, dst_prefix
+            )));
+        }
+
+        let end = prefix_upper_bound(src_prefix);
+        let entries = self.get_range(src_prefix, end.as_deref(), usize::MAX)?;
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        let mut batch = BTreeMap::new();
+        for (key, value) in entries.iter() {
+            let mut dst_key = dst_prefix.to_vec();
+            dst_key.extend_from_slice(&key[src_prefix.len()..]);
+            if self.get(&dst_key)?.is_some() {
+                return Err(Error::Key(format!(
+                    "Destination key {:?} already exists",
+                    dst_key
+                )));
+            }
+
+            batch.insert(key.clone(), Op::Delete);
+            batch.insert(dst_key, Op::Put(value.clone()));
+        }
+
+        let batch: Vec<_> = batch.into_iter().collect();
+        self.apply(&batch, &[])?;
+        Ok(entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Op;
+
+    fn put_batch(prefix: &[u8], range: std::ops::Range<u8>) -> Vec<(Vec<u8>, Op)> {
+        range
+            .map(|i| ([prefix, &[i]].concat(), Op::Put(vec![i])))
+            .collect()
+    }
+
+    #[test]
+    fn move_keys() {
+        let mut merk = TempMerk::new().unwrap();
+        let mut batch = put_batch(b"a/", 0..20);
+        batch.extend(put_batch(b"b/", 0..5));
+        merk.apply(&batch, &[]).unwrap();
+
+        assert_eq!(merk.move_range(b"a/", b"c/").unwrap(), 20);
+        assert_eq!(merk.get(b"a/\x03").unwrap(), None);
+        assert_eq!(merk.get(b"c/\x03").unwrap(), Some(vec![3]));
+
+        // the entries are the same as if the keys had been put under the new
+        // prefix
+        let mut expected = TempMerk::new().unwrap();
+        let mut batch = put_batch(b"b/", 0..5);
+        batch.extend(put_batch(b"c/", 0..20));
+        expected.apply(&batch, &[]).unwrap();
+        let entries = |merk: &TempMerk| merk.get_range(&[], None, usize::MAX).unwrap();
+        assert_eq!(entries(&merk), entries(&expected));
+
+        assert_eq!(merk.move_range(b"a/", b"d/").unwrap(), 0);
+        assert!(merk.move_range(b"c/", b"c/x").is_err());
+        assert!(merk.move_range(b"c/", b"").is_err());
+
+        // moves onto existing keys fail without changing anything
+        merk.apply(&put_batch(b"d/", 3..4), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        assert!(merk.move_range(b"c/", b"d/").is_err());
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(b"c/\x00").unwrap(), Some(vec![0]));
+    }
+}
@@ -0,0 +1,110 @@
+//! Provides `MerkOptions`, which configures where a store keeps its
+//! write-ahead log and when its memtables are flushed.
+
+use super::Merk;
+use crate::Result;
+use std::path::{Path, PathBuf};
+
+/// Options for opening a store with `Merk::open_with_options`, applied on top
+/// of `Merk::default_db_opts`. Options left as `None` keep RocksDB's defaults.
+///
+/// Commits only write to the write-ahead log and the memtables, so their
+/// latency mostly depends on the log's device and on how often memtable
+/// flushes stall writes.
+#[derive(Clone, Debug, Default)]
+pub struct MerkOptions {
+    /// The directory to keep the write-ahead log in, rather than the store's
+    /// directory, e.g. on a separate device so commits don't wait on a disk
+    /// shared with compactions. The store must always be opened with the same
+    /// directory, or writes which were not flushed yet are lost, and
+    /// `Merk::destroy` does not delete the logs kept here.
+    pub wal_dir: Option<PathBuf>,
+    /// The size a memtable grows to before it is flushed.
+    pub write_buffer_size: Option<usize>,
+    /// The size all memtables grow to, across column families, before they
+    /// are flushed.
+    pub db_write_buffer_size: Option<usize>,
+    /// The number of memtables kept in memory, including those being
+    /// flushed, before writes are stalled.
+    pub max_write_buffer_number: Option<i32>,
+    /// The number of full memtables merged into each flush.
+    pub min_write_buffer_number_to_merge: Option<i32>,
+    /// The size the write-ahead log grows to before the memtables it covers
+    /// are flushed so it can be deleted.
+    pub max_total_wal_size: Option<u64>,
+    /// The number of bytes written to the write-ahead log between background
+    /// syncs, which spreads syncing out rather than leaving it all to flushes.
+    pub wal_bytes_per_sync: Option<u64>,
+}
+
+impl MerkOptions {
+    /// Returns the RocksDB options for these options.
+    pub fn db_opts(&self) -> rocksdb::Options {
+        let mut opts = Merk::default_db_opts();
+        if let Some(wal_dir) = &self.wal_dir {
+            opts.set_wal_dir(wal_dir);
+        }
+        if let Some(size) = self.write_buffer_size {
+            opts.set_write_buffer_size(size);
+        }
+        if let Some(size) = self.db_write_buffer_size {
+            opts.set_db_write_buffer_size(size);
+        }
+        if let Some(number) = self.max_write_buffer_number {
+            opts.set_max_write_buffer_number(number);
+        }
+        if let Some(number) = self.min_write_buffer_number_to_merge {
+            opts.set_min_write_buffer_number_to_merge(number);
+        }
+        if let Some(size) = self.max_total_wal_size {
+            opts.set_max_total_wal_size(size);
+        }
+        if let Some(bytes) = self.wal_bytes_per_sync {
+            opts.set_wal_bytes_per_sync(bytes);
+        }
+        opts
+    }
+}
+
+impl Merk {
+    /// Opens a store with the specified file path and the given options. If no
+    /// store exists at that path, one will be created.
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: &MerkOptions) -> Result<Merk> {
+        Merk::open_opt(path, options.db_opts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn separate_wal_dir() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let wal_dir = PathBuf::from(format!("{}-wal", path));
+        let options = MerkOptions {
+            wal_dir: Some(wal_dir.clone()),
+            write_buffer_size: Some(1 << 20),
+            max_total_wal_size: Some(4 << 20),
+            ..Default::default()
+        };
+
+        let mut merk = Merk::open_with_options(&path, &options).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        drop(merk);
+
+        let logs = std::fs::read_dir(&wal_dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("log".as_ref()))
+            .count();
+        assert!(logs > 0);
+
+        let merk = Merk::open_with_options(&path, &options).unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), Some(put_entry_value()));
+        merk.destroy().unwrap();
+        std::fs::remove_dir_all(&wal_dir).unwrap();
+    }
+}
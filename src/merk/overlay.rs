@@ -0,0 +1,311 @@
+//! Provides `Overlay`, which stages writes in memory on top of a store, e.g.
+//! to build a block speculatively before committing it.
+
+use std::collections::{btree_map, BTreeMap};
+use std::iter::Peekable;
+use std::ops::Bound;
+
+use super::Merk;
+use crate::tree::{Batch, BatchEntry, Hash, Op, Tree};
+use crate::Result;
+
+/// Writes staged in memory on top of a `Merk`, created with `Merk::overlay`.
+///
+/// Reads through the overlay see its writes in place of the store's values,
+/// and `root_hash` computes the root hash the store would have with them
+/// applied. Nothing is written to the store until the batch returned by
+/// `into_batch` is applied.
+pub struct Overlay<'a> {
+    base: &'a Merk,
+    /// The staged writes, `None` for deletes.
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> Overlay<'a> {
+    /// Stages a put of `value` at `key`, replacing any staged write of `key`.
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.writes.insert(key, Some(value));
+    }
+
+    /// Stages a delete of `key`, replacing any staged write of `key`.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.writes.insert(key, None);
+    }
+
+    /// Stages every operation in `batch`, which does not need to be sorted.
+    /// Later operations on a key replace earlier ones.
+    pub fn apply(&mut self, batch: &Batch) {
+        for (key, op) in batch {
+            let maybe_value = match op {
+                Op::Put(value) => Some(value.clone()),
+                Op::Delete => None,
+            };
+            self.writes.insert(key.clone(), maybe_value);
+        }
+    }
+
+    /// Gets the value for `key`, from the staged writes if `key` was written
+    /// or from the store otherwise.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.get(key) {
+            Some(maybe_value) => Ok(maybe_value.clone()),
+            None => self.base.get(key),
+        }
+    }
+
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key if `end` is `None`, with the staged
+    /// writes applied to the store's entries, as returned by `range`.
+    pub fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.range(start, end).take(limit).collect()
+    }
+
+    /// Returns an iterator over all entries in key order, as in `range`.
+    pub fn iter(&self) -> OverlayIter {
+        self.range(&[], None)
+    }
+
+    /// Returns an iterator over the entries with keys in `start..end` in key
+    /// order, or from `start` to the last key if `end` is `None`, merging the
+    /// staged writes with the store's entries: staged puts replace the
+    /// store's values and staged deletes hide them.
+    ///
+    /// The store's entries are read by iterating over the stored nodes as in
+    /// `Merk::get_range`, so writes made during a block or an import which
+    /// have not been written to the database yet are not included.
+    pub fn range(&self, start: &[u8], end: Option<&[u8]>) -> OverlayIter {
+        // an empty range if `end` is before `start`
+        let end = end.map(|end| end.max(start));
+        let mut base = self.base.raw_iter();
+        base.seek(self.base.prefixed(start));
+        let writes = match end {
+            Some(end) => self
+                .writes
+                .range::<[u8], _>((Bound::Included(start), Bound::Excluded(end))),
+            None => self
+                .writes
+                .range::<[u8], _>((Bound::Included(start), Bound::Unbounded)),
+        };
+
+        OverlayIter {
+            merk: self.base,
+            base,
+            writes: writes.peekable(),
+            end: end.map(<[u8]>::to_vec),
+            done: matches!(end, Some(end) if start == end),
+        }
+    }
+
+    /// Returns the root hash the store would have with the staged writes
+    /// applied, computed as in `Merk::simulate_apply`.
+    pub fn root_hash(&self) -> Result<Hash> {
+        Ok(self.base.simulate_apply(&self.batch())?.root_hash)
+    }
+
+    /// Returns the number of keys with staged writes.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Returns true if no writes are staged.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Returns the staged writes as a sorted batch.
+    pub fn batch(&self) -> Vec<BatchEntry> {
+        self.writes
+            .iter()
+            .map(|(key, maybe_value)| batch_entry(key.clone(), maybe_value.clone()))
+            .collect()
+    }
+
+    /// Returns the staged writes as a sorted batch, which can be applied to
+    /// the store once the overlay is dropped.
+    pub fn into_batch(self) -> Vec<BatchEntry> {
+        self.writes
+            .into_iter()
+            .map(|(key, maybe_value)| batch_entry(key, maybe_value))
+            .collect()
+    }
+}
+
+/// An iterator over the entries of an `Overlay` in key order, created with
+/// `Overlay::iter` or `Overlay::range`.
+pub struct OverlayIter<'a> {
+    merk: &'a Merk,
+    base: rocksdb::DBRawIterator<'a>,
+    writes: Peekable<btree_map::Range<'a, Vec<u8>, Option<Vec<u8>>>>,
+    end: Option<Vec<u8>>,
+    /// Set once the range is exhausted or an error is returned.
+    done: bool,
+}
+
+impl<'a> OverlayIter<'a> {
+    /// Returns the key of the store's entry at the iterator, or `None` if
+    /// there are no more in the range.
+    fn base_key(&self) -> Result<Option<Vec<u8>>> {
+        if !self.base.valid() {
+            self.base.status()?;
+            return Ok(None);
+        }
+        let key = &self.base.key().unwrap()[self.merk.prefix.len()..];
+        if matches!(&self.end, Some(end) if key >= end.as_slice()) {
+            return Ok(None);
+        }
+        Ok(Some(key.to_vec()))
+    }
+
+    /// Decodes the store's entry at the iterator and moves past it.
+    fn next_base(&mut self, key: Vec<u8>) -> Result<(Vec<u8>, Vec<u8>)> {
+        let value = {
+            let bytes = self.merk.codec.decode(&key, self.base.value().unwrap())?;
+            Tree::decode_value(&bytes)?.to_vec()
+        };
+        self.base.next();
+        Ok((key, value))
+    }
+
+    fn next_entry(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            let base_key = self.base_key()?;
+            let write_key = self.writes.peek().map(|(key, _)| key.as_slice());
+            match (base_key, write_key) {
+                (None, None) => return Ok(None),
+                (Some(base_key), None) => return self.next_base(base_key).map(Some),
+                (Some(base_key), Some(write_key)) if base_key.as_slice() < write_key => {
+                    return self.next_base(base_key).map(Some)
+                }
+                (maybe_base_key, Some(write_key)) => {
+                    // a staged write replaces the store's entry with its key
+                    if maybe_base_key.as_deref() == Some(write_key) {
+                        self.base.next();
+                    }
+                    let (key, maybe_value) = self.writes.next().unwrap();
+                    if let Some(value) = maybe_value {
+                        return Ok(Some((key.clone(), value.clone())));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for OverlayIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.next_entry().transpose();
+        if !matches!(res, Some(Ok(_))) {
+            self.done = true;
+        }
+        res
+    }
+}
+
+fn batch_entry(key: Vec<u8>, maybe_value: Option<Vec<u8>>) -> BatchEntry {
+    match maybe_value {
+        Some(value) => (key, Op::Put(value)),
+        None => (key, Op::Delete),
+    }
+}
+
+impl Merk {
+    /// Creates an overlay with no staged writes on top of the store.
+    pub fn overlay(&self) -> Overlay {
+        Overlay {
+            base: self,
+            writes: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn overlay_writes() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..20), &[]).unwrap();
+        let root_hash = merk.root_hash();
+
+        let mut overlay = merk.overlay();
+        assert_eq!(overlay.root_hash().unwrap(), root_hash);
+        overlay.apply(&[
+            (seq_key(25), Op::Put(vec![25])),
+            (seq_key(3), Op::Delete),
+            (seq_key(1), Op::Delete),
+        ]);
+        overlay.put(seq_key(2), vec![2]);
+        overlay.put(seq_key(1), vec![1]);
+        assert_eq!(overlay.len(), 4);
+
+        assert_eq!(overlay.get(&seq_key(1)).unwrap(), Some(vec![1]));
+        assert_eq!(overlay.get(&seq_key(3)).unwrap(), None);
+        assert_eq!(overlay.get(&seq_key(4)).unwrap(), Some(put_entry_value()));
+        assert_eq!(overlay.get(&seq_key(25)).unwrap(), Some(vec![25]));
+
+        let range = overlay.get_range(&seq_key(0), None, 5).unwrap();
+        let keys: Vec<_> = range.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(keys, [0, 1, 2, 4, 5].map(seq_key).to_vec());
+        assert_eq!(range[2].1, vec![2]);
+        let range = overlay.get_range(&seq_key(18), None, 10).unwrap();
+        let keys: Vec<_> = range.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(keys, [18, 19, 25].map(seq_key).to_vec());
+        assert!(overlay
+            .get_range(&seq_key(5), Some(&seq_key(2)), 10)
+            .unwrap()
+            .is_empty());
+
+        // the store is unchanged until the batch is applied
+        let overlay_root_hash = overlay.root_hash().unwrap();
+        assert_ne!(overlay_root_hash, root_hash);
+        let batch = overlay.into_batch();
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(3)).unwrap(), Some(put_entry_value()));
+        merk.apply(&batch, &[]).unwrap();
+        assert_eq!(merk.root_hash(), overlay_root_hash);
+    }
+
+    #[test]
+    fn merged_iteration() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let mut overlay = merk.overlay();
+        let mut expected: BTreeMap<_, _> =
+            (0..100).map(|i| (seq_key(i), put_entry_value())).collect();
+        for i in (0..120).step_by(3) {
+            overlay.put(seq_key(i), vec![i as u8]);
+            expected.insert(seq_key(i), vec![i as u8]);
+        }
+        for i in (0..120).step_by(7) {
+            overlay.delete(seq_key(i));
+            expected.remove(&seq_key(i));
+        }
+
+        let entries: Vec<_> = overlay.iter().collect::<Result<_>>().unwrap();
+        assert_eq!(entries, expected.clone().into_iter().collect::<Vec<_>>());
+
+        let entries: Vec<_> = overlay
+            .range(&seq_key(10), Some(&seq_key(50)))
+            .collect::<Result<_>>()
+            .unwrap();
+        let expected_range: Vec<_> = expected
+            .range(seq_key(10)..seq_key(50))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        assert_eq!(entries, expected_range);
+        assert_eq!(overlay.range(&seq_key(10), Some(&seq_key(10))).count(), 0);
+    }
+}
@@ -0,0 +1,207 @@
+//! Loads nodes into memory ahead of time (the paths a proof will traverse, or
+//! the upper levels of the tree), reading each level of the tree with a single
+//! batched read instead of one read per node.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use super::{prefixed, telemetry, Merk, MerkSource, NodeAccess};
+use crate::proofs::query::QueryItem;
+use crate::tree::{Fetch, Link, Tree};
+use crate::Result;
+
+/// Nodes read ahead of time, each handed to `Tree::load` once.
+struct Prefetched(RefCell<HashMap<Vec<u8>, Tree>>);
+
+impl Fetch for Prefetched {
+    fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
+        Ok(self.0.borrow_mut().remove(key))
+    }
+}
+
+impl<'a> MerkSource<'a> {
+    /// Reads the nodes with the given keys with a single `multi_get`, so
+    /// RocksDB can issue the reads concurrently.
+    fn fetch_many(&self, keys: &[Vec<u8>]) -> Result<Prefetched> {
+        let prefixed_keys = keys.iter().map(|key| prefixed(self.prefix, key));
+        let mut nodes = HashMap::with_capacity(keys.len());
+        for (key, res) in keys.iter().zip(self.db.multi_get(prefixed_keys)) {
+            telemetry::record_fetch();
+            if let Some(bytes) = res? {
+                if let Some(meter) = self.meter {
+                    meter.record(NodeAccess::Fetch, key, bytes.len());
+                }
+                if let Some(counters) = self.counters {
+                    counters.record_fetch();
+                }
+                let bytes = self.codec.decode(key, &bytes)?;
+                nodes.insert(key.clone(), Tree::decode(key.clone(), &bytes)?);
+            }
+        }
+        Ok(Prefetched(RefCell::new(nodes)))
+    }
+}
+
+/// Loads the pruned nodes on the paths to the keys in `items` (which must be
+/// sorted), level by level, so the proof can then be created from memory.
+///
+/// Only the nodes which must be traversed to reach the queried keys are
+/// loaded. Any other nodes the proof needs (e.g. the neighbors proving a key's
+/// absence) are fetched when the proof is created.
+pub(crate) fn prefetch(tree: &mut Tree, source: &MerkSource, items: &[QueryItem]) -> Result<()> {
+    loop {
+        let mut frontier = vec![];
+        collect_pruned(tree, items, &mut frontier);
+        if frontier.is_empty() {
+            return Ok(());
+        }
+
+        let prefetched = source.fetch_many(&frontier)?;
+        for key in frontier {
+            load_pruned(tree, &key, &prefetched)?;
+        }
+    }
+}
+
+/// Collects the keys of the pruned nodes which are the closest to the root
+/// among those on the paths to the keys in `items`.
+fn collect_pruned(tree: &Tree, items: &[QueryItem], frontier: &mut Vec<Vec<u8>>) {
+    let key = tree.key();
+    let needs_left = items.iter().any(|item| item.lower_bound() < key);
+    let needs_right = items.iter().any(|item| item.upper_bound().0 > key);
+
+    for (left, needed) in [(true, needs_left), (false, needs_right)] {
+        match tree.link(left) {
+            Some(Link::Reference { key, .. }) if needed => frontier.push(key.clone()),
+            Some(link) if needed => {
+                if let Some(child) = link.tree() {
+                    collect_pruned(child, items, frontier);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collects the keys of the pruned nodes closest to the root among those in
+/// the `remaining` levels below `tree`.
+fn collect_pruned_levels(tree: &Tree, remaining: usize, frontier: &mut Vec<Vec<u8>>) {
+    if remaining == 0 {
+        return;
+    }
+
+    for left in [true, false] {
+        match tree.link(left) {
+            Some(Link::Reference { key, .. }) => frontier.push(key.clone()),
+            Some(link) => {
+                if let Some(child) = link.tree() {
+                    collect_pruned_levels(child, remaining - 1, frontier);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Descends to the parent of the pruned node with the given key and loads it
+/// from `prefetched`.
+fn load_pruned(tree: &mut Tree, key: &[u8], prefetched: &Prefetched) -> Result<()> {
+    let left = key < tree.key();
+    match tree.link(left) {
+        Some(Link::Reference { .. }) => tree.load(left, prefetched),
+        Some(_) => load_pruned(tree.child_mut(left).unwrap(), key, prefetched),
+        None => unreachable!("Prefetched key must be in the tree"),
+    }
+}
+
+impl Merk {
+    /// Loads the top `levels` levels of the tree into memory, e.g. right after
+    /// opening or restoring a store, so the first batches and queries do not
+    /// read each upper node from disk. Returns the number of nodes read.
+    ///
+    /// Loaded nodes stay in memory until the store is closed.
+    pub fn preload(&self, levels: usize) -> Result<usize> {
+        self.use_tree_mut(|maybe_tree| {
+            let tree = match maybe_tree {
+                Some(tree) => tree,
+                None => return Ok(0),
+            };
+
+            let source = self.source();
+            let mut count = 0;
+            loop {
+                let mut frontier = vec![];
+                collect_pruned_levels(tree, levels.saturating_sub(1), &mut frontier);
+                if frontier.is_empty() {
+                    return Ok(count);
+                }
+
+                count += frontier.len();
+                let prefetched = source.fetch_many(&frontier)?;
+                for key in frontier {
+                    load_pruned(tree, &key, &prefetched)?;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::tree::GetResult;
+    use crate::Merk;
+
+    #[test]
+    fn prefetch_proof_paths() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        drop(merk);
+
+        // only the root is loaded once the store is reopened
+        let merk = Merk::open(&path).unwrap();
+        let items = vec![
+            QueryItem::Key(seq_key(10)),
+            QueryItem::Range(seq_key(500)..seq_key(510)),
+        ];
+        let mut tree = merk.tree.take().unwrap();
+        prefetch(&mut tree, &merk.source(), &items).unwrap();
+        for n in [10, 500, 509] {
+            assert!(matches!(
+                tree.get_value(&seq_key(n)).unwrap(),
+                GetResult::Found(_)
+            ));
+        }
+        assert!(matches!(
+            tree.get_value(&seq_key(900)).unwrap(),
+            GetResult::Pruned
+        ));
+        merk.tree.set(Some(tree));
+
+        let proof = merk.prove_unchecked(items).unwrap();
+        crate::verify(&proof, merk.root_hash()).unwrap();
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn preload_levels() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.preload(1).unwrap(), 0);
+        assert_eq!(merk.preload(4).unwrap(), 2 + 4 + 8);
+        assert_eq!(merk.preload(4).unwrap(), 0);
+        merk.use_tree(|maybe_tree| {
+            let tree = maybe_tree.unwrap();
+            let child = tree.child(true).unwrap().child(false).unwrap();
+            assert!(child.child(true).is_some());
+            assert!(child.child(true).unwrap().child(true).is_none());
+        });
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+//! A size-bounded cache of generated proofs, keyed by the root hash and query
+//! they were generated for, so nodes serving many identical queries from light
+//! clients only build each proof once per version of the tree.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use super::Merk;
+use crate::proofs::query::QueryItem;
+use crate::tree::{Hash, NULL_HASH};
+use crate::Result;
+
+/// Counters for a store's proof cache, read with `Merk::proof_cache_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProofCacheStats {
+    /// The number of proofs served from the cache.
+    pub hits: u64,
+    /// The number of proofs which had to be generated.
+    pub misses: u64,
+    /// The number of cached proofs.
+    pub entries: usize,
+    /// The total size of the cached proofs and their queries, in bytes.
+    pub bytes: usize,
+}
+
+/// A least-recently-used cache of the proofs generated for the current root
+/// hash. All entries are dropped once a proof is requested for a different
+/// root hash, since they can't be served again.
+pub(crate) struct ProofCache {
+    capacity: usize,
+    root_hash: Hash,
+    /// Proofs by the encoding of their query, with the tick of their last use.
+    entries: HashMap<Vec<u8>, (Vec<u8>, u64)>,
+    /// Cached queries by the tick of their last use, oldest first.
+    order: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+    stats: ProofCacheStats,
+}
+
+impl ProofCache {
+    fn new(capacity: usize) -> Self {
+        ProofCache {
+            capacity,
+            root_hash: NULL_HASH,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            stats: Default::default(),
+        }
+    }
+
+    fn get(&mut self, root_hash: Hash, query: &[u8]) -> Option<&[u8]> {
+        if root_hash != self.root_hash {
+            self.clear();
+            self.root_hash = root_hash;
+        }
+
+        self.tick += 1;
+        match self.entries.get_mut(query) {
+            Some((proof, last_used)) => {
+                let query = self.order.remove(&*last_used).unwrap();
+                self.order.insert(self.tick, query);
+                *last_used = self.tick;
+                self.stats.hits += 1;
+                Some(proof.as_slice())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Caches a proof generated for the root hash of the last call to `get`,
+    /// evicting the least recently used proofs to make room for it. Proofs
+    /// larger than the whole cache are not cached.
+    fn insert(&mut self, query: Vec<u8>, proof: Vec<u8>) {
+        let size = query.len() + proof.len();
+        if size > self.capacity {
+            return;
+        }
+        while self.stats.bytes + size > self.capacity {
+            let oldest = *self.order.keys().next().unwrap();
+            let query = self.order.remove(&oldest).unwrap();
+            let (proof, _) = self.entries.remove(&query).unwrap();
+            self.stats.bytes -= query.len() + proof.len();
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, query.clone());
+        self.entries.insert(query, (proof, self.tick));
+        self.stats.bytes += size;
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.stats.bytes = 0;
+    }
+
+    fn stats(&self) -> ProofCacheStats {
+        ProofCacheStats {
+            entries: self.entries.len(),
+            ..self.stats
+        }
+    }
+}
+
+/// Encodes query items as a cache key. Items are length-prefixed, so distinct
+/// queries never share an encoding.
+fn encode_query(items: &[QueryItem]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for item in items {
+        let (tag, start, end): (u8, &[u8], &[u8]) = match item {
+            QueryItem::Key(key) => (0, key, &[]),
+            QueryItem::Range(range) => (1, &range.start, &range.end),
+            QueryItem::RangeInclusive(range) => (2, range.start(), range.end()),
+        };
+        bytes.push(tag);
+        for bound in [start, end] {
+            bytes.extend_from_slice(&(bound.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(bound);
+        }
+    }
+    bytes
+}
+
+impl Merk {
+    /// Caches generated proofs, up to `capacity` bytes of proofs and queries.
+    /// Subsequent calls to `prove` (and `prove_unchecked` and `prove_into`)
+    /// for a query already proven against the current root hash return the
+    /// cached proof. The cache is emptied whenever the root hash changes.
+    pub fn enable_proof_cache(&mut self, capacity: usize) {
+        self.proof_cache = Some(RefCell::new(ProofCache::new(capacity)));
+    }
+
+    /// Returns the counters of the proof cache, or `None` if it is not
+    /// enabled.
+    pub fn proof_cache_stats(&self) -> Option<ProofCacheStats> {
+        self.proof_cache
+            .as_ref()
+            .map(|cache| cache.borrow().stats())
+    }
+
+    /// Appends the proof of `items` to `output` from the proof cache, or
+    /// generates it with `prove` (and caches it if the cache is enabled).
+    pub(crate) fn prove_cached<F>(
+        &self,
+        items: Vec<QueryItem>,
+        output: &mut Vec<u8>,
+        prove: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(Vec<QueryItem>, &mut Vec<u8>) -> Result<()>,
+    {
+        let cache = match self.proof_cache.as_ref() {
+            Some(cache) => cache,
+            None => return prove(items, output),
+        };
+
+        let query = encode_query(&items);
+        if let Some(proof) = cache.borrow_mut().get(self.root_hash(), &query) {
+            output.extend_from_slice(proof);
+            return Ok(());
+        }
+
+        let start = output.len();
+        prove(items, output)?;
+        cache.borrow_mut().insert(query, output[start..].to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::Query;
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn size_based_eviction() {
+        let mut cache = ProofCache::new(10);
+        assert!(cache.get([1; 32], &[1]).is_none());
+        cache.insert(vec![1], vec![1; 4]);
+        cache.insert(vec![2], vec![2; 4]);
+        assert_eq!(cache.get([1; 32], &[1]), Some(&[1; 4][..]));
+
+        // evicts the least recently used proof
+        cache.insert(vec![3], vec![3; 4]);
+        assert!(cache.get([1; 32], &[2]).is_none());
+        assert!(cache.get([1; 32], &[1]).is_some());
+        assert_eq!(cache.stats().bytes, 10);
+
+        // too large to cache
+        cache.insert(vec![4], vec![4; 10]);
+        assert!(cache.get([1; 32], &[4]).is_none());
+
+        // a new root hash empties the cache
+        assert!(cache.get([2; 32], &[1]).is_none());
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn cached_until_root_changes() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        merk.enable_proof_cache(1 << 20);
+
+        let query = || Query::from(vec![seq_key(5), seq_key(50)]);
+        let proof = merk.prove(query()).unwrap();
+        assert_eq!(merk.prove(query()).unwrap(), proof);
+        let mut output = vec![1];
+        merk.prove_into(query(), &mut output).unwrap();
+        assert_eq!(output[1..], proof[..]);
+        let stats = merk.proof_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 1, 1));
+
+        merk.apply(&[(seq_key(5), Op::Put(vec![1]))], &[]).unwrap();
+        let updated = merk.prove(query()).unwrap();
+        assert_ne!(updated, proof);
+        assert_eq!(merk.proof_cache_stats().unwrap().misses, 2);
+    }
+}
@@ -0,0 +1,123 @@
+//! Provides `ProofService`, which serves proofs of one committed version of a
+//! store to many request handlers at once.
+
+use std::sync::Arc;
+
+use super::{Merk, MerkReader, ReadTransaction};
+use crate::proofs::query::Select;
+use crate::proofs::Query;
+use crate::tree::Hash;
+use crate::Result;
+
+/// A cheaply cloneable handle which proves queries against a pinned root,
+/// created with `Merk::proof_service` or `MerkReader::proof_service`.
+///
+/// Clones share one `ReadTransaction`, so they can be handed to request
+/// handler threads and all serve proofs against the same root hash, however
+/// many commits are made meanwhile. A new service should be created to serve
+/// a newer version.
+#[derive(Clone)]
+pub struct ProofService {
+    txn: Arc<ReadTransaction>,
+}
+
+impl ProofService {
+    /// Creates a service which serves proofs of the version read by `txn`.
+    pub fn new(txn: ReadTransaction) -> Self {
+        ProofService { txn: Arc::new(txn) }
+    }
+
+    /// Returns the root hash all proofs are created against.
+    pub fn root_hash(&self) -> Hash {
+        self.txn.root_hash()
+    }
+
+    /// Gets the value for `key` at the pinned version.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.txn.get(key)
+    }
+
+    /// Creates a Merkle proof for the list of queried keys.
+    pub fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        self.txn.prove(query)
+    }
+
+    /// Creates a proof of the entries selected by `select`, returning them
+    /// along with the proof, which can be checked with `verify_select`.
+    pub fn prove_select(&self, select: &Select) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Vec<u8>)> {
+        let entries = self.txn.select(select)?;
+        let proof = self.txn.prove_unchecked(select.proof_items(&entries))?;
+        Ok((entries, proof))
+    }
+
+    /// Returns the transaction the service reads, e.g. to read ranges of the
+    /// pinned version without proving them.
+    pub fn transaction(&self) -> &ReadTransaction {
+        &self.txn
+    }
+}
+
+impl MerkReader {
+    /// Creates a proof service pinned to the last committed version.
+    pub fn proof_service(&self) -> ProofService {
+        ProofService::new(self.read_transaction())
+    }
+}
+
+impl Merk {
+    /// Creates a proof service pinned to the last committed version, as in
+    /// `MerkReader::proof_service`.
+    pub fn proof_service(&mut self) -> ProofService {
+        self.reader().proof_service()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::query::verify_select;
+    use crate::test_utils::*;
+
+    #[test]
+    fn serve_pinned_proofs() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let service = merk.proof_service();
+        let root_hash = merk.root_hash();
+        assert_eq!(service.root_hash(), root_hash);
+
+        merk.apply(&make_del_batch_seq(0..50), &[]).unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let service = service.clone();
+                std::thread::spawn(move || {
+                    let select = Select::new()
+                        .range(seq_key(i * 10)..seq_key(i * 10 + 20))
+                        .limit(5)
+                        .descending();
+                    let (entries, proof) = service.prove_select(&select).unwrap();
+                    (select, entries, proof)
+                })
+            })
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            let (select, entries, proof) = handle.join().unwrap();
+            let keys: Vec<_> = entries.iter().map(|(key, _)| key.clone()).collect();
+            let last = i as u64 * 10 + 19;
+            assert_eq!(
+                keys,
+                (last - 4..=last).rev().map(seq_key).collect::<Vec<_>>()
+            );
+            assert_eq!(verify_select(&proof, &select, root_hash).unwrap(), entries);
+        }
+
+        let mut query = Query::new();
+        query.insert_key(seq_key(5));
+        let map = crate::verify(&service.prove(query).unwrap(), root_hash).unwrap();
+        assert!(map.get(&seq_key(5)).unwrap().is_some());
+        assert_eq!(service.get(&seq_key(5)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Creates the chunks of a partial state sync of a key range (see
+//! `proofs::range_sync`).
+
+use super::Merk;
+use crate::limits::MAX_KEY_LENGTH;
+use crate::proofs::query::QueryItem;
+use crate::proofs::range_sync::encode_range_chunk;
+use crate::proofs::Query;
+use crate::{Error, Result};
+
+/// A `RangeChunkProducer` creates the chunks of the entries of a key range,
+/// for nodes which only sync part of the state. Chunks are verified with
+/// `proofs::range_sync::RangeSyncVerifier`.
+pub struct RangeChunkProducer<'a> {
+    merk: &'a Merk,
+    start: Vec<u8>,
+    end: Option<Vec<u8>>,
+    /// The first key of each chunk after the first.
+    boundaries: Vec<Vec<u8>>,
+}
+
+impl<'a> RangeChunkProducer<'a> {
+    /// Returns the number of chunks of the range.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.boundaries.len() + 1
+    }
+
+    /// Gets the chunk with the given index. Errors if the index is out of
+    /// bounds.
+    pub fn chunk(&self, index: usize) -> Result<Vec<u8>> {
+        if index >= self.len() {
+            return Err(Error::IndexOutOfBounds("Chunk index out-of-bounds".into()));
+        }
+
+        let start = match index {
+            0 => self.start.clone(),
+            _ => self.boundaries[index - 1].clone(),
+        };
+        let chunk_end = self.boundaries.get(index);
+        let item = match chunk_end.or(self.end.as_ref()) {
+            Some(end) => QueryItem::Range(start..end.clone()),
+            // every key is at most the greatest key of the maximum length
+            None => QueryItem::RangeInclusive(start..=vec![u8::MAX; MAX_KEY_LENGTH]),
+        };
+
+        let proof = self.merk.prove(Query::from(vec![item]))?;
+        let mut bytes = vec![];
+        encode_range_chunk(chunk_end.map(Vec::as_slice), &proof, &mut bytes);
+        Ok(bytes)
+    }
+}
+
+impl Merk {
+    /// Creates a `RangeChunkProducer` for the entries with keys from `start` up
+    /// to (but not including) `end`, or to the last key in the store if `end`
+    /// is `None`, with up to `chunk_size` entries per chunk.
+    ///
+    /// The chunks prove the entries against the root hash of the whole tree,
+    /// so a node tracking only part of the state (e.g. one shard's prefix) can
+    /// sync it without the rest of the tree. Each chunk includes the path from
+    /// its entries to the root, so syncing a range takes more space than the
+    /// same entries take in the chunks of a full replication.
+    pub fn range_chunks(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        chunk_size: usize,
+    ) -> Result<RangeChunkProducer> {
+        if chunk_size == 0 {
+            return Err(Error::Config("Chunk size must be at least 1".into()));
+        }
+
+        let mut boundaries = vec![];
+        let mut iter = self.raw_iter();
+        iter.seek(self.prefixed(start));
+        let mut count = 0;
+        while iter.valid() {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+            if matches!(end, Some(end) if key >= end) {
+                break;
+            }
+            if count > 0 && count % chunk_size == 0 {
+                boundaries.push(key.to_vec());
+            }
+            count += 1;
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(RangeChunkProducer {
+            merk: self,
+            start: start.to_vec(),
+            end: end.map(<[u8]>::to_vec),
+            boundaries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proofs::range_sync::RangeSyncVerifier;
+    use crate::test_utils::*;
+
+    #[test]
+    fn range_sync() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+
+        for (start, end) in [(100, Some(350)), (900, None), (2_000, None)] {
+            let end_key = end.map(seq_key);
+            let chunks = merk
+                .range_chunks(&seq_key(start), end_key.as_deref(), 64)
+                .unwrap();
+            let count = chunks.len();
+
+            let mut verifier =
+                RangeSyncVerifier::new(merk.root_hash(), &seq_key(start), end_key.as_deref());
+            let mut entries = vec![];
+            for index in 0..count {
+                assert!(!verifier.is_done());
+                let chunk = chunks.chunk(index).unwrap();
+                entries.extend(verifier.process_chunk(&chunk).unwrap());
+            }
+            assert!(verifier.is_done());
+
+            let expected = merk
+                .get_range(&seq_key(start), end_key.as_deref(), usize::MAX)
+                .unwrap();
+            assert_eq!(entries, expected);
+            assert_eq!(count, ((expected.len() + 63) / 64).max(1));
+        }
+
+        // chunks must be processed in order, against the right root hash
+        let chunks = merk.range_chunks(&seq_key(0), None, 100).unwrap();
+        let mut verifier = RangeSyncVerifier::new(merk.root_hash(), &seq_key(0), None);
+        assert!(verifier.process_chunk(&chunks.chunk(1).unwrap()).is_err());
+        let mut wrong_root = RangeSyncVerifier::new([1; 32], &seq_key(0), None);
+        assert!(wrong_root.process_chunk(&chunks.chunk(0).unwrap()).is_err());
+    }
+}
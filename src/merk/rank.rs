@@ -0,0 +1,113 @@
+//! Provides index-based reads of a store's keys (`Merk::nth_key` and
+//! `Merk::rank`), and proofs of them, for paginating by index rather than by
+//! key.
+//!
+//! Nodes don't store the sizes of their subtrees, so these read (and their
+//! proofs contain) every key before the one at the index.
+
+use super::Merk;
+use crate::proofs::query::{nth_key_select, rank_select};
+use crate::Result;
+
+impl Merk {
+    /// Returns the key at index `n` (counting from 0) among the keys starting
+    /// with `prefix`, in sorted order, or `None` if there are not more than `n`
+    /// such keys.
+    ///
+    /// Takes time linear in `n`, since the `n` keys before it are iterated.
+    pub fn nth_key(&self, prefix: &[u8], n: u64) -> Result<Option<Vec<u8>>> {
+        let mut iter = self.raw_iter();
+        iter.seek(self.prefixed(prefix));
+
+        let mut index = 0;
+        while iter.valid() {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+            if !key.starts_with(prefix) {
+                break;
+            }
+            if index == n {
+                return Ok(Some(key.to_vec()));
+            }
+            index += 1;
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(None)
+    }
+
+    /// Returns the index of `key` among all keys in the store, in sorted
+    /// order, i.e. the number of keys less than `key`. The key does not need
+    /// to be in the store.
+    ///
+    /// Takes time linear in the rank, since every smaller key is iterated.
+    pub fn rank(&self, key: &[u8]) -> Result<u64> {
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+
+        let mut rank = 0;
+        while iter.valid() && &iter.key().unwrap()[self.prefix.len()..] < key {
+            rank += 1;
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(rank)
+    }
+
+    /// Creates a proof of the key at index `n` among the keys starting with
+    /// `prefix`, which can be checked with `verify_nth_key`.
+    ///
+    /// The proof contains the `n` keys before it and their values, so its size
+    /// and the time to create it grow linearly with `n`.
+    pub fn prove_nth_key(&self, prefix: &[u8], n: u64) -> Result<Vec<u8>> {
+        self.prove_select(&nth_key_select(prefix, n))
+    }
+
+    /// Creates a proof of the rank of `key`, which can be checked with
+    /// `verify_rank`.
+    ///
+    /// The proof contains every key less than `key` along with its value, so
+    /// it grows linearly with the rank. Proving the rank of a key near the end
+    /// of a large store is about as expensive as proving the whole store.
+    pub fn prove_rank(&self, key: &[u8]) -> Result<Vec<u8>> {
+        self.prove_select(&rank_select(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proofs::query::{verify_nth_key, verify_rank};
+    use crate::test_utils::*;
+
+    #[test]
+    fn nth_key_and_rank() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let prefix = &seq_key(0)[..7];
+        assert_eq!(merk.nth_key(&[], 0).unwrap(), Some(seq_key(0)));
+        assert_eq!(merk.nth_key(&[], 42).unwrap(), Some(seq_key(42)));
+        assert_eq!(merk.nth_key(&[], 100).unwrap(), None);
+        assert_eq!(merk.nth_key(prefix, 5).unwrap(), Some(seq_key(5)));
+        assert_eq!(merk.nth_key(&[1], 0).unwrap(), None);
+
+        assert_eq!(merk.rank(&seq_key(0)).unwrap(), 0);
+        assert_eq!(merk.rank(&seq_key(42)).unwrap(), 42);
+        assert_eq!(merk.rank(&[255]).unwrap(), 100);
+
+        let root_hash = merk.root_hash();
+        for n in [0, 42, 99, 100] {
+            let proof = merk.prove_nth_key(prefix, n).unwrap();
+            assert_eq!(
+                verify_nth_key(&proof, prefix, n, root_hash).unwrap(),
+                merk.nth_key(prefix, n).unwrap()
+            );
+        }
+
+        let proof = merk.prove_rank(&seq_key(42)).unwrap();
+        assert_eq!(verify_rank(&proof, &seq_key(42), root_hash).unwrap(), 42);
+        // the proof doesn't cover all of the keys before a later key
+        assert!(verify_rank(&proof, &seq_key(90), root_hash).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Provides raw exports of a store's nodes, for moving a store between
+//! machines of the same operator faster than state sync allows.
+//!
+//! A raw export is a stream of the store's encoded nodes and aux entries,
+//! which are ingested directly as SST files on import rather than being
+//! verified and rewritten chunk by chunk. The stream is encoded as:
+//!
+//! ```text
+//! export := root entry* END
+//! root   := NO_ROOT | ROOT key
+//! entry  := (NODE | AUX) key value
+//! ```
+//!
+//! where keys and values are each prefixed with their length as a big-endian
+//! `u32`, and nodes are in their plain encoding, so the codec of the imported
+//! store does not need to match the exported one.
+
+use super::import::{write_sst, Entries};
+use super::{prefix_read_opts, Merk, AUX_CF_NAME};
+use crate::tree::{Hash, NULL_HASH};
+use crate::{Error, Result};
+use rocksdb::IngestExternalFileOptions;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const NO_ROOT: u8 = 0;
+const ROOT: u8 = 1;
+const NODE: u8 = 2;
+const AUX: u8 = 3;
+const END: u8 = 4;
+
+/// The number of bytes of entries buffered by `Merk::import_raw` before they
+/// are written out as an SST file and ingested.
+const SST_SIZE: usize = 64 * 1024 * 1024;
+
+impl Merk {
+    /// Writes every node and aux entry of the store to `writer` as a raw
+    /// export, returning the number of nodes written.
+    ///
+    /// Raw exports carry no proofs, so they should only be imported from a
+    /// trusted source (e.g. when moving a store to new hardware). Internal
+    /// state such as the recorded height and the changelog is not exported.
+    pub fn export_raw<W: Write>(&self, writer: W) -> Result<usize> {
+        let mut writer = BufWriter::new(writer);
+
+        match self.use_tree(|maybe_tree| maybe_tree.map(|tree| tree.key().to_vec())) {
+            Some(root_key) => {
+                writer.write_all(&[ROOT])?;
+                write_bytes(&mut writer, &root_key)?;
+            }
+            None => writer.write_all(&[NO_ROOT])?,
+        }
+
+        let mut count = 0;
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+            let bytes = self.codec.decode(key, iter.value().unwrap())?;
+            writer.write_all(&[NODE])?;
+            write_bytes(&mut writer, key)?;
+            write_bytes(&mut writer, &bytes)?;
+
+            count += 1;
+            iter.next();
+        }
+        iter.status()?;
+
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let mut iter = self
+            .db
+            .raw_iterator_cf_opt(aux_cf, prefix_read_opts(&self.prefix));
+        iter.seek_to_first();
+        while iter.valid() {
+            writer.write_all(&[AUX])?;
+            write_bytes(&mut writer, &iter.key().unwrap()[self.prefix.len()..])?;
+            write_bytes(&mut writer, iter.value().unwrap())?;
+            iter.next();
+        }
+        iter.status()?;
+
+        writer.write_all(&[END])?;
+        writer.flush()?;
+
+        Ok(count)
+    }
+
+    /// Creates a new store at `path` from a raw export read from `reader`,
+    /// ingesting its entries as SST files. Once every entry is ingested, the
+    /// root node's hash is checked against `expected_root_hash` and the links
+    /// of every node against their children, and the root is only written if
+    /// both checks pass. Errors if `path` already exists.
+    pub fn import_raw<P, R>(path: P, reader: R, expected_root_hash: Hash) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+        R: Read,
+    {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(Error::Path("The given path already exists".into()));
+        }
+
+        let mut merk = Merk::open(path)?;
+        let mut dir = path.as_os_str().to_owned();
+        dir.push("-raw-import");
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir)?;
+
+        let res = merk.ingest_raw(&dir, BufReader::new(reader), expected_root_hash);
+        std::fs::remove_dir_all(&dir)?;
+        res?;
+
+        Ok(merk)
+    }
+
+    fn ingest_raw<R: Read>(
+        &mut self,
+        dir: &Path,
+        mut reader: R,
+        expected_root_hash: Hash,
+    ) -> Result<()> {
+        let maybe_root_key = match read_u8(&mut reader)? {
+            NO_ROOT => None,
+            ROOT => Some(read_bytes(&mut reader)?),
+            tag => return Err(unexpected_tag(tag)),
+        };
+
+        let mut nodes = Entries::new();
+        let mut aux = Entries::new();
+        let mut size = 0;
+        let mut node_count = 0;
+        loop {
+            let tag = read_u8(&mut reader)?;
+            if tag == END {
+                break;
+            }
+            let key = read_bytes(&mut reader)?;
+            let value = read_bytes(&mut reader)?;
+            size += key.len() + value.len();
+            match tag {
+                NODE => {
+                    let value = self.codec.encode(&key, value)?;
+                    nodes.insert(key, Some(value));
+                    node_count += 1;
+                }
+                AUX => {
+                    aux.insert(key, Some(value));
+                }
+                tag => return Err(unexpected_tag(tag)),
+            }
+
+            if size >= SST_SIZE {
+                self.ingest_entries(dir, &mut nodes, &mut aux)?;
+                size = 0;
+            }
+        }
+        self.ingest_entries(dir, &mut nodes, &mut aux)?;
+
+        let root_key = match maybe_root_key {
+            Some(root_key) => root_key,
+            None if expected_root_hash == NULL_HASH => return Ok(()),
+            None => return Err(Error::HashMismatch(expected_root_hash, NULL_HASH)),
+        };
+        let root_hash = self
+            .fetch_node(&root_key)?
+            .ok_or_else(|| Error::Key("Root node is missing from the export".into()))?
+            .hash();
+        if root_hash != expected_root_hash {
+            return Err(Error::HashMismatch(expected_root_hash, root_hash));
+        }
+        if let Some(mismatch) = self.check_links()?.into_iter().next() {
+            return Err(Error::Corruption {
+                key: mismatch.parent,
+            });
+        }
+
+        self.set_root_key(root_key, node_count)?;
+        self.load_root()
+    }
+
+    /// Writes the buffered entries out as SST files, ingests them and clears
+    /// the buffers.
+    fn ingest_entries(&self, dir: &Path, nodes: &mut Entries, aux: &mut Entries) -> Result<()> {
+        let mut ingest_opts = IngestExternalFileOptions::default();
+        ingest_opts.set_move_files(true);
+
+        if let Some(path) = write_sst(dir, "nodes.sst", std::mem::take(nodes))? {
+            self.db
+                .ingest_external_file_opts(&ingest_opts, vec![path])?;
+        }
+        if let Some(path) = write_sst(dir, "aux.sst", std::mem::take(aux))? {
+            let cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+            self.db
+                .ingest_external_file_cf_opts(cf, &ingest_opts, vec![path])?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn unexpected_tag(tag: u8) -> Error {
+    Error::Key(format!("Unexpected raw export tag: {}", tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Error, Merk, Op};
+
+    #[test]
+    fn raw_export_roundtrip() {
+        let path = std::thread::current().name().unwrap().to_owned();
+
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10_000), &[(vec![1], Op::Put(vec![2]))])
+            .unwrap();
+        let mut export = vec![];
+        assert_eq!(merk.export_raw(&mut export).unwrap(), 10_000);
+
+        let res = Merk::import_raw(&path, &export[..], [1; 32]);
+        assert!(matches!(res, Err(Error::HashMismatch(..))));
+        Merk::open(&path).unwrap().destroy().unwrap();
+
+        let imported = Merk::import_raw(&path, &export[..], merk.root_hash()).unwrap();
+        assert_eq!(imported.root_hash(), merk.root_hash());
+        assert_eq!(imported.len(), 10_000);
+        assert_eq!(
+            imported.get(&seq_key(1234)).unwrap(),
+            Some(put_entry_value())
+        );
+        assert_eq!(imported.get_aux(&[1]).unwrap(), Some(vec![2]));
+        imported.destroy().unwrap();
+
+        // a truncated export fails before the root is written
+        let res = Merk::import_raw(&path, &export[..export.len() - 100], merk.root_hash());
+        assert!(res.is_err());
+        let truncated = Merk::open(&path).unwrap();
+        assert!(truncated.get(&seq_key(0)).unwrap().is_none());
+        truncated.destroy().unwrap();
+    }
+}
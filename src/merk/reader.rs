@@ -0,0 +1,372 @@
+//! Provides `MerkReader`, a handle which reads the last committed version of a
+//! store from any thread while the store builds the next one.
+
+use std::sync::{Arc, RwLock};
+
+use rocksdb::DB;
+
+use super::select::{read_select, EntryCursor};
+use super::snapshot::SnapshotSource;
+use super::{prefix_read_opts, prefixed, prove_unchecked, read_range, Merk, NodeCodec};
+use crate::proofs::query::{QueryItem, Select};
+use crate::proofs::Query;
+use crate::tree::{Fetch, Hash, Tree, NULL_HASH};
+use crate::Result;
+
+/// The latest committed version of a store, shared between the store and its
+/// readers.
+pub(crate) type SharedView = Arc<RwLock<Arc<CommittedView>>>;
+
+/// A committed version of a store: a database snapshot taken after the commit
+/// was written, and the root of the tree at that commit.
+pub(crate) struct CommittedView {
+    /// Declared before `_db` so it is dropped before the database it borrows.
+    pub(crate) snapshot: rocksdb::Snapshot<'static>,
+    pub(crate) root: Option<(Vec<u8>, Hash)>,
+    _db: Arc<DB>,
+}
+
+impl CommittedView {
+    fn new(db: &Arc<DB>, root: Option<(Vec<u8>, Hash)>) -> Self {
+        let db = db.clone();
+        // SAFETY: the snapshot borrows the database, which is kept alive by the
+        // `Arc` stored alongside it and is dropped after it
+        let snapshot = unsafe {
+            std::mem::transmute::<rocksdb::Snapshot<'_>, rocksdb::Snapshot<'static>>(db.snapshot())
+        };
+        CommittedView {
+            snapshot,
+            root,
+            _db: db,
+        }
+    }
+}
+
+/// A cloneable, thread-safe handle for reading the last committed version of a
+/// `Merk`, created with `Merk::reader`.
+///
+/// Each read works on the version which was current when the read started,
+/// without blocking (or being blocked by) the store while it applies batches.
+/// Writes made during a block or an import become visible once they are
+/// written to the database.
+#[derive(Clone)]
+pub struct MerkReader {
+    view: SharedView,
+    pub(crate) codec: NodeCodec,
+    pub(crate) prefix: Vec<u8>,
+}
+
+impl MerkReader {
+    /// Gets the value for `key` at the last committed version, or `None` if it
+    /// is not in the store.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let view = self.view();
+        if view.root.is_none() {
+            return Ok(None);
+        }
+        self.source(&view).fetch_value(key)
+    }
+
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key if `end` is `None`, at the last
+    /// committed version.
+    pub fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let view = self.view();
+        let iter = view
+            .snapshot
+            .raw_iterator_opt(prefix_read_opts(&self.prefix));
+        read_range(iter, &self.prefix, &self.codec, start, end, limit)
+    }
+
+    /// Returns the root hash of the last committed version.
+    pub fn root_hash(&self) -> Hash {
+        self.view()
+            .root
+            .as_ref()
+            .map_or(NULL_HASH, |(_, hash)| *hash)
+    }
+
+    /// Creates a Merkle proof for the list of queried keys against the last
+    /// committed version.
+    pub fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        let view = self.view();
+        let source = self.source(&view);
+        let mut maybe_tree = view
+            .root
+            .as_ref()
+            .map(|(key, _)| source.fetch_by_key_expect(key))
+            .transpose()?;
+        prove_unchecked(maybe_tree.as_mut(), source, query)
+    }
+
+    /// Starts a read transaction at the last committed version, which all of
+    /// its reads observe no matter how many commits are made meanwhile.
+    pub fn read_transaction(&self) -> ReadTransaction {
+        ReadTransaction {
+            view: self.view(),
+            codec: self.codec.clone(),
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    pub(crate) fn view(&self) -> Arc<CommittedView> {
+        self.view.read().unwrap().clone()
+    }
+
+    pub(crate) fn source<'a>(&'a self, view: &'a CommittedView) -> SnapshotSource<'a> {
+        SnapshotSource::new(&view.snapshot, &self.codec, &self.prefix)
+    }
+}
+
+/// A read-only view of one committed version of a store, created with
+/// `MerkReader::read_transaction` or `Merk::read_transaction`.
+///
+/// The transaction pins the version's root and a database snapshot until it
+/// is dropped, so every read made with it (e.g. the pages of a long-running
+/// request) observes the same version. Like readers, transactions keep the
+/// database open, and the snapshot keeps RocksDB from compacting away the data
+/// it references, so they should not be held indefinitely.
+pub struct ReadTransaction {
+    view: Arc<CommittedView>,
+    codec: NodeCodec,
+    prefix: Vec<u8>,
+}
+
+impl ReadTransaction {
+    /// Gets the value for `key`, or `None` if it is not in the store.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.view.root.is_none() {
+            return Ok(None);
+        }
+        self.source().fetch_value(key)
+    }
+
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key if `end` is `None`.
+    pub fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        read_range(
+            self.raw_iter(),
+            &self.prefix,
+            &self.codec,
+            start,
+            end,
+            limit,
+        )
+    }
+
+    /// Returns a raw iterator over the stored nodes of the version. For a
+    /// shared store, iteration is bounded to the store's prefix, and the keys
+    /// returned include it.
+    pub fn raw_iter(&self) -> rocksdb::DBRawIterator {
+        self.view
+            .snapshot
+            .raw_iterator_opt(prefix_read_opts(&self.prefix))
+    }
+
+    /// Returns the root hash of the version.
+    pub fn root_hash(&self) -> Hash {
+        self.view.root.as_ref().map_or(NULL_HASH, |(_, hash)| *hash)
+    }
+
+    /// Creates a Merkle proof for the list of queried keys against the
+    /// version's root hash.
+    pub fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        self.prove_unchecked(query)
+    }
+
+    /// Creates a Merkle proof for the queried items as in `prove`, without
+    /// requiring them to be collected into a `Query`.
+    pub fn prove_unchecked<Q, I>(&self, query: I) -> Result<Vec<u8>>
+    where
+        Q: Into<QueryItem>,
+        I: IntoIterator<Item = Q>,
+    {
+        let source = self.source();
+        let mut maybe_tree = self
+            .view
+            .root
+            .as_ref()
+            .map(|(key, _)| source.fetch_by_key_expect(key))
+            .transpose()?;
+        prove_unchecked(maybe_tree.as_mut(), source, query)
+    }
+
+    /// Reads the entries selected by `select`, as in `Merk::select`.
+    pub fn select(&self, select: &Select) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut cursor = TransactionCursor {
+            iter: self.raw_iter(),
+            txn: self,
+        };
+        read_select(&mut cursor, select)
+    }
+
+    /// Creates a proof of the entries selected by `select`, which can be
+    /// checked with `verify_select` against the version's root hash.
+    pub fn prove_select(&self, select: &Select) -> Result<Vec<u8>> {
+        let entries = self.select(select)?;
+        self.prove_unchecked(select.proof_items(&entries))
+    }
+
+    fn source(&self) -> SnapshotSource {
+        SnapshotSource::new(&self.view.snapshot, &self.codec, &self.prefix)
+    }
+}
+
+/// A position in the entries of a `ReadTransaction`, for reading selects.
+struct TransactionCursor<'a> {
+    iter: rocksdb::DBRawIterator<'a>,
+    txn: &'a ReadTransaction,
+}
+
+impl<'a> EntryCursor for TransactionCursor<'a> {
+    fn seek(&mut self, key: &[u8]) {
+        self.iter.seek(prefixed(&self.txn.prefix, key));
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.iter.seek_for_prev(prefixed(&self.txn.prefix, key));
+    }
+
+    fn current(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if !self.iter.valid() {
+            self.iter.status()?;
+            return Ok(None);
+        }
+
+        let key = &self.iter.key().unwrap()[self.txn.prefix.len()..];
+        let bytes = self.txn.codec.decode(key, self.iter.value().unwrap())?;
+        let value = Tree::decode_value(&bytes)?;
+        Ok(Some((key.to_vec(), value.to_vec())))
+    }
+
+    fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if self.iter.valid() {
+            self.iter.next();
+        }
+        self.current()
+    }
+
+    fn prev(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if self.iter.valid() {
+            self.iter.prev();
+        }
+        self.current()
+    }
+}
+
+impl Merk {
+    /// Starts a read transaction at the last committed version, as in
+    /// `MerkReader::read_transaction`.
+    pub fn read_transaction(&mut self) -> ReadTransaction {
+        self.reader().read_transaction()
+    }
+
+    /// Returns a handle for reading the last committed version of the store
+    /// from other threads, e.g. to serve queries while blocks are applied.
+    ///
+    /// Readers keep the database open, so they must be dropped before the
+    /// store is destroyed.
+    pub fn reader(&mut self) -> MerkReader {
+        if self.view.is_none() {
+            let view = self.committed_view();
+            self.view = Some(Arc::new(RwLock::new(Arc::new(view))));
+        }
+        MerkReader {
+            view: self.view.clone().unwrap(),
+            codec: self.codec.clone(),
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    /// Makes the current state of the database visible to readers. Must only
+    /// be called when the tree matches what has been written.
+    pub(crate) fn publish_view(&self) {
+        if let Some(shared) = self.view.as_ref() {
+            let view = Arc::new(self.committed_view());
+            *shared.write().unwrap() = view;
+        }
+    }
+
+    fn committed_view(&self) -> CommittedView {
+        let root =
+            self.use_tree(|maybe_tree| maybe_tree.map(|tree| (tree.key().to_vec(), tree.hash())));
+        CommittedView::new(&self.db, root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn read_committed_version() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let reader = merk.reader();
+        assert_eq!(reader.root_hash(), merk.root_hash());
+
+        merk.begin_block().unwrap();
+        let committed = merk.root_hash();
+        merk.apply(&make_del_batch_seq(0..10), &[]).unwrap();
+        assert_eq!(reader.root_hash(), committed);
+
+        let thread_reader = reader.clone();
+        let handle = std::thread::spawn(move || {
+            let value = thread_reader.get(&seq_key(5)).unwrap();
+            let range = thread_reader.get_range(&seq_key(0), None, 20).unwrap();
+            let mut query = Query::new();
+            query.insert_key(seq_key(5));
+            let proof = thread_reader.prove(query).unwrap();
+            (value, range.len(), proof)
+        });
+        let (value, range_len, proof) = handle.join().unwrap();
+        assert_eq!(value, Some(put_entry_value()));
+        assert_eq!(range_len, 20);
+        let map = crate::verify(&proof, committed).unwrap();
+        assert!(map.get(&seq_key(5)).unwrap().is_some());
+
+        merk.commit_block().unwrap();
+        assert_eq!(reader.root_hash(), merk.root_hash());
+        assert_eq!(reader.get(&seq_key(5)).unwrap(), None);
+    }
+
+    #[test]
+    fn repeatable_reads() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let txn = merk.read_transaction();
+        let root_hash = merk.root_hash();
+
+        merk.apply(&make_del_batch_seq(0..50), &[]).unwrap();
+        merk.apply(&make_batch_seq(100..150), &[]).unwrap();
+        assert_ne!(merk.root_hash(), root_hash);
+        assert_eq!(merk.reader().get(&seq_key(5)).unwrap(), None);
+
+        // reads made after the commits still observe the pinned version
+        let handle = std::thread::spawn(move || {
+            assert_eq!(txn.root_hash(), root_hash);
+            assert_eq!(txn.get(&seq_key(5)).unwrap(), Some(put_entry_value()));
+            assert_eq!(txn.get(&seq_key(120)).unwrap(), None);
+            let range = txn.get_range(&seq_key(0), None, 1_000).unwrap();
+            assert_eq!(range.len(), 100);
+
+            let mut query = Query::new();
+            query.insert_key(seq_key(5));
+            txn.prove(query).unwrap()
+        });
+        let proof = handle.join().unwrap();
+        let map = crate::verify(&proof, root_hash).unwrap();
+        assert!(map.get(&seq_key(5)).unwrap().is_some());
+    }
+}
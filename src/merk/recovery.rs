@@ -0,0 +1,203 @@
+//! Provides `Merk::open_with_recovery`, which repairs a store that RocksDB
+//! fails to open and reports any data which was lost.
+
+use super::migration::load_encoding_version;
+use super::{column_families, Merk, NodeCodec};
+use crate::tree::Tree;
+use crate::Result;
+use ed::Decode;
+use rocksdb::DB;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Describes the state of a store opened with `Merk::open_with_recovery`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Whether RocksDB's repair had to be run before the store could be
+    /// opened.
+    pub repaired: bool,
+    /// The error which prevented the store's state from being loaded after
+    /// the database was opened (e.g. a torn commit or a corrupted root node),
+    /// in which case no store is returned.
+    pub load_error: Option<String>,
+    /// The keys of stored nodes which are corrupted (see `Merk::scrub`).
+    pub corrupted_keys: Vec<Vec<u8>>,
+    /// The keys of nodes which are referenced by another node, but are missing
+    /// from the store.
+    pub missing_keys: Vec<Vec<u8>>,
+}
+
+impl RecoveryReport {
+    /// Returns true if the store was loaded and no corrupted or missing nodes
+    /// were found.
+    pub fn is_intact(&self) -> bool {
+        self.load_error.is_none() && self.corrupted_keys.is_empty() && self.missing_keys.is_empty()
+    }
+}
+
+impl Merk {
+    /// Opens the store at `path` with the given options and codec, running
+    /// RocksDB's repair if the database fails to open (e.g. because of a
+    /// corrupted manifest), then checks the integrity of the tree and reports
+    /// what was lost.
+    ///
+    /// If the database opens but the store's state can't be loaded, the
+    /// stored nodes are still checked, and the report is returned without a
+    /// store, with the error in `RecoveryReport::load_error`.
+    ///
+    /// RocksDB's repair may discard data which can not be recovered, so the
+    /// returned store should not be trusted unless the report is intact.
+    pub fn open_with_recovery<P: AsRef<Path>>(
+        path: P,
+        db_opts: rocksdb::Options,
+        codec: NodeCodec,
+    ) -> Result<(Option<Merk>, RecoveryReport)> {
+        let path = path.as_ref();
+        let mut report = RecoveryReport::default();
+
+        let db = match DB::open_cf_descriptors(&db_opts, path, column_families()) {
+            Ok(db) => db,
+            Err(_) => {
+                DB::repair(&db_opts, path)?;
+                report.repaired = true;
+                DB::open_cf_descriptors(&db_opts, path, column_families())?
+            }
+        };
+        let db = Arc::new(db);
+
+        let loaded = Merk::load(
+            db.clone(),
+            path.to_path_buf(),
+            vec![],
+            codec.clone(),
+            true,
+            false,
+        );
+        let merk = match loaded {
+            Ok(merk) => merk,
+            Err(err) => {
+                report.load_error = Some(err.to_string());
+
+                // decode the nodes with the store's encoding version if it
+                // can still be read
+                let codec = load_encoding_version(codec.clone(), &db, &[], false).unwrap_or(codec);
+                #[cfg(feature = "compression")]
+                let codec = codec.load_dictionary(&db, &[])?;
+                let merk = Merk::unloaded(db, path.to_path_buf(), vec![], codec);
+                report.corrupted_keys = merk.scrub()?;
+                report.missing_keys = merk.missing_nodes()?;
+                return Ok((None, report));
+            }
+        };
+
+        report.corrupted_keys = merk.scrub()?;
+        report.missing_keys = merk.missing_nodes()?;
+
+        Ok((Some(merk), report))
+    }
+
+    /// Returns the keys of nodes which are linked to by a stored node but are
+    /// not in the store.
+    fn missing_nodes(&self) -> Result<Vec<Vec<u8>>> {
+        let mut missing = vec![];
+
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = &iter.key().unwrap()[self.prefix.len()..];
+
+            // nodes which can't be decoded are reported by `scrub`
+            let maybe_node = self
+                .codec
+                .decode(key, iter.value().unwrap())
+                .ok()
+                .and_then(|bytes| <Tree as Decode>::decode(bytes.as_ref()).ok());
+
+            if let Some(node) = maybe_node {
+                for link in [node.link(true), node.link(false)].iter().flatten() {
+                    if self.db.get_pinned(self.prefixed(link.key()))?.is_none() {
+                        missing.push(link.key().to_vec());
+                    }
+                }
+            }
+
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn recover_corrupted_manifest() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let root_hash = {
+            let mut merk = Merk::open(&path).unwrap();
+            merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+            merk.flush().unwrap();
+            merk.root_hash()
+        };
+
+        for entry in std::fs::read_dir(&path).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_name().to_str().unwrap().starts_with("MANIFEST") {
+                std::fs::write(entry.path(), b"garbage").unwrap();
+            }
+        }
+        assert!(Merk::open(&path).is_err());
+
+        let (merk, report) =
+            Merk::open_with_recovery(&path, Merk::default_db_opts(), NodeCodec::new()).unwrap();
+        assert!(report.repaired);
+        assert!(report.is_intact());
+        let merk = merk.unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn report_missing_nodes() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        merk.db.delete(seq_key(0)).unwrap();
+        drop(merk);
+
+        let (merk, report) =
+            Merk::open_with_recovery(&path, Merk::default_db_opts(), NodeCodec::new()).unwrap();
+        assert!(!report.repaired);
+        assert!(report.corrupted_keys.is_empty());
+        assert_eq!(report.missing_keys, vec![seq_key(0)]);
+        merk.unwrap().destroy().unwrap();
+    }
+
+    #[test]
+    fn report_unloadable_store() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let codec = NodeCodec::new().with_checksums();
+        let mut merk =
+            Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone()).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let root_key = merk.use_tree(|tree| tree.unwrap().key().to_vec());
+        let mut bytes = merk.db.get(&root_key).unwrap().unwrap();
+        bytes[0] ^= 1;
+        merk.db.put(&root_key, bytes).unwrap();
+        drop(merk);
+        assert!(Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone()).is_err());
+
+        let (merk, report) =
+            Merk::open_with_recovery(&path, Merk::default_db_opts(), codec).unwrap();
+        assert!(merk.is_none());
+        assert!(!report.repaired);
+        assert!(report.load_error.is_some());
+        assert_eq!(report.corrupted_keys, vec![root_key]);
+        assert!(!report.is_intact());
+        rocksdb::DB::destroy(&Merk::default_db_opts(), &path).unwrap();
+    }
+}
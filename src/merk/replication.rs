@@ -0,0 +1,88 @@
+//! Allows follower processes to replicate a store by applying the change
+//! records committed by the leader, verifying that each one reaches the same
+//! root hash.
+
+use super::{ChangeRecord, CommitOptions, Merk};
+use crate::tree::{BatchEntry, Op};
+use crate::{Error, Result};
+
+impl Merk {
+    /// Applies the exact batch a leader committed, as described by its change
+    /// record, checking that the result has the same root hash as the leader.
+    /// If it does not, nothing is written and a `HashMismatch` error is
+    /// returned.
+    ///
+    /// If the follower's own changelog is enabled, records must be applied in
+    /// order: `record.height` must follow the follower's changelog height.
+    pub fn apply_replication_record(&mut self, record: &ChangeRecord) -> Result<()> {
+        if let Some(height) = self.changelog_height() {
+            if record.height != height + 1 {
+                return Err(Error::Replication(format!(
+                    "Expected record at height {}, got {}",
+                    height + 1,
+                    record.height
+                )));
+            }
+        }
+
+        let batch = to_batch(&record.batch);
+        let aux = to_batch(&record.aux);
+        self.limits.check_batch(&batch)?;
+        let options = CommitOptions {
+            expected_root_hash: Some(record.root_hash),
+            ..Default::default()
+        };
+        unsafe { self.apply_sorted(&batch, &aux, options) }
+    }
+}
+
+fn to_batch(entries: &[(Vec<u8>, Option<Vec<u8>>)]) -> Vec<BatchEntry> {
+    entries
+        .iter()
+        .map(|(key, maybe_value)| match maybe_value {
+            Some(value) => (key.clone(), Op::Put(value.clone())),
+            None => (key.clone(), Op::Delete),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Error, Op};
+
+    #[test]
+    fn replicate() {
+        let mut leader = TempMerk::new().unwrap();
+        leader.enable_changelog().unwrap();
+        let mut follower = TempMerk::new().unwrap();
+        follower.enable_changelog().unwrap();
+
+        leader
+            .apply(&make_batch_seq(0..100), &[(vec![1], Op::Put(vec![2]))])
+            .unwrap();
+        leader.apply(&make_del_batch_seq(10..20), &[]).unwrap();
+        let records = leader.change_records(1, 10).unwrap();
+        assert_eq!(records.len(), 2);
+
+        assert!(follower.apply_replication_record(&records[1]).is_err());
+        follower.apply_replication_record(&records[0]).unwrap();
+
+        let mut tampered = records[1].clone();
+        tampered.batch.pop();
+        match follower.apply_replication_record(&tampered) {
+            Err(Error::HashMismatch(..)) => {}
+            res => panic!("expected hash mismatch, got {:?}", res),
+        }
+        assert_eq!(follower.get(&seq_key(10)).unwrap(), Some(put_entry_value()));
+        assert_eq!(follower.changelog_height(), Some(1));
+
+        follower.apply_replication_record(&records[1]).unwrap();
+        assert_eq!(follower.root_hash(), leader.root_hash());
+        assert_eq!(follower.get_aux(&[1]).unwrap(), Some(vec![2]));
+        assert_eq!(
+            follower.change_records(1, 10).unwrap(),
+            leader.change_records(1, 10).unwrap()
+        );
+    }
+}
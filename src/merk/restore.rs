@@ -1,21 +1,30 @@
 //! Provides `Restorer`, which can create a replica of a Merk instance by
 //! receiving chunk proofs.
 
-use super::Merk;
+use super::{prefixed, telemetry, write_batch, Merk};
 use crate::{
-    merk::MerkSource,
+    merk::{MerkSource, NodeCodec},
     proofs::{
-        chunk::{verify_leaf, verify_trunk, MIN_TRUNK_HEIGHT},
+        arena::TreeArena,
+        chunk::{verify_leaf_in, verify_trunk, ChunkHeader, MIN_TRUNK_HEIGHT},
         tree::{Child, Tree as ProofTree},
-        Decoder, Node,
+        Decoder, Node, Op,
     },
     tree::{Link, RefWalker, Tree},
-    Error, Hash, Result,
+    Error, Hash, Limits, Result, NULL_HASH,
 };
-use rocksdb::WriteBatch;
+use rocksdb::{WriteBatch, DB};
+use std::collections::HashMap;
 use std::iter::Peekable;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel};
+use std::sync::Arc;
+use std::time::Instant;
 use std::{path::Path, u8};
 
+/// The size in bytes of the write batches written by `process_chunks`.
+const WRITE_BATCH_SIZE: usize = 64 * 1024 * 1024;
+
 /// A `Restorer` handles decoding, verifying, and storing chunk proofs to
 /// replicate an entire Merk tree. It expects the chunks to be processed in
 /// order, retrying the last chunk if verification fails.
@@ -23,9 +32,20 @@ pub struct Restorer {
     leaf_hashes: Option<Peekable<std::vec::IntoIter<Hash>>>,
     parent_keys: Option<Peekable<std::vec::IntoIter<Vec<u8>>>>,
     trunk_height: Option<usize>,
+    /// The trunk height chunks were produced with, if not the default.
+    expected_trunk_height: Option<usize>,
     merk: Merk,
     expected_root_hash: Hash,
-    stated_length: usize,
+    /// The number of chunks stated by the peer, if any, checked against the
+    /// trunk.
+    stated_length: Option<usize>,
+    /// Holds each leaf chunk while it is verified and written, reusing its
+    /// allocation between chunks.
+    arena: TreeArena,
+    /// The ops of the parts of a split chunk received so far.
+    pending: Vec<Op>,
+    /// The number of nodes written so far, recorded as the key count.
+    nodes_written: u64,
 }
 
 impl Restorer {
@@ -48,14 +68,83 @@ impl Restorer {
             return Err(Error::Path("The given path already exists".into()));
         }
 
-        Ok(Self {
+        Ok(Self::from_merk(
+            Merk::open(db_path)?,
+            expected_root_hash,
+            Some(stated_length),
+        ))
+    }
+
+    /// Creates a new `Restorer` as in `new`, without a stated number of
+    /// chunks. The number of chunks is learned from the trunk once it is
+    /// verified, so restoring only takes feeding each chunk in order to
+    /// `feed_chunk`, then calling `finalize`.
+    pub fn for_root<P: AsRef<Path>>(db_path: P, expected_root_hash: Hash) -> Result<Self> {
+        if db_path.as_ref().exists() {
+            return Err(Error::Path("The given path already exists".into()));
+        }
+
+        Ok(Self::from_merk(
+            Merk::open(db_path)?,
+            expected_root_hash,
+            None,
+        ))
+    }
+
+    /// Creates a new `Restorer` as in `new`, which rebuilds the tree as the
+    /// shared store under `prefix` in the already-open `db` (see
+    /// `Merk::open_shared`) rather than in a new database, so an application
+    /// hosting several stores in one database can replicate one of them in
+    /// place. The store under `prefix` must be empty.
+    pub fn new_shared(
+        db: Arc<DB>,
+        prefix: Vec<u8>,
+        codec: NodeCodec,
+        expected_root_hash: Hash,
+        stated_length: usize,
+    ) -> Result<Self> {
+        let merk = Merk::open_shared(db, prefix, codec)?;
+        if merk.root_hash() != NULL_HASH {
+            return Err(Error::ChunkProcessing(
+                "Can only restore into an empty shared store".into(),
+            ));
+        }
+
+        Ok(Self::from_merk(
+            merk,
+            expected_root_hash,
+            Some(stated_length),
+        ))
+    }
+
+    fn from_merk(merk: Merk, expected_root_hash: Hash, stated_length: Option<usize>) -> Self {
+        Self {
             expected_root_hash,
             stated_length,
             trunk_height: None,
-            merk: Merk::open(db_path)?,
+            expected_trunk_height: None,
+            merk,
             leaf_hashes: None,
             parent_keys: None,
-        })
+            arena: TreeArena::new(),
+            pending: vec![],
+            nodes_written: 0,
+        }
+    }
+
+    /// Sets the limits chunks are decoded and verified with, which also apply
+    /// to batches applied to the restored store. Returns an error if any limit
+    /// is above what the encodings support.
+    pub fn with_limits(mut self, limits: Limits) -> Result<Self> {
+        self.merk.set_limits(limits)?;
+        Ok(self)
+    }
+
+    /// Expects the trunk to have `trunk_height` layers, for chunks produced by
+    /// `ChunkProducer::with_trunk_height`.
+    pub fn with_trunk_height(mut self, trunk_height: usize) -> Self {
+        self.expected_trunk_height = Some(trunk_height);
+        self
     }
 
     /// Verifies a chunk and writes it to the working RocksDB instance. Expects
@@ -65,16 +154,224 @@ impl Restorer {
     /// Once there are no remaining chunks to be processed, `finalize` should
     /// be called.
     pub fn process_chunk(&mut self, chunk_bytes: &[u8]) -> Result<usize> {
-        let ops = Decoder::new(chunk_bytes);
+        let start = Instant::now();
+        let ops = Decoder::with_limits(chunk_bytes, self.merk.limits);
 
-        match self.leaf_hashes {
+        let remaining = match self.leaf_hashes {
             None => self.process_trunk(ops),
             Some(_) => self.process_leaf(ops),
+        }?;
+        telemetry::record_chunk_verification(start.elapsed());
+        Ok(remaining)
+    }
+
+    /// Verifies and writes a chunk prefixed with its header, as returned by
+    /// `ChunkProducer::chunk_with_header`, as in `process_chunk`. Before
+    /// verifying the chunk, checks that it is the next chunk to be processed
+    /// (see `next_chunk_index`), that it was produced from a tree with the
+    /// expected root hash, and that the header matches its ops.
+    pub fn process_chunk_with_header(&mut self, bytes: &[u8]) -> Result<usize> {
+        let (header, chunk_bytes) = ChunkHeader::decode(bytes)?;
+        if header.root_hash != self.expected_root_hash {
+            return Err(Error::HashMismatch(
+                self.expected_root_hash,
+                header.root_hash,
+            ));
+        }
+        let next_index = self.next_chunk_index();
+        if header.index as usize != next_index {
+            return Err(Error::ChunkProcessing(format!(
+                "Expected chunk {}, but received chunk {}",
+                next_index, header.index
+            )));
+        }
+
+        let start = Instant::now();
+        let ops =
+            Decoder::with_limits(chunk_bytes, self.merk.limits).collect::<Result<Vec<_>>>()?;
+        header.verify(&ops)?;
+        let ops = ops.into_iter().map(Ok);
+        let remaining = match self.leaf_hashes {
+            None => self.process_trunk(ops),
+            Some(_) => self.process_leaf(ops),
+        }?;
+        telemetry::record_chunk_verification(start.elapsed());
+        Ok(remaining)
+    }
+
+    /// Processes a part of a chunk split by `ChunkProducer::chunk_parts`.
+    /// Parts must be passed in order, with `last` set for the final part of
+    /// the chunk, at which point the whole chunk is verified and written as in
+    /// `process_chunk`. Returns the number of remaining chunks, not counting
+    /// the chunk whose parts are being received.
+    ///
+    /// A part which fails to decode can be retried on its own, but if the
+    /// completed chunk fails verification, all of its parts are discarded and
+    /// must be received again.
+    pub fn process_chunk_part(&mut self, part: &[u8], last: bool) -> Result<usize> {
+        let mut ops = Decoder::with_limits(part, self.merk.limits).collect::<Result<Vec<_>>>()?;
+        self.pending.append(&mut ops);
+        if !last {
+            return Ok(self.known_remaining().saturating_sub(1));
+        }
+
+        let start = Instant::now();
+        let ops = std::mem::take(&mut self.pending).into_iter().map(Ok);
+        let remaining = match self.leaf_hashes {
+            None => self.process_trunk(ops),
+            Some(_) => self.process_leaf(ops),
+        }?;
+        telemetry::record_chunk_verification(start.elapsed());
+        Ok(remaining)
+    }
+
+    /// Processes a sequence of consecutive chunks as in `process_chunk`,
+    /// decoding and verifying leaf chunks on `threads` worker threads while a
+    /// writer thread stores the verified nodes in large write batches. Returns
+    /// the number of remaining chunks.
+    ///
+    /// If a chunk fails verification, the chunks before it are still written
+    /// and the error is returned, so processing can resume from the failed
+    /// chunk (which can be checked with `remaining_chunks`).
+    pub fn process_chunks(&mut self, chunks: &[Vec<u8>], threads: usize) -> Result<usize> {
+        let mut chunks = chunks;
+        if self.leaf_hashes.is_none() {
+            let (trunk, rest) = match chunks.split_first() {
+                Some(split) => split,
+                None => return Ok(self.known_remaining()),
+            };
+            self.process_chunk(trunk)?;
+            chunks = rest;
+        }
+
+        let leaf_hashes: Vec<Hash> = self.leaf_hashes.clone().unwrap().collect();
+        if chunks.len() > leaf_hashes.len() {
+            return Err(Error::ChunkProcessing(
+                "Received more chunks than expected".into(),
+            ));
+        }
+
+        let codec = &self.merk.codec;
+        let limits = self.merk.limits;
+        let db = self.merk.db.as_ref();
+        let prefix = self.merk.prefix.as_slice();
+        let next = AtomicUsize::new(0);
+        let (root_keys, nodes_written, maybe_err, written) = std::thread::scope(|scope| {
+            let (write_sender, write_receiver) =
+                sync_channel::<Vec<(Vec<u8>, Vec<u8>)>>(threads * 2);
+            let writer = scope.spawn(move || {
+                let mut batch = WriteBatch::default();
+                let mut batch_size = 0;
+                for nodes in write_receiver {
+                    for (key, bytes) in nodes {
+                        batch_size += key.len() + bytes.len();
+                        batch.put(prefixed(prefix, &key), bytes);
+                    }
+                    if batch_size >= WRITE_BATCH_SIZE {
+                        write_batch(db, std::mem::take(&mut batch))?;
+                        batch_size = 0;
+                    }
+                }
+                write_batch(db, batch)
+            });
+
+            let (result_sender, result_receiver) = channel();
+            for _ in 0..threads.max(1) {
+                let (next, leaf_hashes, result_sender) =
+                    (&next, &leaf_hashes, result_sender.clone());
+                scope.spawn(move || {
+                    let mut arena = TreeArena::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= chunks.len() {
+                            return;
+                        }
+
+                        let start = Instant::now();
+                        let res = verify_leaf_chunk(
+                            &mut arena,
+                            &chunks[index],
+                            leaf_hashes[index],
+                            codec,
+                            limits,
+                        );
+                        telemetry::record_chunk_verification(start.elapsed());
+                        if result_sender.send((index, res)).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+            drop(result_sender);
+
+            // results arrive in any order, but are written in chunk order so a
+            // failure leaves a contiguous prefix of the chunks written
+            let mut results = HashMap::new();
+            let mut root_keys = vec![];
+            let mut nodes_written = 0;
+            let mut maybe_err = None;
+            'receive: for (index, res) in result_receiver.iter() {
+                results.insert(index, res);
+                while let Some(res) = results.remove(&root_keys.len()) {
+                    match res {
+                        Ok((root_key, nodes)) => {
+                            nodes_written += nodes.len() as u64;
+                            if write_sender.send(nodes).is_err() {
+                                break 'receive;
+                            }
+                            root_keys.push(root_key);
+                        }
+                        Err(err) => {
+                            maybe_err = Some(err);
+                            break 'receive;
+                        }
+                    }
+                }
+            }
+            // stop the workers and hang up so the writer finishes
+            next.store(chunks.len(), Ordering::Relaxed);
+            drop(result_receiver);
+            drop(write_sender);
+
+            let written = writer.join().unwrap();
+            (root_keys, nodes_written, maybe_err, written)
+        });
+        written?;
+        self.nodes_written += nodes_written;
+
+        for root_key in root_keys {
+            self.rewrite_parent_link(root_key)?;
+            self.leaf_hashes.as_mut().unwrap().next();
+        }
+
+        match maybe_err {
+            Some(err) => Err(err),
+            None => Ok(self.remaining_chunks_unchecked()),
+        }
+    }
+
+    /// Verifies the next chunk and writes it as in `process_chunk`, returning
+    /// `true` once every chunk has been processed and the restorer is ready
+    /// to be finalized. Returns an error without processing anything if every
+    /// chunk has already been processed.
+    pub fn feed_chunk(&mut self, bytes: &[u8]) -> Result<bool> {
+        if self.is_done() {
+            return Err(Error::ChunkProcessing(
+                "Received a chunk after all chunks were processed".into(),
+            ));
         }
+
+        Ok(self.process_chunk(bytes)? == 0)
+    }
+
+    /// Returns true once every chunk has been processed.
+    pub fn is_done(&self) -> bool {
+        self.remaining_chunks() == Some(0)
     }
 
     /// Consumes the `Restorer` and returns the newly-created, fully-populated
-    /// Merk instance. This method will return an error if called before
+    /// Merk instance, after checking that the root hash of the restored tree
+    /// is the expected one. This method will return an error if called before
     /// processing all chunks (e.g. `restorer.remaining_chunks()` is not equal
     /// to 0).
     pub fn finalize(mut self) -> Result<Merk> {
@@ -88,12 +385,32 @@ impl Restorer {
             self.rewrite_trunk_child_heights()?;
         }
 
+        let mut batch = WriteBatch::default();
+        self.merk.set_key_count(self.nodes_written, &mut batch);
+        self.merk.write(batch)?;
         self.merk.flush()?;
         self.merk.load_root()?;
 
+        let root_hash = self.merk.root_hash();
+        if root_hash != self.expected_root_hash {
+            return Err(Error::HashMismatch(self.expected_root_hash, root_hash));
+        }
+
         Ok(self.merk)
     }
 
+    /// Returns the index of the next chunk to be processed, where the trunk is
+    /// chunk 0, e.g. to resume fetching chunks after an interruption.
+    pub fn next_chunk_index(&self) -> usize {
+        match (self.trunk_height, self.remaining_chunks()) {
+            (Some(trunk_height), Some(remaining)) if trunk_height >= MIN_TRUNK_HEIGHT => {
+                1 + 2usize.pow(trunk_height as u32) - remaining
+            }
+            (Some(_), Some(_)) => 1,
+            _ => 0,
+        }
+    }
+
     /// Returns the number of remaining chunks to be processed. If called before
     /// the first chunk is processed, this method will return `None` since we do
     /// not yet have enough information to know about the number of chunks.
@@ -101,10 +418,19 @@ impl Restorer {
         self.leaf_hashes.as_ref().map(|lh| lh.len())
     }
 
+    /// Returns the number of remaining chunks if the trunk has been processed,
+    /// or otherwise the number of chunks stated by the peer (or 1 for the
+    /// trunk, if no number was stated).
+    fn known_remaining(&self) -> usize {
+        self.remaining_chunks().or(self.stated_length).unwrap_or(1)
+    }
+
     /// Writes the data contained in `tree` (extracted from a verified chunk
     /// proof) to the RocksDB.
     fn write_chunk(&mut self, tree: ProofTree) -> Result<()> {
         let mut batch = WriteBatch::default();
+        let mut maybe_err = None;
+        let mut nodes = 0;
 
         tree.visit_refs(&mut |proof_node| {
             let (key, mut node) = match &proof_node.node {
@@ -119,20 +445,53 @@ impl Restorer {
             *node.slot_mut(true) = proof_node.left.as_ref().map(Child::as_link);
             *node.slot_mut(false) = proof_node.right.as_ref().map(Child::as_link);
 
-            let bytes = node.encode();
-            batch.put(key, bytes);
+            match self.merk.codec.encode(key, node.encode()) {
+                Ok(bytes) => {
+                    batch.put(self.merk.prefixed(key), bytes);
+                    nodes += 1;
+                }
+                Err(err) => {
+                    maybe_err.get_or_insert(err);
+                }
+            }
         });
 
-        self.merk.write(batch)
+        if let Some(err) = maybe_err {
+            return Err(err);
+        }
+
+        self.merk.write(batch)?;
+        self.nodes_written += nodes;
+        Ok(())
+    }
+
+    /// Writes the leaf chunk held in the arena to the RocksDB.
+    fn write_leaf_chunk(&mut self) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        let nodes = encode_leaf_chunk(&mut self.arena, &self.merk.codec)?;
+        let count = nodes.len() as u64;
+        for (key, bytes) in nodes {
+            batch.put(self.merk.prefixed(&key), bytes);
+        }
+        self.merk.write(batch)?;
+        self.nodes_written += count;
+        Ok(())
     }
 
     /// Verifies the trunk then writes its data to the RocksDB.
     ///
     /// The trunk contains a height proof which lets us verify the total number
     /// of expected chunks is the same as `stated_length` as passed into
-    /// `Restorer::new()`. We also verify the expected root hash at this step.
-    fn process_trunk(&mut self, ops: Decoder) -> Result<usize> {
-        let (trunk, height) = verify_trunk(ops)?;
+    /// `Restorer::new()`, if any. We also verify the expected root hash at
+    /// this step.
+    fn process_trunk<I: Iterator<Item = Result<Op>>>(&mut self, ops: I) -> Result<usize> {
+        let (trunk, height) = verify_trunk(ops, self.expected_trunk_height)?;
+        if height > self.merk.limits.max_tree_height {
+            return Err(Error::ChunkProcessing(format!(
+                "Tree height {} exceeds the limit of {}",
+                height, self.merk.limits.max_tree_height
+            )));
+        }
 
         if trunk.hash()? != self.expected_root_hash {
             return Err(Error::HashMismatch(self.expected_root_hash, trunk.hash()?));
@@ -140,7 +499,7 @@ impl Restorer {
 
         let root_key = trunk.key().to_vec();
 
-        let trunk_height = height / 2;
+        let trunk_height = self.expected_trunk_height.unwrap_or(height / 2);
         self.trunk_height = Some(trunk_height);
 
         let chunks_remaining = if trunk_height >= MIN_TRUNK_HEIGHT {
@@ -173,29 +532,37 @@ impl Restorer {
             0
         };
 
-        // FIXME: this one shouldn't be an assert because it comes from a peer
-        assert_eq!(self.stated_length, chunks_remaining + 1);
+        if let Some(stated_length) = self.stated_length {
+            if stated_length != chunks_remaining + 1 {
+                return Err(Error::ChunkProcessing(format!(
+                    "Peer stated {} chunks, but the trunk proves {}",
+                    stated_length,
+                    chunks_remaining + 1
+                )));
+            }
+        }
 
         // note that these writes don't happen atomically, which is fine here
         // because if anything fails during the restore process we will just
         // scrap the whole restore and start over
         self.write_chunk(trunk)?;
-        self.merk.set_root_key(root_key)?;
+        self.merk.set_root_key(root_key, self.nodes_written)?;
 
         Ok(chunks_remaining)
     }
 
     /// Verifies a leaf chunk then writes it to the RocksDB. This needs to be
     /// called in order, retrying the last chunk for any failed verifications.
-    fn process_leaf(&mut self, ops: Decoder) -> Result<usize> {
+    fn process_leaf<I: Iterator<Item = Result<Op>>>(&mut self, ops: I) -> Result<usize> {
         let leaf_hashes = self.leaf_hashes.as_mut().unwrap();
         let leaf_hash = leaf_hashes
             .peek()
             .expect("Received more chunks than expected");
 
-        let leaf = verify_leaf(ops, *leaf_hash)?;
-        self.rewrite_parent_link(&leaf)?;
-        self.write_chunk(leaf)?;
+        let root = verify_leaf_in(&mut self.arena, ops, *leaf_hash)?;
+        let root_key = arena_key(&self.arena, root).to_vec();
+        self.rewrite_parent_link(root_key)?;
+        self.write_leaf_chunk()?;
 
         let leaf_hashes = self.leaf_hashes.as_mut().unwrap();
         leaf_hashes.next();
@@ -207,7 +574,7 @@ impl Restorer {
     /// children when it is first written. Now that we have verified this leaf,
     /// we can write the key into the parent node's entry. Note that this does
     /// not need to recalcuate hashes since it already had the child hash.
-    fn rewrite_parent_link(&mut self, leaf: &ProofTree) -> Result<()> {
+    fn rewrite_parent_link(&mut self, leaf_key: Vec<u8>) -> Result<()> {
         let parent_keys = self.parent_keys.as_mut().unwrap();
         let parent_key = parent_keys.peek().unwrap().clone();
         let mut parent = self
@@ -217,13 +584,15 @@ impl Restorer {
 
         let is_left_child = self.remaining_chunks_unchecked() % 2 == 0;
         if let Some(Link::Reference { ref mut key, .. }) = parent.link_mut(is_left_child) {
-            *key = leaf.key().to_vec();
+            *key = leaf_key;
         } else {
             panic!("Expected parent links to be type Link::Reference");
         };
 
-        let parent_bytes = parent.encode();
-        self.merk.db.put(parent_key, parent_bytes)?;
+        let parent_bytes = self.merk.codec.encode(&parent_key, parent.encode())?;
+        self.merk
+            .db
+            .put(self.merk.prefixed(&parent_key), parent_bytes)?;
 
         if !is_left_child {
             let parent_keys = self.parent_keys.as_mut().unwrap();
@@ -238,26 +607,27 @@ impl Restorer {
             mut node: RefWalker<MerkSource>,
             remaining_depth: usize,
             batch: &mut WriteBatch,
+            merk: &Merk,
         ) -> Result<(u8, u8)> {
             if remaining_depth == 0 {
                 return Ok(node.tree().child_heights());
             }
 
             let mut cloned_node =
-                Tree::decode(node.tree().key().to_vec(), node.tree().encode().as_slice());
+                Tree::decode(node.tree().key().to_vec(), node.tree().encode().as_slice())?;
 
             let left_child = node.walk(true)?.unwrap();
-            let left_child_heights = recurse(left_child, remaining_depth - 1, batch)?;
+            let left_child_heights = recurse(left_child, remaining_depth - 1, batch, merk)?;
             let left_height = left_child_heights.0.max(left_child_heights.1) + 1;
             *cloned_node.link_mut(true).unwrap().child_heights_mut() = left_child_heights;
 
             let right_child = node.walk(false)?.unwrap();
-            let right_child_heights = recurse(right_child, remaining_depth - 1, batch)?;
+            let right_child_heights = recurse(right_child, remaining_depth - 1, batch, merk)?;
             let right_height = right_child_heights.0.max(right_child_heights.1) + 1;
             *cloned_node.link_mut(false).unwrap().child_heights_mut() = right_child_heights;
 
-            let bytes = cloned_node.encode();
-            batch.put(node.tree().key(), bytes);
+            let bytes = merk.codec.encode(node.tree().key(), cloned_node.encode())?;
+            batch.put(merk.prefixed(node.tree().key()), bytes);
 
             Ok((left_height, right_height))
         }
@@ -271,7 +641,7 @@ impl Restorer {
         self.merk.use_tree_mut(|maybe_tree| {
             let tree = maybe_tree.unwrap();
             let walker = RefWalker::new(tree, self.merk.source());
-            recurse(walker, depth, &mut batch)
+            recurse(walker, depth, &mut batch, &self.merk)
         })?;
 
         self.merk.write(batch)?;
@@ -287,6 +657,69 @@ impl Restorer {
     }
 }
 
+/// Verifies a leaf chunk against `leaf_hash` in `arena`, returning the key of
+/// its root and the encoded entries of its nodes.
+fn verify_leaf_chunk(
+    arena: &mut TreeArena,
+    chunk: &[u8],
+    leaf_hash: Hash,
+    codec: &NodeCodec,
+    limits: Limits,
+) -> Result<(Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>)> {
+    let root = verify_leaf_in(arena, Decoder::with_limits(chunk, limits), leaf_hash)?;
+    let root_key = arena_key(arena, root).to_vec();
+    Ok((root_key, encode_leaf_chunk(arena, codec)?))
+}
+
+/// Encodes the nodes of the leaf chunk held in `arena` for storage, moving the
+/// keys and values out of the arena rather than copying them.
+fn encode_leaf_chunk(arena: &mut TreeArena, codec: &NodeCodec) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    // links read their children's keys, so they are all created before any
+    // node is taken
+    let links: Vec<_> = (0..arena.len())
+        .map(|id| {
+            let node = arena.node(id);
+            (
+                node.left.map(|child| arena_link(arena, child)),
+                node.right.map(|child| arena_link(arena, child)),
+            )
+        })
+        .collect();
+
+    let mut nodes = Vec::with_capacity(links.len());
+    for (id, (left, right)) in links.into_iter().enumerate() {
+        let kv_hash = arena.node(id).kv_hash;
+        let (key, value) = match arena.take_node(id) {
+            Node::KV(key, value) => (key, value),
+            _ => unreachable!("Leaf chunks contain only KV nodes"),
+        };
+
+        let node = Tree::from_fields(key, value, kv_hash, left, right);
+        let bytes = codec.encode(node.key(), node.encode())?;
+        nodes.push((node.key().to_vec(), bytes));
+    }
+    Ok(nodes)
+}
+
+/// Returns the key of the node at `id` in a verified leaf chunk, which must not
+/// have been taken yet.
+fn arena_key(arena: &TreeArena, id: usize) -> &[u8] {
+    match &arena.node(id).node {
+        Node::KV(key, _) => key,
+        _ => unreachable!("Leaf chunks contain only KV nodes"),
+    }
+}
+
+/// Returns a link to the node at `id` in the arena, which must not have been
+/// taken yet.
+fn arena_link(arena: &TreeArena, id: usize) -> Link {
+    Link::Reference {
+        hash: arena.node(id).hash,
+        child_heights: arena.child_heights(id),
+        key: arena_key(arena, id).to_vec(),
+    }
+}
+
 impl Merk {
     /// Creates a new `Restorer`, which can be used to verify chunk proofs to
     /// replicate an entire Merk tree. A new Merk instance will be initialized
@@ -335,6 +768,7 @@ impl Child {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::merk::chunks::ChunkProducer;
     use crate::test_utils::*;
     use crate::tree::{Batch, Op};
     use std::path::PathBuf;
@@ -370,6 +804,7 @@ mod tests {
 
         let restored = restorer.finalize().unwrap();
         assert_eq!(restored.root_hash(), original.root_hash());
+        assert_eq!(restored.len(), original.len());
         assert_raw_db_entries_eq(&restored, &original, expected_nodes);
 
         std::fs::remove_dir_all(&path).unwrap();
@@ -406,6 +841,257 @@ mod tests {
         restore_test(&[&make_batch_seq(0..1)], 1);
     }
 
+    #[test]
+    fn restore_with_trunk_height() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        original.flush().unwrap();
+        let chunks = ChunkProducer::with_trunk_height(&original, 9).unwrap();
+        assert_eq!(chunks.len(), 513);
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len())
+            .unwrap()
+            .with_trunk_height(9);
+        for chunk in chunks {
+            restorer.process_chunk(&chunk.unwrap()).unwrap();
+        }
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_raw_db_entries_eq(&restored, &original, 10_000);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_chunks_with_headers() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        original.flush().unwrap();
+        let mut chunks = original.chunks().unwrap();
+        let count = chunks.len();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        let mut restorer = Merk::restore(&path, original.root_hash(), count).unwrap();
+        assert!(restorer
+            .process_chunk_with_header(&chunks.chunk_with_header(1).unwrap())
+            .is_err());
+        for index in 0..count {
+            assert_eq!(restorer.next_chunk_index(), index);
+            let chunk = chunks.chunk_with_header(index).unwrap();
+            let (header, _) = ChunkHeader::decode(&chunk).unwrap();
+            assert_eq!(header.index as usize, index);
+            assert_eq!(header.root_hash, original.root_hash());
+
+            if index == 2 {
+                // a chunk can't be processed twice
+                let previous = chunks.chunk_with_header(1).unwrap();
+                assert!(restorer.process_chunk_with_header(&previous).is_err());
+            }
+            let remaining = restorer.process_chunk_with_header(&chunk).unwrap();
+            assert_eq!(remaining, count - index - 1);
+        }
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_shared() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        original.flush().unwrap();
+
+        let path = std::thread::current().name().unwrap().to_owned();
+        let db = DB::open_cf_descriptors(&Merk::default_db_opts(), &path, Merk::column_families())
+            .map(Arc::new)
+            .unwrap();
+        let mut other = Merk::open_shared(db.clone(), b"b/".to_vec(), NodeCodec::new()).unwrap();
+        other.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let other_hash = other.root_hash();
+
+        let restore = |prefix: &[u8]| {
+            Restorer::new_shared(
+                db.clone(),
+                prefix.to_vec(),
+                NodeCodec::new(),
+                original.root_hash(),
+                original.chunks().unwrap().len(),
+            )
+        };
+        assert!(restore(b"b/").is_err());
+
+        let mut restorer = restore(b"a/").unwrap();
+        for chunk in original.chunks().unwrap() {
+            restorer.process_chunk(&chunk.unwrap()).unwrap();
+        }
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_eq!(
+            restored.get(&seq_key(500)).unwrap(),
+            original.get(&seq_key(500)).unwrap()
+        );
+        assert_eq!(other.root_hash(), other_hash);
+        assert_eq!(other.get(&seq_key(500)).unwrap(), None);
+
+        drop((restored, other));
+        drop(db);
+        DB::destroy(&Merk::default_db_opts(), &path).unwrap();
+    }
+
+    #[test]
+    fn restore_with_limits() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        let mut chunks = original.chunks().unwrap();
+        let trunk = chunks.chunk(0).unwrap();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        for limits in [
+            Limits {
+                max_tree_height: 10,
+                ..Default::default()
+            },
+            Limits {
+                max_proof_ops: 100,
+                ..Default::default()
+            },
+        ] {
+            if path.exists() {
+                std::fs::remove_dir_all(&path).unwrap();
+            }
+            let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len())
+                .unwrap()
+                .with_limits(limits)
+                .unwrap();
+            assert!(restorer.process_chunk(&trunk).is_err());
+        }
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_split_chunks() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        original.flush().unwrap();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        let mut chunks =
+            original
+                .chunks()
+                .unwrap()
+                .with_feedback(|| crate::merk::chunks::TransferFeedback {
+                    max_message_size: Some(crate::merk::chunks::MIN_PART_SIZE),
+                    ..Default::default()
+                });
+        let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len()).unwrap();
+
+        for i in 0..chunks.len() {
+            let parts = chunks.chunk_parts(i).unwrap();
+            let expected_remaining = chunks.len() - i - 1;
+            let (last, parts) = parts.split_last().unwrap();
+            for part in parts {
+                let remaining = restorer.process_chunk_part(part, false).unwrap();
+                assert_eq!(remaining, expected_remaining);
+            }
+            let remaining = restorer.process_chunk_part(last, true).unwrap();
+            assert_eq!(remaining, expected_remaining);
+        }
+
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_raw_db_entries_eq(&restored, &original, 10_000);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_parallel() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        original.flush().unwrap();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        let mut chunks = original
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len()).unwrap();
+
+        // chunks before an invalid chunk are still written
+        let valid = chunks[50].clone();
+        chunks[50] = chunks[51].clone();
+        assert!(restorer.process_chunks(&chunks, 4).is_err());
+        assert_eq!(restorer.remaining_chunks(), Some(chunks.len() - 50));
+
+        chunks[50] = valid;
+        let remaining = restorer.process_chunks(&chunks[50..], 4).unwrap();
+        assert_eq!(remaining, 0);
+
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_raw_db_entries_eq(&restored, &original, 10_000);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_for_root() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        original.flush().unwrap();
+        let chunks = original
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        // a wrong stated length is an error rather than a panic
+        let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len() + 1).unwrap();
+        assert!(matches!(
+            restorer.process_chunk(&chunks[0]),
+            Err(Error::ChunkProcessing(_))
+        ));
+        drop(restorer);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        let mut restorer = Restorer::for_root(&path, original.root_hash()).unwrap();
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert!(!restorer.is_done());
+            let done = restorer.feed_chunk(chunk).unwrap();
+            assert_eq!(done, i == chunks.len() - 1);
+        }
+        assert!(restorer.is_done());
+        assert!(restorer.feed_chunk(&chunks[1]).is_err());
+
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_raw_db_entries_eq(&restored, &original, 10_000);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
     fn assert_raw_db_entries_eq(restored: &Merk, original: &Merk, length: usize) {
         let mut original_entries = original.raw_iter();
         let mut restored_entries = restored.raw_iter();
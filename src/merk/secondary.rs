@@ -0,0 +1,130 @@
+//! Provides read-only follower instances which open the data directory of
+//! another (writing) Merk process as a RocksDB secondary instance.
+
+use super::{column_family_names, Merk, NodeCodec};
+use crate::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+impl Merk {
+    /// Opens the store at `primary_path` as a read-only secondary instance,
+    /// keeping its own info logs at `secondary_path`. The primary may keep
+    /// writing while the secondary is open.
+    ///
+    /// `codec` must be the codec the primary was opened with, e.g. with its
+    /// encryption key; its compression dictionary is loaded from the primary's
+    /// data.
+    ///
+    /// The secondary only sees the state of the primary as of the time it was
+    /// opened; call `try_catch_up` periodically to follow the primary's
+    /// writes. Calls to `apply` on a secondary instance will fail.
+    pub fn open_secondary<P, S>(
+        primary_path: P,
+        secondary_path: S,
+        codec: NodeCodec,
+    ) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+        S: AsRef<Path>,
+    {
+        let db_opts = Merk::default_db_opts();
+        let primary_path = primary_path.as_ref();
+        let secondary_path = secondary_path.as_ref();
+        let db = rocksdb::DB::open_cf_as_secondary(
+            &db_opts,
+            primary_path,
+            secondary_path,
+            column_family_names(),
+        )?;
+
+        Merk::load(
+            Arc::new(db),
+            PathBuf::from(secondary_path),
+            vec![],
+            codec,
+            false,
+            false,
+        )
+    }
+
+    /// Catches a secondary instance up with the writes made by the primary
+    /// since it was opened or last caught up, then reloads all of the
+    /// persisted state (the root node, the encoding version and compression
+    /// dictionary, tombstones, and so on) so subsequent reads and proofs
+    /// reflect the latest committed state.
+    pub fn try_catch_up(&mut self) -> Result<()> {
+        self.db.try_catch_up_with_primary()?;
+        self.load_state(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Merk, NodeCodec};
+
+    #[test]
+    fn follow_primary() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let secondary_path = path.clone() + ".secondary";
+        let codec = NodeCodec::new().with_checksums();
+
+        let mut primary =
+            Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone()).unwrap();
+        primary
+            .apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let mut secondary = Merk::open_secondary(&path, &secondary_path, codec).unwrap();
+        assert_eq!(secondary.root_hash(), primary.root_hash());
+
+        primary.enable_tombstones(10).unwrap();
+        primary
+            .apply_at_height(&make_batch_seq(100..200), &[], 7)
+            .expect("apply failed");
+        assert_eq!(secondary.get(&seq_key(150)).unwrap(), None);
+
+        secondary.try_catch_up().unwrap();
+        assert_eq!(secondary.root_hash(), primary.root_hash());
+        assert_eq!(
+            secondary.get(&seq_key(150)).unwrap(),
+            Some(put_entry_value())
+        );
+        assert_eq!(secondary.last_height(), Some(7));
+        assert_eq!(secondary.len(), 200);
+        assert!(secondary.tombstones.is_some());
+
+        assert!(secondary.apply(&make_batch_seq(200..201), &[]).is_err());
+
+        drop(secondary);
+        primary.destroy().unwrap();
+        std::fs::remove_dir_all(&secondary_path).unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn follow_compressed_primary() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let secondary_path = path.clone() + ".secondary";
+        let codec = NodeCodec::new().with_compression(32);
+
+        let mut primary =
+            Merk::open_with_codec(&path, Merk::default_db_opts(), codec.clone()).unwrap();
+        primary.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        let mut secondary = Merk::open_secondary(&path, &secondary_path, codec).unwrap();
+
+        // nodes written after the dictionary is trained can only be read with
+        // it, so the secondary must pick it up when catching up
+        primary.train_compression_dictionary(1_000, 4_096).unwrap();
+        primary.apply(&make_batch_seq(1_000..2_000), &[]).unwrap();
+        secondary.try_catch_up().unwrap();
+        assert_eq!(secondary.root_hash(), primary.root_hash());
+        for i in (0..2_000).step_by(100) {
+            assert_eq!(secondary.get(&seq_key(i)).unwrap(), Some(put_entry_value()));
+        }
+
+        drop(secondary);
+        primary.destroy().unwrap();
+        std::fs::remove_dir_all(&secondary_path).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+//! Executes and proves `Select` reads against a store.
+
+use super::{Cursor, Merk};
+use crate::proofs::query::{QueryItem, Select};
+use crate::Result;
+
+/// A position in a store's entries which selects are read with, implemented
+/// for `Cursor` and for cursors over a committed version.
+pub(crate) trait EntryCursor {
+    /// Moves to the first key which is greater than or equal to `key`.
+    fn seek(&mut self, key: &[u8]);
+
+    /// Moves to the last key which is less than or equal to `key`.
+    fn seek_for_prev(&mut self, key: &[u8]);
+
+    /// Returns the entry at the cursor, or `None` if the cursor has moved
+    /// past either end of the store.
+    fn current(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>>;
+
+    /// Moves to the next key and returns its entry, if any.
+    fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>>;
+
+    /// Moves to the previous key and returns its entry, if any.
+    fn prev(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>>;
+}
+
+impl<'a> EntryCursor for Cursor<'a> {
+    fn seek(&mut self, key: &[u8]) {
+        Cursor::seek(self, key)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        Cursor::seek_for_prev(self, key)
+    }
+
+    fn current(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        Cursor::current(self)
+    }
+
+    fn next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        Cursor::next(self)
+    }
+
+    fn prev(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        Cursor::prev(self)
+    }
+}
+
+impl Merk {
+    /// Reads the entries selected by `select`.
+    ///
+    /// As with `get_range`, entries are read from the stored nodes, so writes
+    /// made during a block or an import which have not been written to the
+    /// database yet are not included.
+    pub fn select(&self, select: &Select) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        read_select(&mut self.cursor(), select)
+    }
+
+    /// Creates a proof of the entries selected by `select`, which can be
+    /// checked with `verify_select`.
+    pub fn prove_select(&self, select: &Select) -> Result<Vec<u8>> {
+        let entries = self.select(select)?;
+        self.prove_unchecked(select.proof_items(&entries))
+    }
+
+    /// Returns the number of entries selected by `select`.
+    ///
+    /// This reads every selected entry, including its value, so it takes time
+    /// and memory linear in the count rather than logarithmic in the size of
+    /// the store.
+    pub fn count(&self, select: &Select) -> Result<u64> {
+        Ok(self.select(select)?.len() as u64)
+    }
+}
+
+/// Reads the entries selected by `select` with `cursor`.
+pub(crate) fn read_select<C: EntryCursor>(
+    cursor: &mut C,
+    select: &Select,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    select.resolve(|item, limit| read_item(cursor, item, select.is_descending(), limit))
+}
+
+/// Reads up to `limit` entries in `item`, in ascending or descending order.
+fn read_item<C: EntryCursor>(
+    cursor: &mut C,
+    item: &QueryItem,
+    descending: bool,
+    limit: usize,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut entries = vec![];
+    let (end, end_inclusive) = item.upper_bound();
+
+    if descending {
+        cursor.seek_for_prev(end);
+        let mut maybe_entry = cursor.current()?;
+        if !end_inclusive && matches!(&maybe_entry, Some((key, _)) if key.as_slice() == end) {
+            maybe_entry = cursor.prev()?;
+        }
+        while let Some(entry) = maybe_entry {
+            if entries.len() == limit || entry.0.as_slice() < item.lower_bound() {
+                break;
+            }
+            entries.push(entry);
+            maybe_entry = cursor.prev()?;
+        }
+    } else {
+        cursor.seek(item.lower_bound());
+        let mut maybe_entry = cursor.current()?;
+        while let Some(entry) = maybe_entry {
+            if entries.len() == limit || !item.contains(&entry.0) {
+                break;
+            }
+            entries.push(entry);
+            maybe_entry = cursor.next()?;
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proofs::query::{verify_select, Select};
+    use crate::test_utils::*;
+    use crate::tree::Op;
+
+    #[test]
+    fn select_and_verify() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..50), &[]).unwrap();
+        let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<Vec<u8>> {
+            entries.into_iter().map(|(key, _)| key).collect()
+        };
+
+        let selects = vec![
+            (
+                Select::new().range(seq_key(5)..seq_key(10)),
+                (5..10).collect(),
+            ),
+            (
+                Select::new()
+                    .range(seq_key(5)..seq_key(10))
+                    .key(seq_key(30))
+                    .key(seq_key(99))
+                    .limit(6),
+                vec![5, 6, 7, 8, 9, 30],
+            ),
+            (
+                Select::new()
+                    .range_inclusive(seq_key(5)..=seq_key(10))
+                    .prefix(seq_key(40)[..7].to_vec())
+                    .limit(4)
+                    .descending(),
+                vec![49, 48, 47, 46],
+            ),
+            (
+                Select::new()
+                    .range(seq_key(5)..seq_key(10))
+                    .limit(3)
+                    .descending(),
+                vec![9, 8, 7],
+            ),
+        ];
+
+        for (select, expected) in selects {
+            let expected: Vec<_> = expected.into_iter().map(seq_key).collect();
+            assert_eq!(keys(merk.select(&select).unwrap()), expected);
+
+            let proof = merk.prove_select(&select).unwrap();
+            let entries = verify_select(&proof, &select, merk.root_hash()).unwrap();
+            assert_eq!(keys(entries), expected);
+        }
+    }
+
+    #[test]
+    fn count() {
+        let mut merk = TempMerk::new().unwrap();
+        let batch: Vec<_> = (0..30u8)
+            .map(|i| {
+                let prefix = if i < 12 { b"accounts/" } else { b"validator" };
+                let mut key = prefix.to_vec();
+                key.push(i);
+                (key, Op::Put(vec![i]))
+            })
+            .collect();
+        merk.apply(&batch, &[]).unwrap();
+
+        let accounts = Select::new().prefix(b"accounts/".to_vec());
+        assert_eq!(merk.count(&accounts).unwrap(), 12);
+
+        let none = Select::new().prefix(b"blocks/".to_vec());
+        assert_eq!(merk.count(&none).unwrap(), 0);
+
+        let mut end = b"accounts/".to_vec();
+        end.push(6);
+        let partial = Select::new().range(b"accounts/".to_vec()..end);
+        assert_eq!(merk.count(&partial).unwrap(), 6);
+    }
+}
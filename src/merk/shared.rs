@@ -0,0 +1,136 @@
+//! Allows a Merk store to live inside a RocksDB instance owned by the
+//! application, alongside the application's own data and other stores.
+
+use super::{column_families, column_family_names, prefix_read_opts, Merk, NodeCodec};
+use crate::{Error, Result};
+use rocksdb::{ColumnFamilyDescriptor, WriteBatch, DB};
+use std::sync::Arc;
+
+impl Merk {
+    /// Returns the column families a RocksDB instance must be opened with in
+    /// order to hold Merk stores (see `open_shared`).
+    pub fn column_families() -> Vec<ColumnFamilyDescriptor> {
+        column_families()
+    }
+
+    /// Opens a store inside an existing RocksDB instance, namespacing all of
+    /// its keys (in every column family) under `prefix`. The application keeps
+    /// ownership of `db` and may use it for its own data, or open several
+    /// stores in it, as long as no prefix in use is a prefix of another.
+    ///
+    /// `db` must have been opened with the column families returned by
+    /// `Merk::column_families`. Operations which act on the whole database
+    /// (`checkpoint`, `repair` and `create_backup`) are not supported for
+    /// shared stores, and `destroy` only deletes the keys under `prefix`.
+    pub fn open_shared(db: Arc<DB>, prefix: Vec<u8>, codec: NodeCodec) -> Result<Merk> {
+        if prefix.is_empty() {
+            return Err(Error::Key("Shared store prefix must not be empty".into()));
+        }
+
+        let path = db.path().to_path_buf();
+        Merk::load(db, path, prefix, codec, true, false)
+    }
+
+    /// Returns true if the store was opened with `open_shared`.
+    pub fn is_shared(&self) -> bool {
+        !self.prefix.is_empty()
+    }
+
+    pub(crate) fn ensure_not_shared(&self, operation: &str) -> Result<()> {
+        if self.is_shared() {
+            return Err(Error::Unsupported(format!(
+                "{} is not supported for shared stores",
+                operation
+            )));
+        }
+        Ok(())
+    }
+
+    /// Deletes all of the keys under the store's prefix, in every column
+    /// family.
+    pub(crate) fn delete_prefixed(self) -> Result<()> {
+        let mut batch = WriteBatch::default();
+
+        let mut iter = self.db.raw_iterator_opt(prefix_read_opts(&self.prefix));
+        iter.seek_to_first();
+        while iter.valid() {
+            batch.delete(iter.key().unwrap());
+            iter.next();
+        }
+        iter.status()?;
+
+        for name in column_family_names() {
+            let cf = self.db.cf_handle(name).unwrap();
+            let mut iter = self
+                .db
+                .raw_iterator_cf_opt(cf, prefix_read_opts(&self.prefix));
+            iter.seek_to_first();
+            while iter.valid() {
+                batch.delete_cf(cf, iter.key().unwrap());
+                iter.next();
+            }
+            iter.status()?;
+        }
+
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Merk, NodeCodec, Op};
+    use rocksdb::DB;
+    use std::sync::Arc;
+
+    #[test]
+    fn shared_stores() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let db = DB::open_cf_descriptors(&Merk::default_db_opts(), &path, Merk::column_families())
+            .map(Arc::new)
+            .unwrap();
+        db.put(b"app", b"data").unwrap();
+
+        let mut plain = TempMerk::new().unwrap();
+        plain
+            .apply(&make_batch_seq(0..100), &[(vec![1], Op::Put(vec![2]))])
+            .unwrap();
+
+        let mut a = Merk::open_shared(db.clone(), b"a/".to_vec(), NodeCodec::new()).unwrap();
+        let mut b = Merk::open_shared(db.clone(), b"b/".to_vec(), NodeCodec::new()).unwrap();
+        a.apply(&make_batch_seq(0..100), &[(vec![1], Op::Put(vec![2]))])
+            .unwrap();
+        b.apply(&make_batch_seq(50..60), &[]).unwrap();
+
+        assert_eq!(a.root_hash(), plain.root_hash());
+        assert_eq!(a.get_aux(&[1]).unwrap(), Some(vec![2]));
+        assert_eq!(b.get_aux(&[1]).unwrap(), None);
+        assert_eq!(b.get(&seq_key(10)).unwrap(), None);
+        assert_eq!(b.get(&seq_key(55)).unwrap(), Some(put_entry_value()));
+        let chunks = |merk: &Merk| -> Vec<_> {
+            merk.chunks()
+                .unwrap()
+                .into_iter()
+                .map(Result::unwrap)
+                .collect()
+        };
+        assert_eq!(chunks(&a), chunks(&plain));
+        assert!(a.checkpoint(path.clone() + ".checkpoint").is_err());
+
+        let root_hash = a.root_hash();
+        drop(a);
+        let a = Merk::open_shared(db.clone(), b"a/".to_vec(), NodeCodec::new()).unwrap();
+        assert_eq!(a.root_hash(), root_hash);
+
+        a.destroy().unwrap();
+        let a = Merk::open_shared(db.clone(), b"a/".to_vec(), NodeCodec::new()).unwrap();
+        assert_eq!(a.get(&seq_key(10)).unwrap(), None);
+        assert_eq!(b.get(&seq_key(55)).unwrap(), Some(put_entry_value()));
+        assert_eq!(db.get(b"app").unwrap(), Some(b"data".to_vec()));
+
+        drop((a, b));
+        drop(db);
+        DB::destroy(&Merk::default_db_opts(), &path).unwrap();
+    }
+}
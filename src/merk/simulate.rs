@@ -0,0 +1,155 @@
+//! Provides `Merk::simulate_apply`, which applies a batch to a scratch copy of
+//! the tree to find the resulting root hash without writing anything, e.g. to
+//! validate transactions before they are included in a block.
+
+use super::stats::ApplyCounters;
+use super::{ApplyStats, Merk, MerkSource};
+use crate::tree::{Batch, Fetch, Hash, NoopCommit, Tree, Walker, NULL_HASH};
+use crate::Result;
+
+/// The outcome of a batch applied with `Merk::simulate_apply`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimulatedApply {
+    /// The root hash the store would have after applying the batch.
+    pub root_hash: Hash,
+    /// The counters the apply would record, except that nodes read from
+    /// memory are not counted as fetched.
+    pub stats: ApplyStats,
+    /// The number of keys the batch would insert (rather than update).
+    pub inserted: u64,
+    /// The number of keys the batch would delete.
+    pub deleted: u64,
+}
+
+/// Reads nodes from the store's tree in memory if they are loaded, so they can
+/// be copied rather than read from the database, which would miss the changes
+/// of a block in progress.
+#[derive(Clone)]
+struct OverlaySource<'a> {
+    tree: Option<&'a Tree>,
+    source: MerkSource<'a>,
+}
+
+impl<'a> Fetch for OverlaySource<'a> {
+    fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
+        match self.tree.and_then(|tree| find_loaded(tree, key)) {
+            Some(node) => Ok(Some(shallow_copy(node)?)),
+            None => self.source.fetch_by_key(key),
+        }
+    }
+
+    fn record_rotation(&self) {
+        self.source.record_rotation();
+    }
+
+    fn record_inserts(&self, count: usize) {
+        self.source.record_inserts(count);
+    }
+}
+
+/// Returns the node with the given key if it is loaded in memory.
+fn find_loaded<'a>(tree: &'a Tree, key: &[u8]) -> Option<&'a Tree> {
+    let mut cursor = tree;
+    loop {
+        if key == cursor.key() {
+            return Some(cursor);
+        }
+        cursor = cursor.link(key < cursor.key())?.tree()?;
+    }
+}
+
+/// Copies a node, with references to its children in place of any loaded
+/// subtrees.
+fn shallow_copy(tree: &Tree) -> Result<Tree> {
+    Tree::decode(tree.key().to_vec(), &tree.encode())
+}
+
+impl Merk {
+    /// Applies a batch to a copy of the tree as in `apply`, returning the
+    /// resulting root hash and counters without changing the store or
+    /// writing anything.
+    ///
+    /// Only the nodes on the paths the batch touches are copied, from memory
+    /// if they are loaded (including the changes of a block in progress) or
+    /// from the database otherwise.
+    pub fn simulate_apply(&self, batch: &Batch) -> Result<SimulatedApply> {
+        self.limits.check_batch(batch)?;
+
+        self.use_tree(|maybe_tree| -> Result<SimulatedApply> {
+            let counters = ApplyCounters::default();
+            let source = OverlaySource {
+                tree: maybe_tree,
+                source: MerkSource {
+                    counters: Some(&counters),
+                    ..self.source()
+                },
+            };
+            let maybe_walker = maybe_tree
+                .map(shallow_copy)
+                .transpose()?
+                .map(|tree| Walker::new(tree, source.clone()));
+
+            let (mut maybe_tree, deleted_keys) = Walker::apply_to(maybe_walker, batch, source)?;
+            let stats = counters.finish(maybe_tree.as_ref(), deleted_keys.len());
+            let root_hash = match maybe_tree.as_mut() {
+                Some(tree) => {
+                    tree.commit(&mut NoopCommit {})?;
+                    tree.hash()
+                }
+                None => NULL_HASH,
+            };
+
+            Ok(SimulatedApply {
+                root_hash,
+                stats,
+                inserted: counters.inserted(),
+                deleted: deleted_keys.len() as u64,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Merk;
+
+    #[test]
+    fn simulate_batches() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        let simulated = merk.simulate_apply(&make_batch_seq(0..100)).unwrap();
+        assert_eq!(simulated.inserted, 100);
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        assert_eq!(simulated.root_hash, merk.root_hash());
+        drop(merk);
+
+        // only the root is loaded after reopening, so the rest is fetched
+        let mut merk = Merk::open(&path).unwrap();
+        let root_hash = merk.root_hash();
+        let mut batch = make_del_batch_seq(10..20);
+        batch.extend(make_batch_seq(100..110));
+        let simulated = merk.simulate_apply(&batch).unwrap();
+        assert_eq!(simulated.inserted, 10);
+        assert_eq!(simulated.deleted, 10);
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), Some(put_entry_value()));
+
+        // changes of a block in progress are seen before they are written
+        merk.begin_block().unwrap();
+        merk.apply(&batch, &[]).unwrap();
+        let batch = make_del_batch_seq(100..105);
+        let simulated = merk.simulate_apply(&batch).unwrap();
+        merk.apply(&batch, &[]).unwrap();
+        assert_eq!(simulated.root_hash, merk.root_hash());
+        assert_eq!(
+            simulated.stats.nodes_written,
+            merk.last_apply_stats().nodes_written
+        );
+        merk.commit_block().unwrap();
+
+        let simulated = merk.simulate_apply(&make_del_batch_seq(0..10)).unwrap();
+        assert_eq!(simulated.deleted, 10);
+        merk.destroy().unwrap();
+    }
+}
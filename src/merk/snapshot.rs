@@ -1,5 +1,6 @@
 use std::cell::Cell;
 
+use super::NodeCodec;
 use crate::{
     proofs::{query::QueryItem, Query},
     tree::{Fetch, RefWalker, Tree, NULL_HASH},
@@ -9,13 +10,22 @@ use crate::{
 pub struct Snapshot<'a> {
     db: rocksdb::Snapshot<'a>,
     tree: Cell<Option<Tree>>,
+    codec: &'a NodeCodec,
+    prefix: &'a [u8],
 }
 
 impl<'a> Snapshot<'a> {
-    pub fn new(db: rocksdb::Snapshot<'a>, tree: Option<Tree>) -> Self {
+    pub fn new(
+        db: rocksdb::Snapshot<'a>,
+        tree: Option<Tree>,
+        codec: &'a NodeCodec,
+        prefix: &'a [u8],
+    ) -> Self {
         Snapshot {
             db,
             tree: Cell::new(tree),
+            codec,
+            prefix,
         }
     }
 
@@ -27,6 +37,17 @@ impl<'a> Snapshot<'a> {
         })
     }
 
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key in the snapshot if `end` is `None`.
+    pub fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        super::read_range(self.raw_iter(), self.prefix, self.codec, start, end, limit)
+    }
+
     pub fn root_hash(&self) -> Hash {
         self.use_tree(|tree| tree.map_or(NULL_HASH, |tree| tree.hash()))
     }
@@ -56,11 +77,12 @@ impl<'a> Snapshot<'a> {
     }
 
     pub fn raw_iter(&self) -> rocksdb::DBRawIterator {
-        self.db.raw_iterator()
+        self.db
+            .raw_iterator_opt(super::prefix_read_opts(self.prefix))
     }
 
     fn source(&self) -> SnapshotSource {
-        SnapshotSource(&self.db)
+        SnapshotSource(&self.db, self.codec, self.prefix)
     }
 
     fn use_tree<T>(&self, f: impl FnOnce(Option<&Tree>) -> T) -> T {
@@ -79,13 +101,36 @@ impl<'a> Snapshot<'a> {
 }
 
 #[derive(Clone)]
-pub struct SnapshotSource<'a>(&'a rocksdb::Snapshot<'a>);
+pub struct SnapshotSource<'a>(&'a rocksdb::Snapshot<'a>, &'a NodeCodec, &'a [u8]);
+
+impl<'a> SnapshotSource<'a> {
+    pub(crate) fn new(
+        snapshot: &'a rocksdb::Snapshot<'a>,
+        codec: &'a NodeCodec,
+        prefix: &'a [u8],
+    ) -> Self {
+        SnapshotSource(snapshot, codec, prefix)
+    }
+}
 
 impl<'a> Fetch for SnapshotSource<'a> {
     fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
-        Ok(self
-            .0
-            .get(key)?
-            .map(|bytes| Tree::decode(key.to_vec(), &bytes)))
+        self.0
+            .get(super::prefixed(self.2, key))?
+            .map(|bytes| {
+                let bytes = self.1.decode(key, &bytes)?;
+                Tree::decode(key.to_vec(), &bytes)
+            })
+            .transpose()
+    }
+
+    fn fetch_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.0
+            .get(super::prefixed(self.2, key))?
+            .map(|bytes| {
+                let bytes = self.1.decode(key, &bytes)?;
+                Ok(Tree::decode_value(&bytes)?.to_vec())
+            })
+            .transpose()
     }
 }
@@ -0,0 +1,218 @@
+//! Counters describing the work done by each apply, so key patterns which
+//! degrade the tree's balance can be noticed in production, and by individual
+//! proofs, so queries which read much more than they prove can be found.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::cost::Meter;
+use super::prefetch::prefetch;
+use super::{Merk, MerkSource};
+use crate::proofs::{encode_into, encoded_len, query::QueryItem, Op, Query};
+use crate::tree::{RefWalker, Tree};
+use crate::Result;
+
+/// Counters for a single call to `apply`, returned by
+/// `Merk::last_apply_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApplyStats {
+    /// The number of AVL rotations performed to rebalance the tree. A double
+    /// rotation counts as two.
+    pub rotations: u64,
+    /// The number of nodes read from the database.
+    pub nodes_fetched: u64,
+    /// The number of nodes written or deleted by the commit.
+    pub nodes_written: u64,
+    /// The depth of the deepest node written, where the root has depth 0.
+    pub max_depth: u32,
+}
+
+/// Counters for a single proof, returned by `Merk::prove_with_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProofStats {
+    /// The number of nodes read from the database to create the proof.
+    pub nodes_read: u64,
+    /// The total size of the stored encodings of the nodes read.
+    pub bytes_read: u64,
+    /// The number of nodes included in the proof, as hashes or key/value
+    /// pairs.
+    pub proof_nodes: u64,
+    /// The size of the encoded proof.
+    pub proof_bytes: u64,
+}
+
+impl ProofStats {
+    /// Returns the number of bytes read from the database per byte of the
+    /// proof, or 0 if the proof is empty.
+    pub fn read_amplification(&self) -> f64 {
+        if self.proof_bytes == 0 {
+            return 0.0;
+        }
+        self.bytes_read as f64 / self.proof_bytes as f64
+    }
+}
+
+/// Counts rotations and fetches while a batch is applied, possibly from
+/// several threads.
+#[derive(Default)]
+pub(crate) struct ApplyCounters {
+    rotations: AtomicU64,
+    nodes_fetched: AtomicU64,
+    inserted: AtomicU64,
+}
+
+impl ApplyCounters {
+    pub(crate) fn record_rotation(&self) {
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_fetch(&self) {
+        self.nodes_fetched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_inserts(&self, count: usize) {
+        self.inserted.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the number of keys inserted into the tree (rather than
+    /// updated).
+    pub(crate) fn inserted(&self) -> u64 {
+        self.inserted.load(Ordering::Relaxed)
+    }
+
+    /// Returns the stats for the apply, given the resulting tree (before it is
+    /// committed) and the number of nodes it deleted.
+    pub(crate) fn finish(&self, maybe_tree: Option<&Tree>, deleted: usize) -> ApplyStats {
+        // the root is always written by the commit
+        let (modified, max_depth) = maybe_tree.map_or((0, 0), |tree| modified_nodes(tree, 0));
+
+        ApplyStats {
+            rotations: self.rotations.load(Ordering::Relaxed),
+            nodes_fetched: self.nodes_fetched.load(Ordering::Relaxed),
+            nodes_written: (modified + deleted) as u64,
+            max_depth,
+        }
+    }
+}
+
+/// Returns the number of nodes in `tree` which will be written when it is
+/// committed, and the depth of the deepest one.
+fn modified_nodes(tree: &Tree, depth: u32) -> (usize, u32) {
+    let mut count = 1;
+    let mut max_depth = depth;
+    for left in [true, false] {
+        if let Some(child) = tree.link(left).filter(|link| link.is_modified()) {
+            let (child_count, child_depth) = modified_nodes(child.tree().unwrap(), depth + 1);
+            count += child_count;
+            max_depth = max_depth.max(child_depth);
+        }
+    }
+    (count, max_depth)
+}
+
+impl Merk {
+    /// Returns the counters for the last batch applied to the store since it
+    /// was opened, or zeroed counters if none was applied.
+    pub fn last_apply_stats(&self) -> ApplyStats {
+        self.apply_stats
+    }
+
+    /// Creates a proof as in `prove`, also returning how many nodes and bytes
+    /// were read from the database to create it, and how many ended up in the
+    /// proof.
+    ///
+    /// Nodes already held in memory (e.g. loaded by earlier proofs or
+    /// applies) are not read again, so the stats of a query depend on what
+    /// the store read before it. The proof cache is bypassed, so the proof is
+    /// always created from the tree.
+    pub fn prove_with_stats(&self, query: Query) -> Result<(Vec<u8>, ProofStats)> {
+        let items: Vec<QueryItem> = query.into_iter().collect();
+        let meter = Meter::default();
+        let source = MerkSource {
+            meter: Some(&meter),
+            ..self.source()
+        };
+
+        let proof = self.use_tree_mut(|maybe_tree| -> Result<_> {
+            let tree = match maybe_tree {
+                Some(tree) => tree,
+                None => return Ok(None),
+            };
+            prefetch(tree, &source, &items)?;
+            let (proof, _) = RefWalker::new(tree, source.clone()).create_proof(&items)?;
+            Ok(Some(proof))
+        })?;
+
+        let mut bytes = vec![];
+        let mut proof_nodes = 0;
+        if let Some(proof) = proof {
+            bytes.reserve(encoded_len(proof.iter()));
+            encode_into(proof.iter(), &mut bytes);
+            proof_nodes = proof.iter().filter(|op| matches!(op, Op::Push(_))).count() as u64;
+        }
+
+        let (nodes_read, bytes_read) = meter.fetched();
+        let stats = ProofStats {
+            nodes_read,
+            bytes_read,
+            proof_nodes,
+            proof_bytes: bytes.len() as u64,
+        };
+        Ok((bytes, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn sequential_inserts_rotate() {
+        let mut merk = TempMerk::new().unwrap();
+        assert_eq!(merk.last_apply_stats(), ApplyStats::default());
+
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        let stats = merk.last_apply_stats();
+        assert_eq!(stats.rotations, 0);
+        assert_eq!(stats.nodes_written, 1_000);
+        assert_eq!(stats.max_depth, 9);
+
+        // appending keys one at a time keeps rotating the right edge
+        let mut rotations = 0;
+        for i in 1_000..1_100 {
+            merk.apply(&make_batch_seq(i..i + 1), &[]).unwrap();
+            let stats = merk.last_apply_stats();
+            assert_eq!(stats.nodes_fetched, 0);
+            assert!(stats.max_depth > 0);
+            rotations += stats.rotations;
+        }
+        assert!(rotations > 0);
+    }
+
+    #[test]
+    fn proof_stats() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        let query = || {
+            let mut query = Query::new();
+            query.insert_key(seq_key(10));
+            query
+        };
+        let (proof, stats) = merk.prove_with_stats(query()).unwrap();
+        assert_eq!(proof, merk.prove(query()).unwrap());
+        assert_eq!(stats.proof_bytes, proof.len() as u64);
+        assert!(stats.nodes_read > 0);
+        assert!(stats.proof_nodes > stats.nodes_read);
+        assert!(stats.bytes_read > 0);
+
+        // the nodes are held in memory after the first proof
+        let (_, stats) = merk.prove_with_stats(query()).unwrap();
+        assert_eq!((stats.nodes_read, stats.bytes_read), (0, 0));
+        assert_eq!(stats.read_amplification(), 0.0);
+        merk.destroy().unwrap();
+    }
+}
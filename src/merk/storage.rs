@@ -0,0 +1,90 @@
+//! Provides APIs for monitoring a store's disk usage and scheduling
+//! compaction.
+
+use super::{column_family_names, prefix_upper_bound, prefixed, Merk};
+use crate::Result;
+
+impl Merk {
+    /// Returns the total size in bytes of the files in the store's RocksDB
+    /// directory (SST files, write-ahead logs and metadata). For a shared
+    /// store, this covers the whole database rather than just the store's own
+    /// keys.
+    pub fn size_on_disk(&self) -> Result<u64> {
+        let mut size = 0;
+        for entry in std::fs::read_dir(self.db.path())? {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                size += metadata.len();
+            }
+        }
+        Ok(size)
+    }
+
+    /// Returns RocksDB's estimate of the size in bytes of the live data in all
+    /// column families, which excludes overwritten or deleted entries that
+    /// have not been compacted away yet. Comparing this to `size_on_disk`
+    /// gives an estimate of space amplification.
+    pub fn estimate_live_data_size(&self) -> Result<u64> {
+        self.sum_int_property("rocksdb.estimate-live-data-size")
+    }
+
+    /// Returns the total size in bytes of the SST files in all column
+    /// families.
+    pub fn total_sst_files_size(&self) -> Result<u64> {
+        self.sum_int_property("rocksdb.total-sst-files-size")
+    }
+
+    /// Compacts the stored nodes with keys between `start` and `end`, where
+    /// `None` leaves that side of the range unbounded. Blocks until the
+    /// compaction is done.
+    ///
+    /// This is useful after deleting a large number of keys (e.g. when
+    /// pruning), to reclaim their space sooner than RocksDB's background
+    /// compactions would.
+    pub fn compact_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) {
+        let start = match start {
+            Some(start) => prefixed(&self.prefix, start),
+            None => self.prefix.clone(),
+        };
+        let end = match end {
+            Some(end) => Some(prefixed(&self.prefix, end)),
+            None => prefix_upper_bound(&self.prefix),
+        };
+        self.db.compact_range(Some(start), end);
+    }
+
+    pub(crate) fn sum_int_property(&self, name: &str) -> Result<u64> {
+        let mut sum = self.db.property_int_value(name)?.unwrap_or(0);
+        for cf_name in column_family_names() {
+            let cf = self.db.cf_handle(cf_name).unwrap();
+            sum += self.db.property_int_value_cf(cf, name)?.unwrap_or(0);
+        }
+        Ok(sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn compact_after_delete() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        merk.flush().unwrap();
+
+        let live_size = merk.estimate_live_data_size().unwrap();
+        assert!(live_size > 0);
+        assert!(merk.total_sst_files_size().unwrap() > 0);
+        assert!(merk.size_on_disk().unwrap() >= merk.total_sst_files_size().unwrap());
+
+        let deletes: Vec<_> = (0..9_000).map(|i| (seq_key(i), Op::Delete)).collect();
+        merk.apply(&deletes, &[]).unwrap();
+        merk.flush().unwrap();
+        merk.compact_range(None, None);
+
+        assert!(merk.estimate_live_data_size().unwrap() < live_size);
+        assert_eq!(merk.get(&seq_key(9_500)).unwrap(), Some(put_entry_value()));
+    }
+}
@@ -0,0 +1,159 @@
+//! Traits over the store types, so state machines can be written once and run
+//! against a `Merk`, a `Snapshot` or a `MemMerk` (e.g. in unit tests).
+
+use super::{MemMerk, Merk, Snapshot};
+use crate::proofs::Query;
+use crate::tree::{Batch, Op};
+use crate::{Hash, Result};
+
+/// Read access to a Merkle key/value store.
+pub trait Store {
+    /// Gets the value for `key`, or `None` if it is not in the store.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Returns up to `limit` entries with keys in `start..end` in key order,
+    /// or from `start` to the last key in the store if `end` is `None`.
+    fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Returns the root hash of the store.
+    fn root_hash(&self) -> Hash;
+
+    /// Creates a Merkle proof for the list of queried keys.
+    fn prove(&self, query: Query) -> Result<Vec<u8>>;
+}
+
+/// Write access to a Merkle key/value store.
+pub trait StoreMut: Store {
+    /// Applies a batch of operations, whose keys must be sorted and unique.
+    fn apply_batch(&mut self, batch: &Batch) -> Result<()>;
+
+    /// Puts `value` at `key`.
+    fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.apply_batch(&[(key, Op::Put(value))])
+    }
+
+    /// Deletes `key`.
+    fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+        self.apply_batch(&[(key, Op::Delete)])
+    }
+}
+
+impl Store for Merk {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Merk::get(self, key)
+    }
+
+    fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Merk::get_range(self, start, end, limit)
+    }
+
+    fn root_hash(&self) -> Hash {
+        Merk::root_hash(self)
+    }
+
+    fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        Merk::prove(self, query)
+    }
+}
+
+impl StoreMut for Merk {
+    fn apply_batch(&mut self, batch: &Batch) -> Result<()> {
+        self.apply(batch, &[])
+    }
+}
+
+impl<'a> Store for Snapshot<'a> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Snapshot::get(self, key)
+    }
+
+    fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Snapshot::get_range(self, start, end, limit)
+    }
+
+    fn root_hash(&self) -> Hash {
+        Snapshot::root_hash(self)
+    }
+
+    fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        Snapshot::prove(self, query)
+    }
+}
+
+impl Store for MemMerk {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        MemMerk::get(self, key)
+    }
+
+    fn get_range(
+        &self,
+        start: &[u8],
+        end: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        MemMerk::get_range(self, start, end, limit)
+    }
+
+    fn root_hash(&self) -> Hash {
+        MemMerk::root_hash(self)
+    }
+
+    fn prove(&self, query: Query) -> Result<Vec<u8>> {
+        MemMerk::prove(self, query)
+    }
+}
+
+impl StoreMut for MemMerk {
+    fn apply_batch(&mut self, batch: &Batch) -> Result<()> {
+        self.apply(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn increment<S: StoreMut>(store: &mut S, key: &[u8]) -> Result<u8> {
+        let count = store.get(key)?.map_or(0, |value| value[0]) + 1;
+        store.put(key.to_vec(), vec![count])?;
+        Ok(count)
+    }
+
+    #[test]
+    fn generic_state_machine() {
+        let mut merk = TempMerk::new().unwrap();
+        let mut mem = MemMerk::new();
+        for _ in 0..3 {
+            increment(&mut *merk, b"a").unwrap();
+            increment(&mut mem, b"a").unwrap();
+        }
+        increment(&mut *merk, b"b").unwrap();
+        increment(&mut mem, b"b").unwrap();
+        mem.delete(b"b".to_vec()).unwrap();
+        merk.delete(b"b".to_vec()).unwrap();
+
+        assert_eq!(Store::root_hash(&mem), Store::root_hash(&*merk));
+        let snapshot = merk.snapshot().unwrap();
+        assert_eq!(Store::get(&snapshot, b"a").unwrap(), Some(vec![3]));
+        assert_eq!(
+            Store::get_range(&snapshot, b"", None, 10).unwrap(),
+            Store::get_range(&mem, b"", None, 10).unwrap()
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Reports the store's operations through the `metrics` crate facade, so they
+//! can be exported to Prometheus (e.g. with `metrics-exporter-prometheus`) or
+//! any other recorder installed by the application.
+//!
+//! Without the `metrics` feature, recording is a no-op.
+
+use std::time::Duration;
+
+use super::ApplyStats;
+
+#[cfg(feature = "metrics")]
+const APPLY_DURATION: &str = "merk_apply_duration_seconds";
+#[cfg(feature = "metrics")]
+const APPLY_BATCH_SIZE: &str = "merk_apply_batch_size";
+#[cfg(feature = "metrics")]
+const COMMIT_NODES_WRITTEN: &str = "merk_commit_nodes_written";
+#[cfg(feature = "metrics")]
+const APPLY_ROTATIONS: &str = "merk_apply_rotations";
+#[cfg(feature = "metrics")]
+const APPLY_MAX_DEPTH: &str = "merk_apply_max_depth";
+#[cfg(feature = "metrics")]
+const NODES_FETCHED: &str = "merk_nodes_fetched_total";
+#[cfg(feature = "metrics")]
+const PROOF_BYTES: &str = "merk_proof_bytes";
+#[cfg(feature = "metrics")]
+const CHUNK_VERIFY_DURATION: &str = "merk_chunk_verify_duration_seconds";
+
+/// Registers descriptions and units for all of the metrics recorded by merk
+/// with the installed recorder. Should be called after the recorder is
+/// installed.
+#[cfg(feature = "metrics")]
+pub fn describe_metrics() {
+    use ::metrics::{describe_counter, describe_histogram, Unit};
+
+    describe_histogram!(
+        APPLY_DURATION,
+        Unit::Seconds,
+        "Time taken to apply and commit a batch"
+    );
+    describe_histogram!(
+        APPLY_BATCH_SIZE,
+        Unit::Count,
+        "Number of operations in each applied batch"
+    );
+    describe_histogram!(
+        COMMIT_NODES_WRITTEN,
+        Unit::Count,
+        "Number of tree nodes written or deleted by each commit"
+    );
+    describe_histogram!(
+        APPLY_ROTATIONS,
+        Unit::Count,
+        "Number of AVL rotations performed by each applied batch"
+    );
+    describe_histogram!(
+        APPLY_MAX_DEPTH,
+        Unit::Count,
+        "Depth of the deepest node written by each applied batch"
+    );
+    describe_counter!(
+        NODES_FETCHED,
+        Unit::Count,
+        "Number of tree nodes fetched from the database"
+    );
+    describe_histogram!(
+        PROOF_BYTES,
+        Unit::Bytes,
+        "Size of each generated query proof"
+    );
+    describe_histogram!(
+        CHUNK_VERIFY_DURATION,
+        Unit::Seconds,
+        "Time taken to verify and write each restored chunk"
+    );
+}
+
+/// Records an applied batch of `batch_size` operations which took `elapsed`,
+/// including its commit.
+pub(crate) fn record_apply(batch_size: usize, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::histogram!(APPLY_DURATION).record(elapsed);
+        ::metrics::histogram!(APPLY_BATCH_SIZE).record(batch_size as f64);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (batch_size, elapsed);
+}
+
+/// Records the balance counters of an applied batch.
+pub(crate) fn record_apply_stats(stats: &ApplyStats) {
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::histogram!(APPLY_ROTATIONS).record(stats.rotations as f64);
+        ::metrics::histogram!(APPLY_MAX_DEPTH).record(stats.max_depth as f64);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = stats;
+}
+
+/// Records a commit which wrote or deleted `nodes` tree nodes.
+pub(crate) fn record_commit(nodes: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(COMMIT_NODES_WRITTEN).record(nodes as f64);
+    #[cfg(not(feature = "metrics"))]
+    let _ = nodes;
+}
+
+/// Records a tree node being fetched from the database.
+pub(crate) fn record_fetch() {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(NODES_FETCHED).increment(1);
+}
+
+/// Records a generated proof of `bytes` bytes.
+pub(crate) fn record_proof(bytes: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(PROOF_BYTES).record(bytes as f64);
+    #[cfg(not(feature = "metrics"))]
+    let _ = bytes;
+}
+
+/// Records a restored chunk which took `elapsed` to verify and write.
+pub(crate) fn record_chunk_verification(elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(CHUNK_VERIFY_DURATION).record(elapsed);
+    #[cfg(not(feature = "metrics"))]
+    let _ = elapsed;
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use crate::test_utils::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    #[test]
+    fn record_operations() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let mut merk = TempMerk::new().unwrap();
+            merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        });
+
+        let snapshot = snapshotter.snapshot().into_hashmap();
+        let batch_sizes = snapshot
+            .iter()
+            .find(|(key, _)| key.key().name() == super::APPLY_BATCH_SIZE)
+            .map(|(_, (_, _, value))| value.clone());
+        assert_eq!(batch_sizes, Some(DebugValue::Histogram(vec![100.0.into()])));
+    }
+}
@@ -0,0 +1,245 @@
+//! Provides optional soft deletes. While enabled, the value of each key
+//! deleted from the tree is kept as a tombstone in aux storage, written in the
+//! same batch as the commit, so an operator can restore it with
+//! `Merk::undelete` until the tombstone is compacted.
+//!
+//! Deleted keys are still removed from the tree, so tombstones don't affect
+//! the root hash or proofs.
+
+use std::convert::TryInto;
+
+use rocksdb::{WriteBatch, DB};
+
+use super::{prefix_read_opts, prefixed, Merk, AUX_CF_NAME, INTERNAL_CF_NAME};
+use crate::tree::{Batch, Op};
+use crate::{Error, Result};
+
+/// The prefix of the aux keys tombstones are stored under, followed by the
+/// deleted key. Each tombstone is the big-endian number of the commit which
+/// deleted the key, followed by its value.
+pub const TOMBSTONE_PREFIX: &[u8] = b"\x00merk/tombstone/";
+
+/// The internal key of the compaction window and the number of commits made
+/// since tombstones were enabled.
+const TOMBSTONES_KEY: &[u8] = b"tombstones";
+
+/// The state of a store's tombstones, if they are enabled.
+#[derive(Clone, Copy)]
+pub(crate) struct Tombstones {
+    window: u64,
+    commits: u64,
+}
+
+impl Tombstones {
+    /// Returns the state after the next commit, and the internal entry which
+    /// records it.
+    pub(crate) fn next(&self, prefix: &[u8]) -> (Tombstones, (Vec<u8>, Option<Vec<u8>>)) {
+        let next = Tombstones {
+            commits: self.commits + 1,
+            ..*self
+        };
+        (next, next.entry(prefix))
+    }
+
+    fn entry(&self, prefix: &[u8]) -> (Vec<u8>, Option<Vec<u8>>) {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.window.to_be_bytes());
+        bytes.extend_from_slice(&self.commits.to_be_bytes());
+        (prefixed(prefix, TOMBSTONES_KEY), Some(bytes))
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Tombstones> {
+        if bytes.len() != 16 {
+            return None;
+        }
+        Some(Tombstones {
+            window: u64::from_be_bytes(bytes[..8].try_into().ok()?),
+            commits: u64::from_be_bytes(bytes[8..].try_into().ok()?),
+        })
+    }
+}
+
+/// Loads the state of the tombstones of the store under `prefix`, or `None` if
+/// they are not enabled.
+pub(crate) fn load_tombstones(db: &DB, prefix: &[u8]) -> Result<Option<Tombstones>> {
+    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+    db.get_cf(internal_cf, prefixed(prefix, TOMBSTONES_KEY))?
+        .map(|bytes| {
+            Tombstones::decode(&bytes).ok_or_else(|| Error::Corruption {
+                key: TOMBSTONES_KEY.to_vec(),
+            })
+        })
+        .transpose()
+}
+
+fn tombstone_key(key: &[u8]) -> Vec<u8> {
+    let mut tombstone_key = TOMBSTONE_PREFIX.to_vec();
+    tombstone_key.extend_from_slice(key);
+    tombstone_key
+}
+
+fn decode_tombstone(key: &[u8], bytes: &[u8]) -> Result<(u64, Vec<u8>)> {
+    if bytes.len() < 8 {
+        return Err(Error::Corruption {
+            key: tombstone_key(key),
+        });
+    }
+    let (commit, value) = bytes.split_at(8);
+    Ok((
+        u64::from_be_bytes(commit.try_into().unwrap()),
+        value.to_vec(),
+    ))
+}
+
+impl Merk {
+    /// Starts keeping the value of each key deleted by subsequent commits as a
+    /// tombstone, which can be restored with `undelete` until it is compacted
+    /// by `compact_tombstones` once `window` more commits have been made. If
+    /// tombstones are already enabled, only the window is changed.
+    pub fn enable_tombstones(&mut self, window: u64) -> Result<()> {
+        let tombstones = Tombstones {
+            window,
+            commits: self.tombstones.map_or(0, |tombstones| tombstones.commits),
+        };
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let (key, value) = tombstones.entry(&self.prefix);
+        self.db.put_cf(internal_cf, key, value.unwrap())?;
+        self.tombstones = Some(tombstones);
+        Ok(())
+    }
+
+    /// Returns the tombstones to write along with the commit of `batch`, as
+    /// aux entries: one for each key `batch` deletes, holding its current
+    /// value. Must be called before `batch` is applied.
+    pub(crate) fn tombstone_entries(&self, batch: &Batch) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let commit = match self.tombstones {
+            Some(tombstones) => tombstones.commits + 1,
+            None => return Ok(vec![]),
+        };
+
+        let mut entries = vec![];
+        for (key, op) in batch {
+            if let Op::Delete = op {
+                if let Some(value) = self.get(key)? {
+                    let mut bytes = Vec::with_capacity(8 + value.len());
+                    bytes.extend_from_slice(&commit.to_be_bytes());
+                    bytes.extend_from_slice(&value);
+                    entries.push((tombstone_key(key), bytes));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the value `key` had when it was last deleted, along with the
+    /// number of the commit which deleted it (counted from when tombstones
+    /// were enabled), or `None` if there is no tombstone for `key`.
+    ///
+    /// Tombstones written during a block are only read once the block is
+    /// committed.
+    pub fn tombstone(&self, key: &[u8]) -> Result<Option<(u64, Vec<u8>)>> {
+        self.get_aux(&tombstone_key(key))?
+            .map(|bytes| decode_tombstone(key, &bytes))
+            .transpose()
+    }
+
+    /// Puts the value kept in the tombstone of `key` back into the store and
+    /// removes the tombstone, in a single commit. Returns false without
+    /// changing anything if there is no tombstone for `key`, or if `key` has
+    /// been put again since it was deleted.
+    pub fn undelete(&mut self, key: &[u8]) -> Result<bool> {
+        let value = match self.tombstone(key)? {
+            Some((_, value)) => value,
+            None => return Ok(false),
+        };
+        if self.get(key)?.is_some() {
+            return Ok(false);
+        }
+
+        self.apply(
+            &[(key.to_vec(), Op::Put(value))],
+            &[(tombstone_key(key), Op::Delete)],
+        )?;
+        Ok(true)
+    }
+
+    /// Deletes the tombstones written at least `window` commits ago (see
+    /// `enable_tombstones`), returning the number deleted. Returns an error if
+    /// tombstones are not enabled.
+    pub fn compact_tombstones(&mut self) -> Result<usize> {
+        let tombstones = self
+            .tombstones
+            .ok_or_else(|| Error::Unsupported("Tombstones are not enabled".into()))?;
+
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let tombstone_prefix = prefixed(&self.prefix, TOMBSTONE_PREFIX);
+        let mut iter = self
+            .db
+            .raw_iterator_cf_opt(aux_cf, prefix_read_opts(&tombstone_prefix));
+        iter.seek_to_first();
+
+        let mut batch = WriteBatch::default();
+        let mut count = 0;
+        while iter.valid() {
+            let key = iter.key().unwrap();
+            let (commit, _) =
+                decode_tombstone(&key[tombstone_prefix.len()..], iter.value().unwrap())?;
+            if commit.saturating_add(tombstones.window) <= tombstones.commits {
+                batch.delete_cf(aux_cf, key);
+                count += 1;
+            }
+            iter.next();
+        }
+        iter.status()?;
+        drop(iter);
+
+        self.write(batch)?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Merk, Op};
+
+    #[test]
+    fn undelete() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        merk.enable_tombstones(2).unwrap();
+
+        merk.apply(&make_del_batch_seq(0..3), &[]).unwrap();
+        assert_eq!(merk.get(&seq_key(1)).unwrap(), None);
+        assert_eq!(
+            merk.tombstone(&seq_key(1)).unwrap(),
+            Some((1, put_entry_value()))
+        );
+        assert_eq!(merk.tombstone(&seq_key(5)).unwrap(), None);
+
+        let root_hash = merk.root_hash();
+        assert!(merk.undelete(&seq_key(1)).unwrap());
+        assert_eq!(merk.get(&seq_key(1)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.tombstone(&seq_key(1)).unwrap(), None);
+        assert!(!merk.undelete(&seq_key(1)).unwrap());
+        assert_ne!(merk.root_hash(), root_hash);
+
+        // keys put again after being deleted are not undeleted
+        merk.apply(&[(seq_key(2), Op::Put(vec![1]))], &[]).unwrap();
+        assert!(!merk.undelete(&seq_key(2)).unwrap());
+        assert_eq!(merk.get(&seq_key(2)).unwrap(), Some(vec![1]));
+        drop(merk);
+
+        // tombstones are compacted once the window has passed
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_del_batch_seq(5..6), &[]).unwrap();
+        assert_eq!(merk.compact_tombstones().unwrap(), 2);
+        assert_eq!(merk.tombstone(&seq_key(0)).unwrap(), None);
+        assert_eq!(
+            merk.tombstone(&seq_key(5)).unwrap(),
+            Some((4, put_entry_value()))
+        );
+        merk.destroy().unwrap();
+    }
+}
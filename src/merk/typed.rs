@@ -0,0 +1,167 @@
+//! Provides `TypedMerk`, which wraps a `Merk` to read and write typed keys and
+//! values rather than raw bytes.
+
+use std::marker::PhantomData;
+
+use ed::{Decode, Encode};
+
+use super::Merk;
+use crate::proofs::Query;
+use crate::tree::{BatchEntry, Op};
+use crate::{Error, Hash, Result};
+
+/// A `Merk` whose keys and values are encoded with `ed`.
+///
+/// Entries are ordered by their encoded keys, so range reads only follow the
+/// natural ordering of `K` if its encoding preserves it (as the big-endian
+/// encodings of unsigned integers do).
+pub struct TypedMerk<K, V> {
+    merk: Merk,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> TypedMerk<K, V>
+where
+    K: Encode + Decode,
+    V: Encode + Decode,
+{
+    /// Wraps `merk`. Every entry in the store must have been written with the
+    /// same key and value types.
+    pub fn new(merk: Merk) -> Self {
+        TypedMerk {
+            merk,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the underlying store.
+    pub fn inner(&self) -> &Merk {
+        &self.merk
+    }
+
+    /// Returns the underlying store, e.g. to write auxiliary data.
+    pub fn inner_mut(&mut self) -> &mut Merk {
+        &mut self.merk
+    }
+
+    /// Unwraps the underlying store.
+    pub fn into_inner(self) -> Merk {
+        self.merk
+    }
+
+    /// Gets the value for `key`, or `None` if it is not in the store.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.merk
+            .get(&key.encode()?)?
+            .map(|bytes| Ok(V::decode(bytes.as_slice())?))
+            .transpose()
+    }
+
+    /// Puts `value` at `key`.
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<()> {
+        self.apply(vec![(key, Some(value))])
+    }
+
+    /// Deletes `key`, which must be in the store.
+    pub fn remove(&mut self, key: &K) -> Result<()> {
+        self.apply(vec![(key, None)])
+    }
+
+    /// Applies a batch of puts (`Some`) and deletes (`None`), in any order.
+    /// Errors if the batch contains a key more than once.
+    pub fn apply<'a, I>(&mut self, batch: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a K, Option<&'a V>)>,
+        K: 'a,
+        V: 'a,
+    {
+        let mut batch = batch
+            .into_iter()
+            .map(|(key, maybe_value)| {
+                let op = match maybe_value {
+                    Some(value) => Op::Put(value.encode()?),
+                    None => Op::Delete,
+                };
+                Ok((key.encode()?, op))
+            })
+            .collect::<Result<Vec<BatchEntry>>>()?;
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+
+        self.merk.apply(&batch, &[])
+    }
+
+    /// Returns up to `limit` entries with keys from `start` up to (but not
+    /// including) `end`, or to the last key in the store if `end` is `None`,
+    /// ordered by their encoded keys.
+    pub fn range(&self, start: &K, end: Option<&K>, limit: usize) -> Result<Vec<(K, V)>> {
+        let end = end.map(Encode::encode).transpose()?;
+        self.merk
+            .get_range(&start.encode()?, end.as_deref(), limit)?
+            .into_iter()
+            .map(|(key, value)| Ok((K::decode(key.as_slice())?, V::decode(value.as_slice())?)))
+            .collect()
+    }
+
+    /// Returns the root hash of the store.
+    pub fn root_hash(&self) -> Hash {
+        self.merk.root_hash()
+    }
+
+    /// Creates a proof of the values of `keys` (or their absence), which can be
+    /// checked with `TypedMerk::verify`.
+    pub fn prove<'a, I>(&self, keys: I) -> Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = &'a K>,
+        K: 'a,
+    {
+        let mut query = Query::new();
+        for key in keys {
+            query.insert_key(key.encode()?);
+        }
+        self.merk.prove(query)
+    }
+
+    /// Verifies `proof` against `root_hash`, returning the proven value of
+    /// `key`, or `None` if the proof shows it is not in the store.
+    pub fn verify(proof: &[u8], root_hash: Hash, key: &K) -> Result<Option<V>> {
+        let map = crate::verify(proof, root_hash)?;
+        let key = key.encode()?;
+        let maybe_value = map.get(&key)?;
+        maybe_value
+            .map(|bytes| V::decode(bytes).map_err(Error::from))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_roundtrip() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk: TypedMerk<u64, u32> = TypedMerk::new(Merk::open(path).unwrap());
+
+        let entries: Vec<(u64, u32)> = (0..20).map(|i| (19 - i, i as u32)).collect();
+        merk.apply(entries.iter().map(|(key, value)| (key, Some(value))))
+            .unwrap();
+        assert_eq!(merk.get(&3).unwrap(), Some(16));
+
+        merk.insert(&3, &100).unwrap();
+        merk.remove(&4).unwrap();
+        assert_eq!(merk.get(&3).unwrap(), Some(100));
+        assert_eq!(merk.get(&4).unwrap(), None);
+
+        let range = merk.range(&2, Some(&6), 10).unwrap();
+        assert_eq!(range, vec![(2, 17), (3, 100), (5, 14)]);
+
+        let proof = merk.prove(&[3, 4]).unwrap();
+        let root_hash = merk.root_hash();
+        let value = TypedMerk::<u64, u32>::verify(&proof, root_hash, &3).unwrap();
+        assert_eq!(value, Some(100));
+        let value = TypedMerk::<u64, u32>::verify(&proof, root_hash, &4).unwrap();
+        assert_eq!(value, None);
+
+        merk.into_inner().destroy().unwrap();
+    }
+}
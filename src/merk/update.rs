@@ -0,0 +1,74 @@
+//! Creates update proofs (see `proofs::query::verify_update`) of the change to
+//! a key's value made by a batch, or between two retained versions.
+
+use super::{Merk, VersionedMerk};
+use crate::proofs::query::encode_update_proof;
+use crate::proofs::Query;
+use crate::tree::Batch;
+use crate::Result;
+
+impl Merk {
+    /// Applies a batch as in `apply`, returning a proof of the value of `key`
+    /// before and after the batch, which can be checked with `verify_update`
+    /// against the root hashes from before and after.
+    pub fn apply_and_prove_update(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+        key: &[u8],
+    ) -> Result<Vec<u8>> {
+        let query = || Query::from(vec![key.to_vec()]);
+        let before = self.prove(query())?;
+        self.apply(batch, aux)?;
+        let after = self.prove(query())?;
+        Ok(encode_update_proof(&before, &after))
+    }
+}
+
+impl VersionedMerk {
+    /// Creates a proof of the value of `key` at height `from` and at height
+    /// `to`, which can be checked with `verify_update` against
+    /// `root_hash_at(from)` and `root_hash_at(to)`. Returns an error if
+    /// either version is not retained.
+    pub fn prove_update(&mut self, key: &[u8], from: u64, to: u64) -> Result<Vec<u8>> {
+        let query = || Query::from(vec![key.to_vec()]);
+        let before = self.prove_at(from, query())?;
+        let after = self.prove_at(to, query())?;
+        Ok(encode_update_proof(&before, &after))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proofs::query::verify_update;
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn update_proofs() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..50), &[]).unwrap();
+
+        let cases = vec![
+            (
+                seq_key(10),
+                Op::Put(vec![1]),
+                Some(put_entry_value()),
+                Some(vec![1]),
+            ),
+            (seq_key(100), Op::Put(vec![2]), None, Some(vec![2])),
+            (seq_key(20), Op::Delete, Some(put_entry_value()), None),
+        ];
+        for (key, op, old, new) in cases {
+            let old_root = merk.root_hash();
+            let proof = merk
+                .apply_and_prove_update(&[(key.clone(), op)], &[], &key)
+                .unwrap();
+            let new_root = merk.root_hash();
+
+            let update = verify_update(&proof, &key, old_root, new_root).unwrap();
+            assert_eq!(update, (old, new));
+            assert!(verify_update(&proof, &key, new_root, old_root).is_err());
+        }
+    }
+}
@@ -0,0 +1,770 @@
+//! Provides `VersionedMerk`, which retains recent versions of a store so they
+//! can be queried and proven against after newer batches have been applied
+//! (e.g. for archival RPC nodes).
+
+use super::accumulator::load_leaf_count;
+use super::chunks::ChunkProducer;
+use super::{column_family_names, prefix_read_opts, Merk, NodeCodec, StoreMetadata, StoreMode};
+use crate::proofs::Query;
+use crate::tree::{Batch, Hash, NULL_HASH};
+use crate::{Error, Result};
+use rocksdb::{DBRawIterator, WriteBatch};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::JoinHandle;
+
+/// The number of checkpoints a `VersionedMerk` keeps open at once, unless
+/// changed with `set_max_open_versions`.
+const DEFAULT_MAX_OPEN_VERSIONS: usize = 8;
+
+/// Determines which versions a `VersionedMerk` retains. The latest version is
+/// always retained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruningPolicy {
+    /// Retain every version.
+    KeepAll,
+    /// Retain the given number of most recent versions.
+    KeepLast(u64),
+    /// Retain the versions whose height is a multiple of the given interval.
+    KeepEvery(u64),
+}
+
+impl PruningPolicy {
+    /// Returns true if the version at `height` should be retained once
+    /// `latest` is the latest height.
+    pub fn retains(&self, height: u64, latest: u64) -> bool {
+        if height == latest {
+            return true;
+        }
+        match *self {
+            PruningPolicy::KeepAll => true,
+            PruningPolicy::KeepLast(n) => height + n > latest,
+            PruningPolicy::KeepEvery(interval) => height % interval == 0,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match *self {
+            PruningPolicy::KeepLast(0) => {
+                Err(Error::Version("Must retain at least one version".into()))
+            }
+            PruningPolicy::KeepEvery(0) => {
+                Err(Error::Version("Pruning interval must be non-zero".into()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Deletes expired versions on a background thread, so pruning does not
+/// block writes. Each version is sent with its handle, if it was open, so the
+/// handle is also closed on the background thread.
+struct Pruner {
+    sender: Option<Sender<(PathBuf, Option<Merk>)>>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl Pruner {
+    fn spawn() -> Self {
+        let (sender, receiver) = channel::<(PathBuf, Option<Merk>)>();
+        let handle = std::thread::spawn(move || {
+            for (path, version) in receiver {
+                drop(version);
+                // rename first so a partially deleted version is never opened
+                let trash = path.with_extension("pruning");
+                std::fs::rename(&path, &trash)?;
+                std::fs::remove_dir_all(&trash)?;
+            }
+            Ok(())
+        });
+
+        Pruner {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    fn prune(&self, path: PathBuf, version: Option<Merk>) -> Result<()> {
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send((path, version))
+            .map_err(|_| Error::Version("Pruner has stopped".into()))
+    }
+
+    /// Waits for all queued versions to be deleted, returning the first error
+    /// the pruner encountered.
+    fn join(&mut self) -> Result<()> {
+        self.sender.take();
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| Error::Version("Pruner panicked".into()))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Pruner {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+/// The checkpoints which are currently open, closing the least recently used
+/// one when a checkpoint is opened while `capacity` are already open.
+struct OpenVersions {
+    capacity: usize,
+    /// Open checkpoints by height, with the tick of their last use.
+    versions: BTreeMap<u64, (Merk, u64)>,
+    tick: u64,
+}
+
+impl OpenVersions {
+    fn new(capacity: usize) -> Self {
+        OpenVersions {
+            capacity,
+            versions: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Returns the checkpoint of the version at `height`, opening it from
+    /// `path` if it is not already open.
+    fn get<'a>(&'a mut self, height: u64, path: &Path, codec: &NodeCodec) -> Result<&'a Merk> {
+        if !self.versions.contains_key(&height) {
+            let version = Merk::open_checked(path, Merk::default_db_opts(), codec.clone(), true)?;
+            self.insert(height, version);
+        }
+        self.tick += 1;
+        let (version, last_used) = self.versions.get_mut(&height).unwrap();
+        *last_used = self.tick;
+        Ok(version)
+    }
+
+    fn insert(&mut self, height: u64, version: Merk) {
+        self.versions.remove(&height);
+        self.shrink_to(self.capacity - 1);
+        self.tick += 1;
+        self.versions.insert(height, (version, self.tick));
+    }
+
+    fn remove(&mut self, height: u64) -> Option<Merk> {
+        self.versions.remove(&height).map(|(version, _)| version)
+    }
+
+    /// Closes the least recently used checkpoints until at most `len` are
+    /// open.
+    fn shrink_to(&mut self, len: usize) {
+        while self.versions.len() > len {
+            let oldest = self
+                .versions
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(height, _)| *height)
+                .unwrap();
+            self.versions.remove(&oldest);
+        }
+    }
+}
+
+/// A store which keeps a RocksDB checkpoint of the tree as of each of the
+/// heights retained by its `PruningPolicy`.
+///
+/// Each call to `apply` increments the height by one, recording it in the
+/// same write as the batch. The latest version is read from the store itself,
+/// so a height is only checkpointed if the policy retains it once it is no
+/// longer the latest (e.g. every height with `KeepLast(n)` for `n > 1`, but
+/// only the multiples of the interval with `KeepEvery`). Checkpoints hard-link
+/// the database's immutable files, so versions share the storage of any data
+/// which did not change between them, although creating one flushes the
+/// memtable.
+///
+/// Checkpoints are opened when they are first queried, and at most
+/// `DEFAULT_MAX_OPEN_VERSIONS` (8) are kept open at once, closing the least
+/// recently used first. Since querying a version may open it, the methods
+/// which read versions take `&mut self`.
+pub struct VersionedMerk {
+    pub(crate) merk: Merk,
+    pub(crate) height: u64,
+    pub(crate) accumulates_roots: bool,
+    policy: PruningPolicy,
+    /// The heights which have a checkpoint, which may include the latest.
+    checkpoints: BTreeSet<u64>,
+    open: OpenVersions,
+    versions_path: PathBuf,
+    pruner: Pruner,
+}
+
+impl VersionedMerk {
+    /// Opens a versioned store at `path`, creating it if it does not exist,
+    /// which retains versions according to `policy` and encodes nodes with
+    /// `codec`. Versions are kept in a directory next to the store, named
+    /// after `path` with a `-versions` suffix.
+    ///
+    /// If the latest version should have been checkpointed but was not (e.g.
+    /// the process crashed after applying a batch but before its checkpoint
+    /// was created), it is checkpointed now.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        policy: PruningPolicy,
+        codec: NodeCodec,
+    ) -> Result<VersionedMerk> {
+        policy.validate()?;
+
+        let merk = Merk::open_checked(&path, Merk::default_db_opts(), codec, true)?;
+        let metadata = merk.metadata()?;
+        match metadata.mode {
+            StoreMode::Versioned(configured) if configured != policy => {
+                return Err(Error::Config(format!(
+                    "Store is configured with {:?}, but was opened with {:?}",
+                    configured, policy
+                )));
+            }
+            StoreMode::Versioned(_) => {}
+            StoreMode::Latest => merk.set_metadata(&StoreMetadata {
+                mode: StoreMode::Versioned(policy),
+                ..metadata
+            })?,
+        }
+        let mut versions_path = path.as_ref().to_path_buf().into_os_string();
+        versions_path.push("-versions");
+        let versions_path = PathBuf::from(versions_path);
+        std::fs::create_dir_all(&versions_path)?;
+
+        let mut checkpoints = BTreeSet::new();
+        for entry in std::fs::read_dir(&versions_path)? {
+            let entry = entry?;
+            if entry.path().extension() == Some("pruning".as_ref()) {
+                // left behind by a pruner which was interrupted
+                std::fs::remove_dir_all(entry.path())?;
+                continue;
+            }
+            let height = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u64>().ok())
+                .ok_or_else(|| Error::Version(format!("Unexpected file in {:?}", versions_path)))?;
+            checkpoints.insert(height);
+        }
+
+        let mut open = OpenVersions::new(DEFAULT_MAX_OPEN_VERSIONS);
+        let height = match merk.last_height() {
+            Some(height) => height,
+            // stores written before heights were recorded checkpointed every
+            // height, so the latest checkpoint is the latest version unless
+            // the store changed after it was created
+            None => match checkpoints.iter().next_back().copied() {
+                Some(latest) => {
+                    let path = versions_path.join(latest.to_string());
+                    let latest_hash = open.get(latest, &path, &merk.codec)?.root_hash();
+                    latest + (merk.root_hash() != latest_hash) as u64
+                }
+                None => (merk.root_hash() != NULL_HASH) as u64,
+            },
+        };
+
+        // left behind by a rollback which was interrupted
+        for newer in checkpoints.split_off(&(height + 1)) {
+            open.remove(newer);
+            std::fs::remove_dir_all(versions_path.join(newer.to_string()))?;
+        }
+
+        let mut versioned = VersionedMerk {
+            accumulates_roots: load_leaf_count(&merk)?.is_some(),
+            merk,
+            height,
+            policy,
+            checkpoints,
+            open,
+            versions_path,
+            pruner: Pruner::spawn(),
+        };
+        versioned.checkpoint_latest()?;
+        versioned.sync_roots()?;
+        versioned.prune()?;
+
+        Ok(versioned)
+    }
+
+    /// Applies a batch to the store as in `Merk::apply`, recording the
+    /// resulting tree as the next version, and returns its height.
+    pub fn apply(&mut self, batch: &Batch, aux: &Batch) -> Result<u64> {
+        let height = self.height + 1;
+        self.merk.apply_at_height(batch, aux, height)?;
+        self.height = height;
+        self.checkpoint_latest()?;
+
+        self.sync_roots()?;
+        self.prune()?;
+        Ok(height)
+    }
+
+    /// Returns the height of the latest version, or 0 if nothing has been
+    /// applied yet.
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Returns the store's pruning policy.
+    pub fn pruning_policy(&self) -> PruningPolicy {
+        self.policy
+    }
+
+    /// Changes the store's pruning policy, pruning any versions the new policy
+    /// does not retain.
+    pub fn set_pruning_policy(&mut self, policy: PruningPolicy) -> Result<()> {
+        policy.validate()?;
+        self.merk.set_metadata(&StoreMetadata {
+            mode: StoreMode::Versioned(policy),
+            ..self.merk.metadata()?
+        })?;
+        self.policy = policy;
+        // the new policy may retain the latest version after the next batch
+        self.checkpoint_latest()?;
+        self.prune()
+    }
+
+    /// Changes the number of checkpoints which are kept open at once (8 by
+    /// default), closing the least recently used ones if more are open.
+    pub fn set_max_open_versions(&mut self, max: usize) -> Result<()> {
+        if max == 0 {
+            return Err(Error::Version("Must keep at least one version open".into()));
+        }
+        self.open.capacity = max;
+        self.open.shrink_to(max);
+        Ok(())
+    }
+
+    /// Blocks until the background pruner has deleted all expired versions.
+    pub fn wait_for_pruning(&mut self) -> Result<()> {
+        let res = self.pruner.join();
+        self.pruner = Pruner::spawn();
+        res
+    }
+
+    /// Returns the heights of the retained versions, in ascending order.
+    pub fn heights(&self) -> impl Iterator<Item = u64> + '_ {
+        let latest =
+            Some(self.height).filter(|height| *height > 0 && !self.checkpoints.contains(height));
+        self.checkpoints.iter().copied().chain(latest)
+    }
+
+    /// Returns a read-only view of the store as of `height`, opening its
+    /// checkpoint if needed, or an error if that version is not retained.
+    pub fn at(&mut self, height: u64) -> Result<&Merk> {
+        if height == self.height && height > 0 {
+            return Ok(&self.merk);
+        }
+        if !self.checkpoints.contains(&height) {
+            return Err(not_retained(height));
+        }
+        let path = self.versions_path.join(height.to_string());
+        self.open.get(height, &path, &self.merk.codec)
+    }
+
+    /// Gets the value for `key` as of `height`.
+    pub fn get_at(&mut self, height: u64, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.at(height)?.get(key)
+    }
+
+    /// Returns the root hash of the tree as of `height`.
+    pub fn root_hash_at(&mut self, height: u64) -> Result<Hash> {
+        Ok(self.at(height)?.root_hash())
+    }
+
+    /// Creates a proof for `query` against the tree as of `height`, which can
+    /// be verified against `root_hash_at(height)`.
+    pub fn prove_at(&mut self, height: u64, query: Query) -> Result<Vec<u8>> {
+        self.at(height)?.prove(query)
+    }
+
+    /// Creates a `ChunkProducer` for the tree as of `height`, so peers which
+    /// started syncing a version before newer ones were applied can still be
+    /// served its chunks, which restore to `root_hash_at(height)`. Errors if
+    /// that version is not retained.
+    pub fn chunks_at(&mut self, height: u64) -> Result<ChunkProducer> {
+        self.at(height)?.chunks()
+    }
+
+    /// Rolls the store back to the retained version at `height` (e.g. to
+    /// recover from applying a bad block), deleting all newer versions. The
+    /// next batch applied will be recorded as `height + 1`.
+    ///
+    /// The entries which differ from the version are rewritten in a single
+    /// batch, so an interrupted rollback leaves the store unchanged.
+    pub fn rollback_to(&mut self, height: u64) -> Result<()> {
+        if !self.heights().any(|retained| retained == height) {
+            return Err(not_retained(height));
+        }
+        if height != self.height {
+            let path = self.versions_path.join(height.to_string());
+            let version = self.open.get(height, &path, &self.merk.codec)?;
+            self.merk.copy_from(version)?;
+            // the version's metadata may predate a change of pruning policy
+            self.merk.set_metadata(&StoreMetadata {
+                mode: StoreMode::Versioned(self.policy),
+                ..self.merk.metadata()?
+            })?;
+        }
+
+        // pruning of newer versions must finish before their heights are reused
+        self.wait_for_pruning()?;
+        for newer in self.checkpoints.split_off(&(height + 1)) {
+            self.open.remove(newer);
+            std::fs::remove_dir_all(self.versions_path.join(newer.to_string()))?;
+        }
+        self.height = height;
+
+        // the version's copy of the accumulator predates its own root hash
+        self.sync_roots()
+    }
+
+    /// Closes and deletes the store and all of its versions.
+    pub fn destroy(mut self) -> Result<()> {
+        self.pruner.join()?;
+        drop(self.open);
+        std::fs::remove_dir_all(&self.versions_path)?;
+        self.merk.destroy()
+    }
+
+    /// Checkpoints the latest version if the policy retains it once it is no
+    /// longer the latest and it has not been checkpointed yet.
+    fn checkpoint_latest(&mut self) -> Result<()> {
+        let height = self.height;
+        if height == 0
+            || self.checkpoints.contains(&height)
+            || !self.policy.retains(height, height + 1)
+        {
+            return Ok(());
+        }
+
+        let version = self
+            .merk
+            .checkpoint(self.versions_path.join(height.to_string()))?;
+        self.open.insert(height, version);
+        self.checkpoints.insert(height);
+        Ok(())
+    }
+
+    /// Hands the versions which are no longer retained to the background
+    /// pruner. They can no longer be queried once this returns.
+    fn prune(&mut self) -> Result<()> {
+        let expired: Vec<_> = self
+            .checkpoints
+            .iter()
+            .copied()
+            .filter(|height| !self.policy.retains(*height, self.height))
+            .collect();
+        for height in expired {
+            self.checkpoints.remove(&height);
+            let version = self.open.remove(height);
+            let path = self.versions_path.join(height.to_string());
+            self.pruner.prune(path, version)?;
+        }
+        Ok(())
+    }
+}
+
+impl Merk {
+    /// Overwrites the contents of the store with those of `source`, in every
+    /// column family, then reloads its persisted state. Only the entries which
+    /// differ are written, all in one batch.
+    pub(crate) fn copy_from(&mut self, source: &Merk) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        self.diff_into(&mut batch, source, None)?;
+        for name in column_family_names() {
+            self.diff_into(&mut batch, source, Some(name))?;
+        }
+        self.write(batch)?;
+        self.load_state(true)
+    }
+
+    /// Adds the writes which make the given column family (or the default
+    /// column family, for nodes) match `source` to `batch`.
+    fn diff_into(
+        &self,
+        batch: &mut WriteBatch,
+        source: &Merk,
+        cf_name: Option<&str>,
+    ) -> Result<()> {
+        let cf = cf_name.map(|name| self.db.cf_handle(name).unwrap());
+        let mut put = |key: &[u8], value: &[u8]| match cf {
+            Some(cf) => batch.put_cf(cf, self.prefixed(key), value),
+            None => batch.put(self.prefixed(key), value),
+        };
+
+        let mut target_iter = raw_iter_cf(self, cf_name);
+        let mut source_iter = raw_iter_cf(source, cf_name);
+        target_iter.seek_to_first();
+        source_iter.seek_to_first();
+
+        let mut deleted = vec![];
+        loop {
+            let target_key = target_iter.key().map(|key| &key[self.prefix.len()..]);
+            let source_key = source_iter.key().map(|key| &key[source.prefix.len()..]);
+            let ordering = match (target_key, source_key) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(target_key), Some(source_key)) => target_key.cmp(source_key),
+            };
+
+            match ordering {
+                Ordering::Less => {
+                    deleted.push(target_key.unwrap().to_vec());
+                    target_iter.next();
+                }
+                Ordering::Greater => {
+                    put(source_key.unwrap(), source_iter.value().unwrap());
+                    source_iter.next();
+                }
+                Ordering::Equal => {
+                    if target_iter.value() != source_iter.value() {
+                        put(source_key.unwrap(), source_iter.value().unwrap());
+                    }
+                    target_iter.next();
+                    source_iter.next();
+                }
+            }
+        }
+        target_iter.status()?;
+        source_iter.status()?;
+
+        for key in deleted {
+            match cf {
+                Some(cf) => batch.delete_cf(cf, self.prefixed(&key)),
+                None => batch.delete(self.prefixed(&key)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn not_retained(height: u64) -> Error {
+    Error::Version(format!("Height {} is not retained", height))
+}
+
+/// Returns a raw iterator over the store's entries in the given column family,
+/// or in the default column family if `cf_name` is `None`.
+fn raw_iter_cf<'a>(merk: &'a Merk, cf_name: Option<&str>) -> DBRawIterator<'a> {
+    let opts = prefix_read_opts(&merk.prefix);
+    match cf_name {
+        Some(name) => merk
+            .db
+            .raw_iterator_cf_opt(merk.db.cf_handle(name).unwrap(), opts),
+        None => merk.db.raw_iterator_opt(opts),
+    }
+}
+
+impl Deref for VersionedMerk {
+    type Target = Merk;
+
+    fn deref(&self) -> &Merk {
+        &self.merk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::{verify, Op};
+
+    #[test]
+    fn query_at_height() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepLast(3), NodeCodec::new()).unwrap();
+        assert_eq!(merk.height(), 0);
+
+        let mut root_hashes = vec![];
+        for i in 0..5u8 {
+            let height = merk
+                .apply(
+                    &[(vec![1], Op::Put(vec![i])), (vec![i + 2], Op::Put(vec![i]))],
+                    &[],
+                )
+                .unwrap();
+            assert_eq!(height, i as u64 + 1);
+            root_hashes.push(merk.root_hash());
+        }
+
+        assert_eq!(merk.heights().collect::<Vec<_>>(), vec![3, 4, 5]);
+        assert!(merk.get_at(2, &[1]).is_err());
+        assert_eq!(merk.get_at(3, &[1]).unwrap(), Some(vec![2]));
+        assert_eq!(merk.get_at(3, &[5]).unwrap(), None);
+        assert_eq!(merk.get_at(5, &[1]).unwrap(), Some(vec![4]));
+        assert_eq!(merk.get(&[1]).unwrap(), Some(vec![4]));
+
+        let mut query = Query::new();
+        query.insert_key(vec![1]);
+        let proof = merk.prove_at(4, query).unwrap();
+        let root_hash = merk.root_hash_at(4).unwrap();
+        assert_eq!(root_hash, root_hashes[3]);
+        let map = verify(&proof, root_hash).unwrap();
+        assert_eq!(map.get(&[1]).unwrap(), Some(&[3][..]));
+        drop(merk);
+
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepLast(3), NodeCodec::new()).unwrap();
+        assert_eq!(merk.height(), 5);
+        assert_eq!(merk.root_hash_at(3).unwrap(), root_hashes[2]);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn rollback() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepLast(10), NodeCodec::new()).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[(vec![1], Op::Put(vec![1]))])
+            .unwrap();
+        let root_hash = merk.root_hash();
+
+        merk.apply(&make_batch_seq(100..200), &[(vec![1], Op::Put(vec![2]))])
+            .unwrap();
+        merk.apply(&make_del_batch_seq(0..50), &[(vec![2], Op::Put(vec![3]))])
+            .unwrap();
+        assert_eq!(merk.height(), 3);
+
+        merk.rollback_to(1).unwrap();
+        assert_eq!(merk.height(), 1);
+        assert_eq!(merk.heights().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), Some(put_entry_value()));
+        assert_eq!(merk.get(&seq_key(150)).unwrap(), None);
+        assert_eq!(merk.get_aux(&[1]).unwrap(), Some(vec![1]));
+        assert_eq!(merk.get_aux(&[2]).unwrap(), None);
+        assert!(merk.rollback_to(2).is_err());
+
+        assert_eq!(merk.apply(&make_batch_seq(100..200), &[]).unwrap(), 2);
+        drop(merk);
+
+        let merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepLast(10), NodeCodec::new()).unwrap();
+        assert_eq!(merk.height(), 2);
+        assert!(merk.scrub().unwrap().is_empty());
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn chunks_at_height() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepLast(3), NodeCodec::new()).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        merk.apply(&make_batch_seq(1_000..2_000), &[]).unwrap();
+        merk.apply(&make_del_batch_seq(0..500), &[]).unwrap();
+
+        let chunks = merk.chunks_at(1).unwrap();
+        let restore_path = format!("{}-restore", path);
+        let mut restorer = Merk::restore(&restore_path, root_hash, chunks.len()).unwrap();
+        for chunk in chunks {
+            restorer.process_chunk(&chunk.unwrap()).unwrap();
+        }
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), merk.root_hash_at(1).unwrap());
+        assert_eq!(restored.len(), 1_000);
+        restored.destroy().unwrap();
+
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        assert!(matches!(merk.chunks_at(1), Err(Error::Version(_))));
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn policy_retains() {
+        assert!(PruningPolicy::KeepAll.retains(1, 100));
+        assert!(PruningPolicy::KeepLast(3).retains(98, 100));
+        assert!(!PruningPolicy::KeepLast(3).retains(97, 100));
+        assert!(PruningPolicy::KeepEvery(10).retains(90, 100));
+        assert!(!PruningPolicy::KeepEvery(10).retains(91, 100));
+        assert!(PruningPolicy::KeepEvery(10).retains(101, 101));
+        assert!(PruningPolicy::KeepEvery(0).validate().is_err());
+    }
+
+    #[test]
+    fn background_pruning() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepAll, NodeCodec::new()).unwrap();
+        for i in 0..10u8 {
+            merk.apply(&[(vec![i], Op::Put(vec![i]))], &[]).unwrap();
+        }
+        assert_eq!(merk.heights().count(), 10);
+
+        merk.set_pruning_policy(PruningPolicy::KeepEvery(4))
+            .unwrap();
+        assert_eq!(merk.heights().collect::<Vec<_>>(), vec![4, 8, 10]);
+        merk.wait_for_pruning().unwrap();
+        assert_eq!(std::fs::read_dir(&merk.versions_path).unwrap().count(), 3);
+
+        merk.apply(&[(vec![10], Op::Put(vec![10]))], &[]).unwrap();
+        assert_eq!(merk.heights().collect::<Vec<_>>(), vec![4, 8, 11]);
+        assert_eq!(merk.get_at(4, &[3]).unwrap(), Some(vec![3]));
+        assert_eq!(merk.get_at(4, &[4]).unwrap(), None);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn lazily_opened_versions() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let codec = NodeCodec::new().with_checksums();
+        let mut merk = VersionedMerk::open(&path, PruningPolicy::KeepAll, codec.clone()).unwrap();
+        merk.set_max_open_versions(2).unwrap();
+        for i in 0..5u8 {
+            merk.apply(&[(vec![i], Op::Put(vec![i]))], &[]).unwrap();
+        }
+        assert_eq!(merk.open.versions.len(), 2);
+        for height in 1..=5u64 {
+            let key = [height as u8 - 1];
+            assert_eq!(merk.get_at(height, &key).unwrap(), Some(key.to_vec()));
+            assert!(merk.open.versions.len() <= 2);
+        }
+        assert!(merk.set_max_open_versions(0).is_err());
+        drop(merk);
+
+        // checkpoints are not opened until they are queried
+        let mut merk = VersionedMerk::open(&path, PruningPolicy::KeepAll, codec).unwrap();
+        assert!(merk.open.versions.is_empty());
+        assert_eq!(merk.get_at(2, &[1]).unwrap(), Some(vec![1]));
+        assert_eq!(merk.get_at(2, &[2]).unwrap(), None);
+        assert_eq!(merk.open.versions.len(), 1);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn checkpoints_retained_heights() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepEvery(3), NodeCodec::new()).unwrap();
+        for i in 0..7u8 {
+            merk.apply(&[(vec![i], Op::Put(vec![i]))], &[]).unwrap();
+        }
+        assert_eq!(merk.heights().collect::<Vec<_>>(), vec![3, 6, 7]);
+        merk.wait_for_pruning().unwrap();
+        // the latest version is read from the store itself
+        assert_eq!(std::fs::read_dir(&merk.versions_path).unwrap().count(), 2);
+        assert_eq!(merk.get_at(7, &[6]).unwrap(), Some(vec![6]));
+        assert_eq!(merk.root_hash_at(7).unwrap(), merk.root_hash());
+
+        merk.rollback_to(6).unwrap();
+        assert_eq!(merk.get(&[6]).unwrap(), None);
+        assert_eq!(merk.apply(&[(vec![7], Op::Put(vec![7]))], &[]).unwrap(), 7);
+        drop(merk);
+
+        let mut merk =
+            VersionedMerk::open(&path, PruningPolicy::KeepEvery(3), NodeCodec::new()).unwrap();
+        assert_eq!(merk.height(), 7);
+        assert_eq!(merk.heights().collect::<Vec<_>>(), vec![3, 6, 7]);
+        assert_eq!(merk.get_at(3, &[3]).unwrap(), None);
+        assert_eq!(merk.get_at(7, &[7]).unwrap(), Some(vec![7]));
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+//! Provides notifications of the changes made to watched key prefixes, so
+//! applications can keep caches or client subscriptions up to date.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use super::Merk;
+use crate::tree::{Batch, Op};
+use crate::Result;
+
+/// A change to a key under a watched prefix, sent once the commit which made
+/// it has been written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// The changed key.
+    pub key: Vec<u8>,
+    /// The value before the commit, or `None` if the key was not in the store.
+    pub old_value: Option<Vec<u8>>,
+    /// The value after the commit, or `None` if the key was deleted.
+    pub new_value: Option<Vec<u8>>,
+    /// The height of the commit, if it was applied with `apply_at_height`.
+    pub height: Option<u64>,
+}
+
+/// The prefixes watched on a store, along with their subscribers.
+#[derive(Default)]
+pub(crate) struct Watchers {
+    watchers: Vec<(Vec<u8>, Sender<WatchEvent>)>,
+}
+
+impl Watchers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.watchers.is_empty()
+    }
+
+    fn is_watched(&self, key: &[u8]) -> bool {
+        self.watchers
+            .iter()
+            .any(|(prefix, _)| key.starts_with(prefix))
+    }
+
+    /// Sends each event to the subscribers watching a prefix of its key,
+    /// dropping those which have hung up.
+    pub(crate) fn notify(&mut self, events: Vec<WatchEvent>) {
+        for event in events {
+            self.watchers.retain(|(prefix, subscriber)| {
+                !event.key.starts_with(prefix) || subscriber.send(event.clone()).is_ok()
+            });
+        }
+    }
+}
+
+impl Merk {
+    /// Returns a receiver which is sent a `WatchEvent` for every subsequent
+    /// change to a key starting with `prefix`, in order. Changes made during a
+    /// block are sent once the block is committed.
+    pub fn watch(&mut self, prefix: Vec<u8>) -> Receiver<WatchEvent> {
+        let (sender, receiver) = channel();
+        self.watchers.watchers.push((prefix, sender));
+        receiver
+    }
+
+    /// Reads the current values of the watched keys in `batch`, returning the
+    /// events it will cause once applied (without their heights).
+    pub(crate) fn watch_events(&self, batch: &Batch) -> Result<Vec<WatchEvent>> {
+        if self.watchers.is_empty() {
+            return Ok(vec![]);
+        }
+
+        batch
+            .iter()
+            .filter(|(key, _)| self.watchers.is_watched(key))
+            .map(|(key, op)| {
+                Ok(WatchEvent {
+                    key: key.clone(),
+                    old_value: self.get(key)?,
+                    new_value: match op {
+                        Op::Put(value) => Some(value.clone()),
+                        Op::Delete => None,
+                    },
+                    height: None,
+                })
+            })
+            .filter(|event| !matches!(event, Ok(event) if event.old_value == event.new_value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn watch_prefixes() {
+        let mut merk = TempMerk::new().unwrap();
+        let accounts = merk.watch(b"accounts/".to_vec());
+        let alice = merk.watch(b"accounts/alice".to_vec());
+
+        merk.apply(
+            &[
+                (b"accounts/alice".to_vec(), Op::Put(vec![1])),
+                (b"accounts/bob".to_vec(), Op::Put(vec![2])),
+                (b"config".to_vec(), Op::Put(vec![3])),
+            ],
+            &[],
+        )
+        .unwrap();
+        merk.begin_block().unwrap();
+        merk.apply_at_height(
+            &[
+                (b"accounts/alice".to_vec(), Op::Delete),
+                (b"accounts/bob".to_vec(), Op::Put(vec![2])),
+            ],
+            &[],
+            7,
+        )
+        .unwrap();
+        assert!(alice.try_recv().is_ok());
+        assert!(alice.try_recv().is_err());
+        merk.commit_block().unwrap();
+
+        assert_eq!(
+            alice.try_recv().unwrap(),
+            WatchEvent {
+                key: b"accounts/alice".to_vec(),
+                old_value: Some(vec![1]),
+                new_value: None,
+                height: Some(7),
+            }
+        );
+        assert_eq!(accounts.try_iter().count(), 3);
+    }
+}
@@ -0,0 +1,238 @@
+//! An arena for the trees built while executing chunk proofs, so verifying a
+//! long sequence of chunks reuses one allocation instead of allocating every
+//! node separately.
+
+use super::{Node, Op, MAX_TREE_HEIGHT};
+use crate::error::{Error, Result};
+use crate::tree::{kv_hash, node_hash, Hash, Hasher, NULL_HASH};
+
+/// A node of a tree stored in a `TreeArena`. Children are referenced by their
+/// index in the arena.
+#[derive(Debug)]
+pub struct ArenaNode {
+    pub node: Node,
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub height: usize,
+    /// The hash of the node's key/value pair, or `NULL_HASH` for `Node::Hash`
+    /// nodes. Computed once the node is attached to its parent.
+    pub kv_hash: Hash,
+    /// The hash of the node. Computed once the node is attached to its parent.
+    pub hash: Hash,
+}
+
+/// Storage for the nodes of the trees built by `verify_leaf_in`.
+///
+/// Executing a proof into an arena clears it first, so the same arena can be
+/// used for each chunk of a restore, keeping its capacity between chunks.
+#[derive(Debug, Default)]
+pub struct TreeArena {
+    nodes: Vec<ArenaNode>,
+}
+
+impl TreeArena {
+    /// Creates an empty arena.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the node at `id`. Panics if there is no such node.
+    pub fn node(&self, id: usize) -> &ArenaNode {
+        &self.nodes[id]
+    }
+
+    /// Returns the number of nodes in the arena.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns true if the arena contains no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the heights of the children of the node at `id`.
+    pub fn child_heights(&self, id: usize) -> (u8, u8) {
+        let node = self.node(id);
+        let height = |child: Option<usize>| child.map_or(0, |id| self.node(id).height as u8);
+        (height(node.left), height(node.right))
+    }
+
+    /// Takes the node at `id` out of the arena, leaving a `Node::Hash` with its
+    /// hash in its place.
+    #[cfg(feature = "full")]
+    pub(crate) fn take_node(&mut self, id: usize) -> Node {
+        let node = &mut self.nodes[id];
+        std::mem::replace(&mut node.node, Node::Hash(node.hash))
+    }
+
+    /// Executes a proof as in `tree::execute` (without collapsing), building
+    /// the resulting tree in the arena and returning the index of its root.
+    pub(crate) fn execute<I, F>(&mut self, ops: I, mut visit_node: F) -> Result<usize>
+    where
+        I: IntoIterator<Item = Result<Op>>,
+        F: FnMut(&Node) -> Result<()>,
+    {
+        self.nodes.clear();
+        let mut stack: Vec<usize> = Vec::with_capacity(32);
+        let mut maybe_last_kv: Option<usize> = None;
+
+        fn try_pop(stack: &mut Vec<usize>) -> Result<usize> {
+            stack.pop().ok_or(Error::StackUnderflow)
+        }
+
+        for op in ops {
+            match op? {
+                Op::Parent => {
+                    let (parent, child) = (try_pop(&mut stack)?, try_pop(&mut stack)?);
+                    self.attach(parent, true, child)?;
+                    stack.push(parent);
+                }
+                Op::Child => {
+                    let (child, parent) = (try_pop(&mut stack)?, try_pop(&mut stack)?);
+                    self.attach(parent, false, child)?;
+                    stack.push(parent);
+                }
+                Op::Push(node) => {
+                    if let Node::KV(key, _) = &node {
+                        // keys should always increase
+                        if let Some(last) = maybe_last_kv {
+                            if let Node::KV(last_key, _) = &self.nodes[last].node {
+                                if key <= last_key {
+                                    return Err(Error::Key("Incorrect key ordering".into()));
+                                }
+                            }
+                        }
+                        maybe_last_kv = Some(self.nodes.len());
+                    }
+
+                    visit_node(&node)?;
+
+                    stack.push(self.nodes.len());
+                    self.nodes.push(ArenaNode {
+                        node,
+                        left: None,
+                        right: None,
+                        height: 1,
+                        kv_hash: NULL_HASH,
+                        hash: NULL_HASH,
+                    });
+                }
+            }
+        }
+
+        if stack.len() != 1 {
+            return Err(Error::Proof(
+                "Expected proof to result in exactly on stack item".into(),
+            ));
+        }
+
+        let root = stack.pop().unwrap();
+        self.compute_hash(root)?;
+        Ok(root)
+    }
+
+    /// Attaches the node at `child` to the given side of the node at `parent`.
+    fn attach(&mut self, parent: usize, left: bool, child: usize) -> Result<()> {
+        self.compute_hash(child)?;
+
+        let child_height = self.nodes[child].height;
+        if child_height >= MAX_TREE_HEIGHT {
+            return Err(Error::Proof("Proof tree is too tall".into()));
+        }
+        let parent = &mut self.nodes[parent];
+        let slot = if left {
+            &mut parent.left
+        } else {
+            &mut parent.right
+        };
+        if slot.is_some() {
+            return Err(Error::Attach(
+                "Tried to attach to left child, but it is already Some".into(),
+            ));
+        }
+        *slot = Some(child);
+        parent.height = parent.height.max(child_height + 1);
+
+        Ok(())
+    }
+
+    /// Computes and stores the hashes of the node at `id`, whose children must
+    /// already be hashed.
+    fn compute_hash(&mut self, id: usize) -> Result<()> {
+        let child_hash = |child: Option<usize>| child.map_or(NULL_HASH, |id| self.nodes[id].hash);
+        let node = &self.nodes[id];
+        let (kv, hash) = match &node.node {
+            Node::Hash(hash) => (NULL_HASH, *hash),
+            Node::KVHash(kv) => (*kv, NULL_HASH),
+            Node::KV(key, value) => (kv_hash::<Hasher>(key, value)?, NULL_HASH),
+        };
+        let hash = match node.node {
+            Node::Hash(_) => hash,
+            _ => node_hash::<Hasher>(&kv, &child_hash(node.left), &child_hash(node.right)),
+        };
+
+        let node = &mut self.nodes[id];
+        node.kv_hash = kv;
+        node.hash = hash;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tree::execute;
+    use super::*;
+
+    fn make_ops() -> Vec<Op> {
+        vec![
+            Op::Push(Node::KV(vec![1], vec![1])),
+            Op::Push(Node::KV(vec![2], vec![2])),
+            Op::Parent,
+            Op::Push(Node::Hash([3; 32])),
+            Op::Child,
+        ]
+    }
+
+    #[test]
+    fn execute_matches_tree() {
+        let mut arena = TreeArena::new();
+        let root = arena
+            .execute(make_ops().into_iter().map(Ok), |_| Ok(()))
+            .unwrap();
+        let tree = execute(
+            make_ops().into_iter().map(Ok),
+            false,
+            MAX_TREE_HEIGHT,
+            |_| Ok(()),
+        )
+        .unwrap();
+
+        assert_eq!(arena.len(), 3);
+        assert_eq!(arena.node(root).hash, tree.hash().unwrap());
+        assert_eq!(arena.node(root).height, tree.height);
+        assert_eq!(arena.child_heights(root), (1, 1));
+        assert_eq!(arena.node(root).node, Node::KV(vec![2], vec![2]));
+
+        // the arena is cleared before executing again
+        let root = arena
+            .execute(make_ops().into_iter().map(Ok), |_| Ok(()))
+            .unwrap();
+        assert_eq!(arena.len(), 3);
+        assert_eq!(arena.node(root).hash, tree.hash().unwrap());
+    }
+
+    #[test]
+    fn execute_invalid() {
+        let mut arena = TreeArena::new();
+        let ops = vec![
+            Op::Push(Node::KV(vec![2], vec![2])),
+            Op::Push(Node::KV(vec![1], vec![1])),
+            Op::Parent,
+        ];
+        assert!(arena.execute(ops.into_iter().map(Ok), |_| Ok(())).is_err());
+
+        let ops = vec![Op::Parent];
+        assert!(arena.execute(ops.into_iter().map(Ok), |_| Ok(())).is_err());
+    }
+}
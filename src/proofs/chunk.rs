@@ -1,14 +1,27 @@
+//! Creates and verifies the chunk proofs used to replicate entire trees (state
+//! sync).
+//!
+//! A tree is replicated as a trunk chunk, verified with `verify_trunk` against
+//! a trusted root hash, followed by the leaf chunks below it in key order, each
+//! verified with `verify_leaf` (or `verify_leaf_in`) against the hash of the
+//! corresponding node in the bottom layer of the trunk. Chunk bytes are decoded
+//! into operators with `proofs::Decoder`. `Restorer` (with the `full`
+//! feature) does this while writing a new store, but the functions here are
+//! enough for restores which write elsewhere.
+//!
+//! `verify_trunk`, `verify_leaf`, `verify_leaf_in`, `get_next_chunk`,
+//! `ChunkEnd` and `ChunkHeader` are part of the stable API: their signatures
+//! and the chunk format they accept only change in major releases.
+
 #[cfg(feature = "full")]
-use {
-    super::tree::{execute, Tree as ProofTree},
-    crate::tree::Hash,
-    crate::tree::Tree,
-    rocksdb::DBRawIterator,
-};
-
-use super::{Node, Op};
+use {crate::limits::Limits, crate::merk::NodeCodec, crate::tree::Tree, rocksdb::DBRawIterator};
+
+use super::arena::TreeArena;
+use super::tree::{execute, Tree as ProofTree};
+use super::{Node, Op, MAX_TREE_HEIGHT};
 use crate::error::{Error, Result};
-use crate::tree::{Fetch, RefWalker};
+use crate::tree::{Fetch, Hash, RefWalker, HASH_LENGTH};
+use std::convert::{TryFrom, TryInto};
 
 /// The minimum number of layers the trunk will be guaranteed to have before
 /// splitting into multiple chunks. If the tree's height is less than double
@@ -25,18 +38,29 @@ where
     /// whether or not there will be more chunks to follow. If the chunk
     /// contains the entire tree, the boolean will be `false`, if the chunk
     /// is abdriged and will be connected to leaf chunks, it will be `true`.
-    pub fn create_trunk_proof(&mut self) -> Result<(Vec<Op>, bool)> {
-        let approx_size = 2usize.pow((self.tree().height() / 2) as u32) * 3;
+    ///
+    /// The trunk contains the top `trunk_height` layers of the tree, or half of
+    /// the height of its left edge if `trunk_height` is `None`. Each layer
+    /// doubles the number of leaf chunks, so a taller trunk makes for more,
+    /// smaller leaf chunks. A trunk height under `MIN_TRUNK_HEIGHT` puts the
+    /// entire tree in the trunk, and a trunk height which doesn't fit in the
+    /// tree (with two children for every node above the bottom layer) is an
+    /// error. The same `trunk_height` must be passed to `verify_trunk`.
+    pub fn create_trunk_proof(&mut self, trunk_height: Option<usize>) -> Result<(Vec<Op>, bool)> {
+        let approx_height = trunk_height
+            .unwrap_or(usize::MAX)
+            .min(self.tree().height() as usize / 2);
+        let approx_size = 2usize.pow(approx_height as u32) * 3;
         let mut proof = Vec::with_capacity(approx_size);
 
-        let trunk_height = self.traverse_for_height_proof(&mut proof, 1)?;
+        let trunk_height = self.traverse_for_height_proof(&mut proof, 1, trunk_height)?;
 
         if trunk_height < MIN_TRUNK_HEIGHT {
             proof.clear();
-            self.traverse_for_trunk(&mut proof, usize::MAX, true)?;
+            self.traverse_for_trunk(&mut proof, None, true)?;
             Ok((proof, false))
         } else {
-            self.traverse_for_trunk(&mut proof, trunk_height, true)?;
+            self.traverse_for_trunk(&mut proof, Some(trunk_height), true)?;
             Ok((proof, true))
         }
     }
@@ -44,14 +68,21 @@ where
     /// Traverses down the left edge of the tree and pushes ops to the proof, to
     /// act as a proof of the height of the tree. This is the first step in
     /// generating a trunk proof.
-    fn traverse_for_height_proof(&mut self, proof: &mut Vec<Op>, depth: usize) -> Result<usize> {
+    ///
+    /// Returns the height of the trunk, which is `trunk_height` if set.
+    fn traverse_for_height_proof(
+        &mut self,
+        proof: &mut Vec<Op>,
+        depth: usize,
+        trunk_height: Option<usize>,
+    ) -> Result<usize> {
         let maybe_left = self.walk(true)?;
         let has_left_child = maybe_left.is_some();
 
         let trunk_height = if let Some(mut left) = maybe_left {
-            left.traverse_for_height_proof(proof, depth + 1)?
+            left.traverse_for_height_proof(proof, depth + 1, trunk_height)?
         } else {
-            depth / 2
+            trunk_height.unwrap_or(depth / 2)
         };
 
         if depth > trunk_height {
@@ -71,15 +102,16 @@ where
     }
 
     /// Traverses down the tree and adds KV push ops for all nodes up to a
-    /// certain depth. This expects the proof to contain a height proof as
-    /// generated by `traverse_for_height_proof`.
+    /// certain depth, or for the whole tree if `remaining_depth` is `None`.
+    /// This expects the proof to contain a height proof as generated by
+    /// `traverse_for_height_proof`.
     fn traverse_for_trunk(
         &mut self,
         proof: &mut Vec<Op>,
-        remaining_depth: usize,
+        remaining_depth: Option<usize>,
         is_leftmost: bool,
     ) -> Result<()> {
-        if remaining_depth == 0 {
+        if remaining_depth == Some(0) {
             // return early if we have reached bottom of trunk
 
             // for leftmost node, we already have height proof
@@ -93,15 +125,23 @@ where
             return Ok(());
         }
 
-        // traverse left
         let has_left_child = self.tree().link(true).is_some();
+        let has_right_child = self.tree().link(false).is_some();
+        if remaining_depth.is_some() && !(has_left_child && has_right_child) {
+            return Err(Error::Tree(
+                "Trunk height exceeds the complete layers of the tree".into(),
+            ));
+        }
+        let remaining_depth = remaining_depth.map(|depth| depth - 1);
+
+        // traverse left
         if has_left_child {
             let mut left = self.walk(true)?.unwrap();
-            left.traverse_for_trunk(proof, remaining_depth - 1, is_leftmost)?;
+            left.traverse_for_trunk(proof, remaining_depth, is_leftmost)?;
         }
 
         // add this node's data
-        proof.push(Op::Push(self.to_kv_node()));
+        proof.push(Op::Push(self.to_kv_node()?));
 
         if has_left_child {
             proof.push(Op::Parent);
@@ -109,7 +149,7 @@ where
 
         // traverse right
         if let Some(mut right) = self.walk(false)? {
-            right.traverse_for_trunk(proof, remaining_depth - 1, false)?;
+            right.traverse_for_trunk(proof, remaining_depth, false)?;
             proof.push(Op::Child);
         }
 
@@ -117,28 +157,64 @@ where
     }
 }
 
-/// Builds a chunk proof by iterating over values in a RocksDB, ending the chunk
-/// when a node with key `end_key` is encountered.
+/// Determines where `get_next_chunk` ends a chunk.
+#[cfg(feature = "full")]
+pub enum ChunkEnd<'a> {
+    /// The chunk runs until the iterator is exhausted.
+    Unbounded,
+    /// The chunk ends at the node with the given key, which is skipped (e.g.
+    /// the trunk node which separates two leaf chunks).
+    Boundary(&'a [u8]),
+    /// The chunk ends before the first node with a key greater than or equal
+    /// to the given key, leaving the iterator at that node.
+    Exclusive(&'a [u8]),
+    /// The chunk ends after the last node with a key less than or equal to
+    /// the given key, leaving the iterator at the node after it.
+    Inclusive(&'a [u8]),
+    /// The chunk ends before the first node for which the predicate returns
+    /// `true`, leaving the iterator at that node. The predicate is called with
+    /// each node's key and the encoded length of the chunk before it.
+    Predicate(&'a mut dyn FnMut(&[u8], usize) -> bool),
+}
+
+/// Builds a chunk proof by iterating over values in a RocksDB, until `end` is
+/// reached. Stored nodes are decoded with `codec`, and their keys are stripped
+/// of `prefix`.
 ///
-/// Advances the iterator for all nodes in the chunk and the `end_key` (if any).
+/// Only chunks ending at the boundaries of the leaf chunks of a trunk (or at
+/// the end of the tree) verify as a single subtree with `verify_leaf`. Chunks
+/// cut elsewhere (e.g. by size, for custom snapshot formats) may leave several
+/// subtrees, which the caller must join back up when processing them.
 #[cfg(feature = "full")]
-pub(crate) fn get_next_chunk(iter: &mut DBRawIterator, end_key: Option<&[u8]>) -> Result<Vec<Op>> {
+pub fn get_next_chunk(
+    iter: &mut DBRawIterator,
+    mut end: ChunkEnd,
+    prefix: &[u8],
+    codec: &NodeCodec,
+) -> Result<Vec<Op>> {
     let mut chunk = Vec::with_capacity(512);
+    let mut chunk_len = 0;
     let mut stack = Vec::with_capacity(32);
     let mut node = Tree::new(vec![], vec![])?;
 
     while iter.valid() {
-        let key = iter.key().unwrap();
+        let key = &iter.key().unwrap()[prefix.len()..];
 
-        if let Some(end_key) = end_key {
-            if key == end_key {
-                break;
-            }
+        let done = match &mut end {
+            ChunkEnd::Unbounded => false,
+            ChunkEnd::Boundary(end_key) | ChunkEnd::Exclusive(end_key) => key >= *end_key,
+            ChunkEnd::Inclusive(end_key) => key > *end_key,
+            ChunkEnd::Predicate(predicate) => predicate(key, chunk_len),
+        };
+        if done {
+            break;
         }
 
-        let encoded_node = iter.value().unwrap();
-        Tree::decode_into(&mut node, vec![], encoded_node);
+        let encoded_node = codec.decode(key, iter.value().unwrap())?;
+        Tree::decode_into(&mut node, vec![], &encoded_node)?;
 
+        let start = chunk.len();
+        Limits::check_provable(key, node.value())?;
         let kv = Node::KV(key.to_vec(), node.value().to_vec());
         chunk.push(Op::Push(kv));
 
@@ -158,25 +234,128 @@ pub(crate) fn get_next_chunk(iter: &mut DBRawIterator, end_key: Option<&[u8]>) -
             }
         }
 
+        chunk_len += chunk[start..].iter().map(Op::encoded_len).sum::<usize>();
         iter.next();
     }
 
-    if iter.valid() {
-        iter.next();
+    if let ChunkEnd::Boundary(_) = end {
+        if iter.valid() {
+            iter.next();
+        }
     }
 
     Ok(chunk)
 }
 
+/// Metadata about a chunk, prepended to it by
+/// `ChunkProducer::chunk_with_header` so transports can route chunks and
+/// restorers can check their order without tracking chunk indexes separately.
+///
+/// The header is encoded as:
+///
+/// ```text
+/// index (u32, big-endian) root_hash
+/// first_key_len (u8) first_key last_key_len (u8) last_key
+/// op_count (u32, big-endian)
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkHeader {
+    /// The index of the chunk, where the trunk is chunk 0.
+    pub index: u32,
+    /// The root hash of the tree the chunk was produced from.
+    pub root_hash: Hash,
+    /// The first key with its value in the chunk, or empty if there is none.
+    pub first_key: Vec<u8>,
+    /// The last key with its value in the chunk, or empty if there is none.
+    pub last_key: Vec<u8>,
+    /// The number of ops in the chunk.
+    pub op_count: u32,
+}
+
+impl ChunkHeader {
+    /// Creates the header of the chunk with the given index and ops.
+    pub fn new(index: usize, root_hash: Hash, ops: &[Op]) -> Result<Self> {
+        let mut keys = ops.iter().filter_map(|op| match op {
+            Op::Push(Node::KV(key, _)) => Some(key),
+            _ => None,
+        });
+        let first_key = keys.next().cloned().unwrap_or_default();
+        let last_key = keys.last().cloned().unwrap_or_else(|| first_key.clone());
+
+        Ok(ChunkHeader {
+            index: u32::try_from(index)?,
+            root_hash,
+            first_key,
+            last_key,
+            op_count: u32::try_from(ops.len())?,
+        })
+    }
+
+    /// Appends the encoding of the header to `output`.
+    pub fn encode_into(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.index.to_be_bytes());
+        output.extend_from_slice(&self.root_hash);
+        for key in [&self.first_key, &self.last_key] {
+            output.push(key.len() as u8);
+            output.extend_from_slice(key);
+        }
+        output.extend_from_slice(&self.op_count.to_be_bytes());
+    }
+
+    /// Decodes the header at the start of `bytes`, returning it and the rest of
+    /// the bytes (the encoded ops of the chunk).
+    pub fn decode(bytes: &[u8]) -> Result<(Self, &[u8])> {
+        fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+            if input.len() < len {
+                return Err(Error::ChunkProcessing(
+                    "Unexpected end of chunk header".into(),
+                ));
+            }
+            let (bytes, rest) = input.split_at(len);
+            *input = rest;
+            Ok(bytes)
+        }
+
+        let mut input = bytes;
+        let index = u32::from_be_bytes(take(&mut input, 4)?.try_into().unwrap());
+        let root_hash = take(&mut input, HASH_LENGTH)?.try_into().unwrap();
+        let first_key_len = take(&mut input, 1)?[0] as usize;
+        let first_key = take(&mut input, first_key_len)?.to_vec();
+        let last_key_len = take(&mut input, 1)?[0] as usize;
+        let last_key = take(&mut input, last_key_len)?.to_vec();
+        let op_count = u32::from_be_bytes(take(&mut input, 4)?.try_into().unwrap());
+
+        let header = ChunkHeader {
+            index,
+            root_hash,
+            first_key,
+            last_key,
+            op_count,
+        };
+        Ok((header, input))
+    }
+
+    /// Checks that the header describes `ops`, the decoded ops of its chunk.
+    pub fn verify(&self, ops: &[Op]) -> Result<()> {
+        let expected = ChunkHeader::new(self.index as usize, self.root_hash, ops)?;
+        if *self != expected {
+            return Err(Error::ChunkProcessing(format!(
+                "Header of chunk {} does not match its ops",
+                self.index
+            )));
+        }
+        Ok(())
+    }
+}
+
 /// Verifies a leaf chunk proof by executing its operators. Checks that there
 /// were no abridged nodes (Hash or KVHash) and the proof hashes to
 /// `expected_hash`.
-#[cfg(feature = "full")]
-pub(crate) fn verify_leaf<I: Iterator<Item = Result<Op>>>(
+pub fn verify_leaf<I: Iterator<Item = Result<Op>>>(
     ops: I,
     expected_hash: Hash,
 ) -> Result<ProofTree> {
-    let tree = execute(ops, false, |node| match node {
+    let tree = execute(ops, false, MAX_TREE_HEIGHT, |node| match node {
         Node::KV(_, _) => Ok(()),
         _ => Err(Error::Tree("Leaf chunks must contain full subtree".into())),
     })?;
@@ -188,32 +367,60 @@ pub(crate) fn verify_leaf<I: Iterator<Item = Result<Op>>>(
     Ok(tree)
 }
 
+/// Verifies a leaf chunk proof as in `verify_leaf`, building the resulting
+/// tree in `arena` (replacing its contents) and returning the index of its
+/// root node.
+pub fn verify_leaf_in<I: Iterator<Item = Result<Op>>>(
+    arena: &mut TreeArena,
+    ops: I,
+    expected_hash: Hash,
+) -> Result<usize> {
+    let root = arena.execute(ops, |node| match node {
+        Node::KV(_, _) => Ok(()),
+        _ => Err(Error::Tree("Leaf chunks must contain full subtree".into())),
+    })?;
+
+    let hash = arena.node(root).hash;
+    if hash != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, hash));
+    }
+
+    Ok(root)
+}
+
 /// Verifies a trunk chunk proof by executing its operators. Ensures the
 /// resulting tree contains a valid height proof, the trunk is the correct
 /// height, and all of its inner nodes are not abridged. Returns the tree and
 /// the height given by the height proof.
-#[cfg(feature = "full")]
-pub(crate) fn verify_trunk<I: Iterator<Item = Result<Op>>>(ops: I) -> Result<(ProofTree, usize)> {
+///
+/// `trunk_height` must be the one the trunk was created with (see
+/// `create_trunk_proof`). The leaf chunks are verified against the hashes of
+/// the trunk's bottom layer, `trunk_height.unwrap_or(height / 2)`.
+pub fn verify_trunk<I: Iterator<Item = Result<Op>>>(
+    ops: I,
+    trunk_height: Option<usize>,
+) -> Result<(ProofTree, usize)> {
     fn verify_height_proof(tree: &ProofTree) -> Result<usize> {
-        Ok(match tree.child(true) {
-            Some(child) => {
-                if let Node::Hash(_) = child.tree.node {
-                    return Err(Error::UnexpectedNode(
-                        "Expected height proof to only contain KV and KVHash nodes".into(),
-                    ));
-                }
-                verify_height_proof(&child.tree)? + 1
+        let mut height = 1;
+        let mut node = tree;
+        while let Some(child) = node.child(true) {
+            if let Node::Hash(_) = child.tree.node {
+                return Err(Error::UnexpectedNode(
+                    "Expected height proof to only contain KV and KVHash nodes".into(),
+                ));
             }
-            None => 1,
-        })
+            height += 1;
+            node = &child.tree;
+        }
+        Ok(height)
     }
 
     fn verify_completeness(tree: &ProofTree, remaining_depth: usize, leftmost: bool) -> Result<()> {
-        let recurse = |left, leftmost| {
-            if let Some(child) = tree.child(left) {
-                verify_completeness(&child.tree, remaining_depth - 1, left && leftmost)?;
-            }
-            Ok(())
+        let recurse = |left, leftmost| match tree.child(left) {
+            Some(child) => verify_completeness(&child.tree, remaining_depth - 1, left && leftmost),
+            None => Err(Error::UnexpectedNode(
+                "Expected trunk inner nodes to have two children".into(),
+            )),
         };
 
         if remaining_depth > 0 {
@@ -245,13 +452,13 @@ pub(crate) fn verify_trunk<I: Iterator<Item = Result<Op>>>(ops: I) -> Result<(Pr
     }
 
     let mut kv_only = true;
-    let tree = execute(ops, false, |node| {
+    let tree = execute(ops, false, MAX_TREE_HEIGHT, |node| {
         kv_only &= matches!(node, Node::KV(_, _));
         Ok(())
     })?;
 
     let height = verify_height_proof(&tree)?;
-    let trunk_height = height / 2;
+    let trunk_height = trunk_height.unwrap_or(height / 2);
 
     if trunk_height < MIN_TRUNK_HEIGHT {
         if !kv_only {
@@ -269,6 +476,7 @@ mod tests {
     use std::usize;
 
     use super::super::tree::Tree;
+    use super::super::{encode_into, Decoder};
     use super::*;
     use crate::test_utils::*;
     use crate::tree::{NoopCommit, PanicSource, Tree as BaseTree};
@@ -299,11 +507,11 @@ mod tests {
         let mut tree = make_tree_seq(31);
         let mut walker = RefWalker::new(&mut tree, PanicSource {});
 
-        let (proof, has_more) = walker.create_trunk_proof().unwrap();
+        let (proof, has_more) = walker.create_trunk_proof(None).unwrap();
         assert!(!has_more);
 
         println!("{:?}", &proof);
-        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok)).unwrap();
+        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok), None).unwrap();
 
         let counts = count_node_types(trunk);
         assert_eq!(counts.hash, 0);
@@ -316,9 +524,9 @@ mod tests {
         let mut tree = make_tree_seq(2u64.pow(MIN_TRUNK_HEIGHT as u32 * 2 + 1) - 1);
         let mut walker = RefWalker::new(&mut tree, PanicSource {});
 
-        let (proof, has_more) = walker.create_trunk_proof().unwrap();
+        let (proof, has_more) = walker.create_trunk_proof(None).unwrap();
         assert!(has_more);
-        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok)).unwrap();
+        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok), None).unwrap();
 
         let counts = count_node_types(trunk);
         // are these formulas correct for all values of `MIN_TRUNK_HEIGHT`? 🤔
@@ -330,16 +538,46 @@ mod tests {
         assert_eq!(counts.kvhash, MIN_TRUNK_HEIGHT + 1);
     }
 
+    #[test]
+    fn trunk_height_override_roundtrip() {
+        let mut tree = make_tree_seq(2u64.pow(MIN_TRUNK_HEIGHT as u32 * 2 + 1) - 1);
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let trunk_height = MIN_TRUNK_HEIGHT + 2;
+        let (proof, has_more) = walker.create_trunk_proof(Some(trunk_height)).unwrap();
+        assert!(has_more);
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+        let ops = || Decoder::new(&bytes);
+        let (trunk, _) = verify_trunk(ops(), Some(trunk_height)).unwrap();
+        assert_eq!(
+            trunk.layer(trunk_height).count(),
+            2usize.pow(trunk_height as u32)
+        );
+        assert!(verify_trunk(ops(), None).is_err());
+
+        // a small trunk height puts the whole tree in the trunk
+        let (proof, has_more) = walker.create_trunk_proof(Some(1)).unwrap();
+        assert!(!has_more);
+        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok), Some(1)).unwrap();
+        assert_eq!(count_node_types(trunk).hash, 0);
+
+        // the trunk can't be taller than the complete layers of the tree
+        assert!(walker
+            .create_trunk_proof(Some(MIN_TRUNK_HEIGHT * 2 + 1))
+            .is_err());
+    }
+
     #[test]
     fn one_node_tree_trunk_roundtrip() -> Result<()> {
         let mut tree = BaseTree::new(vec![0], vec![])?;
         tree.commit(&mut NoopCommit {}).unwrap();
 
         let mut walker = RefWalker::new(&mut tree, PanicSource {});
-        let (proof, has_more) = walker.create_trunk_proof().unwrap();
+        let (proof, has_more) = walker.create_trunk_proof(None).unwrap();
         assert!(!has_more);
 
-        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok)).unwrap();
+        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok), None).unwrap();
         let counts = count_node_types(trunk);
         assert_eq!(counts.hash, 0);
         assert_eq!(counts.kv, 1);
@@ -356,10 +594,10 @@ mod tests {
             BaseTree::new(vec![0], vec![])?.attach(false, Some(BaseTree::new(vec![1], vec![])?));
         tree.commit(&mut NoopCommit {}).unwrap();
         let mut walker = RefWalker::new(&mut tree, PanicSource {});
-        let (proof, has_more) = walker.create_trunk_proof().unwrap();
+        let (proof, has_more) = walker.create_trunk_proof(None).unwrap();
         assert!(!has_more);
 
-        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok)).unwrap();
+        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok), None).unwrap();
         let counts = count_node_types(trunk);
         assert_eq!(counts.hash, 0);
         assert_eq!(counts.kv, 2);
@@ -376,10 +614,10 @@ mod tests {
             BaseTree::new(vec![1], vec![])?.attach(true, Some(BaseTree::new(vec![0], vec![])?));
         tree.commit(&mut NoopCommit {}).unwrap();
         let mut walker = RefWalker::new(&mut tree, PanicSource {});
-        let (proof, has_more) = walker.create_trunk_proof().unwrap();
+        let (proof, has_more) = walker.create_trunk_proof(None).unwrap();
         assert!(!has_more);
 
-        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok)).unwrap();
+        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok), None).unwrap();
         let counts = count_node_types(trunk);
         assert_eq!(counts.hash, 0);
         assert_eq!(counts.kv, 2);
@@ -398,10 +636,10 @@ mod tests {
         tree.commit(&mut NoopCommit {}).unwrap();
 
         let mut walker = RefWalker::new(&mut tree, PanicSource {});
-        let (proof, has_more) = walker.create_trunk_proof().unwrap();
+        let (proof, has_more) = walker.create_trunk_proof(None).unwrap();
         assert!(!has_more);
 
-        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok)).unwrap();
+        let (trunk, _) = verify_trunk(proof.into_iter().map(Ok), None).unwrap();
         let counts = count_node_types(trunk);
         assert_eq!(counts.hash, 0);
         assert_eq!(counts.kv, 3);
@@ -422,7 +660,7 @@ mod tests {
         // whole tree as 1 leaf
         let mut iter = merk.db.raw_iterator();
         iter.seek_to_first();
-        let chunk = get_next_chunk(&mut iter, None).unwrap();
+        let chunk = get_next_chunk(&mut iter, ChunkEnd::Unbounded, &[], &merk.codec).unwrap();
         let ops = chunk.into_iter().map(Ok);
         let chunk = verify_leaf(ops, merk.root_hash()).unwrap();
         let counts = count_node_types(chunk);
@@ -435,7 +673,8 @@ mod tests {
         iter.seek_to_first();
 
         // left leaf
-        let chunk = get_next_chunk(&mut iter, Some(root_key.as_slice())).unwrap();
+        let chunk =
+            get_next_chunk(&mut iter, ChunkEnd::Boundary(&root_key), &[], &merk.codec).unwrap();
         let ops = chunk.into_iter().map(Ok);
         let chunk = verify_leaf(
             ops,
@@ -451,7 +690,7 @@ mod tests {
         assert_eq!(counts.kvhash, 0);
 
         // right leaf
-        let chunk = get_next_chunk(&mut iter, None).unwrap();
+        let chunk = get_next_chunk(&mut iter, ChunkEnd::Unbounded, &[], &merk.codec).unwrap();
         let ops = chunk.into_iter().map(Ok);
         let chunk = verify_leaf(
             ops,
@@ -466,4 +705,103 @@ mod tests {
         assert_eq!(counts.hash, 0);
         assert_eq!(counts.kvhash, 0);
     }
+
+    #[test]
+    fn chunk_end_bounds() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(make_batch_seq(0..31).as_slice(), &[]).unwrap();
+        let kv_count = |chunk: &[Op]| {
+            chunk
+                .iter()
+                .filter(|op| matches!(op, Op::Push(Node::KV(..))))
+                .count()
+        };
+
+        let mut iter = merk.db.raw_iterator();
+        iter.seek_to_first();
+        let end = ChunkEnd::Exclusive(&seq_key(10));
+        let chunk = get_next_chunk(&mut iter, end, &[], &merk.codec).unwrap();
+        assert_eq!(kv_count(&chunk), 10);
+        assert_eq!(iter.key(), Some(&seq_key(10)[..]));
+
+        let end = ChunkEnd::Inclusive(&seq_key(20));
+        let chunk = get_next_chunk(&mut iter, end, &[], &merk.codec).unwrap();
+        assert_eq!(kv_count(&chunk), 11);
+
+        let mut predicate = |_: &[u8], len: usize| len >= 200;
+        let end = ChunkEnd::Predicate(&mut predicate);
+        let chunk = get_next_chunk(&mut iter, end, &[], &merk.codec).unwrap();
+        let len: usize = chunk.iter().map(Op::encoded_len).sum();
+        assert!(len >= 200);
+        let sized = kv_count(&chunk);
+        assert!(sized > 0 && sized < 10);
+
+        let chunk = get_next_chunk(&mut iter, ChunkEnd::Unbounded, &[], &merk.codec).unwrap();
+        assert_eq!(kv_count(&chunk), 10 - sized);
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn leaf_chunk_in_arena() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(make_batch_seq(0..31).as_slice(), &[]).unwrap();
+
+        let mut iter = merk.db.raw_iterator();
+        iter.seek_to_first();
+        let chunk = get_next_chunk(&mut iter, ChunkEnd::Unbounded, &[], &merk.codec).unwrap();
+        let mut arena = TreeArena::new();
+        let root = verify_leaf_in(&mut arena, chunk.into_iter().map(Ok), merk.root_hash()).unwrap();
+        assert_eq!(arena.len(), 31);
+        assert_eq!(arena.node(root).height, 5);
+
+        let chunk = vec![Op::Push(Node::Hash([0; 32]))];
+        assert!(verify_leaf_in(&mut arena, chunk.into_iter().map(Ok), [0; 32]).is_err());
+    }
+
+    #[test]
+    fn deep_malformed_trunk() {
+        // a left spine far taller than any real tree is rejected rather than
+        // overflowing the stack
+        let mut ops = vec![Op::Push(Node::KVHash([0; 32]))];
+        for _ in 0..100_000 {
+            ops.push(Op::Push(Node::KVHash([0; 32])));
+            ops.push(Op::Parent);
+        }
+        assert!(verify_trunk(ops.into_iter().map(Ok), None).is_err());
+
+        // inner trunk nodes must have both children
+        let mut ops = vec![Op::Push(Node::KVHash([0; 32]))];
+        for i in 0..10u8 {
+            ops.push(Op::Push(Node::KV(vec![i], vec![])));
+            ops.push(Op::Parent);
+        }
+        assert!(verify_trunk(ops.into_iter().map(Ok), None).is_err());
+    }
+
+    #[test]
+    fn chunk_header_roundtrip() {
+        let ops = vec![
+            Op::Push(Node::KV(vec![1], vec![])),
+            Op::Push(Node::KV(vec![2], vec![])),
+            Op::Parent,
+            Op::Push(Node::Hash([0; 32])),
+            Op::Child,
+        ];
+        let header = ChunkHeader::new(3, [1; 32], &ops).unwrap();
+        assert_eq!(
+            (header.first_key.as_slice(), header.last_key.as_slice()),
+            (&[1][..], &[2][..])
+        );
+        assert_eq!(header.op_count, 5);
+
+        let mut bytes = vec![];
+        header.encode_into(&mut bytes);
+        bytes.push(123);
+        let (decoded, rest) = ChunkHeader::decode(&bytes).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(rest, &[123]);
+        decoded.verify(&ops).unwrap();
+        assert!(decoded.verify(&ops[..4]).is_err());
+        assert!(ChunkHeader::decode(&bytes[..10]).is_err());
+    }
 }
@@ -3,7 +3,8 @@ use std::io::{Read, Write};
 use ed::{Decode, Encode, Terminated};
 
 use super::{Node, Op};
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::limits::Limits;
 use crate::tree::HASH_LENGTH;
 
 impl Encode for Op {
@@ -81,11 +82,14 @@ impl Decode for Op {
 impl Terminated for Op {}
 
 impl Op {
-    fn encode_into<W: Write>(&self, dest: &mut W) -> Result<()> {
-        Ok(Encode::encode_into(self, dest)?)
+    /// Appends the encoding of the op to `dest`.
+    pub fn encode_into(&self, dest: &mut Vec<u8>) {
+        // writing to a `Vec` can not fail
+        Encode::encode_into(self, dest).unwrap()
     }
 
-    fn encoding_length(&self) -> usize {
+    /// Returns the length of the op's encoding in bytes.
+    pub fn encoded_len(&self) -> usize {
         Encode::encoding_length(self).unwrap()
     }
 
@@ -94,24 +98,57 @@ impl Op {
     }
 }
 
+/// Appends the encoding of a proof made of `ops` to `output`, e.g. a buffer
+/// reused between proofs. Reserve `encoded_len` bytes first to avoid
+/// reallocating while encoding.
 pub fn encode_into<'a, T: Iterator<Item = &'a Op>>(ops: T, output: &mut Vec<u8>) {
     for op in ops {
-        op.encode_into(output).unwrap();
+        op.encode_into(output);
     }
 }
 
+/// Returns the length in bytes of the encoding of a proof made of `ops`.
+pub fn encoded_len<'a, T: Iterator<Item = &'a Op>>(ops: T) -> usize {
+    ops.map(Op::encoded_len).sum()
+}
+
 pub struct Decoder<'a> {
     offset: usize,
     bytes: &'a [u8],
+    limits: Limits,
+    decoded: usize,
 }
 
 impl<'a> Decoder<'a> {
     pub fn new(proof_bytes: &'a [u8]) -> Self {
+        Decoder::with_limits(proof_bytes, Limits::default())
+    }
+
+    /// Creates a decoder which yields an error (and stops) once it decodes
+    /// more than `limits.max_proof_ops` ops, or a key or value longer than
+    /// allowed by `limits`.
+    pub fn with_limits(proof_bytes: &'a [u8], limits: Limits) -> Self {
         Decoder {
             offset: 0,
             bytes: proof_bytes,
+            limits,
+            decoded: 0,
         }
     }
+
+    fn check_limits(&self, op: &Op) -> Result<()> {
+        if self.decoded > self.limits.max_proof_ops {
+            return Err(Error::Proof(format!(
+                "Proof exceeds the limit of {} ops",
+                self.limits.max_proof_ops
+            )));
+        }
+        if let Op::Push(Node::KV(key, value)) = op {
+            self.limits.check_key(key)?;
+            self.limits.check_value(key, value)?;
+        }
+        Ok(())
+    }
 }
 
 impl<'a> Iterator for Decoder<'a> {
@@ -122,27 +159,38 @@ impl<'a> Iterator for Decoder<'a> {
             return None;
         }
 
-        Some((|| {
-            let bytes = &self.bytes[self.offset..];
-            let op = Op::decode(bytes)?;
-            self.offset += op.encoding_length();
-            Ok(op)
-        })())
+        let bytes = &self.bytes[self.offset..];
+        self.decoded += 1;
+        match Op::decode(bytes).and_then(|op| self.check_limits(&op).map(|_| op)) {
+            Ok(op) => {
+                self.offset += op.encoded_len();
+                Some(Ok(op))
+            }
+            Err(err) => {
+                // the rest of the input can't be framed, so stop after the
+                // first error rather than yielding it forever
+                self.offset = self.bytes.len();
+                Some(Err(err))
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::super::{Node, Op};
+    use super::Decoder;
+    use crate::error::{Error, Result};
+    use crate::limits::Limits;
     use crate::tree::HASH_LENGTH;
 
     #[test]
     fn encode_push_hash() {
         let op = Op::Push(Node::Hash([123; HASH_LENGTH]));
-        assert_eq!(op.encoding_length(), 1 + HASH_LENGTH);
+        assert_eq!(op.encoded_len(), 1 + HASH_LENGTH);
 
         let mut bytes = vec![];
-        op.encode_into(&mut bytes).unwrap();
+        op.encode_into(&mut bytes);
         assert_eq!(
             bytes,
             vec![
@@ -156,10 +204,10 @@ mod test {
     #[test]
     fn encode_push_kvhash() {
         let op = Op::Push(Node::KVHash([123; HASH_LENGTH]));
-        assert_eq!(op.encoding_length(), 1 + HASH_LENGTH);
+        assert_eq!(op.encoded_len(), 1 + HASH_LENGTH);
 
         let mut bytes = vec![];
-        op.encode_into(&mut bytes).unwrap();
+        op.encode_into(&mut bytes);
         assert_eq!(
             bytes,
             vec![
@@ -173,30 +221,30 @@ mod test {
     #[test]
     fn encode_push_kv() {
         let op = Op::Push(Node::KV(vec![1, 2, 3], vec![4, 5, 6]));
-        assert_eq!(op.encoding_length(), 10);
+        assert_eq!(op.encoded_len(), 10);
 
         let mut bytes = vec![];
-        op.encode_into(&mut bytes).unwrap();
+        op.encode_into(&mut bytes);
         assert_eq!(bytes, vec![0x03, 3, 1, 2, 3, 0, 3, 4, 5, 6]);
     }
 
     #[test]
     fn encode_parent() {
         let op = Op::Parent;
-        assert_eq!(op.encoding_length(), 1);
+        assert_eq!(op.encoded_len(), 1);
 
         let mut bytes = vec![];
-        op.encode_into(&mut bytes).unwrap();
+        op.encode_into(&mut bytes);
         assert_eq!(bytes, vec![0x10]);
     }
 
     #[test]
     fn encode_child() {
         let op = Op::Child;
-        assert_eq!(op.encoding_length(), 1);
+        assert_eq!(op.encoded_len(), 1);
 
         let mut bytes = vec![];
-        op.encode_into(&mut bytes).unwrap();
+        op.encode_into(&mut bytes);
         assert_eq!(bytes, vec![0x11]);
     }
 
@@ -205,7 +253,22 @@ mod test {
     fn encode_push_kv_long_key() {
         let op = Op::Push(Node::KV(vec![123; 300], vec![4, 5, 6]));
         let mut bytes = vec![];
-        op.encode_into(&mut bytes).unwrap();
+        op.encode_into(&mut bytes);
+    }
+
+    #[test]
+    fn encode_proof() {
+        let ops = vec![
+            Op::Push(Node::KV(vec![1, 2, 3], vec![4, 5, 6])),
+            Op::Push(Node::Hash([123; HASH_LENGTH])),
+            Op::Child,
+        ];
+        assert_eq!(super::encoded_len(ops.iter()), 10 + 1 + HASH_LENGTH + 1);
+
+        let mut bytes = vec![0xff];
+        super::encode_into(ops.iter(), &mut bytes);
+        assert_eq!(bytes.len(), 1 + super::encoded_len(ops.iter()));
+        assert_eq!(&bytes[..11], &[0xff, 0x03, 3, 1, 2, 3, 0, 3, 4, 5, 6]);
     }
 
     #[test]
@@ -254,4 +317,72 @@ mod test {
         let bytes = [0x88];
         assert!(Op::decode(&bytes[..]).is_err());
     }
+
+    #[test]
+    fn decode_malformed() {
+        let ops = vec![
+            Op::Push(Node::KV(vec![1, 2, 3], vec![4, 5, 6])),
+            Op::Push(Node::KVHash([1; HASH_LENGTH])),
+            Op::Parent,
+            Op::Push(Node::Hash([2; HASH_LENGTH])),
+            Op::Child,
+        ];
+        let mut bytes = vec![];
+        super::encode_into(ops.iter(), &mut bytes);
+
+        let mut inputs: Vec<Vec<u8>> = (0..bytes.len()).map(|len| bytes[..len].to_vec()).collect();
+        for i in 0..bytes.len() {
+            for byte in [0x00, 0x01, 0x03, 0x10, 0x11, 0xff] {
+                let mut mutated = bytes.clone();
+                mutated[i] = byte;
+                inputs.push(mutated);
+            }
+        }
+
+        for input in inputs {
+            // the decoder stops after the first error
+            let results: Vec<_> = Decoder::new(&input).collect();
+            let errors = results.iter().filter(|res| res.is_err()).count();
+            assert!(errors <= 1);
+            assert!(errors == 0 || results.last().unwrap().is_err());
+
+            let _ = super::super::tree::execute(
+                Decoder::new(&input),
+                false,
+                super::super::MAX_TREE_HEIGHT,
+                |_| Ok(()),
+            );
+            let _ = super::super::query::verify(&input, [0; HASH_LENGTH]);
+        }
+    }
+
+    #[test]
+    fn decode_with_limits() {
+        let ops = vec![
+            Op::Push(Node::KV(vec![1, 2, 3], vec![4, 5, 6])),
+            Op::Push(Node::Hash([2; HASH_LENGTH])),
+            Op::Child,
+        ];
+        let mut bytes = vec![];
+        super::encode_into(ops.iter(), &mut bytes);
+
+        let decode = |limits| Decoder::with_limits(&bytes, limits).collect::<Result<Vec<_>>>();
+        assert_eq!(decode(Limits::default()).unwrap(), ops);
+
+        let limits = Limits {
+            max_proof_ops: 2,
+            ..Default::default()
+        };
+        assert!(matches!(decode(limits), Err(Error::Proof(_))));
+        let limits = Limits {
+            max_key_len: 2,
+            ..Default::default()
+        };
+        assert!(matches!(decode(limits), Err(Error::LimitExceeded { .. })));
+        let limits = Limits {
+            max_value_len: 2,
+            ..Default::default()
+        };
+        assert!(matches!(decode(limits), Err(Error::LimitExceeded { .. })));
+    }
 }
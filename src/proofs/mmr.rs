@@ -0,0 +1,199 @@
+//! A Merkle mountain range accumulating the sequence of a store's historical
+//! root hashes, so light clients holding only the latest commitment can verify
+//! that a root hash was the state of the store at a given height.
+//!
+//! Leaf `i` is the root hash as of height `i + 1`. Nodes are addressed by
+//! their level (0 for leaves) and their index within that level, so node
+//! `(level, index)` covers leaves `index << level` to `((index + 1) << level) - 1`.
+
+use crate::error::{Error, Result};
+use crate::tree::{Hash, Hasher};
+use sha2::Digest;
+
+/// The `(level, index)` address of a node in the mountain range.
+pub type Address = (u8, u64);
+
+/// Hashes two sibling nodes of the mountain range.
+pub fn parent_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = <Hasher as Digest>::new();
+    Digest::update(&mut hasher, [2]);
+    Digest::update(&mut hasher, left);
+    Digest::update(&mut hasher, right);
+
+    let res = Digest::finalize(hasher);
+    let mut hash: Hash = Default::default();
+    hash.copy_from_slice(&res[..]);
+    hash
+}
+
+/// Hashes the peaks of a mountain range with `leaf_count` leaves into the
+/// single commitment held by light clients.
+pub fn commitment(leaf_count: u64, peaks: &[Hash]) -> Hash {
+    let mut hasher = <Hasher as Digest>::new();
+    Digest::update(&mut hasher, [3]);
+    Digest::update(&mut hasher, leaf_count.to_be_bytes());
+    for peak in peaks {
+        Digest::update(&mut hasher, peak);
+    }
+
+    let res = Digest::finalize(hasher);
+    let mut hash: Hash = Default::default();
+    hash.copy_from_slice(&res[..]);
+    hash
+}
+
+/// Returns the `(level, index)` addresses of the peaks of a mountain range
+/// with `leaf_count` leaves, from the oldest (tallest) mountain to the newest.
+pub fn peaks(leaf_count: u64) -> Vec<Address> {
+    (0..64u8)
+        .rev()
+        .filter(|level| leaf_count & (1 << level) != 0)
+        .map(|level| (level, (leaf_count >> level) - 1))
+        .collect()
+}
+
+/// Returns the addresses of the nodes created by appending leaf `leaf_index`
+/// (above the leaf itself), along with the addresses of the two children each
+/// is hashed from, in the order they must be computed.
+pub fn parents(leaf_index: u64) -> Vec<(Address, Address, Address)> {
+    let mut parents = vec![];
+    let mut index = leaf_index;
+    let mut level = 0;
+    while index & 1 == 1 {
+        parents.push(((level + 1, index >> 1), (level, index - 1), (level, index)));
+        index >>= 1;
+        level += 1;
+    }
+    parents
+}
+
+/// Returns the mountain containing leaf `leaf_index` in a mountain range with
+/// `leaf_count` leaves, as its position in `peaks(leaf_count)` and its level.
+fn mountain(leaf_index: u64, leaf_count: u64) -> Option<(usize, u8)> {
+    let mut start = 0;
+    for (i, (level, _)) in peaks(leaf_count).into_iter().enumerate() {
+        start += 1 << level;
+        if leaf_index < start {
+            return Some((i, level));
+        }
+    }
+    None
+}
+
+/// Returns the addresses of the siblings on the path from leaf `leaf_index`
+/// to the peak of its mountain, from the bottom up.
+pub fn sibling_path(leaf_index: u64, leaf_count: u64) -> Result<Vec<Address>> {
+    let (_, height) = mountain(leaf_index, leaf_count)
+        .ok_or_else(|| Error::IndexOutOfBounds(format!("Leaf {} is not in range", leaf_index)))?;
+    Ok((0..height)
+        .map(|level| (level, (leaf_index >> level) ^ 1))
+        .collect())
+}
+
+/// Proves that a root hash was the state of a store at a given height, against
+/// the commitment to a mountain range of `leaf_count` leaves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RootProof {
+    /// The height the root hash is claimed to be the state at.
+    pub height: u64,
+    /// The number of leaves in the mountain range, which is the latest height.
+    pub leaf_count: u64,
+    /// The siblings on the path from the leaf to its peak, from the bottom up.
+    pub siblings: Vec<Hash>,
+    /// The peaks of the mountain range.
+    pub peaks: Vec<Hash>,
+}
+
+impl RootProof {
+    /// Verifies that `root_hash` was the state at `self.height`, given the
+    /// latest `commitment`.
+    pub fn verify(&self, root_hash: &Hash, expected_commitment: &Hash) -> Result<()> {
+        let leaf_index = self
+            .height
+            .checked_sub(1)
+            .ok_or_else(|| Error::Proof("Height must be at least 1".into()))?;
+        let (peak_index, level) = mountain(leaf_index, self.leaf_count)
+            .ok_or_else(|| Error::Proof("Height is after the latest height".into()))?;
+        if self.siblings.len() != level as usize || self.peaks.len() != peaks(self.leaf_count).len()
+        {
+            return Err(Error::Proof("Proof has the wrong shape".into()));
+        }
+
+        let mut hash = *root_hash;
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            hash = if (leaf_index >> level) & 1 == 0 {
+                parent_hash(&hash, sibling)
+            } else {
+                parent_hash(sibling, &hash)
+            };
+        }
+        if hash != self.peaks[peak_index] {
+            return Err(Error::Proof("Root hash does not match its peak".into()));
+        }
+
+        let actual = commitment(self.leaf_count, &self.peaks);
+        if actual != *expected_commitment {
+            return Err(Error::HashMismatch(*expected_commitment, actual));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Builds a mountain range over `leaves`, returning all of its nodes.
+    fn build(leaves: &[Hash]) -> HashMap<Address, Hash> {
+        let mut nodes = HashMap::new();
+        for (i, leaf) in leaves.iter().enumerate() {
+            nodes.insert((0, i as u64), *leaf);
+            for (parent, left, right) in parents(i as u64) {
+                let hash = parent_hash(&nodes[&left], &nodes[&right]);
+                nodes.insert(parent, hash);
+            }
+        }
+        nodes
+    }
+
+    #[test]
+    fn peak_addresses() {
+        assert_eq!(peaks(0), vec![]);
+        assert_eq!(peaks(1), vec![(0, 0)]);
+        assert_eq!(peaks(6), vec![(2, 0), (1, 2)]);
+        assert_eq!(peaks(7), vec![(2, 0), (1, 2), (0, 6)]);
+        assert_eq!(
+            parents(3),
+            vec![((1, 1), (0, 2), (0, 3)), ((2, 0), (1, 0), (1, 1))]
+        );
+    }
+
+    #[test]
+    fn prove_every_height() {
+        let leaves: Vec<Hash> = (0..13u8).map(|i| [i; 32]).collect();
+        let nodes = build(&leaves);
+        let leaf_count = leaves.len() as u64;
+        let peaks: Vec<_> = peaks(leaf_count).iter().map(|addr| nodes[addr]).collect();
+        let latest = commitment(leaf_count, &peaks);
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let siblings = sibling_path(i as u64, leaf_count)
+                .unwrap()
+                .iter()
+                .map(|addr| nodes[addr])
+                .collect();
+            let proof = RootProof {
+                height: i as u64 + 1,
+                leaf_count,
+                siblings,
+                peaks: peaks.clone(),
+            };
+            proof.verify(leaf, &latest).unwrap();
+            assert!(proof.verify(&[0xff; 32], &latest).is_err());
+        }
+
+        assert!(sibling_path(13, leaf_count).is_err());
+    }
+}
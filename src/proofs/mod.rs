@@ -1,16 +1,26 @@
+pub mod arena;
 pub mod chunk;
 pub mod encoding;
+pub mod mmr;
 pub mod query;
+pub mod range_sync;
 pub mod tree;
 
 use crate::tree::Hash;
 
-pub use encoding::{encode_into, Decoder};
+/// The greatest height of a tree built while executing a proof. Merk trees
+/// store their children's heights as `u8`s, so no valid proof exceeds this,
+/// and rejecting taller trees bounds the recursion when walking or dropping
+/// trees built from untrusted proofs.
+pub const MAX_TREE_HEIGHT: usize = u8::MAX as usize;
+
+pub use encoding::{encode_into, encoded_len, Decoder};
 pub use query::Query;
 pub use tree::Tree;
 
 /// A proof operator, executed to verify the data in a Merkle proof.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Op {
     /// Pushes a node on the stack.
     Push(Node),
@@ -29,6 +39,7 @@ pub enum Op {
 /// A selected piece of data about a single tree node, to be contained in a
 /// `Push` operator in a proof.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Node {
     /// Represents the hash of a tree node.
     Hash(Hash),
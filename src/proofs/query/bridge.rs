@@ -0,0 +1,88 @@
+//! Bridge proofs, which prove entries of a child tree whose root hash is
+//! stored as a value in a parent tree (e.g. one shared store per module, with
+//! their root hashes committed to a top-level store), so a client holding only
+//! the parent's root hash can check them with one call.
+//!
+//! A bridge proof is not a single op stream but two proofs in a versioned
+//! envelope. Version 1, the only version, is laid out as:
+//!
+//! - the version byte, `1`
+//! - the length of the parent proof, as a 4-byte big-endian integer
+//! - the proof of the child's key against the parent root
+//! - the proof of the queried entries against the child root
+//!
+//! Verifiers reject other versions, so the layout can change later without
+//! old verifiers misreading new proofs.
+
+use std::convert::TryInto;
+
+use super::{verify, Map};
+use crate::error::{Error, Result};
+use crate::tree::Hash;
+
+/// The version of the bridge proof format created by `encode_bridge_proof`.
+const BRIDGE_PROOF_VERSION: u8 = 1;
+
+/// Encodes the proof of a child root in its parent tree and a proof against
+/// that child root as a bridge proof.
+#[cfg(feature = "full")]
+pub(crate) fn encode_bridge_proof(parent: &[u8], child: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(5 + parent.len() + child.len());
+    bytes.push(BRIDGE_PROOF_VERSION);
+    bytes.extend_from_slice(&(parent.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(parent);
+    bytes.extend_from_slice(child);
+    bytes
+}
+
+/// Verifies a bridge proof created by `Merk::prove_bridge` against the root
+/// hash of the parent tree, returning the child root stored at `child_key`
+/// and the map of the entries proven in the child tree. Returns an error if
+/// the proof is not in a known version of the format, or does not show a
+/// 32-byte root hash stored at `child_key`.
+pub fn verify_bridge(bytes: &[u8], root_hash: Hash, child_key: &[u8]) -> Result<(Hash, Map)> {
+    let malformed = || Error::Proof("Malformed bridge proof".into());
+    if bytes.len() < 5 {
+        return Err(malformed());
+    }
+    if bytes[0] != BRIDGE_PROOF_VERSION {
+        return Err(Error::Proof(format!(
+            "Unsupported bridge proof version {}",
+            bytes[0]
+        )));
+    }
+    let parent_len = u32::from_be_bytes(bytes[1..5].try_into().unwrap()) as usize;
+    let bytes = &bytes[5..];
+    if parent_len > bytes.len() {
+        return Err(malformed());
+    }
+    let (parent, child) = bytes.split_at(parent_len);
+
+    let child_root: Hash = verify(parent, root_hash)?
+        .get(child_key)?
+        .ok_or_else(|| Error::Proof("Child root is not in the parent tree".into()))?
+        .try_into()
+        .map_err(|_| Error::Proof("Child root is not a hash".into()))?;
+    let map = verify(child, child_root)?;
+    Ok((child_root, map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::NULL_HASH;
+
+    #[test]
+    fn malformed_bridge_proofs() {
+        assert!(verify_bridge(&[1, 0, 0], NULL_HASH, &[1]).is_err());
+        assert!(verify_bridge(&[1, 0, 0, 0, 1], NULL_HASH, &[1]).is_err());
+
+        // the empty parent tree has no child roots
+        assert!(verify_bridge(&[1, 0, 0, 0, 0], NULL_HASH, &[1]).is_err());
+
+        match verify_bridge(&[2, 0, 0, 0, 0], NULL_HASH, &[1]) {
+            Err(err) => assert!(err.to_string().contains("version")),
+            Ok(_) => panic!("unknown versions should be rejected"),
+        }
+    }
+}
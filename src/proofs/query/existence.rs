@@ -0,0 +1,75 @@
+//! Existence proofs, which prove a key is in the tree with a given value
+//! without including the value, for clients which already have the value
+//! (e.g. from another channel) and only need to check it against a root hash.
+//!
+//! An existence proof is the proof of the key as created by `Merk::prove`,
+//! with the key's node included as the hash of its key/value pair rather than
+//! the pair itself. The node is encoded in 33 bytes rather than the 4 bytes
+//! plus the length of the key and value it takes in a full proof, so the
+//! existence proof is smaller only if the key and value together are longer
+//! than 29 bytes. Since key/value hashes commit to the value bytes directly,
+//! the value is needed to verify the proof.
+
+#[cfg(feature = "full")]
+use std::collections::LinkedList;
+#[cfg(feature = "full")]
+use {super::QueryItem, crate::proofs::Op, crate::tree::Fetch, crate::tree::RefWalker};
+
+use super::super::tree::execute;
+use super::super::{Decoder, Node, MAX_TREE_HEIGHT};
+use crate::error::{Error, Result};
+use crate::tree::{kv_hash, Hash, Hasher};
+
+#[cfg(feature = "full")]
+impl<'a, S> RefWalker<'a, S>
+where
+    S: Fetch + Sized + Send + Clone,
+{
+    /// Generates an existence proof of `key`, or returns `None` if the key is
+    /// not in the tree.
+    pub(crate) fn create_existence_proof(&mut self, key: &[u8]) -> Result<Option<LinkedList<Op>>> {
+        let (mut proof, _) = self.create_proof(&[QueryItem::Key(key.to_vec())])?;
+
+        let mut found = false;
+        for op in proof.iter_mut() {
+            if let Op::Push(Node::KV(node_key, value)) = op {
+                if node_key.as_slice() == key {
+                    let hash = kv_hash::<Hasher>(node_key, value)?;
+                    *op = Op::Push(Node::KVHash(hash));
+                    found = true;
+                }
+            }
+        }
+
+        Ok(if found { Some(proof) } else { None })
+    }
+}
+
+/// Verifies a proof created by `Merk::prove_existence` that `key` is in the
+/// tree with root hash `expected_hash`, with the value `value`.
+///
+/// Returns an error if the proof is invalid, or doesn't include the hash of
+/// the key/value pair.
+pub fn verify_existence(bytes: &[u8], key: &[u8], value: &[u8], expected_hash: Hash) -> Result<()> {
+    let expected_kv_hash = kv_hash::<Hasher>(key, value)?;
+
+    let mut found = false;
+    let root = execute(Decoder::new(bytes), true, MAX_TREE_HEIGHT, |node| {
+        if let Node::KVHash(kv_hash) = node {
+            found |= *kv_hash == expected_kv_hash;
+        }
+        Ok(())
+    })?;
+
+    if root.hash()? != expected_hash {
+        return Err(Error::HashMismatch(expected_hash, root.hash()?));
+    }
+    if !found {
+        return Err(Error::Proof(format!(
+            "Proof does not include key {:?} with the expected value",
+            key
+        )));
+    }
+
+    Ok(())
+}
@@ -83,12 +83,37 @@ impl Map {
     /// yield an error.
     pub fn range<'a, R: RangeBounds<&'a [u8]>>(&'a self, bounds: R) -> Range {
         let start_key = bound_to_inner(bounds.start_bound()).map(|x| (*x).into());
+        let end_bound = bound_to_vec(bounds.end_bound());
         let bounds = bounds_to_vec(bounds);
 
         Range {
             map: self,
             prev_key: start_key.as_ref().cloned(),
             start_key,
+            end_bound,
+            iter: self.entries.range(bounds),
+        }
+    }
+}
+
+impl Map {
+    /// Returns an iterator over all (key, value) entries in the requested range
+    /// of keys, in descending key order. As with `range`, if during iteration
+    /// we encounter a gap in the data, the iterator will yield an error.
+    pub fn range_rev<'a, R: RangeBounds<&'a [u8]>>(&'a self, bounds: R) -> RangeRev {
+        let start_key = match bounds.start_bound() {
+            Bound::Included(key) => Some(key.to_vec()),
+            _ => None,
+        };
+        let end_bound = bound_to_vec(bounds.end_bound());
+        let bounds = bounds_to_vec(bounds);
+
+        RangeRev {
+            map: self,
+            start_key,
+            end_bound,
+            checked_end: false,
+            prev: None,
             iter: self.entries.range(bounds),
         }
     }
@@ -124,6 +149,7 @@ fn bounds_to_vec<'a, R: RangeBounds<&'a [u8]>>(bounds: R) -> impl RangeBounds<Ve
 pub struct Range<'a> {
     map: &'a Map,
     start_key: Option<Vec<u8>>,
+    end_bound: Bound<Vec<u8>>,
     iter: btree_map::Range<'a, Vec<u8>, (bool, Vec<u8>)>,
     prev_key: Option<Vec<u8>>,
 }
@@ -132,6 +158,12 @@ impl<'a> Range<'a> {
     /// Returns an error if the proof does not properly prove the end of the
     /// range.
     fn check_end_bound(&self) -> Result<()> {
+        if let Bound::Included(key) = &self.end_bound {
+            if self.map.entries.contains_key(key) {
+                return Ok(());
+            }
+        }
+
         let excluded_data = match self.prev_key {
             // unbounded end, ensure proof has not excluded data at global right
             // edge of tree
@@ -200,6 +232,84 @@ impl<'a> Iterator for Range<'a> {
     }
 }
 
+/// An iterator over (key, value) entries as extracted from a verified proof, in
+/// descending key order. If during iteration we encounter a gap in the data,
+/// the iterator will yield an error.
+pub struct RangeRev<'a> {
+    map: &'a Map,
+    /// The lower bound of the range, if it is inclusive.
+    start_key: Option<Vec<u8>>,
+    end_bound: Bound<Vec<u8>>,
+    checked_end: bool,
+    /// The key and contiguity of the last entry yielded.
+    prev: Option<(&'a [u8], bool)>,
+    iter: btree_map::Range<'a, Vec<u8>, (bool, Vec<u8>)>,
+}
+
+impl<'a> RangeRev<'a> {
+    /// Returns an error if the proof does not properly prove the upper end of
+    /// the range, where iteration starts.
+    fn check_end_bound(&self) -> Result<()> {
+        let next_node = match &self.end_bound {
+            Bound::Unbounded => None,
+            Bound::Included(key) if self.map.entries.contains_key(key) => return Ok(()),
+            Bound::Included(key) => self
+                .map
+                .entries
+                .range((Bound::Excluded(key.to_vec()), Bound::Unbounded))
+                .next(),
+            Bound::Excluded(key) => self
+                .map
+                .entries
+                .range((Bound::Included(key.to_vec()), Bound::Unbounded))
+                .next(),
+        };
+
+        let excluded_data = match next_node {
+            // reached global right edge of tree
+            None => !self.map.right_edge,
+
+            // got the node after the range, must be contiguous
+            Some((_, (contiguous, _))) => !contiguous,
+        };
+
+        if excluded_data {
+            return Err(Error::MissingData);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for RangeRev<'a> {
+    type Item = Result<(&'a [u8], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.checked_end {
+            self.checked_end = true;
+            if let Err(err) = self.check_end_bound() {
+                return Some(Err(err));
+            }
+        }
+
+        let next = self.iter.next_back();
+
+        // the previous entry must be contiguous with this one, or with the
+        // node before the range, unless it is an exact match for the lower
+        // bound
+        if let Some((prev_key, contiguous)) = self.prev.take() {
+            let exact_start = next.is_none() && self.start_key.as_deref() == Some(prev_key);
+            if !contiguous && !exact_start {
+                return Some(Err(Error::MissingData));
+            }
+        }
+
+        let (key, (contiguous, value)) = next?;
+        self.prev = Some((key.as_slice(), *contiguous));
+        Some(Ok((key.as_slice(), value.as_slice())))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -316,6 +426,25 @@ mod tests {
         assert!(range.next().is_none());
     }
 
+    #[test]
+    fn range_inclusive_end() {
+        let mut builder = MapBuilder::new();
+        builder.insert(&Node::KV(vec![1, 2, 3], vec![1])).unwrap();
+        builder.insert(&Node::KV(vec![1, 2, 4], vec![2])).unwrap();
+        builder.insert(&Node::Hash([0; HASH_LENGTH])).unwrap();
+
+        let map = builder.build();
+        let mut range = map.range(&[1u8, 2, 3][..]..=&[1u8, 2, 4][..]);
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 3][..], &[1][..]));
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 4][..], &[2][..]));
+        assert!(range.next().is_none());
+
+        let mut range = map.range(&[1u8, 2, 3][..]..=&[1u8, 2, 5][..]);
+        range.next().unwrap().unwrap();
+        range.next().unwrap().unwrap();
+        assert!(matches!(range.next(), Some(Err(Error::MissingData))));
+    }
+
     #[test]
     #[should_panic(expected = "MissingData")]
     fn range_lower_unbounded_map_non_contiguous() {
@@ -330,4 +459,29 @@ mod tests {
         range.next().unwrap().unwrap();
         assert_eq!(range.next().unwrap().unwrap(), (&[1][..], &[1][..]));
     }
+
+    #[test]
+    fn range_rev() {
+        let mut builder = MapBuilder::new();
+        builder.insert(&Node::Hash([0; HASH_LENGTH])).unwrap();
+        builder.insert(&Node::KV(vec![1, 2, 3], vec![1])).unwrap();
+        builder.insert(&Node::KV(vec![1, 2, 4], vec![2])).unwrap();
+        builder.insert(&Node::KV(vec![1, 2, 5], vec![3])).unwrap();
+        builder.insert(&Node::Hash([0; HASH_LENGTH])).unwrap();
+
+        let map = builder.build();
+        let mut range = map.range_rev(&[1u8, 2, 3][..]..&[1u8, 2, 5][..]);
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 4][..], &[2][..]));
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 3][..], &[1][..]));
+        assert!(range.next().is_none());
+
+        let mut range = map.range_rev(&[1u8, 2, 4][..]..);
+        assert!(range.next().unwrap().is_err());
+
+        let mut range = map.range_rev(&[1u8, 2][..]..=&[1u8, 2, 5][..]);
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 5][..], &[3][..]));
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 4][..], &[2][..]));
+        assert_eq!(range.next().unwrap().unwrap(), (&[1, 2, 3][..], &[1][..]));
+        assert!(range.next().unwrap().is_err());
+    }
 }
@@ -1,17 +1,32 @@
+mod bridge;
+mod existence;
 mod map;
+mod select;
+mod update;
 
 #[cfg(feature = "full")]
 use {super::Op, std::collections::LinkedList};
 
 use super::tree::execute;
-use super::{Decoder, Node};
+use super::{Decoder, Node, MAX_TREE_HEIGHT};
 use crate::error::{Error, Result};
-use crate::tree::{Fetch, Hash, Link, RefWalker};
+use crate::limits::Limits;
+use crate::tree::{Fetch, Hash, Link, RefWalker, NULL_HASH};
 use std::cmp::{max, min, Ordering};
 use std::collections::BTreeSet;
 use std::ops::{Range, RangeInclusive};
 
+#[cfg(feature = "full")]
+pub(crate) use bridge::encode_bridge_proof;
+pub use bridge::verify_bridge;
+pub use existence::verify_existence;
 pub use map::*;
+#[cfg(feature = "full")]
+pub(crate) use select::{nth_key_select, rank_select, KEY_UPPER_BOUND};
+pub use select::{verify_nth_key, verify_rank, verify_select, Select};
+#[cfg(feature = "full")]
+pub(crate) use update::encode_update_proof;
+pub use update::verify_update;
 
 /// `Query` represents one or more keys or ranges of keys, which can be used to
 /// resolve a proof which will include all of the requested values.
@@ -229,9 +244,12 @@ impl<'a, S> RefWalker<'a, S>
 where
     S: Fetch + Sized + Send + Clone,
 {
-    /// Creates a `Node::KV` from the key/value pair of the root node.
-    pub(crate) fn to_kv_node(&self) -> Node {
-        Node::KV(self.tree().key().to_vec(), self.tree().value().to_vec())
+    /// Creates a `Node::KV` from the key/value pair of the root node. Returns
+    /// an error if the value is too long to be encoded in a proof.
+    pub(crate) fn to_kv_node(&self) -> Result<Node> {
+        let (key, value) = (self.tree().key(), self.tree().value());
+        Limits::check_provable(key, value)?;
+        Ok(Node::KV(key.to_vec(), value.to_vec()))
     }
 
     /// Creates a `Node::KVHash` from the hash of the key/value pair of the root
@@ -291,10 +309,10 @@ where
         let (has_left, has_right) = (!proof.is_empty(), !right_proof.is_empty());
 
         proof.push_back(match search {
-            Ok(_) => Op::Push(self.to_kv_node()),
+            Ok(_) => Op::Push(self.to_kv_node()?),
             Err(_) => {
                 if left_absence.1 || right_absence.0 {
-                    Op::Push(self.to_kv_node())
+                    Op::Push(self.to_kv_node()?)
                 } else {
                     Op::Push(self.to_kvhash_node())
                 }
@@ -338,10 +356,27 @@ where
 }
 
 pub fn verify(bytes: &[u8], expected_hash: Hash) -> Result<Map> {
-    let ops = Decoder::new(bytes);
+    verify_with_limits(bytes, expected_hash, Limits::default())
+}
+
+/// Verifies a proof as in `verify`, returning an error if it exceeds the proof
+/// size, key, value or tree height limits of `limits`.
+pub fn verify_with_limits(bytes: &[u8], expected_hash: Hash, limits: Limits) -> Result<Map> {
     let mut map_builder = MapBuilder::new();
 
-    let root = execute(ops, true, |node| map_builder.insert(node))?;
+    // an empty proof proves the tree is empty, so every key is absent
+    if bytes.is_empty() {
+        if expected_hash != NULL_HASH {
+            return Err(Error::HashMismatch(expected_hash, NULL_HASH));
+        }
+        return Ok(map_builder.build());
+    }
+
+    let ops = Decoder::with_limits(bytes, limits);
+
+    let root = execute(ops, true, limits.max_tree_height, |node| {
+        map_builder.insert(node)
+    })?;
 
     if root.hash()? != expected_hash {
         return Err(Error::HashMismatch(expected_hash, root.hash()?));
@@ -373,7 +408,7 @@ pub fn verify_query(
 
     let ops = Decoder::new(bytes);
 
-    let root = execute(ops, true, |node| {
+    let root = execute(ops, true, MAX_TREE_HEIGHT, |node| {
         if let Node::KV(key, value) = node {
             while let Some(item) = query.peek() {
                 // get next item in query
@@ -1477,4 +1512,30 @@ mod test {
 
         let _result = verify_query(bytes.as_slice(), &query, [42; 32]).expect("verify failed");
     }
+
+    #[test]
+    fn verify_with_limits_rejects_large_proofs() {
+        let mut tree = make_tree_seq(10);
+        let expected_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_proof(vec![QueryItem::Range(vec![]..vec![255])].as_slice())
+            .unwrap();
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let map = verify_with_limits(&bytes, expected_hash, Limits::default()).unwrap();
+        assert_eq!(map.range(..).count(), 11);
+
+        let limits = Limits {
+            max_tree_height: 2,
+            ..Default::default()
+        };
+        assert!(verify_with_limits(&bytes, expected_hash, limits).is_err());
+        let limits = Limits {
+            max_proof_ops: 3,
+            ..Default::default()
+        };
+        assert!(verify_with_limits(&bytes, expected_hash, limits).is_err());
+    }
 }
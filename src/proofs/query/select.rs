@@ -0,0 +1,236 @@
+use std::convert::TryFrom;
+use std::ops::{Bound, Range, RangeInclusive};
+
+use super::{verify, Query, QueryItem};
+use crate::error::Result;
+use crate::tree::Hash;
+
+/// A key which sorts after every valid key, since keys are at most 255 bytes.
+pub(crate) const KEY_UPPER_BOUND: [u8; 256] = [255; 256];
+
+/// `Select` describes a read of keys, ranges and prefixes of keys, optionally
+/// limited to a number of entries taken in ascending or descending key order.
+///
+/// A `Select` can be executed against a store with `Merk::select`, or proven
+/// with `Merk::prove_select` and checked by a client with `verify_select`,
+/// which returns the same entries.
+///
+/// # Example
+/// ```
+/// use merk::proofs::query::Select;
+///
+/// let select = Select::new()
+///     .prefix(b"accounts/".to_vec())
+///     .key(b"config".to_vec())
+///     .limit(10)
+///     .descending();
+/// ```
+#[derive(Default)]
+pub struct Select {
+    query: Query,
+    limit: Option<usize>,
+    descending: bool,
+}
+
+impl Select {
+    /// Creates a new select which contains no keys.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds an individual key.
+    pub fn key(mut self, key: Vec<u8>) -> Self {
+        self.query.insert_item(QueryItem::Key(key));
+        self
+    }
+
+    /// Adds a range of keys.
+    pub fn range(mut self, range: Range<Vec<u8>>) -> Self {
+        self.query.insert_range(range);
+        self
+    }
+
+    /// Adds an inclusive range of keys.
+    pub fn range_inclusive(mut self, range: RangeInclusive<Vec<u8>>) -> Self {
+        self.query.insert_range_inclusive(range);
+        self
+    }
+
+    /// Adds all of the keys which start with `prefix`.
+    pub fn prefix(mut self, prefix: Vec<u8>) -> Self {
+        let end = prefix_end(&prefix);
+        self.query.insert_range(prefix..end);
+        self
+    }
+
+    /// Limits the number of entries read, taking them in key order (or in
+    /// reverse key order if `descending` is set).
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Reads entries in descending key order.
+    pub fn descending(mut self) -> Self {
+        self.descending = true;
+        self
+    }
+
+    /// Returns true if entries are read in descending key order.
+    pub fn is_descending(&self) -> bool {
+        self.descending
+    }
+
+    /// Reads the selected entries in order, where `read` is called with each
+    /// item (in the select's direction) and the maximum number of entries to
+    /// read from it.
+    pub(crate) fn resolve<F>(&self, mut read: F) -> Result<Vec<(Vec<u8>, Vec<u8>)>>
+    where
+        F: FnMut(&QueryItem, usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>>,
+    {
+        let limit = self.limit.unwrap_or(usize::MAX);
+        let mut entries = vec![];
+        for item in self.items() {
+            if entries.len() >= limit {
+                break;
+            }
+            entries.extend(read(item, limit - entries.len())?);
+        }
+        Ok(entries)
+    }
+
+    /// Returns the query items to prove for the select, given the entries it
+    /// read. If the limit was reached, the items after the last entry are
+    /// trimmed, so the proof only includes the data needed to verify the
+    /// entries which were read.
+    #[cfg(feature = "full")]
+    pub(crate) fn proof_items(&self, entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<QueryItem> {
+        let last_key = match (self.limit, entries.last()) {
+            (Some(limit), Some((key, _))) if entries.len() >= limit => key.as_slice(),
+            _ => return self.query.iter().cloned().collect(),
+        };
+
+        let mut items = vec![];
+        for item in self.items() {
+            if !item.contains(last_key) {
+                items.push(item.clone());
+                continue;
+            }
+
+            let trimmed = match (item, self.descending) {
+                (QueryItem::Key(_), _) => item.clone(),
+                (_, false) => {
+                    QueryItem::RangeInclusive(item.lower_bound().to_vec()..=last_key.to_vec())
+                }
+                (_, true) => match item.upper_bound() {
+                    (end, true) => QueryItem::RangeInclusive(last_key.to_vec()..=end.to_vec()),
+                    (end, false) => QueryItem::Range(last_key.to_vec()..end.to_vec()),
+                },
+            };
+            items.push(trimmed);
+            break;
+        }
+        items
+    }
+
+    fn items(&self) -> Box<dyn Iterator<Item = &QueryItem> + '_> {
+        if self.descending {
+            Box::new(self.query.iter().collect::<Vec<_>>().into_iter().rev())
+        } else {
+            Box::new(self.query.iter())
+        }
+    }
+}
+
+/// Returns the bounds of the keys in `item`.
+fn item_bounds(item: &QueryItem) -> (Bound<&[u8]>, Bound<&[u8]>) {
+    let end = match item.upper_bound() {
+        (end, true) => Bound::Included(end),
+        (end, false) => Bound::Excluded(end),
+    };
+    (Bound::Included(item.lower_bound()), end)
+}
+
+/// Returns the first key after all of the keys starting with `prefix`.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(byte) = end.pop() {
+        if byte < 255 {
+            end.push(byte + 1);
+            return end;
+        }
+    }
+    KEY_UPPER_BOUND.to_vec()
+}
+
+/// Verifies a proof created by `Merk::prove_select` against the expected root
+/// hash, returning the entries read by `select`.
+///
+/// Returns `Err` if the proof is invalid or does not include all of the
+/// entries `select` would read from the tree.
+pub fn verify_select(
+    bytes: &[u8],
+    select: &Select,
+    expected_hash: Hash,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let map = verify(bytes, expected_hash)?;
+
+    select.resolve(|item, limit| {
+        let bounds = item_bounds(item);
+        let to_owned = |entry: Result<(&[u8], &[u8])>| {
+            entry.map(|(key, value)| (key.to_vec(), value.to_vec()))
+        };
+        if select.is_descending() {
+            map.range_rev(bounds).take(limit).map(to_owned).collect()
+        } else {
+            map.range(bounds).take(limit).map(to_owned).collect()
+        }
+    })
+}
+
+/// Returns the select read to find (and prove) the key at index `n` among
+/// the keys starting with `prefix`: every key up to and including it.
+pub(crate) fn nth_key_select(prefix: &[u8], n: u64) -> Select {
+    let limit = usize::try_from(n).unwrap_or(usize::MAX).saturating_add(1);
+    Select::new().prefix(prefix.to_vec()).limit(limit)
+}
+
+/// Returns the select read to prove the rank of `key`: every key before it.
+pub(crate) fn rank_select(key: &[u8]) -> Select {
+    Select::new().range(vec![]..key.to_vec())
+}
+
+/// Verifies a proof created by `Merk::prove_nth_key` against the expected
+/// root hash, returning the key at index `n` among the keys starting with
+/// `prefix`, or `None` if the proof shows there are not more than `n` of them.
+pub fn verify_nth_key(
+    bytes: &[u8],
+    prefix: &[u8],
+    n: u64,
+    expected_hash: Hash,
+) -> Result<Option<Vec<u8>>> {
+    let entries = verify_select(bytes, &nth_key_select(prefix, n), expected_hash)?;
+    let index = usize::try_from(n).unwrap_or(usize::MAX);
+    Ok(entries.into_iter().nth(index).map(|(key, _)| key))
+}
+
+/// Verifies a proof created by `Merk::prove_rank` against the expected root
+/// hash, returning the number of keys in the tree less than `key`. The proof
+/// holds each of those keys, so verifying it takes time linear in the rank.
+pub fn verify_rank(bytes: &[u8], key: &[u8], expected_hash: Hash) -> Result<u64> {
+    let entries = verify_select(bytes, &rank_select(key), expected_hash)?;
+    Ok(entries.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_bounds() {
+        assert_eq!(prefix_end(&[1, 2]), vec![1, 3]);
+        assert_eq!(prefix_end(&[1, 255]), vec![2]);
+        assert_eq!(prefix_end(&[255, 255]), KEY_UPPER_BOUND.to_vec());
+        assert_eq!(prefix_end(&[]), KEY_UPPER_BOUND.to_vec());
+    }
+}
@@ -0,0 +1,71 @@
+//! Update proofs, which show how the value of a key changed between two
+//! versions of a tree (e.g. before and after a batch), so bridges and fraud
+//! proof systems can check individual state transitions.
+//!
+//! An update proof is the proof of the key against the old root, followed by
+//! its proof against the new root, with the length of the first as a 4-byte
+//! big-endian prefix.
+
+use std::convert::TryInto;
+
+use super::verify;
+use crate::error::{Error, Result};
+use crate::tree::Hash;
+
+/// Encodes the proofs of a key against the old and new roots as an update
+/// proof.
+#[cfg(feature = "full")]
+pub(crate) fn encode_update_proof(before: &[u8], after: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + before.len() + after.len());
+    bytes.extend_from_slice(&(before.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(before);
+    bytes.extend_from_slice(after);
+    bytes
+}
+
+/// Verifies an update proof created by `Merk::apply_and_prove_update` or
+/// `VersionedMerk::prove_update` against the old and new root hashes,
+/// returning the value of `key` before and after the update. A value of
+/// `None` means the key was proven absent, so an insertion returns
+/// `(None, Some(value))` and a deletion `(Some(value), None)`.
+pub fn verify_update(
+    bytes: &[u8],
+    key: &[u8],
+    old_root: Hash,
+    new_root: Hash,
+) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+    let malformed = || Error::Proof("Malformed update proof".into());
+    if bytes.len() < 4 {
+        return Err(malformed());
+    }
+    let before_len = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+    let bytes = &bytes[4..];
+    if before_len > bytes.len() {
+        return Err(malformed());
+    }
+    let (before, after) = bytes.split_at(before_len);
+
+    let old = verify(before, old_root)?.get(key)?.map(<[u8]>::to_vec);
+    let new = verify(after, new_root)?.get(key)?.map(<[u8]>::to_vec);
+    Ok((old, new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::NULL_HASH;
+
+    #[test]
+    fn malformed_update_proofs() {
+        assert!(verify_update(&[0, 0], &[1], NULL_HASH, NULL_HASH).is_err());
+        assert!(verify_update(&[0, 0, 0, 1], &[1], NULL_HASH, NULL_HASH).is_err());
+
+        // empty proofs of the empty tree
+        let proof = [0, 0, 0, 0];
+        assert_eq!(
+            verify_update(&proof, &[1], NULL_HASH, NULL_HASH).unwrap(),
+            (None, None)
+        );
+        assert!(verify_update(&proof, &[1], NULL_HASH, [1; 32]).is_err());
+    }
+}
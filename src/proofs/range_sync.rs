@@ -0,0 +1,154 @@
+//! Partial state sync of a key range, for nodes which only track part of the
+//! state (e.g. the keys under one shard's prefix).
+//!
+//! The entries of the range are split into chunks of consecutive entries.
+//! Each chunk is a range proof of its part of the range, so its path to the
+//! root bridges it to the root hash of the whole tree, and the chunks can be
+//! checked against that root hash without syncing the rest of the tree. A
+//! chunk is encoded as the end of its part of the range, followed by the
+//! proof:
+//!
+//! ```text
+//! chunk := 0x00 proof                  (ends at the end of the synced range)
+//!        | 0x01 key_len (u8) key proof (ends before key)
+//! ```
+//!
+//! Each chunk's part of the range starts where the previous one ended, so
+//! processing the chunks in order proves every entry of the range.
+
+use std::ops::Bound;
+
+use super::query::verify_with_limits;
+use crate::error::{Error, Result};
+use crate::limits::Limits;
+use crate::tree::Hash;
+
+const END_OF_RANGE: u8 = 0;
+const END_KEY: u8 = 1;
+
+/// Appends the encoding of a chunk ending before `end` (or at the end of the
+/// synced range if `None`) with the given proof to `output`.
+#[cfg(feature = "full")]
+pub(crate) fn encode_range_chunk(end: Option<&[u8]>, proof: &[u8], output: &mut Vec<u8>) {
+    match end {
+        None => output.push(END_OF_RANGE),
+        Some(key) => {
+            output.push(END_KEY);
+            output.push(key.len() as u8);
+            output.extend_from_slice(key);
+        }
+    }
+    output.extend_from_slice(proof);
+}
+
+/// Verifies the chunks created by `Merk::range_chunks` for a key range against
+/// the root hash of the whole tree, returning the entries each one proves.
+pub struct RangeSyncVerifier {
+    root_hash: Hash,
+    /// The start of the part of the range the next chunk must prove, or `None`
+    /// once the whole range is proven.
+    cursor: Option<Vec<u8>>,
+    end: Option<Vec<u8>>,
+    limits: Limits,
+}
+
+impl RangeSyncVerifier {
+    /// Creates a verifier for the chunks of the entries with keys from `start`
+    /// up to (but not including) `end`, or to the last key in the tree if
+    /// `end` is `None`, of the tree with root hash `root_hash`.
+    pub fn new(root_hash: Hash, start: &[u8], end: Option<&[u8]>) -> Self {
+        RangeSyncVerifier {
+            root_hash,
+            cursor: Some(start.to_vec()),
+            end: end.map(<[u8]>::to_vec),
+            limits: Limits::default(),
+        }
+    }
+
+    /// Sets the limits chunks are verified with.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Verifies the next chunk, returning the entries of its part of the
+    /// range in key order. Chunks must be passed in order, and a chunk which
+    /// fails verification can be retried.
+    pub fn process_chunk(&mut self, bytes: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let start = match self.cursor.as_ref() {
+            Some(start) => start,
+            None => {
+                return Err(Error::ChunkProcessing(
+                    "Received a chunk after the end of the range".into(),
+                ))
+            }
+        };
+
+        let (chunk_end, proof) = decode_range_chunk(bytes)?;
+        if let Some(chunk_end) = chunk_end {
+            let in_range = self.end.as_deref().map_or(true, |end| chunk_end < end);
+            if chunk_end <= start.as_slice() || !in_range {
+                return Err(Error::ChunkProcessing(
+                    "Chunk does not end within the remaining range".into(),
+                ));
+            }
+        }
+
+        let end = chunk_end.or(self.end.as_deref());
+        let end_bound = end.map_or(Bound::Unbounded, Bound::Excluded);
+        let map = verify_with_limits(proof, self.root_hash, self.limits)?;
+        let entries = map
+            .range((Bound::Included(start.as_slice()), end_bound))
+            .map(|entry| entry.map(|(key, value)| (key.to_vec(), value.to_vec())))
+            .collect::<Result<_>>()?;
+
+        self.cursor = chunk_end.map(<[u8]>::to_vec);
+        Ok(entries)
+    }
+
+    /// Returns `true` once chunks proving the whole range have been processed.
+    pub fn is_done(&self) -> bool {
+        self.cursor.is_none()
+    }
+}
+
+fn decode_range_chunk(bytes: &[u8]) -> Result<(Option<&[u8]>, &[u8])> {
+    let unexpected_end = || Error::ChunkProcessing("Unexpected end of range chunk".into());
+    match bytes.split_first() {
+        Some((&END_OF_RANGE, proof)) => Ok((None, proof)),
+        Some((&END_KEY, rest)) => {
+            let (&key_len, rest) = rest.split_first().ok_or_else(unexpected_end)?;
+            if rest.len() < key_len as usize {
+                return Err(unexpected_end());
+            }
+            let (key, proof) = rest.split_at(key_len as usize);
+            Ok((Some(key), proof))
+        }
+        Some((byte, _)) => Err(Error::ChunkProcessing(format!(
+            "Unexpected range chunk tag: {}",
+            byte
+        ))),
+        None => Err(unexpected_end()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_range_chunks() {
+        let mut verifier = RangeSyncVerifier::new([1; 32], &[5], Some(&[10]));
+        assert!(verifier.process_chunk(&[]).is_err());
+        assert!(verifier.process_chunk(&[2]).is_err());
+        assert!(verifier.process_chunk(&[1, 3, 6]).is_err());
+
+        // chunks must end within the remaining range
+        assert!(verifier.process_chunk(&[1, 1, 5]).is_err());
+        assert!(verifier.process_chunk(&[1, 1, 10]).is_err());
+
+        // the proof must match the root hash
+        assert!(verifier.process_chunk(&[0]).is_err());
+        assert!(!verifier.is_done());
+    }
+}
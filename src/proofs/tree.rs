@@ -1,4 +1,4 @@
-use super::{Node, Op};
+use super::{Node, Op, MAX_TREE_HEIGHT};
 use crate::error::{Error, Result};
 use crate::tree::{kv_hash, node_hash, Hash, Hasher, NULL_HASH};
 
@@ -118,6 +118,9 @@ impl Tree {
                 "Tried to attach to left child, but it is already Some".into(),
             ));
         }
+        if child.height >= MAX_TREE_HEIGHT {
+            return Err(Error::Proof("Proof tree is too tall".into()));
+        }
 
         self.height = self.height.max(child.height + 1);
 
@@ -138,8 +141,14 @@ impl Tree {
 
     /// Consumes the tree node, calculates its hash, and returns a `Node::Hash`
     /// variant.
+    ///
+    /// The height is kept, so the heights of collapsed trees are still counted
+    /// against the maximum.
     fn try_into_hash(self) -> Result<Tree> {
-        self.hash().map(Node::Hash).map(Into::into)
+        let height = self.height;
+        let mut tree: Tree = Node::Hash(self.hash()?).into();
+        tree.height = height;
+        Ok(tree)
     }
 
     #[cfg(feature = "full")]
@@ -228,10 +237,17 @@ impl<'a> Iterator for LayerIter<'a> {
 /// `Node::Hash`. If `false`, the returned `Tree` will contain the entire
 /// subtree contained in the proof.
 ///
+/// Returns an error if the tree grows taller than `max_height`.
+///
 /// `visit_node` will be called once for every push operation in the proof, in
 /// key-order. If `visit_node` returns an `Err` result, it will halt the
 /// execution and `execute` will return the error.
-pub(crate) fn execute<I, F>(ops: I, collapse: bool, mut visit_node: F) -> Result<Tree>
+pub(crate) fn execute<I, F>(
+    ops: I,
+    collapse: bool,
+    max_height: usize,
+    mut visit_node: F,
+) -> Result<Tree>
 where
     I: IntoIterator<Item = Result<Op>>,
     F: FnMut(&Node) -> Result<()>,
@@ -246,6 +262,16 @@ where
         }
     }
 
+    fn check_height(tree: &Tree, max_height: usize) -> Result<()> {
+        if tree.height > max_height {
+            return Err(Error::Proof(format!(
+                "Proof tree exceeds the height limit of {}",
+                max_height
+            )));
+        }
+        Ok(())
+    }
+
     for op in ops {
         match op? {
             Op::Parent => {
@@ -258,6 +284,7 @@ where
                         child
                     },
                 )?;
+                check_height(&parent, max_height)?;
                 stack.push(parent);
             }
             Op::Child => {
@@ -270,6 +297,7 @@ where
                         child
                     },
                 )?;
+                check_height(&parent, max_height)?;
                 stack.push(parent);
             }
             Op::Push(node) => {
@@ -0,0 +1,175 @@
+use crate::tree::{Fetch, Tree};
+use crate::{Error, Result};
+use rand::prelude::*;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The failures injected by a `FlakySource`.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    /// The probability that a fetch fails with an error.
+    pub error_rate: f64,
+    /// The probability that a fetched node is corrupted, so its value no
+    /// longer matches its stored hash.
+    pub corruption_rate: f64,
+    /// The time each fetch sleeps before reading from the wrapped source.
+    pub delay: Duration,
+    /// Keys whose fetches always fail.
+    pub fail_keys: HashSet<Vec<u8>>,
+    /// Keys whose nodes are always corrupted.
+    pub corrupt_keys: HashSet<Vec<u8>>,
+    /// The seed for the random failures, so test runs are reproducible.
+    pub seed: u64,
+}
+
+struct State {
+    faults: Faults,
+    rng: Mutex<SmallRng>,
+    errors: AtomicU64,
+    corruptions: AtomicU64,
+}
+
+/// Wraps a `Fetch` source and injects errors, delays and corrupted nodes into
+/// its fetches, for testing how code walking a tree handles storage failures.
+///
+/// Clones share their state, so the counts of injected failures include the
+/// fetches made through every clone (e.g. by the workers of a parallel apply).
+#[derive(Clone)]
+pub struct FlakySource<S> {
+    inner: S,
+    state: Arc<State>,
+}
+
+impl<S: Fetch> FlakySource<S> {
+    /// Wraps `inner`, injecting the given faults.
+    pub fn new(inner: S, faults: Faults) -> Self {
+        let rng = Mutex::new(SmallRng::seed_from_u64(faults.seed));
+        FlakySource {
+            inner,
+            state: Arc::new(State {
+                faults,
+                rng,
+                errors: AtomicU64::new(0),
+                corruptions: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Returns the number of fetches which failed with an injected error.
+    pub fn injected_errors(&self) -> u64 {
+        self.state.errors.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of nodes which were corrupted.
+    pub fn injected_corruptions(&self) -> u64 {
+        self.state.corruptions.load(Ordering::Relaxed)
+    }
+
+    fn roll(&self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        let mut rng = self.state.rng.lock().unwrap();
+        rng.gen_bool(probability.min(1.0))
+    }
+}
+
+impl<S: Fetch> Fetch for FlakySource<S> {
+    fn fetch_by_key(&self, key: &[u8]) -> Result<Option<Tree>> {
+        let faults = &self.state.faults;
+        if !faults.delay.is_zero() {
+            std::thread::sleep(faults.delay);
+        }
+
+        if faults.fail_keys.contains(key) || self.roll(faults.error_rate) {
+            self.state.errors.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::Fetch(format!(
+                "Injected failure fetching key {key:?}"
+            )));
+        }
+
+        let maybe_tree = self.inner.fetch_by_key(key)?;
+        let corrupted = faults.corrupt_keys.contains(key) || self.roll(faults.corruption_rate);
+        match maybe_tree {
+            Some(tree) if corrupted => {
+                self.state.corruptions.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(corrupt(tree)))
+            }
+            maybe_tree => Ok(maybe_tree),
+        }
+    }
+}
+
+/// Flips the last byte of the node's encoding (the end of its value, or of its
+/// KV hash if the value is empty), so the node no longer hashes correctly.
+fn corrupt(tree: Tree) -> Tree {
+    let mut bytes = tree.encode();
+    *bytes.last_mut().unwrap() ^= 1;
+    Tree::decode(tree.key().to_vec(), &bytes).expect("flipped byte is still a valid encoding")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::query::QueryItem;
+    use crate::test_utils::*;
+    use crate::tree::{RefWalker, Walker};
+    use crate::Merk;
+
+    /// Opens a store with 1,000 entries where only the root is in memory.
+    fn reopened(path: &str) -> Merk {
+        let mut merk = Merk::open(path).unwrap();
+        merk.apply(&make_batch_seq(0..1_000), &[]).unwrap();
+        drop(merk);
+        Merk::open(path).unwrap()
+    }
+
+    #[test]
+    fn injected_errors() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let merk = reopened(&path);
+        let source = FlakySource::new(
+            merk.source(),
+            Faults {
+                error_rate: 1.0,
+                ..Default::default()
+            },
+        );
+
+        let tree = merk.tree.take().unwrap();
+        let walker = Walker::new(tree, source.clone());
+        assert!(Walker::apply_to(Some(walker), &make_batch_seq(10..11), source.clone()).is_err());
+        assert_eq!(source.injected_errors(), 1);
+
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn corrupted_nodes_fail_verification() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let merk = reopened(&path);
+        let key = seq_key(100);
+        let source = FlakySource::new(
+            merk.source(),
+            Faults {
+                corrupt_keys: std::iter::once(key.clone()).collect(),
+                ..Default::default()
+            },
+        );
+
+        let mut tree = merk.tree.take().unwrap();
+        let (proof, _) = RefWalker::new(&mut tree, source.clone())
+            .create_proof(&[QueryItem::Key(key)])
+            .unwrap();
+        assert_eq!(source.injected_corruptions(), 1);
+
+        let mut bytes = vec![];
+        crate::proofs::encode_into(proof.iter(), &mut bytes);
+        assert!(crate::verify(&bytes, merk.root_hash()).is_err());
+
+        merk.tree.set(Some(tree));
+        merk.destroy().unwrap();
+    }
+}
@@ -1,4 +1,7 @@
 mod crash_merk;
+mod flaky_source;
+#[cfg(feature = "proptest")]
+pub mod strategies;
 mod temp_merk;
 
 use crate::tree::{Batch, BatchEntry, NoopCommit, Op, PanicSource, Tree, Walker};
@@ -8,6 +11,7 @@ use std::convert::TryInto;
 use std::ops::Range;
 
 pub use crash_merk::CrashMerk;
+pub use flaky_source::{Faults, FlakySource};
 pub use temp_merk::TempMerk;
 
 pub fn assert_tree_invariants(tree: &Tree) {
@@ -0,0 +1,159 @@
+//! `proptest` strategies for generating keys, batches and trees, so code using
+//! merk can be property-tested against arbitrary inputs rather than only the
+//! fixed sequences built by `make_batch_seq` and friends.
+//!
+//! Requires the `proptest` feature.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use proptest::collection::{btree_map, vec};
+use proptest::prelude::*;
+
+use crate::tree::{BatchEntry, NoopCommit, Op, PanicSource, Tree, Walker};
+
+/// The longest key generated by `key`.
+pub const MAX_KEY_LENGTH: usize = 64;
+
+/// The longest value generated by `value`.
+pub const MAX_VALUE_LENGTH: usize = 256;
+
+/// How the keys of generated batches are distributed over the key space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyPattern {
+    /// Arbitrary bytes, of lengths up to `MAX_KEY_LENGTH`.
+    Random,
+    /// Big-endian `u64`s, as created by `seq_key`, starting from an arbitrary
+    /// offset.
+    Sequential,
+    /// Keys sharing one of a few short prefixes, followed by arbitrary bytes,
+    /// as when an application namespaces its keys.
+    Prefixed,
+}
+
+/// Generates one of the `KeyPattern`s.
+pub fn key_pattern() -> impl Strategy<Value = KeyPattern> {
+    prop_oneof![
+        Just(KeyPattern::Random),
+        Just(KeyPattern::Sequential),
+        Just(KeyPattern::Prefixed),
+    ]
+}
+
+/// Generates a non-empty key.
+pub fn key() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 1..=MAX_KEY_LENGTH)
+}
+
+/// Generates a value, which may be empty.
+pub fn value() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..=MAX_VALUE_LENGTH)
+}
+
+/// Generates a put or delete operation.
+pub fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => value().prop_map(Op::Put),
+        // `Op` is not `Clone`, so deletes can not be generated with `Just`
+        1 => Just(()).prop_map(|_| Op::Delete),
+    ]
+}
+
+/// Generates a set of distinct keys following `pattern`, with a number of
+/// keys in `size`.
+pub fn keys(pattern: KeyPattern, size: Range<usize>) -> BoxedStrategy<Vec<Vec<u8>>> {
+    match pattern {
+        KeyPattern::Random => vec(key(), size).boxed(),
+        KeyPattern::Sequential => (any::<u64>(), size)
+            .prop_map(|(start, len)| {
+                (0..len as u64)
+                    .map(|n| start.wrapping_add(n).to_be_bytes().to_vec())
+                    .collect()
+            })
+            .boxed(),
+        KeyPattern::Prefixed => vec(
+            (0..4u8, vec(any::<u8>(), 0..8)).prop_map(|(prefix, suffix)| {
+                let mut key = vec![b'/', prefix];
+                key.extend(suffix);
+                key
+            }),
+            size,
+        )
+        .boxed(),
+    }
+    .prop_map(|mut keys| {
+        keys.sort();
+        keys.dedup();
+        keys
+    })
+    .boxed()
+}
+
+/// Generates a batch of puts and deletes, sorted by key with no duplicate
+/// keys, as required by `Merk::apply`.
+pub fn batch(size: Range<usize>) -> impl Strategy<Value = Vec<BatchEntry>> {
+    btree_map(key(), op(), size).prop_map(into_batch)
+}
+
+/// Generates a batch of puts only, sorted by key with no duplicate keys.
+pub fn put_batch(size: Range<usize>) -> impl Strategy<Value = Vec<BatchEntry>> {
+    btree_map(key(), value().prop_map(Op::Put), size).prop_map(into_batch)
+}
+
+/// Generates a batch of puts to keys following an arbitrary `KeyPattern`.
+pub fn patterned_put_batch(size: Range<usize>) -> impl Strategy<Value = Vec<BatchEntry>> {
+    key_pattern()
+        .prop_flat_map(move |pattern| keys(pattern, size.clone()))
+        .prop_flat_map(|keys| {
+            let len = keys.len();
+            (Just(keys), vec(value(), len))
+        })
+        .prop_map(|(keys, values)| {
+            keys.into_iter()
+                .zip(values)
+                .map(|(key, value)| (key, Op::Put(value)))
+                .collect()
+        })
+}
+
+/// Generates a committed in-memory tree with a number of nodes in `size`,
+/// built by applying a single batch of puts.
+pub fn tree(size: Range<usize>) -> impl Strategy<Value = Tree> {
+    put_batch(size.start.max(1)..size.end.max(2)).prop_map(|batch| {
+        let mut tree = Walker::<PanicSource>::apply_to(None, &batch, PanicSource {})
+            .expect("apply failed")
+            .0
+            .expect("expected tree");
+        tree.commit(&mut NoopCommit {}).expect("commit failed");
+        tree
+    })
+}
+
+fn into_batch(entries: BTreeMap<Vec<u8>, Op>) -> Vec<BatchEntry> {
+    entries.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{apply_memonly, assert_tree_invariants};
+
+    proptest! {
+        #[test]
+        fn batches_are_sorted(batch in batch(0..100)) {
+            prop_assert!(batch.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+
+        #[test]
+        fn patterned_keys_are_sorted(batch in patterned_put_batch(1..100)) {
+            prop_assert!(!batch.is_empty());
+            prop_assert!(batch.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+
+        #[test]
+        fn trees_stay_balanced(tree in tree(1..100), batch in put_batch(0..100)) {
+            assert_tree_invariants(&tree);
+            apply_memonly(tree, &batch);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Writes a tree in the Graphviz DOT format, for visually inspecting balance
+//! and proof structure in small trees.
+
+use std::io::Write;
+
+use super::{Fetch, Link, RefWalker, Tree};
+use crate::error::Result;
+
+/// The number of bytes of each node hash included in node labels.
+const HASH_LABEL_LENGTH: usize = 4;
+
+/// Bounds the part of a tree written by `RefWalker::write_dot`. Children which
+/// are not traversed are drawn as dashed nodes.
+#[derive(Clone, Debug, Default)]
+pub struct DotOptions {
+    /// The maximum depth to traverse, where the root is at depth 0.
+    pub max_depth: Option<usize>,
+    /// If set, only subtrees which can contain keys in `start..end` are
+    /// traversed.
+    pub range: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl DotOptions {
+    fn traverses(&self, tree: &Tree, depth: usize, left: bool) -> bool {
+        if matches!(self.max_depth, Some(max_depth) if depth >= max_depth) {
+            return false;
+        }
+        match &self.range {
+            Some((start, _)) if left => start.as_slice() < tree.key(),
+            Some((_, end)) => end.as_slice() > tree.key(),
+            None => true,
+        }
+    }
+}
+
+impl<'a, S> RefWalker<'a, S>
+where
+    S: Fetch + Sized + Clone + Send,
+{
+    /// Writes the tree as a DOT digraph, labeling each node with its key, the
+    /// beginning of its hash, and its balance factor. Keys are written as hex.
+    ///
+    /// Pruned nodes are fetched from the source as they are traversed.
+    pub fn write_dot<W: Write>(&mut self, out: &mut W, options: &DotOptions) -> Result<()> {
+        writeln!(out, "digraph merk {{")?;
+        writeln!(out, "  node [shape=record, fontname=monospace];")?;
+        self.write_dot_node(out, options, 0)?;
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+
+    fn write_dot_node<W: Write>(
+        &mut self,
+        out: &mut W,
+        options: &DotOptions,
+        depth: usize,
+    ) -> Result<()> {
+        let tree = self.tree();
+        let id = hex::encode(tree.key());
+        writeln!(
+            out,
+            "  \"{}\" [label=\"{{{}|hash {}|balance {}}}\"];",
+            id,
+            id,
+            hex::encode(&tree.hash()[..HASH_LABEL_LENGTH]),
+            tree.balance_factor()
+        )?;
+
+        for left in [true, false] {
+            let tree = self.tree();
+            let link = match tree.link(left) {
+                Some(link) => link,
+                None => continue,
+            };
+            let child_id = hex::encode(link.key());
+            writeln!(
+                out,
+                "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                id,
+                child_id,
+                if left { "L" } else { "R" }
+            )?;
+
+            if options.traverses(tree, depth, left) {
+                let mut child = self.walk(left)?.unwrap();
+                child.write_dot_node(out, options, depth + 1)?;
+            } else {
+                write_dot_link(out, link)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a child which is not traversed as a dashed node.
+fn write_dot_link<W: Write>(out: &mut W, link: &Link) -> Result<()> {
+    let id = hex::encode(link.key());
+    writeln!(
+        out,
+        "  \"{}\" [style=dashed, label=\"{{{}|hash {}|height {}}}\"];",
+        id,
+        id,
+        hex::encode(&link.hash()[..HASH_LABEL_LENGTH]),
+        link.height()
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::tree::PanicSource;
+
+    #[test]
+    fn write_bounded_dot() {
+        let mut tree = apply_to_memonly(None, &make_batch_seq(0..15)).unwrap();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let mut out = vec![];
+        walker.write_dot(&mut out, &DotOptions::default()).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.starts_with("digraph merk {"));
+        assert_eq!(dot.matches(" -> ").count(), 14);
+        assert!(!dot.contains("dashed"));
+
+        let options = DotOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let mut out = vec![];
+        walker.write_dot(&mut out, &options).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert_eq!(dot.matches(" -> ").count(), 6);
+        assert_eq!(dot.matches("dashed").count(), 4);
+
+        let options = DotOptions {
+            range: Some((seq_key(0), seq_key(1))),
+            ..Default::default()
+        };
+        let mut out = vec![];
+        walker.write_dot(&mut out, &options).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.contains(&hex::encode(seq_key(0))));
+        assert!(!dot.contains(&hex::encode(seq_key(14))));
+    }
+}
@@ -1,4 +1,5 @@
-use super::Tree;
+use super::{Tree, HASH_LENGTH};
+use crate::error::{Error, Result};
 use ed::{Decode, Encode};
 
 impl Tree {
@@ -20,19 +21,50 @@ impl Tree {
         Encode::encoding_length(self).unwrap()
     }
 
+    /// Decodes a node from `input` into `self`, reusing its allocations.
+    /// Errors (leaving `self` in an unspecified state) if `input` is not a
+    /// valid encoding, since it may come from a peer.
     #[inline]
-    pub fn decode_into(&mut self, key: Vec<u8>, input: &[u8]) {
-        // operation is infallible so it's ok to unwrap
-        Decode::decode_into(self, input).unwrap();
+    pub fn decode_into(&mut self, key: Vec<u8>, input: &[u8]) -> Result<()> {
+        Decode::decode_into(self, input)?;
         self.inner.kv.key = key;
+        Ok(())
     }
 
+    /// Decodes a node from `input`, erroring if it is not a valid encoding.
     #[inline]
-    pub fn decode(key: Vec<u8>, input: &[u8]) -> Tree {
-        // operation is infallible so it's ok to unwrap
-        let mut tree: Tree = Decode::decode(input).unwrap();
+    pub fn decode(key: Vec<u8>, input: &[u8]) -> Result<Tree> {
+        let mut tree: Tree = Decode::decode(input)?;
         tree.inner.kv.key = key;
-        tree
+        Ok(tree)
+    }
+
+    /// Returns the value of the node encoded in `input`, borrowed from it,
+    /// skipping over the node's links and hash rather than decoding them.
+    /// Errors if `input` ends before the value.
+    pub fn decode_value(input: &[u8]) -> Result<&[u8]> {
+        fn eof() -> Error {
+            std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+        }
+        fn skip(input: &[u8], len: usize) -> Result<&[u8]> {
+            input.get(len..).ok_or_else(eof)
+        }
+
+        let mut input = input;
+        for _ in 0..2 {
+            let (tag, rest) = input.split_first().ok_or_else(eof)?;
+            input = match tag {
+                0 => rest,
+                // a link is its key (prefixed with its length), hash and
+                // child heights
+                1 => {
+                    let key_len = *rest.first().unwrap_or(&0) as usize;
+                    skip(rest, 1 + key_len + HASH_LENGTH + 2)?
+                }
+                tag => return Err(ed::Error::UnexpectedByte(*tag).into()),
+            };
+        }
+        skip(input, HASH_LENGTH)
     }
 }
 
@@ -153,7 +185,7 @@ mod tests {
             0, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
             1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
         ];
-        let tree = Tree::decode(vec![0], bytes.as_slice());
+        let tree = Tree::decode(vec![0], bytes.as_slice()).unwrap();
         assert_eq!(tree.key(), &[0]);
         assert_eq!(tree.value(), &[1]);
     }
@@ -166,7 +198,7 @@ mod tests {
             55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
             55, 55, 55, 55, 55, 1,
         ];
-        let tree = Tree::decode(vec![0], bytes.as_slice());
+        let tree = Tree::decode(vec![0], bytes.as_slice()).unwrap();
         assert_eq!(tree.key(), &[0]);
         assert_eq!(tree.value(), &[1]);
         if let Some(Link::Reference {
@@ -182,4 +214,61 @@ mod tests {
             panic!("Expected Link::Reference");
         }
     }
+
+    #[test]
+    fn decode_value() {
+        let tree = Tree::from_fields(
+            vec![0],
+            vec![1, 2, 3],
+            [55; 32],
+            Some(Link::Reference {
+                hash: [66; 32],
+                child_heights: (123, 124),
+                key: vec![2],
+            }),
+            Some(Link::Reference {
+                hash: [77; 32],
+                child_heights: (1, 0),
+                key: vec![3, 4],
+            }),
+        );
+        let bytes = tree.encode();
+        assert_eq!(Tree::decode_value(&bytes).unwrap(), &[1, 2, 3]);
+
+        for len in [0, 1, 36, 71] {
+            assert!(Tree::decode_value(&bytes[..len]).is_err());
+        }
+        assert!(Tree::decode_value(&[2, 0]).is_err());
+    }
+
+    #[test]
+    fn decode_malformed_tree() {
+        let mut tree = Tree::from_fields(
+            vec![0],
+            vec![1],
+            [55; 32],
+            Some(Link::Reference {
+                key: vec![2],
+                child_heights: (3, 4),
+                hash: [66; 32],
+            }),
+            None,
+        );
+        let bytes = tree.encode();
+
+        // truncations either fail or decode to a node with a shorter value
+        for len in 0..bytes.len() {
+            let _ = Tree::decode(vec![0], &bytes[..len]);
+            let _ = tree.decode_into(vec![0], &bytes[..len]);
+        }
+
+        let mut bad_variant = bytes.clone();
+        bad_variant[0] = 2;
+        assert!(Tree::decode(vec![0], &bad_variant).is_err());
+
+        // a child height which would overflow the height arithmetic
+        let mut too_tall = bytes.clone();
+        too_tall[35] = 255;
+        assert!(Tree::decode(vec![0], &too_tall).is_err());
+    }
 }
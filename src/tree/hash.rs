@@ -1,9 +1,21 @@
-use sha2::{Digest, Sha512_256};
+use sha2::Digest;
+#[cfg(not(feature = "blake3-hash"))]
+use sha2::Sha512_256;
 use std::{convert::TryFrom, num::TryFromIntError};
 
 /// The hash algorithm used for both KV hashes and node hashes.
+#[cfg(not(feature = "blake3-hash"))]
 pub type Hasher = Sha512_256;
 
+/// The hash algorithm used for both KV hashes and node hashes. BLAKE3 is
+/// selected at runtime from the fastest SIMD implementation the CPU supports
+/// (e.g. SSE4.1, AVX2 or AVX-512).
+///
+/// **NOTE:** Stores and proofs built with this feature have different hashes,
+/// so they can not be used with builds without it.
+#[cfg(feature = "blake3-hash")]
+pub type Hasher = blake3::Hasher;
+
 /// The length of a `Hash` (in bytes).
 pub const HASH_LENGTH: usize = 32;
 
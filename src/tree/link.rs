@@ -128,6 +128,18 @@ impl Link {
         }
     }
 
+    /// Returns the heights of the children of the tree referenced by the link,
+    /// as `(left_child_height, right_child_height)`.
+    #[inline]
+    pub fn child_heights(&self) -> (u8, u8) {
+        match self {
+            Link::Reference { child_heights, .. } => *child_heights,
+            Link::Modified { child_heights, .. } => *child_heights,
+            Link::Uncommitted { child_heights, .. } => *child_heights,
+            Link::Loaded { child_heights, .. } => *child_heights,
+        }
+    }
+
     /// Returns the height of the children of the tree referenced by the link,
     /// if any (note: not the height of the referenced tree itself). Return
     /// value is `(left_child_height, right_child_height)`.
@@ -286,8 +298,8 @@ impl Decode for Link {
 
             input.read_exact(&mut hash[..])?;
 
-            child_heights.0 = read_u8(&mut input)?;
-            child_heights.1 = read_u8(&mut input)?;
+            child_heights.0 = read_child_height(&mut input)?;
+            child_heights.1 = read_child_height(&mut input)?;
         } else {
             unreachable!()
         }
@@ -298,6 +310,18 @@ impl Decode for Link {
 
 impl Terminated for Link {}
 
+/// Reads a child height, rejecting heights large enough to overflow the `u8`
+/// and `i8` arithmetic in `height` and `balance_factor`. A balanced tree this
+/// tall would have more than 2^80 nodes.
+#[inline]
+fn read_child_height<R: Read>(input: R) -> Result<u8> {
+    let height = read_u8(input)?;
+    if height > i8::MAX as u8 {
+        return Err(ed::Error::UnexpectedByte(height));
+    }
+    Ok(height)
+}
+
 #[inline]
 fn read_u8<R: Read>(mut input: R) -> Result<u8> {
     let mut length = [0];
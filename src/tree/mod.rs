@@ -1,6 +1,8 @@
 mod commit;
 #[cfg(feature = "full")]
 mod debug;
+#[cfg(feature = "full")]
+mod dot;
 mod encoding;
 mod fuzz_tests;
 mod hash;
@@ -16,6 +18,8 @@ use ed::{Decode, Encode, Terminated};
 
 use super::error::Result;
 pub use commit::{Commit, NoopCommit};
+#[cfg(feature = "full")]
+pub use dot::DotOptions;
 pub use hash::{kv_hash, node_hash, Hash, Hasher, HASH_LENGTH, NULL_HASH};
 use kv::KV;
 pub use link::Link;
@@ -1,10 +1,12 @@
 use super::{Fetch, Tree, Walker};
 use crate::error::Result;
+use std::cell::Cell;
 use std::collections::LinkedList;
 use std::fmt;
 use Op::*;
 
 /// An operation to be applied to a key in the store.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Op {
     Put(Vec<u8>),
     Delete,
@@ -23,6 +25,40 @@ impl fmt::Debug for Op {
     }
 }
 
+/// The minimum number of operations for a node's children before they are
+/// updated on separate threads.
+const PARALLEL_BATCH_SIZE: usize = 10_000;
+
+thread_local! {
+    /// How many times the batch being applied on the current thread has been
+    /// split across threads by `recurse_parallel`.
+    static PARALLEL_DEPTH: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Restores the parallel depth a thread had before applying a sub-batch, even
+/// if applying it panics.
+struct ParallelDepthGuard(u32);
+
+impl ParallelDepthGuard {
+    fn enter(depth: u32) -> Self {
+        ParallelDepthGuard(PARALLEL_DEPTH.with(|cell| cell.replace(depth)))
+    }
+}
+
+impl Drop for ParallelDepthGuard {
+    fn drop(&mut self) {
+        PARALLEL_DEPTH.with(|cell| cell.set(self.0));
+    }
+}
+
+/// The number of times a batch may be split across threads, each split
+/// doubling the threads applying it, so at most one thread runs per available
+/// core.
+fn max_parallel_depth() -> u32 {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    usize::BITS - 1 - threads.leading_zeros()
+}
+
 /// A single `(key, operation)` pair.
 pub type BatchEntry = (Vec<u8>, Op);
 
@@ -94,6 +130,11 @@ where
             Put(value) => value,
         };
 
+        // the tree is built from every put in the batch, with the sub-batches
+        // built under `PanicSource`, so the inserts are only recorded here
+        let inserts = batch.iter().filter(|(_, op)| matches!(op, Put(_))).count();
+        source.record_inserts(inserts);
+
         // TODO: take from batch so we don't have to clone
         let mid_tree = Tree::new(mid_key.to_vec(), mid_value.to_vec())?;
         let mid_walker = Walker::new(mid_tree, PanicSource {});
@@ -157,8 +198,10 @@ where
     /// Recursively applies operations to the tree's children (if there are any
     /// operations for them).
     ///
-    /// This recursion executes serially in the same thread, but in the future
-    /// will be dispatched to workers in other threads.
+    /// If both children have operations and there are at least
+    /// `PARALLEL_BATCH_SIZE` of them, the children are updated concurrently,
+    /// so large batches are applied across the top-level subtrees in parallel.
+    /// Splits stop once there are as many threads as available cores.
     fn recurse(
         self,
         batch: &Batch,
@@ -172,6 +215,14 @@ where
             &batch[mid..]
         };
 
+        if !left_batch.is_empty()
+            && !right_batch.is_empty()
+            && left_batch.len() + right_batch.len() >= PARALLEL_BATCH_SIZE
+            && PARALLEL_DEPTH.with(Cell::get) < max_parallel_depth()
+        {
+            return self.recurse_parallel(left_batch, right_batch);
+        }
+
         let mut deleted_keys = LinkedList::default();
 
         let tree = if !left_batch.is_empty() {
@@ -203,6 +254,47 @@ where
         Ok((Some(tree), deleted_keys))
     }
 
+    /// Applies operations to both of the tree's children at once, the left
+    /// child on a scoped worker thread and the right child on the current
+    /// thread, then rebalances the tree.
+    fn recurse_parallel(
+        self,
+        left_batch: &Batch,
+        right_batch: &Batch,
+    ) -> Result<(Option<Self>, LinkedList<Vec<u8>>)> {
+        let source = self.clone_source();
+        let (tree, maybe_left) = self.detach(true)?;
+        let (tree, maybe_right) = tree.detach(false)?;
+        let depth = PARALLEL_DEPTH.with(Cell::get) + 1;
+
+        let (left, right) = std::thread::scope(|scope| {
+            let left_source = source.clone();
+            let left = scope.spawn(move || {
+                let _guard = ParallelDepthGuard::enter(depth);
+                Self::apply_to(maybe_left, left_batch, left_source)
+            });
+            let right = {
+                let _guard = ParallelDepthGuard::enter(depth);
+                Self::apply_to(maybe_right, right_batch, source)
+            };
+            let left = left
+                .join()
+                .unwrap_or_else(|err| std::panic::resume_unwind(err));
+            (left, right)
+        });
+
+        let (maybe_left, mut deleted_keys) = left?;
+        let (maybe_right, mut deleted_keys_right) = right?;
+        deleted_keys.append(&mut deleted_keys_right);
+
+        let tree = tree
+            .attach(true, maybe_left)
+            .attach(false, maybe_right)
+            .maybe_balance()?;
+
+        Ok((Some(tree), deleted_keys))
+    }
+
     /// Gets the wrapped tree's balance factor.
     #[inline]
     fn balance_factor(&self) -> i8 {
@@ -233,6 +325,7 @@ where
     /// Applies an AVL tree rotation, a constant-time operation which only needs
     /// to swap pointers in order to rebalance a tree.
     fn rotate(self, left: bool) -> Result<Self> {
+        self.source().record_rotation();
         let (tree, child) = self.detach_expect(left)?;
         let (child, maybe_grandchild) = child.detach(!left)?;
 
@@ -541,4 +634,24 @@ mod test {
         maybe_walker.expect("should be Some");
         assert_eq!(deleted_keys.len(), 1_500);
     }
+
+    #[test]
+    fn apply_large_batch_in_parallel() {
+        let tree = make_tree_seq(20_000);
+
+        let mut batch: Vec<_> = (0..5_000).map(del_entry).collect();
+        batch.extend((5_000..25_000).map(put_entry));
+        assert!(batch.len() >= PARALLEL_BATCH_SIZE);
+        let threads = std::thread::available_parallelism().unwrap().get();
+        assert!(1 << max_parallel_depth() <= threads);
+
+        let (maybe_walker, deleted_keys) = Walker::new(tree, PanicSource {})
+            .apply(&batch)
+            .expect("apply errored");
+        let mut tree = maybe_walker.expect("should be Some").into_inner();
+        tree.commit(&mut NoopCommit {}).expect("commit failed");
+        assert_tree_invariants(&tree);
+        assert_eq!(deleted_keys.len(), 5_000);
+        assert_eq!(tree.iter().count(), 20_001);
+    }
 }
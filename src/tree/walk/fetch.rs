@@ -13,6 +13,22 @@ pub trait Fetch {
         self.fetch_by_key_expect(link.key())
     }
 
+    /// Called when the tree is rotated while a batch is applied, so sources
+    /// can count rotations. Does nothing by default.
+    fn record_rotation(&self) {}
+
+    /// Called when `count` new keys are added to the tree while a batch is
+    /// applied, so sources can count the keys in the tree. Does nothing by
+    /// default.
+    fn record_inserts(&self, _count: usize) {}
+
+    /// Fetches the value of the node with the given key, if any. Sources which
+    /// read encoded nodes can override this to read the value without
+    /// decoding the rest of the node.
+    fn fetch_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.fetch_by_key(key)?.map(|node| node.value().to_vec()))
+    }
+
     fn fetch_by_key_expect(&self, key: &[u8]) -> Result<Tree> {
         self.fetch_by_key(key)?
             .ok_or_else(|| Error::Key(format!("Key does not exist: {key:?}")))
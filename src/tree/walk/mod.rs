@@ -111,6 +111,11 @@ where
         Walker::new(tree, self.source.clone())
     }
 
+    /// Returns this `Walker`'s source.
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
     /// Returns a clone of this `Walker`'s source.
     pub fn clone_source(&self) -> S {
         self.source.clone()
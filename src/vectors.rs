@@ -0,0 +1,325 @@
+//! Generates canonical test vectors: the contents of a few trees, their root
+//! hashes, and encoded query and chunk proofs against them, written as JSON so
+//! implementations of proof verification in other languages can be checked
+//! against this crate.
+//!
+//! The trees are built from fixed inputs (no randomness), so the vectors only
+//! change if the hashing or proof encoding changes. Vectors generated with the
+//! `blake3-hash` feature are labeled as such, since their hashes differ.
+
+use std::fmt::Write as _;
+use std::io::Write;
+
+use sha2::{Digest, Sha256};
+
+use crate::merk::HashAlgorithm;
+use crate::proofs::query::QueryItem;
+use crate::proofs::Query;
+use crate::test_utils::TempMerk;
+use crate::tree::{Hash, Op};
+use crate::Result;
+
+/// The inputs of a test vector: the entries of a tree, and the proofs to
+/// generate against it.
+#[derive(Clone, Debug)]
+pub struct VectorCase {
+    pub name: String,
+    /// The entries of the tree, sorted by key.
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The queries to prove. Overlapping items within a query are merged, as
+    /// in `Query::insert_item`.
+    pub queries: Vec<Vec<QueryItem>>,
+    /// Whether to include the tree's state sync chunks.
+    pub chunks: bool,
+}
+
+/// A proof of a query, and the entries it proves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryVector {
+    pub items: Vec<QueryItem>,
+    pub proof: Vec<u8>,
+    /// The entries of the tree matching the query, sorted by key. Queried keys
+    /// missing from this list are proven to be absent.
+    pub result: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// A generated test vector.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestVector {
+    pub name: String,
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    pub root_hash: Hash,
+    pub queries: Vec<QueryVector>,
+    /// The encoded chunks, in order, starting with the trunk.
+    pub chunks: Vec<Vec<u8>>,
+}
+
+/// Returns the cases covered by the canonical vectors: an empty tree, a single
+/// entry, a small tree of sequential keys, and a larger tree of hashed keys
+/// with values of varying lengths.
+pub fn default_cases() -> Vec<VectorCase> {
+    let seq: Vec<_> = (0..15u64)
+        .map(|n| (n.to_be_bytes().to_vec(), vec![n as u8; 4]))
+        .collect();
+
+    let mut hashed: Vec<_> = (0..500u64)
+        .map(|n| {
+            let key = Sha256::digest(n.to_be_bytes())[..8].to_vec();
+            let value = vec![n as u8; 1 + (n % 97) as usize];
+            (key, value)
+        })
+        .collect();
+    hashed.sort();
+    let hashed_key = |i: usize| hashed[i].0.clone();
+
+    vec![
+        VectorCase {
+            name: "empty".into(),
+            entries: vec![],
+            queries: vec![
+                vec![QueryItem::Key(vec![1])],
+                vec![QueryItem::Range(vec![]..vec![255])],
+            ],
+            chunks: false,
+        },
+        VectorCase {
+            name: "single".into(),
+            entries: vec![(vec![1], vec![2])],
+            queries: vec![
+                vec![QueryItem::Key(vec![1])],
+                vec![QueryItem::Key(vec![0])],
+                vec![QueryItem::Key(vec![2])],
+            ],
+            chunks: true,
+        },
+        VectorCase {
+            name: "sequential".into(),
+            queries: vec![
+                vec![QueryItem::Key(seq[7].0.clone())],
+                vec![
+                    QueryItem::Key(seq[0].0.clone()),
+                    QueryItem::Key(seq[14].0.clone()),
+                ],
+                vec![QueryItem::Range(seq[3].0.clone()..seq[9].0.clone())],
+                vec![QueryItem::RangeInclusive(
+                    seq[3].0.clone()..=seq[9].0.clone(),
+                )],
+                vec![QueryItem::Key(100u64.to_be_bytes().to_vec())],
+                vec![QueryItem::Range(vec![]..vec![255])],
+            ],
+            entries: seq,
+            chunks: true,
+        },
+        VectorCase {
+            name: "hashed".into(),
+            queries: vec![
+                vec![QueryItem::Key(hashed_key(250))],
+                vec![QueryItem::Key(vec![0x80; 8])],
+                vec![
+                    QueryItem::Range(hashed_key(10)..hashed_key(20)),
+                    QueryItem::Key(hashed_key(300)),
+                    QueryItem::RangeInclusive(hashed_key(400)..=hashed_key(405)),
+                ],
+            ],
+            entries: hashed,
+            chunks: true,
+        },
+    ]
+}
+
+/// Builds the tree of `case` in a temporary store and generates its proofs.
+pub fn generate(case: &VectorCase) -> Result<TestVector> {
+    let mut merk = TempMerk::new()?;
+    let batch: Vec<_> = case
+        .entries
+        .iter()
+        .map(|(key, value)| (key.clone(), Op::Put(value.clone())))
+        .collect();
+    merk.apply(&batch, &[])?;
+
+    let mut queries = vec![];
+    for items in case.queries.iter() {
+        let mut query = Query::new();
+        for item in items.iter().cloned() {
+            query.insert_item(item);
+        }
+        let items: Vec<QueryItem> = query.into();
+        let result = case
+            .entries
+            .iter()
+            .filter(|(key, _)| items.iter().any(|item| item.contains(key)))
+            .cloned()
+            .collect();
+        queries.push(QueryVector {
+            proof: merk.prove_unchecked(items.clone())?,
+            items,
+            result,
+        });
+    }
+
+    let mut chunks = vec![];
+    if case.chunks && !case.entries.is_empty() {
+        let mut producer = merk.chunks()?;
+        for index in 0..producer.len() {
+            chunks.push(producer.chunk(index)?);
+        }
+    }
+
+    Ok(TestVector {
+        name: case.name.clone(),
+        entries: case.entries.clone(),
+        root_hash: merk.root_hash(),
+        queries,
+        chunks,
+    })
+}
+
+/// Generates the vectors for `cases` and writes them to `writer` as a single
+/// JSON object, with all bytes encoded as hex.
+pub fn write_json<W: Write>(cases: &[VectorCase], writer: &mut W) -> Result<()> {
+    let mut vectors = vec![];
+    for case in cases {
+        vectors.push(generate(case)?.to_json());
+    }
+
+    writeln!(
+        writer,
+        "{{\"hash_algorithm\":\"{:?}\",\"vectors\":[\n{}\n]}}",
+        HashAlgorithm::current(),
+        vectors.join(",\n")
+    )?;
+    Ok(())
+}
+
+impl TestVector {
+    /// Encodes the vector as a JSON object, with all bytes encoded as hex.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write!(json, "{{\"name\":\"{}\",\"entries\":", self.name).unwrap();
+        write_entries(&mut json, &self.entries);
+        write!(json, ",\"root_hash\":\"{}\"", hex::encode(self.root_hash)).unwrap();
+
+        json.push_str(",\"queries\":[");
+        for (i, query) in self.queries.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str("{\"items\":[");
+            for (i, item) in query.items.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                match item {
+                    QueryItem::Key(key) => write!(json, "{{\"key\":\"{}\"}}", hex::encode(key)),
+                    QueryItem::Range(range) => write!(
+                        json,
+                        "{{\"range\":[\"{}\",\"{}\"]}}",
+                        hex::encode(&range.start),
+                        hex::encode(&range.end)
+                    ),
+                    QueryItem::RangeInclusive(range) => write!(
+                        json,
+                        "{{\"range_inclusive\":[\"{}\",\"{}\"]}}",
+                        hex::encode(range.start()),
+                        hex::encode(range.end())
+                    ),
+                }
+                .unwrap();
+            }
+            write!(
+                json,
+                "],\"proof\":\"{}\",\"result\":",
+                hex::encode(&query.proof)
+            )
+            .unwrap();
+            write_entries(&mut json, &query.result);
+            json.push('}');
+        }
+
+        json.push_str("],\"chunks\":[");
+        let chunks: Vec<_> = self
+            .chunks
+            .iter()
+            .map(|chunk| format!("\"{}\"", hex::encode(chunk)))
+            .collect();
+        json.push_str(&chunks.join(","));
+        json.push_str("]}");
+        json
+    }
+}
+
+fn write_entries(json: &mut String, entries: &[(Vec<u8>, Vec<u8>)]) {
+    json.push('[');
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write!(
+            json,
+            "{{\"key\":\"{}\",\"value\":\"{}\"}}",
+            hex::encode(key),
+            hex::encode(value)
+        )
+        .unwrap();
+    }
+    json.push(']');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::chunk::{verify_leaf, verify_trunk};
+    use crate::proofs::Decoder;
+    use crate::verify;
+
+    #[test]
+    fn vectors_verify() {
+        for case in default_cases() {
+            let vector = generate(&case).unwrap();
+            assert_eq!(generate(&case).unwrap(), vector);
+
+            for query in vector.queries.iter() {
+                let map = verify(&query.proof, vector.root_hash).unwrap();
+                let mut proven = vec![];
+                for item in query.items.iter() {
+                    let entries: Vec<_> = match item {
+                        QueryItem::Key(key) => map
+                            .get(key)
+                            .unwrap()
+                            .map(|value| (key.as_slice(), value))
+                            .into_iter()
+                            .collect(),
+                        QueryItem::Range(range) => map
+                            .range(range.start.as_slice()..range.end.as_slice())
+                            .collect::<Result<_>>()
+                            .unwrap(),
+                        QueryItem::RangeInclusive(range) => map
+                            .range(range.start().as_slice()..=range.end().as_slice())
+                            .collect::<Result<_>>()
+                            .unwrap(),
+                    };
+                    proven.extend(
+                        entries
+                            .into_iter()
+                            .map(|(key, value)| (key.to_vec(), value.to_vec())),
+                    );
+                }
+                assert_eq!(proven, query.result);
+            }
+
+            if let Some((trunk, leaves)) = vector.chunks.split_first() {
+                let (trunk, height) = verify_trunk(Decoder::new(trunk), None).unwrap();
+                assert_eq!(trunk.hash().unwrap(), vector.root_hash);
+                for (leaf, node) in leaves.iter().zip(trunk.layer(height / 2)) {
+                    verify_leaf(Decoder::new(leaf), node.hash().unwrap()).unwrap();
+                }
+            }
+        }
+
+        let mut json = vec![];
+        write_json(&default_cases(), &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"hash_algorithm\":"));
+        assert!(json.contains("\"name\":\"hashed\""));
+    }
+}